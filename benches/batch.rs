@@ -0,0 +1,54 @@
+//! Render the same sound at 30 different pitches, one at a time and with `render_batch`.
+//!
+//! Run with `cargo bench --bench batch`.
+
+use std::time::{Duration, Instant};
+
+use pokemon_synthesizer::{gen1, SynthesisOptions};
+
+const RUNS: usize = 5;
+
+/// Two pulse channels and a noise channel, a bit over a second long.
+const ROM: [u8; 35] = [
+    0x84, 0x09, 0x00, 0x05, 0x16, 0x00, 0x07, 0x1f, 0x00, // header
+    0x2f, 0xf2, 0x00, 0x07, 0x2f, 0xf2, 0x80, 0x06, 0x2f, 0xa4, 0x40, 0x07, 0xff, // pulse1
+    0x2f, 0xc3, 0x20, 0x07, 0x2f, 0xc3, 0xa0, 0x06, 0xff, // pulse2
+    0x2f, 0xf4, 0x44, 0xff, // noise
+];
+
+fn best_of(mut f: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn main() {
+    let pitches: Vec<i8> = (0..30).map(|n| (n * 8 - 120) as i8).collect();
+    let options = SynthesisOptions::new();
+
+    let naive = best_of(|| {
+        for &pitch in &pitches {
+            let pcm = gen1::synthesis_with_options(&ROM, 0, 0, pitch, 1, options);
+            assert!(pcm.to_buffer().is_some());
+        }
+    });
+
+    let batch = best_of(|| {
+        let buffers = gen1::render_batch(&ROM, 0, 0, &pitches, 1, options);
+        assert_eq!(buffers.map(|buffers| buffers.len()), Some(pitches.len()));
+    });
+
+    println!(
+        "render 30 pitches one at a time: {:?} (best of {})",
+        naive, RUNS
+    );
+    println!(
+        "render 30 pitches with render_batch: {:?} (best of {})",
+        batch, RUNS
+    );
+}
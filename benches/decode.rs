@@ -0,0 +1,44 @@
+//! Measure the length of a looping, command dense sound, which is dominated by decoding the
+//! commands since no samples are synthesized.
+//!
+//! Run with `cargo bench --bench decode`.
+
+use std::time::{Duration, Instant};
+
+use pokemon_synthesizer::gen1;
+
+const RUNS: usize = 100;
+
+/// Twenty duty_cycle commands followed by a one frame long square_note, looped 255 times.
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0x04, 0x03, 0x00];
+
+    for _ in 0..20 {
+        rom.extend([0xec, 0x01]);
+    }
+
+    rom.extend([0x20, 0xf1, 0x00, 0x07]); // square_note
+    rom.extend([0xfe, 0xff, 0x03, 0x00]); // loop
+    rom.push(0xff); // return
+
+    rom
+}
+
+fn main() {
+    let rom = rom();
+    let pcm = gen1::synthesis(&rom, 0, 0, 0, 0);
+    let mut best = Duration::MAX;
+
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        assert!(pcm.total_duration().is_some());
+        best = best.min(start.elapsed());
+    }
+
+    println!(
+        "measure a looping sound with {} commands: {:?} (best of {})",
+        255 * 22,
+        best,
+        RUNS
+    );
+}
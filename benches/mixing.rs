@@ -0,0 +1,81 @@
+//! Mix 1000 frames of a sound that plays on both pulse channels and the noise channel. The wave
+//! channel is left out, since SFX wave data can't be synthesized yet.
+//!
+//! The same frames are pulled with `fill`, `next_frame` and one sample at a time with `next`, to
+//! compare the overhead of each of them.
+//!
+//! Run with `cargo bench --bench mixing`.
+
+use std::time::{Duration, Instant};
+
+use pokemon_synthesizer::{gen1, SAMPLES_PER_FRAME};
+
+const FRAMES: usize = 1000;
+const RUNS: usize = 10;
+
+/// Three channels, each looping a single note forever.
+const ROM: [u8; 32] = [
+    0x84, 0x09, 0x00, 0x05, 0x11, 0x00, 0x07, 0x19, 0x00, // header
+    0x2f, 0xf0, 0x00, 0x07, 0xfe, 0x00, 0x09, 0x00, // pulse1
+    0x2f, 0xf0, 0x40, 0x07, 0xfe, 0x00, 0x11, 0x00, // pulse2
+    0x2f, 0xf0, 0x44, 0xfe, 0x00, 0x19, 0x00, // noise
+];
+
+fn main() {
+    let pcm = gen1::synthesis(&ROM, 0, 0, 0, 0);
+    let mut buffer = vec![0.0; SAMPLES_PER_FRAME];
+
+    report(
+        "fill",
+        measure(|| {
+            let mut iter = pcm.iter();
+            for _ in 0..FRAMES {
+                assert_eq!(iter.fill(&mut buffer), SAMPLES_PER_FRAME);
+            }
+        }),
+    );
+
+    report(
+        "next_frame",
+        measure(|| {
+            let mut iter = pcm.iter();
+            for _ in 0..FRAMES {
+                assert!(iter.next_frame().is_some());
+            }
+        }),
+    );
+
+    report(
+        "next",
+        measure(|| {
+            let mut iter = pcm.iter();
+            for _ in 0..(FRAMES * SAMPLES_PER_FRAME) {
+                assert!(iter.next().is_some());
+            }
+        }),
+    );
+}
+
+/// The best time of `RUNS` runs of `f`.
+fn measure(mut f: impl FnMut()) -> Duration {
+    let mut best = Duration::MAX;
+
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        f();
+        best = best.min(start.elapsed());
+    }
+
+    best
+}
+
+fn report(name: &str, best: Duration) {
+    println!(
+        "mix {} frames of 3 channels with {}: {:?} (best of {}), {:?} per frame",
+        FRAMES,
+        name,
+        best,
+        RUNS,
+        best / (FRAMES as u32)
+    );
+}
@@ -0,0 +1,85 @@
+//! Synthesis throughput of a few cries from Pokémon Yellow, in samples per second at
+//! `SOURCE_SAMPLE_RATE`: a short cry, a long cry with a fade-out, the noise channel of a cry on its
+//! own, the cries of the first 20 species, and a cry resampled to 48 kHz.
+//!
+//! Needs the ROM in `roms/`, the same as the tests. Run with `cargo bench --bench synthesis`.
+
+use std::time::{Duration, Instant};
+
+use pokemon_synthesizer::{gen1, ChannelMask, SynthesisOptions, Voice};
+
+const POKEYELLOW: &[u8] = include_bytes!("../roms/pokeyellow.gbc");
+
+const RUNS: usize = 10;
+
+/// The cries are all shorter than this, it only keeps the benchmark from running forever on a
+/// ROM that isn't Pokémon Yellow.
+const MAX_DURATION: Duration = Duration::from_secs(10);
+
+/// The best time of `RUNS` runs of `f`, and the number of samples that it rendered.
+fn best_of(mut f: impl FnMut() -> usize) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut samples = 0;
+
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        samples = f();
+        best = best.min(start.elapsed());
+    }
+
+    (best, samples)
+}
+
+fn report(name: &str, (best, samples): (Duration, usize)) {
+    println!(
+        "{}: {} samples in {:?} (best of {}), {:.0} samples/s",
+        name,
+        samples,
+        best,
+        RUNS,
+        samples as f64 / best.as_secs_f64()
+    );
+}
+
+/// Render the cry at the given address, and return the number of samples.
+fn render(addr: u16, pitch: i8, length: u8, options: SynthesisOptions) -> usize {
+    let pcm = gen1::synthesis_with_options(POKEYELLOW, 0x02, addr, pitch, length, options);
+    pcm.iter().fold(0, |len, _| len + 1)
+}
+
+fn main() {
+    let options = SynthesisOptions::new().max_duration(Some(MAX_DURATION));
+
+    report("Diglett", best_of(|| render(0x409f, -86, 1, options)));
+    report("Snorlax", best_of(|| render(0x4069, 85, 1, options)));
+    report(
+        "Aerodactyl, noise channel only",
+        best_of(|| {
+            let options = options.channel_mask(ChannelMask::solo(Voice::Noise));
+            render(0x4177, 32, 240, options)
+        }),
+    );
+
+    report(
+        "20 cries",
+        best_of(|| {
+            (1..=20)
+                .map(|species| match gen1::lookup_cry(POKEYELLOW, species) {
+                    Ok(cry) => render(cry.location.addr, cry.pitch, cry.length, options),
+                    Err(err) => panic!("{}", err),
+                })
+                .sum()
+        }),
+    );
+
+    // the throughput is in source samples, to compare it with the other cries
+    report(
+        "Snorlax at 48 kHz",
+        best_of(|| {
+            let pcm = gen1::synthesis_with_options(POKEYELLOW, 0x02, 0x4069, 85, 1, options);
+            let source_len = pcm.iter().count();
+            pcm.resample(48000).iter().fold(0, |len, _| len + 1);
+            source_len
+        }),
+    );
+}
@@ -0,0 +1,78 @@
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=data/names.txt");
+
+    if env::var_os("CARGO_FEATURE_NAMES").is_some() {
+        generate_names();
+    }
+}
+
+/// Turn `data/names.txt` into a sorted table of `(game, bank, index, name)`.
+fn generate_names() {
+    let input = fs::read_to_string("data/names.txt").unwrap();
+    let mut entries = Vec::new();
+
+    for (number, line) in input.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [games, bank, index, name] = fields[..] else {
+            panic!("data/names.txt:{}: expected 4 fields", number + 1);
+        };
+
+        let bank = u8::from_str_radix(bank, 16).unwrap();
+        let index = u8::from_str_radix(index, 16).unwrap();
+
+        for game in games.split(',') {
+            let game = match game {
+                "red" => 0,
+                "blue" => 1,
+                "yellow" => 2,
+                "gold" => 3,
+                "silver" => 4,
+                "crystal" => 5,
+                _ => panic!("data/names.txt:{}: unknown game {}", number + 1, game),
+            };
+
+            entries.push((game, bank, index, name));
+        }
+    }
+
+    entries.sort();
+
+    for pair in entries.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        assert!(
+            (a.0, a.1, a.2) != (b.0, b.1, b.2),
+            "Duplicate entry: {}",
+            b.3
+        );
+    }
+
+    const GAMES: [&str; 6] = ["Red", "Blue", "Yellow", "Gold", "Silver", "Crystal"];
+
+    let mut output = String::from("static NAMES: &[(Game, u8, u8, &str)] = &[\n");
+
+    for (game, bank, index, name) in entries {
+        writeln!(
+            output,
+            "    (Game::{}, 0x{:02x}, 0x{:02x}, {:?}),",
+            GAMES[game], bank, index, name
+        )
+        .unwrap();
+    }
+
+    output.push_str("];\n");
+
+    let path = Path::new(&env::var_os("OUT_DIR").unwrap()).join("names.rs");
+    fs::write(path, output).unwrap();
+}
@@ -0,0 +1,197 @@
+use pokemon_synthesizer::{gen1, gen2, pitch, RomLocation, SynthesisOptions, Synthesizer};
+
+const SAMPLE_RATE: u32 = 48000;
+
+/// Read a MIDI variable length quantity.
+fn read_varlen(data: &[u8], pos: &mut usize) -> u32 {
+    let mut value = 0;
+
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value = (value << 7) | (byte & 0x7f) as u32;
+
+        if byte & 0x80 == 0 {
+            return value;
+        }
+    }
+}
+
+/// Parse a standard MIDI file into a list of (time in seconds, note) for every note-on event,
+/// from all tracks and channels.
+fn read_midi(data: &[u8]) -> Vec<(f64, u8)> {
+    assert_eq!(&data[0..4], b"MThd", "Not a MIDI file");
+
+    let division = u16::from_be_bytes([data[12], data[13]]);
+    assert!(division & 0x8000 == 0, "SMPTE timing is not supported");
+
+    let mut notes = Vec::new();
+    let mut tempos = vec![(0, 500_000)];
+    let mut pos = 8 + u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]);
+        let end = pos + 8 + len as usize;
+
+        if &data[pos..(pos + 4)] != b"MTrk" {
+            pos = end;
+            continue;
+        }
+
+        pos += 8;
+
+        let mut tick = 0u64;
+        let mut status = 0u8;
+
+        while pos < end {
+            tick += read_varlen(data, &mut pos) as u64;
+
+            if data[pos] & 0x80 != 0 {
+                status = data[pos];
+                pos += 1;
+            }
+
+            match status {
+                0xff => {
+                    let kind = data[pos];
+                    pos += 1;
+                    let len = read_varlen(data, &mut pos) as usize;
+
+                    if kind == 0x51 && len == 3 {
+                        let tempo =
+                            u32::from_be_bytes([0, data[pos], data[pos + 1], data[pos + 2]]);
+                        tempos.push((tick, tempo));
+                    }
+
+                    pos += len;
+                }
+                0xf0 | 0xf7 => {
+                    let len = read_varlen(data, &mut pos) as usize;
+                    pos += len;
+                }
+                0xc0..=0xdf => pos += 1,
+                _ => {
+                    if status & 0xf0 == 0x90 && data[pos + 1] > 0 {
+                        notes.push((tick, data[pos]));
+                    }
+
+                    pos += 2;
+                }
+            }
+        }
+
+        pos = end;
+    }
+
+    tempos.sort_by_key(|&(tick, _)| tick);
+
+    let to_seconds = |tick: u64| {
+        let mut seconds = 0.0;
+        let mut last = (0, 500_000);
+
+        for &(start, tempo) in tempos.iter().take_while(|&&(start, _)| start <= tick) {
+            seconds += (start - last.0) as f64 * last.1 as f64 / 1e6 / division as f64;
+            last = (start, tempo);
+        }
+
+        seconds + (tick - last.0) as f64 * last.1 as f64 / 1e6 / division as f64
+    };
+
+    notes
+        .into_iter()
+        .map(|(tick, note)| (to_seconds(tick), note))
+        .collect()
+}
+
+fn write_wav(path: &str, data: Vec<f32>) {
+    let bytes = pokemon_synthesizer::PcmBuffer::new(data, SAMPLE_RATE)
+        .unwrap()
+        .into_i16_bytes();
+
+    let mut file = Vec::with_capacity(44 + bytes.len());
+    file.extend(b"RIFF");
+    file.extend((36 + bytes.len() as u32).to_le_bytes());
+    file.extend(b"WAVEfmt ");
+    file.extend(16u32.to_le_bytes()); // remaining header size
+    file.extend(1u16.to_le_bytes()); // PCM type
+    file.extend(1u16.to_le_bytes()); // channels
+    file.extend(SAMPLE_RATE.to_le_bytes()); // sample rate
+    file.extend((SAMPLE_RATE * 2).to_le_bytes()); // byte rate
+    file.extend(2u16.to_le_bytes()); // block align
+    file.extend(16u16.to_le_bytes()); // bits per sample
+    file.extend(b"data");
+    file.extend((bytes.len() as u32).to_le_bytes());
+    file.extend(bytes);
+
+    std::fs::write(path, file).unwrap();
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.len() != 5 {
+        eprintln!("Usage: midi_cries <rom_file_path> <bank:addr> <midi_file_path> <out_file_path>");
+        std::process::exit(1);
+    }
+
+    let rom = std::fs::read(&args[1]).unwrap();
+
+    let mut bank_addr = args[2].split(':');
+    let bank: u8 = u8::from_str_radix(bank_addr.next().unwrap(), 16).unwrap();
+    let addr: u16 = u16::from_str_radix(bank_addr.next().unwrap(), 16).unwrap();
+    let location = RomLocation::new(bank, addr);
+
+    let notes = read_midi(&std::fs::read(&args[3]).unwrap());
+    let synthesizer = Synthesizer::new(rom).unwrap();
+    let generation = synthesizer.game().generation();
+
+    let (events, length) = match generation {
+        1 => (
+            gen1::synthesis(synthesizer.rom(), bank, addr, 0, 0x80).note_events(),
+            0x80,
+        ),
+        _ => (
+            gen2::synthesis(synthesizer.rom(), bank, addr, 0, 0x100).note_events(),
+            0x100,
+        ),
+    };
+
+    let base = pitch::fundamental(&events).expect("The sound has no pulse1 notes");
+    eprintln!("Fundamental of the sound is {:.1} Hz", base);
+
+    let options = SynthesisOptions::new().preview(SAMPLE_RATE);
+    let mut output: Vec<f32> = Vec::new();
+
+    for (time, note) in notes {
+        let mut value = pitch::for_midi_note(base, note);
+
+        if generation == 1 {
+            if !(0..=255).contains(&value) {
+                eprintln!(
+                    "Skipping note {}, out of range for the first generation",
+                    note
+                );
+                continue;
+            }
+
+            value = (value as u8 as i8) as i16;
+        }
+
+        let cry: Vec<f32> = synthesizer.iter(location, value, length, options).collect();
+        let start = (time * SAMPLE_RATE as f64).round() as usize;
+
+        if output.len() < start + cry.len() {
+            output.resize(start + cry.len(), 0.0);
+        }
+
+        for (out, sample) in output[start..].iter_mut().zip(cry) {
+            *out += sample;
+        }
+    }
+
+    eprintln!(
+        "Writing {:.1} seconds",
+        output.len() as f64 / SAMPLE_RATE as f64
+    );
+    write_wav(&args[4], output);
+}
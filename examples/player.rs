@@ -1,39 +1,4 @@
-use pokemon_synthesizer::gen1::SoundIterator;
-use rodio::{OutputStream, Source};
-
-struct PcmSource<'a>(SoundIterator<'a>);
-
-impl<'a> PcmSource<'a> {
-    fn new(source: SoundIterator<'a>) -> PcmSource<'a> {
-        PcmSource(source)
-    }
-}
-
-impl Iterator for PcmSource<'_> {
-    type Item = f32;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.0.next()
-    }
-}
-
-impl Source for PcmSource<'_> {
-    fn current_frame_len(&self) -> Option<usize> {
-        None
-    }
-
-    fn channels(&self) -> u16 {
-        self.0.channels()
-    }
-
-    fn sample_rate(&self) -> u32 {
-        self.0.sample_rate()
-    }
-
-    fn total_duration(&self) -> Option<std::time::Duration> {
-        None
-    }
-}
+use rodio::OutputStream;
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
@@ -59,7 +24,7 @@ fn main() {
 
     let (_stream, stream_handle) = OutputStream::try_default().unwrap();
 
-    stream_handle.play_raw(PcmSource::new(pcm.iter())).unwrap();
+    stream_handle.play_raw(pcm.iter()).unwrap();
 
     eprintln!("Playing for {:?}", duration);
     std::thread::sleep(duration);
@@ -0,0 +1,490 @@
+//! A minimal model of the Game Boy APU, driven by register writes, used as an independent
+//! reference for the synthesizer by the `apu-compare` binary. This is not part of the public
+//! API, and is only built with the `apu-ref` feature.
+//!
+//! The interpreter's state is translated into the register writes that the game's sound engine
+//! would make each frame, and the APU then plays them with the hardware timings: the envelope
+//! ticks at 64 Hz and the sweep at 128 Hz, instead of once per frame, and the pulse channels
+//! use the duty waveforms of the hardware. The wave channel and the length counters aren't
+//! modelled, since no sound effect that can be synthesized uses them.
+
+use crate::{NoteEvent, SAMPLES_PER_FRAME};
+
+/// Maximum number of frames to translate, a minute, since music loops forever.
+pub const MAX_FRAMES: usize = 3600;
+
+/// Number of samples, at `SOURCE_SAMPLE_RATE`, between each step of the frame sequencer.
+const FRAME_SEQUENCER_PERIOD: usize = 2048;
+
+/// Output of each of the eight steps of the four duty cycles, from Pan Docs.
+const DUTY_WAVEFORMS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// What the engine did to a channel during a frame.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FrameState {
+    /// The note that was triggered during the frame, if any.
+    pub trigger: Option<NoteEvent>,
+    pub duty: u8,
+    /// Value of the pitch sweep register.
+    pub sweep: u8,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RegisterWrite {
+    /// Sample, at `SOURCE_SAMPLE_RATE`, at which the write happens.
+    pub at: usize,
+    /// Address of the register, e.g. 0xff10 for NR10.
+    pub register: u16,
+    pub value: u8,
+}
+
+/// Translate the frame states of each channel, in the order pulse1, pulse2, wave and noise,
+/// into register writes. A channel is turned off by clearing its DAC once it ends.
+pub fn register_writes(channels: &[Option<Vec<FrameState>>; 4]) -> Vec<RegisterWrite> {
+    let mut writes = vec![
+        RegisterWrite::new(0, 0xff26, 0x80),
+        RegisterWrite::new(0, 0xff24, 0x77),
+        RegisterWrite::new(0, 0xff25, 0xff),
+    ];
+
+    for (index, states) in channels.iter().enumerate() {
+        let Some(states) = states else { continue };
+
+        // the wave channel isn't modelled
+        let base = match index {
+            0 => 0xff10,
+            1 => 0xff15,
+            3 => 0xff1f,
+            _ => continue,
+        };
+
+        let mut duty = None;
+
+        for (frame, state) in states.iter().enumerate() {
+            let at = frame * SAMPLES_PER_FRAME;
+
+            if index < 2 && duty != Some(state.duty) {
+                writes.push(RegisterWrite::new(at, base + 1, state.duty << 6));
+                duty = Some(state.duty);
+            }
+
+            if let Some(event) = state.trigger {
+                let envelope = (event.volume << 4) | ((event.fade as u8) & 0x0f);
+
+                if index == 0 {
+                    writes.push(RegisterWrite::new(at, 0xff10, state.sweep));
+                }
+
+                writes.push(RegisterWrite::new(at, base + 2, envelope));
+
+                if index == 3 {
+                    writes.push(RegisterWrite::new(at, base + 3, event.freq as u8));
+                    writes.push(RegisterWrite::new(at, base + 4, 0x80));
+                } else {
+                    let [lo, hi] = event.freq.to_le_bytes();
+                    writes.push(RegisterWrite::new(at, base + 3, lo));
+                    writes.push(RegisterWrite::new(at, base + 4, 0x80 | (hi & 0x07)));
+                }
+            }
+        }
+
+        writes.push(RegisterWrite::new(
+            states.len() * SAMPLES_PER_FRAME,
+            base + 2,
+            0,
+        ));
+    }
+
+    writes.sort_by_key(|write| write.at);
+    writes
+}
+
+impl RegisterWrite {
+    fn new(at: usize, register: u16, value: u8) -> RegisterWrite {
+        RegisterWrite {
+            at,
+            register,
+            value,
+        }
+    }
+}
+
+/// Same scale as the synthesizer, so that the outputs can be compared directly.
+fn sample(bit: bool, volume: u8) -> f32 {
+    if bit {
+        -(volume as f32) / 16.0
+    } else {
+        volume as f32 / 16.0
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.register & 0x07;
+    }
+
+    fn tick(&mut self) {
+        let period = self.register & 0x07;
+
+        if period == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+
+        if self.timer == 0 {
+            self.timer = period;
+
+            if self.register & 0x08 != 0 && self.volume < 15 {
+                self.volume += 1;
+            } else if self.register & 0x08 == 0 && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.register & 0xf8 != 0
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Pulse {
+    enabled: bool,
+    duty: u8,
+    freq: u16,
+    step: usize,
+    timer: usize,
+    envelope: Envelope,
+    sweep: u8,
+    sweep_timer: u8,
+    sweep_enabled: bool,
+}
+
+impl Pulse {
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.timer = 2048 - self.freq as usize;
+        self.envelope.trigger();
+
+        let period = (self.sweep >> 4) & 0x07;
+        self.sweep_timer = if period == 0 { 8 } else { period };
+        self.sweep_enabled = period != 0 || self.sweep & 0x07 != 0;
+
+        if self.sweep & 0x07 != 0 && self.next_sweep_freq() > 0x7ff {
+            self.enabled = false;
+        }
+    }
+
+    fn next_sweep_freq(&self) -> u16 {
+        let offset = self.freq >> (self.sweep & 0x07);
+
+        if self.sweep & 0x08 != 0 {
+            self.freq.wrapping_sub(offset)
+        } else {
+            self.freq + offset
+        }
+    }
+
+    fn tick_sweep(&mut self) {
+        self.sweep_timer = self.sweep_timer.saturating_sub(1);
+
+        if self.sweep_timer > 0 {
+            return;
+        }
+
+        let period = (self.sweep >> 4) & 0x07;
+        self.sweep_timer = if period == 0 { 8 } else { period };
+
+        if !self.sweep_enabled || period == 0 {
+            return;
+        }
+
+        let freq = self.next_sweep_freq();
+
+        if freq > 0x7ff {
+            self.enabled = false;
+        } else if self.sweep & 0x07 != 0 {
+            self.freq = freq;
+
+            if self.next_sweep_freq() > 0x7ff {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn output(&mut self) -> f32 {
+        let bit = (DUTY_WAVEFORMS[self.duty as usize] >> (7 - self.step)) & 1 == 1;
+
+        if self.timer <= 1 {
+            self.timer = 2048 - self.freq as usize;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+
+        match self.enabled {
+            true => sample(bit, self.envelope.volume),
+            false => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Noise {
+    enabled: bool,
+    params: u8,
+    lfsr: u16,
+    timer: usize,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn period(&self) -> Option<usize> {
+        let shift = self.params >> 4;
+        let divider = (self.params & 0x07) as usize;
+
+        match (shift, divider) {
+            (0xe.., _) => None,
+            (_, 0) => Some(2 << shift),
+            (_, _) => Some((4 * divider) << shift),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.lfsr = 0x7fff;
+        self.timer = self.period().unwrap_or(0);
+        self.envelope.trigger();
+    }
+
+    fn output(&mut self) -> f32 {
+        let bit = self.lfsr & 1 == 0;
+
+        if let Some(period) = self.period() {
+            self.timer = self.timer.saturating_sub(1);
+
+            if self.timer == 0 {
+                self.timer = period;
+
+                let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+                self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+
+                if self.params & 0x08 != 0 {
+                    self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+                }
+            }
+        }
+
+        match self.enabled {
+            true => sample(bit, self.envelope.volume),
+            false => 0.0,
+        }
+    }
+}
+
+/// The pulse and noise channels of the APU, clocked once per sample at `SOURCE_SAMPLE_RATE`.
+#[derive(Debug, Default, Clone)]
+pub struct Apu {
+    pulse: [Pulse; 2],
+    noise: Noise,
+    sequencer_timer: usize,
+    sequencer_step: usize,
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu::default()
+    }
+
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0xff10 => self.pulse[0].sweep = value,
+            0xff11 | 0xff16 => self.pulse[pulse_index(register)].duty = value >> 6,
+            0xff12 | 0xff17 | 0xff21 => {
+                let envelope = match register {
+                    0xff21 => &mut self.noise.envelope,
+                    _ => &mut self.pulse[pulse_index(register)].envelope,
+                };
+
+                envelope.register = value;
+
+                if !envelope.dac_enabled() {
+                    match register {
+                        0xff21 => self.noise.enabled = false,
+                        _ => self.pulse[pulse_index(register)].enabled = false,
+                    }
+                }
+            }
+            0xff13 | 0xff18 => {
+                let pulse = &mut self.pulse[pulse_index(register)];
+                pulse.freq = (pulse.freq & 0x700) | value as u16;
+            }
+            0xff14 | 0xff19 => {
+                let pulse = &mut self.pulse[pulse_index(register)];
+                pulse.freq = (pulse.freq & 0xff) | (((value & 0x07) as u16) << 8);
+
+                if value & 0x80 != 0 {
+                    pulse.trigger();
+                }
+            }
+            0xff22 => self.noise.params = value,
+            0xff23 if value & 0x80 != 0 => self.noise.trigger(),
+            _ => {}
+        }
+    }
+
+    /// Advance one sample and return the mixed output, on the same scale as the synthesizer.
+    pub fn next_sample(&mut self) -> f32 {
+        self.sequencer_timer += 1;
+
+        if self.sequencer_timer == FRAME_SEQUENCER_PERIOD {
+            self.sequencer_timer = 0;
+            self.sequencer_step = (self.sequencer_step + 1) % 8;
+
+            if self.sequencer_step == 2 || self.sequencer_step == 6 {
+                self.pulse[0].tick_sweep();
+            }
+
+            if self.sequencer_step == 7 {
+                self.pulse[0].envelope.tick();
+                self.pulse[1].envelope.tick();
+                self.noise.envelope.tick();
+            }
+        }
+
+        (self.pulse[0].output() + self.pulse[1].output() + self.noise.output()) / 3.0
+    }
+}
+
+fn pulse_index(register: u16) -> usize {
+    match register {
+        0xff10..=0xff14 => 0,
+        _ => 1,
+    }
+}
+
+/// Play the register writes on the APU, returning `len` samples.
+pub fn render(writes: &[RegisterWrite], len: usize) -> Vec<f32> {
+    let mut apu = Apu::new();
+    let mut writes = writes.iter().peekable();
+
+    (0..len)
+        .map(|index| {
+            while let Some(write) = writes.next_if(|write| write.at <= index) {
+                apu.write(write.register, write.value);
+            }
+
+            apu.next_sample()
+        })
+        .collect()
+}
+
+/// How closely the synthesizer matches the APU, see `compare`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Report {
+    /// Pearson correlation of the two outputs.
+    pub correlation: f64,
+    pub rms_error: f64,
+    pub max_error: f64,
+    /// Difference in length, in samples, positive when the synthesizer is longer.
+    pub length_delta: i64,
+}
+
+/// Compare the output of the synthesizer with the output of the APU, over their common length.
+pub fn compare(synthesized: &[f32], reference: &[f32]) -> Report {
+    let len = synthesized.len().min(reference.len());
+    let (a, b) = (&synthesized[..len], &reference[..len]);
+
+    let mean = |data: &[f32]| data.iter().map(|&x| x as f64).sum::<f64>() / len.max(1) as f64;
+    let (mean_a, mean_b) = (mean(a), mean(b));
+
+    let mut covariance = 0.0;
+    let mut variance_a = 0.0;
+    let mut variance_b = 0.0;
+    let mut squares = 0.0;
+    let mut max_error: f64 = 0.0;
+
+    for (&x, &y) in a.iter().zip(b) {
+        let (dx, dy) = (x as f64 - mean_a, y as f64 - mean_b);
+        covariance += dx * dy;
+        variance_a += dx * dx;
+        variance_b += dy * dy;
+
+        let error = (x as f64 - y as f64).abs();
+        squares += error * error;
+        max_error = max_error.max(error);
+    }
+
+    Report {
+        correlation: covariance / (variance_a * variance_b).sqrt().max(f64::MIN_POSITIVE),
+        rms_error: (squares / len.max(1) as f64).sqrt(),
+        max_error,
+        length_delta: synthesized.len() as i64 - reference.len() as i64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen1;
+
+    #[test]
+    fn test_square_note() {
+        // a single pulse channel with a duty cycle 2 note, fading out
+        let rom = [0x04, 0x03, 0x00, 0xec, 0x02, 0x2f, 0xf1, 0x00, 0x07, 0xff];
+        let pcm = gen1::synthesis(&rom, 0, 0, 0, 0x80);
+        let writes = pcm.register_writes();
+
+        assert!(writes.contains(&RegisterWrite::new(0, 0xff11, 0x80)));
+        assert!(writes.contains(&RegisterWrite::new(0, 0xff12, 0xf1)));
+        assert!(writes.contains(&RegisterWrite::new(0, 0xff14, 0x87)));
+
+        let reference = render(&writes, 4 * SAMPLES_PER_FRAME);
+
+        // a 512 Hz tone, 2048 samples per period
+        let edges = (1..reference.len())
+            .filter(|&i| reference[i - 1] >= 0.0 && reference[i] < 0.0)
+            .count();
+        assert_eq!(edges, reference.len() / 2048);
+        assert_eq!(reference[0].abs(), 15.0 / 16.0 / 3.0);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut apu = Apu::new();
+        apu.write(0xff12, 0xf1);
+        apu.write(0xff14, 0x80);
+
+        // one step of the envelope every 64th of a second
+        let peak = |apu: &mut Apu| {
+            (0..16384)
+                .map(|_| apu.next_sample().abs())
+                .fold(0.0, f32::max)
+        };
+
+        assert_eq!(peak(&mut apu), 15.0 / 16.0 / 3.0);
+        assert_eq!(peak(&mut apu), 14.0 / 16.0 / 3.0);
+    }
+
+    #[test]
+    fn test_compare() {
+        let data = [0.0, 0.5, -0.5, 0.25];
+        let report = compare(&data, &data);
+
+        assert!((report.correlation - 1.0).abs() < 1e-9);
+        assert_eq!(report.rms_error, 0.0);
+        assert_eq!(report.length_delta, 0);
+
+        let inverted: Vec<f32> = data.iter().map(|x| -x).collect();
+        assert!((compare(&data, &inverted).correlation + 1.0).abs() < 1e-9);
+    }
+}
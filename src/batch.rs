@@ -0,0 +1,52 @@
+//! Rendering the same sound at several pitches in parallel.
+
+use std::thread;
+
+use crate::generation::Render;
+use crate::PcmBuffer;
+
+/// Render every sound to the end, in parallel. The sounds must all have the same length, which
+/// is the case for the same sound at different pitches. Returns `None` if the sounds are
+/// infinitely long.
+pub(crate) fn render_batch<I: Render + Sync>(sounds: Vec<I>) -> Option<Vec<PcmBuffer>> {
+    // the pitch never changes which commands are executed, so every render has the same length
+    let len = match sounds.first() {
+        Some(sound) => sound.clone().count(),
+        None => return Some(Vec::new()),
+    };
+
+    if len == usize::MAX {
+        return None;
+    }
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = (sounds.len() + threads - 1) / threads;
+
+    let result = thread::scope(|scope| {
+        let handles: Vec<_> = sounds
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || chunk.iter().map(|sound| render(sound, len)).collect())
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| -> Vec<PcmBuffer> {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+
+    Some(result)
+}
+
+fn render<I: Render>(sound: &I, len: usize) -> PcmBuffer {
+    let mut data = vec![0.0; len];
+    let written = sound.clone().fill(&mut data);
+    data.truncate(written);
+
+    PcmBuffer::rendered(data, sound.sample_rate())
+}
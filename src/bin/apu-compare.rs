@@ -0,0 +1,60 @@
+//! Compare the synthesizer with a reference model of the APU, fed the same register writes.
+//!
+//! Usage: `cargo run --bin apu-compare --features apu-ref -- <rom> <bank:addr> [pitch] [length]`
+//!
+//! The pitch defaults to 0, and the length to the one used for sound effects. Music loops
+//! forever, so only the first minute is compared.
+
+use std::process::ExitCode;
+
+use pokemon_synthesizer::{apu, gen1, gen2, Game};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+
+    if !(3..=5).contains(&args.len()) {
+        eprintln!("Usage: apu-compare <rom_file_path> <bank:addr> [pitch] [length]");
+        return ExitCode::from(2);
+    }
+
+    let rom = std::fs::read(&args[1]).unwrap();
+
+    let mut bank_addr = args[2].split(':');
+    let bank: u8 = u8::from_str_radix(bank_addr.next().unwrap(), 16).unwrap();
+    let addr: u16 = u16::from_str_radix(bank_addr.next().unwrap(), 16).unwrap();
+
+    let pitch: i16 = args.get(3).map_or(0, |pitch| pitch.parse().unwrap());
+
+    let Some(game) = Game::detect(&rom) else {
+        eprintln!("Unknown ROM");
+        return ExitCode::from(2);
+    };
+
+    let max_len = apu::MAX_FRAMES * pokemon_synthesizer::SAMPLES_PER_FRAME;
+
+    let (synthesized, writes): (Vec<f32>, _) = match game.generation() {
+        1 => {
+            let length: u8 = args.get(4).map_or(0x80, |length| length.parse().unwrap());
+            let pcm = gen1::synthesis(&rom, bank, addr, pitch as i8, length);
+            (pcm.iter().take(max_len).collect(), pcm.register_writes())
+        }
+        _ => {
+            let length: u16 = args.get(4).map_or(0x100, |length| length.parse().unwrap());
+            let pcm = gen2::synthesis(&rom, bank, addr, pitch, length);
+            (pcm.iter().take(max_len).collect(), pcm.register_writes())
+        }
+    };
+
+    let len = writes.last().map_or(0, |write| write.at);
+    let reference = apu::render(&writes, len);
+    let report = apu::compare(&synthesized, &reference);
+
+    println!("sound          {:02x}:{:04x} ({:?})", bank, addr, game);
+    println!("register writes {}", writes.len());
+    println!("correlation    {:.4}", report.correlation);
+    println!("rms error      {:.4}", report.rms_error);
+    println!("max error      {:.4}", report.max_error);
+    println!("length delta   {} samples", report.length_delta);
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,157 @@
+//! Render every fixture and report how far it is from its reference render.
+//!
+//! Usage: `cargo run --bin check-accuracy --features fixtures -- [--only <name>] [--update]`
+//!
+//! `--only` limits the check to fixtures whose name or id contains `<name>`, e.g. `pikachu` or
+//! `gen2/`. `--update` overwrites the reference renders with the current output of the first
+//! generation, which the second generation is then checked against. The renders of the second
+//! generation are never written, so `--only gen2/ --update` leaves the references as they are.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use pokemon_synthesizer::fixtures::{compare, Comparison, Fixture, FIXTURES};
+
+enum Outcome {
+    Compared(Comparison),
+    Missing(PathBuf),
+}
+
+fn main() -> ExitCode {
+    let mut only = None;
+    let mut update = false;
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--only" => match args.next() {
+                Some(name) => only = Some(name),
+                None => return usage(),
+            },
+            "--update" => update = true,
+            _ => return usage(),
+        }
+    }
+
+    let fixtures: Vec<&Fixture> = FIXTURES
+        .iter()
+        .filter(|fixture| match &only {
+            Some(only) => fixture.id().contains(only.as_str()),
+            None => true,
+        })
+        .collect();
+
+    if fixtures.is_empty() {
+        eprintln!("No fixtures match {:?}", only.unwrap_or_default());
+        return ExitCode::from(2);
+    }
+
+    let mut roms: HashMap<PathBuf, Option<Vec<u8>>> = HashMap::new();
+    let mut results = Vec::new();
+
+    for fixture in fixtures {
+        let rom_path = fixture.rom_path();
+        let rom = roms
+            .entry(rom_path.clone())
+            .or_insert_with(|| fs::read(&rom_path).ok());
+
+        let Some(rom) = rom else {
+            results.push((fixture, Outcome::Missing(rom_path)));
+            continue;
+        };
+
+        let actual = match fixture.render(rom) {
+            Ok(actual) => actual,
+            Err(err) => {
+                eprintln!("Failed to render {}: {}", fixture.id(), err);
+                return ExitCode::FAILURE;
+            }
+        };
+
+        // the second generation is checked against the references of the first
+        if update && fixture.generation == 1 {
+            if let Err(err) = fs::write(fixture.expected_path(), &actual) {
+                eprintln!(
+                    "Failed to update {}: {}",
+                    fixture.expected_path().display(),
+                    err
+                );
+                return ExitCode::FAILURE;
+            }
+        }
+
+        let outcome = match fs::read(fixture.expected_path()) {
+            Ok(expected) => Outcome::Compared(compare(&actual, &expected)),
+            Err(_) => Outcome::Missing(fixture.expected_path()),
+        };
+
+        results.push((fixture, outcome));
+    }
+
+    // worst first, missing files before everything else
+    results.sort_by(|(_, a), (_, b)| match (a, b) {
+        (Outcome::Missing(_), Outcome::Missing(_)) => std::cmp::Ordering::Equal,
+        (Outcome::Missing(_), _) => std::cmp::Ordering::Less,
+        (_, Outcome::Missing(_)) => std::cmp::Ordering::Greater,
+        (Outcome::Compared(a), Outcome::Compared(b)) => (b.max_deviation, b.length_delta.abs())
+            .cmp(&(a.max_deviation, a.length_delta.abs()))
+            .then(b.rms_error.total_cmp(&a.rms_error)),
+    });
+
+    println!(
+        "{:<22} {:>8} {:>8} {:>10} {:>13}  status",
+        "fixture", "max dev", "at", "rms error", "duration (ms)"
+    );
+
+    let mut failures = 0;
+
+    for (fixture, outcome) in &results {
+        match outcome {
+            Outcome::Missing(path) => {
+                failures += 1;
+                println!("{:<22} missing {}", fixture.id(), path.display());
+            }
+            Outcome::Compared(comparison) => {
+                let status = match (comparison.is_within_tolerance(), comparison.header_matches) {
+                    (true, _) => "ok",
+                    (false, false) => "FAIL (header)",
+                    (false, true) => "FAIL",
+                };
+
+                if !comparison.is_within_tolerance() {
+                    failures += 1;
+                }
+
+                println!(
+                    "{:<22} {:>8} {:>8} {:>10.4} {:>+13.2}  {}",
+                    fixture.id(),
+                    comparison.max_deviation,
+                    comparison.max_deviation_at,
+                    comparison.rms_error,
+                    comparison.length_delta as f64 / 48.0,
+                    status
+                );
+            }
+        }
+    }
+
+    println!();
+    println!(
+        "{} of {} fixtures within tolerance",
+        results.len() - failures,
+        results.len()
+    );
+
+    if failures > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("Usage: check-accuracy [--only <name>] [--update]");
+    ExitCode::from(2)
+}
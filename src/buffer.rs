@@ -0,0 +1,497 @@
+use std::fmt;
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::Sample;
+
+/// Error returned by the `PcmBuffer` functions that take a channel layout, sample rate, channel
+/// or bit depth.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BufferError {
+    /// A buffer needs at least one channel.
+    NoChannels,
+    /// The sample rate is 0.
+    NoSampleRate,
+    /// The number of samples isn't a multiple of the number of channels.
+    IncompleteFrame,
+    /// The buffer doesn't have the channel.
+    InvalidChannel(u16),
+    /// The bit depth isn't in the range 1 to 24.
+    InvalidBitDepth(u32),
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferError::NoChannels => write!(f, "PcmBuffer needs at least one channel"),
+            BufferError::NoSampleRate => write!(f, "PcmBuffer needs a non-zero sample rate"),
+            BufferError::IncompleteFrame => write!(f, "Incomplete sample frame"),
+            BufferError::InvalidChannel(channel) => write!(f, "Invalid channel: {}", channel),
+            BufferError::InvalidBitDepth(bits) => write!(f, "Invalid bit depth: {}", bits),
+        }
+    }
+}
+
+impl std::error::Error for BufferError {}
+
+/// Fully rendered PCM data, as opposed to the lazily synthesized `gen1::Pcm` and `gen2::Pcm`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PcmBuffer<S = f32> {
+    /// Samples, interleaved when there are multiple channels.
+    data: Vec<S>,
+    channels: u16,
+    sample_rate: u32,
+    /// Offset, in sample frames, of this buffer in the buffer it was sliced from.
+    offset: usize,
+}
+
+/// PCM data mixed with `f64` precision.
+pub type Pcm64 = PcmBuffer<f64>;
+
+impl<S: Sample> PcmBuffer<S> {
+    /// Create a mono buffer, returns an error if `sample_rate` is 0.
+    pub fn new(data: Vec<S>, sample_rate: u32) -> Result<PcmBuffer<S>, BufferError> {
+        PcmBuffer::from_interleaved(data, 1, sample_rate)
+    }
+
+    /// Create a buffer from interleaved samples for the given number of channels.
+    ///
+    /// Returns an error if `channels` or `sample_rate` is 0, or if the length of `data` isn't a
+    /// multiple of `channels`.
+    pub fn from_interleaved(
+        data: Vec<S>,
+        channels: u16,
+        sample_rate: u32,
+    ) -> Result<PcmBuffer<S>, BufferError> {
+        if channels == 0 {
+            return Err(BufferError::NoChannels);
+        }
+
+        if sample_rate == 0 {
+            return Err(BufferError::NoSampleRate);
+        }
+
+        if data.len() % (channels as usize) != 0 {
+            return Err(BufferError::IncompleteFrame);
+        }
+
+        Ok(PcmBuffer {
+            data,
+            channels,
+            sample_rate,
+            offset: 0,
+        })
+    }
+
+    /// Create a mono buffer of samples rendered by the crate, at the sample rate of a `Pcm`,
+    /// which is never 0.
+    pub(crate) fn rendered(data: Vec<S>, sample_rate: u32) -> PcmBuffer<S> {
+        PcmBuffer {
+            data,
+            channels: 1,
+            sample_rate: sample_rate.max(1),
+            offset: 0,
+        }
+    }
+
+    /// Create a buffer with the channels and sample rate of this one, for samples that are
+    /// interleaved the same way.
+    pub(crate) fn with_data(&self, data: Vec<S>) -> PcmBuffer<S> {
+        PcmBuffer {
+            data,
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            offset: 0,
+        }
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Number of sample frames, i.e. the number of samples in each channel.
+    pub fn frames(&self) -> usize {
+        self.data.len() / (self.channels as usize)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn total_duration(&self) -> Duration {
+        Duration::from_secs_f64((self.frames() as f64) / (self.sample_rate as f64))
+    }
+
+    pub fn data(&self) -> &[S] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [S] {
+        &mut self.data
+    }
+
+    /// Iterate over all the samples, interleaved when there are multiple channels.
+    pub fn iter(&self) -> impl Iterator<Item = S> + '_ {
+        self.data.iter().copied()
+    }
+
+    /// Iterate over the samples of a single channel, returns an error if the buffer doesn't have
+    /// the channel.
+    pub fn channel_iter(&self, channel: u16) -> Result<impl Iterator<Item = S> + '_, BufferError> {
+        if channel >= self.channels {
+            return Err(BufferError::InvalidChannel(channel));
+        }
+
+        Ok(self
+            .data
+            .iter()
+            .skip(channel as usize)
+            .step_by(self.channels as usize)
+            .copied())
+    }
+
+    /// Offset of this buffer in the original render, if it was created with `slice` or `split_at`.
+    pub fn source_offset(&self) -> Duration {
+        self.frame_to_duration(self.offset)
+    }
+
+    /// Copy the given time range into a new buffer. \
+    /// The range is clamped to the length of the buffer, and rounded to the nearest sample frame.
+    pub fn slice(&self, range: Range<Duration>) -> PcmBuffer<S> {
+        let start = self.duration_to_frame(range.start);
+        let end = self.duration_to_frame(range.end).max(start);
+        let channels = self.channels as usize;
+
+        PcmBuffer {
+            data: self.data[(start * channels)..(end * channels)].to_vec(),
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            offset: self.offset + start,
+        }
+    }
+
+    /// Split the buffer in two at the given time, clamped to the length of the buffer.
+    pub fn split_at(&self, at: Duration) -> (PcmBuffer<S>, PcmBuffer<S>) {
+        let end = self.frame_to_duration(self.frames());
+        (self.slice(Duration::ZERO..at), self.slice(at..end))
+    }
+
+    /// Convert the samples to `f32`.
+    pub fn to_f32(&self) -> PcmBuffer<f32> {
+        PcmBuffer {
+            data: self.iter().map(S::to_f32).collect(),
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            offset: self.offset,
+        }
+    }
+
+    fn duration_to_frame(&self, duration: Duration) -> usize {
+        let frames = self.frames();
+        let frame =
+            (duration.as_nanos() * (self.sample_rate as u128) + 500_000_000) / 1_000_000_000;
+        frame.min(frames as u128) as usize
+    }
+
+    /// The constructors reject a sample rate of 0, so this never divides by zero.
+    fn frame_to_duration(&self, frame: usize) -> Duration {
+        let nanos = (frame as u128) * 1_000_000_000 / (self.sample_rate as u128);
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+}
+
+impl PcmBuffer<f32> {
+    /// Iterate over the samples converted to 16-bit integers. \
+    /// Samples are clamped to [-1.0, 1.0], scaled by 32767 and rounded to the nearest integer.
+    pub fn iter_i16(&self) -> impl Iterator<Item = i16> + '_ {
+        self.data.iter().map(|&sample| to_i16(sample))
+    }
+
+    /// View the samples as raw bytes without copying them.
+    ///
+    /// Each sample is four bytes, in the native endianness of the platform (little endian on
+    /// every platform that is commonly used with WebAudio, JNI, etc.). The returned slice has an
+    /// alignment of 4, even though the type only guarantees an alignment of 1.
+    pub fn as_f32_bytes(&self) -> &[u8] {
+        // SAFETY: f32 has no padding or invalid bit patterns, u8 has an alignment of 1, and the
+        // returned slice covers exactly the memory of `self.data` with the same lifetime.
+        unsafe {
+            std::slice::from_raw_parts(
+                self.data.as_ptr() as *const u8,
+                std::mem::size_of_val(self.data.as_slice()),
+            )
+        }
+    }
+
+    /// Convert the samples into 16-bit little endian bytes, going through the same clamping and
+    /// rounding as `iter_i16`.
+    ///
+    /// The f32 samples are released as part of the conversion, so at most one extra buffer of
+    /// half the size is allocated.
+    pub fn into_i16_bytes(self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.data.len() * 2);
+
+        for sample in self.data {
+            result.extend(to_i16(sample).to_le_bytes());
+        }
+
+        result
+    }
+
+    /// Largest absolute value of any sample, 0.0 for an empty buffer.
+    pub fn peak(&self) -> f32 {
+        self.data
+            .iter()
+            .fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    /// Multiply every sample by `factor`. The samples aren't clamped, so a factor above 1.0 can
+    /// take them outside of [-1.0, 1.0].
+    pub fn apply_gain(&mut self, factor: f32) {
+        for sample in &mut self.data {
+            *sample *= factor;
+        }
+    }
+
+    /// Scale the samples so that the peak ends up at `target_peak`, e.g. to bring a single
+    /// channel sound effect, which the mix leaves at a third of full scale, up to 1.0. Silence is
+    /// left as is.
+    pub fn normalize(&mut self, target_peak: f32) {
+        let peak = self.peak();
+
+        if peak > 0.0 {
+            self.apply_gain(target_peak / peak);
+        }
+    }
+
+    /// Number of sample frames at the end that are silent, i.e. below `threshold`.
+    ///
+    /// The end is checked in windows of 1 ms, and a window only counts as silent if every sample
+    /// in it is below the threshold. A quiet fade-out that happens to pass through zero is kept,
+    /// as long as the threshold is below its level.
+    pub fn trailing_silence_len(&self, threshold: f32) -> usize {
+        let channels = self.channels as usize;
+        let window = (self.sample_rate as usize / 1000).max(1);
+        let mut end = self.frames();
+
+        while end > 0 {
+            let start = end.saturating_sub(window);
+            let samples = &self.data[(start * channels)..(end * channels)];
+
+            if samples.iter().any(|sample| sample.abs() >= threshold) {
+                break;
+            }
+
+            end = start;
+        }
+
+        self.frames() - end
+    }
+
+    /// Remove the silence at the end, see `trailing_silence_len`.
+    pub fn trim_trailing_silence(&mut self, threshold: f32) {
+        let len = self.frames() - self.trailing_silence_len(threshold);
+        self.data.truncate(len * self.channels as usize);
+    }
+
+    /// Reduce the samples to the given bit depth, for a crunchier, lo-fi sound. This is a
+    /// creative effect, use `SynthesisOptions::quantize_dac` for the levels of the hardware.
+    ///
+    /// Samples are clamped to [-1.0, 1.0] and rounded to the nearest of `2^bits` evenly spaced
+    /// levels, including -1.0 and 1.0. Returns an error if `bits` isn't in the range 1 to 24.
+    pub fn quantize_bits(&mut self, bits: u32) -> Result<(), BufferError> {
+        if !(1..=24).contains(&bits) {
+            return Err(BufferError::InvalidBitDepth(bits));
+        }
+
+        let step = 2.0 / (((1u32 << bits) - 1) as f32);
+
+        for sample in &mut self.data {
+            *sample = ((sample.clamp(-1.0, 1.0) + 1.0) / step).round() * step - 1.0;
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * 32767.0).round() as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: [f32; 6] = [0.0, 1.0, -1.0, 0.5, -2.0, 0.000_01];
+
+    #[test]
+    fn test_as_f32_bytes() {
+        let pcm = PcmBuffer::new(SAMPLES.to_vec(), 48000).unwrap();
+
+        let expected: Vec<u8> = SAMPLES.iter().flat_map(|s| s.to_ne_bytes()).collect();
+        assert_eq!(pcm.as_f32_bytes(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_into_i16_bytes() {
+        let pcm = PcmBuffer::new(SAMPLES.to_vec(), 48000).unwrap();
+
+        let expected: Vec<u8> = [0i16, 32767, -32767, 16384, -32767, 0]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+
+        assert_eq!(pcm.iter_i16().count(), SAMPLES.len());
+        assert_eq!(pcm.into_i16_bytes(), expected);
+    }
+
+    #[test]
+    fn test_quantize_bits() {
+        let data: Vec<f32> = (0..1000).map(|i| (i as f32 / 250.0) - 2.0).collect();
+        let mut pcm = PcmBuffer::new(data, 1000).unwrap();
+        pcm.quantize_bits(3).unwrap();
+
+        let mut levels: Vec<f32> = pcm.data().to_vec();
+        levels.dedup();
+        assert_eq!(levels.len(), 8);
+        assert_eq!(levels[0], -1.0);
+        assert!((levels[7] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut pcm = PcmBuffer::new(vec![0.0, 0.25, -0.125, 0.1], 1000).unwrap();
+        assert_eq!(pcm.peak(), 0.25);
+
+        pcm.apply_gain(2.0);
+        assert_eq!(pcm.data(), [0.0, 0.5, -0.25, 0.2]);
+
+        pcm.normalize(1.0);
+        assert_eq!(pcm.peak(), 1.0);
+        assert_eq!(pcm.data()[2], -0.5);
+
+        let mut silence = PcmBuffer::new(vec![0.0; 100], 1000).unwrap();
+        silence.normalize(1.0);
+        assert_eq!(silence.data(), [0.0; 100]);
+        assert_eq!(PcmBuffer::new(Vec::new(), 1000).unwrap().peak(), 0.0);
+    }
+
+    #[test]
+    fn test_trailing_silence() {
+        // a quiet square wave, with single zero samples, followed by 2.5 ms of silence
+        let mut data: Vec<f32> = (0..100).map(|i| [0.01, 0.0, -0.01, 0.0][i % 4]).collect();
+        data.extend([0.0; 25]);
+
+        let mut pcm = PcmBuffer::new(data, 10000).unwrap();
+        assert_eq!(pcm.trailing_silence_len(0.005), 20);
+        assert_eq!(pcm.trailing_silence_len(0.02), 125);
+
+        pcm.trim_trailing_silence(0.005);
+        assert_eq!(pcm.frames(), 105);
+
+        // the last volume step of the fade-out of Snorlax's cry
+        let wav = include_bytes!("../expected/snorlax-cry.wav");
+        let data = wav[44..]
+            .iter()
+            .map(|&b| (b as f32 - 128.0) / 127.0)
+            .collect();
+        let pcm = PcmBuffer::new(data, 48000).unwrap();
+        assert_eq!(pcm.trailing_silence_len(0.001), 0);
+        assert_eq!(pcm.trailing_silence_len(0.025), 768);
+    }
+
+    #[test]
+    fn test_slice() {
+        let pcm = PcmBuffer::new((0..1000).map(|i| i as f32).collect(), 1000).unwrap();
+
+        let slice = pcm.slice(Duration::from_millis(100)..Duration::from_millis(250));
+        assert_eq!(slice.data(), &pcm.data()[100..250]);
+        assert_eq!(slice.sample_rate(), 1000);
+        assert_eq!(slice.channels(), 1);
+        assert_eq!(slice.source_offset(), Duration::from_millis(100));
+
+        let slice = slice.slice(Duration::from_millis(50)..Duration::from_secs(10));
+        assert_eq!(slice.data(), &pcm.data()[150..250]);
+        assert_eq!(slice.source_offset(), Duration::from_millis(150));
+
+        let slice = pcm.slice(Duration::from_secs(5)..Duration::from_secs(10));
+        assert!(slice.data().is_empty());
+    }
+
+    #[test]
+    fn test_split_at() {
+        let pcm = PcmBuffer::new((0..1000).map(|i| i as f32).collect(), 1000).unwrap();
+
+        for at in [0, 1, 333, 999, 1000, 5000] {
+            let (head, tail) = pcm.split_at(Duration::from_millis(at));
+
+            assert_eq!(head.data().len(), (at as usize).min(1000));
+            assert_eq!(tail.source_offset(), head.total_duration());
+            assert_eq!([head.data(), tail.data()].concat(), pcm.data());
+        }
+    }
+
+    #[test]
+    fn test_durations() {
+        let pcm = PcmBuffer::new(vec![0.0; 3], 1).unwrap();
+        assert_eq!(pcm.total_duration(), Duration::from_secs(3));
+
+        let (head, tail) = pcm.split_at(Duration::MAX);
+        assert_eq!(head.total_duration(), Duration::from_secs(3));
+        assert_eq!(tail.source_offset(), Duration::from_secs(3));
+
+        let pcm = PcmBuffer::new(vec![0.0; 3], u32::MAX).unwrap();
+        assert_eq!(pcm.slice(Duration::ZERO..Duration::MAX).frames(), 3);
+
+        // both divide by the sample rate, which can't be 0
+        assert_eq!(
+            PcmBuffer::new(vec![0.0; 3], 0),
+            Err(BufferError::NoSampleRate)
+        );
+    }
+
+    #[test]
+    fn test_stereo() {
+        let data: Vec<f32> = (0..2000).map(|i| (i % 2) as f32 - (i / 2) as f32).collect();
+        let pcm = PcmBuffer::from_interleaved(data, 2, 1000).unwrap();
+
+        assert_eq!(pcm.channels(), 2);
+        assert_eq!(pcm.frames(), 1000);
+        assert_eq!(pcm.total_duration(), Duration::from_secs(1));
+
+        let left: Vec<f32> = pcm.channel_iter(0).unwrap().collect();
+        let right: Vec<f32> = pcm.channel_iter(1).unwrap().collect();
+        assert_eq!(left, (0..1000).map(|i| -i as f32).collect::<Vec<_>>());
+        assert_eq!(right, (0..1000).map(|i| 1.0 - i as f32).collect::<Vec<_>>());
+
+        let (head, tail) = pcm.split_at(Duration::from_millis(250));
+        assert_eq!((head.frames(), tail.frames()), (250, 750));
+        assert_eq!(tail.channels(), 2);
+        assert_eq!(tail.channel_iter(0).unwrap().next(), Some(-250.0));
+        assert_eq!(tail.channel_iter(1).unwrap().next(), Some(-249.0));
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        let err = PcmBuffer::from_interleaved(vec![0.0; 4], 0, 1000);
+        assert_eq!(err, Err(BufferError::NoChannels));
+
+        let err = PcmBuffer::from_interleaved(vec![0.0; 5], 2, 1000);
+        assert_eq!(err, Err(BufferError::IncompleteFrame));
+
+        let err = PcmBuffer::new(vec![0.0; 4], 0);
+        assert_eq!(err, Err(BufferError::NoSampleRate));
+
+        let mut pcm = PcmBuffer::from_interleaved(vec![0.5; 4], 2, 1000).unwrap();
+        assert!(matches!(
+            pcm.channel_iter(u16::MAX),
+            Err(BufferError::InvalidChannel(u16::MAX))
+        ));
+        assert_eq!(pcm.quantize_bits(0), Err(BufferError::InvalidBitDepth(0)));
+        assert_eq!(
+            pcm.quantize_bits(u32::MAX),
+            Err(BufferError::InvalidBitDepth(u32::MAX))
+        );
+        assert_eq!(pcm.data(), [0.5; 4]);
+    }
+}
@@ -0,0 +1,253 @@
+//! Command-level differences between two sounds, for reviewing what a ROM hack changed.
+
+use std::fmt;
+use std::mem::discriminant;
+
+use crate::generation::{ChannelStart, Flow, Generation, MAX_COMMANDS};
+use crate::{RomLocation, Voice};
+
+/// Differences between the command streams of two sounds, channel by channel.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SoundDiff {
+    pub channels: Vec<ChannelDiff>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelDiff {
+    pub voice: Voice,
+    pub entries: Vec<DiffEntry>,
+}
+
+/// A difference in a command stream, commands are formatted with their `Debug` representation.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DiffEntry {
+    /// A command only present in the second sound, at `addr` in the second sound.
+    Inserted { addr: u16, command: String },
+    /// A command only present in the first sound, at `addr` in the first sound.
+    Removed { addr: u16, command: String },
+    /// A command of the same kind but with different parameters.
+    Changed {
+        addr_a: u16,
+        addr_b: u16,
+        before: String,
+        after: String,
+    },
+}
+
+impl SoundDiff {
+    pub fn is_empty(&self) -> bool {
+        self.channels
+            .iter()
+            .all(|channel| channel.entries.is_empty())
+    }
+}
+
+impl fmt::Display for SoundDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for channel in self.channels.iter().filter(|c| !c.entries.is_empty()) {
+            writeln!(f, "{}:", channel.voice.short_name())?;
+
+            for entry in &channel.entries {
+                match entry {
+                    DiffEntry::Inserted { addr, command } => {
+                        writeln!(f, "  + {addr:04x}: {command}")?
+                    }
+                    DiffEntry::Removed { addr, command } => {
+                        writeln!(f, "  - {addr:04x}: {command}")?
+                    }
+                    DiffEntry::Changed {
+                        addr_a,
+                        addr_b,
+                        before,
+                        after,
+                    } => writeln!(f, "  ~ {addr_a:04x} -> {addr_b:04x}: {before} -> {after}")?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Diff the command streams of two sounds, channel by channel.
+pub(crate) fn diff_sounds<G: Generation>(
+    rom_a: &[u8],
+    a: RomLocation,
+    rom_b: &[u8],
+    b: RomLocation,
+) -> SoundDiff {
+    let commands = |rom, bank, channel: Option<_>| {
+        channel.map_or_else(Vec::new, |channel| commands::<G>(rom, bank, channel))
+    };
+
+    SoundDiff {
+        channels: G::channels(rom_a, a)
+            .into_iter()
+            .zip(G::channels(rom_b, b))
+            .filter(|((_, a), (_, b))| a.is_some() || b.is_some())
+            .map(|((voice, channel_a), (_, channel_b))| ChannelDiff {
+                voice,
+                entries: diff_commands(
+                    &commands(rom_a, a.bank, channel_a),
+                    &commands(rom_b, b.bank, channel_b),
+                    |a, b| discriminant(a) == discriminant(b),
+                ),
+            })
+            .collect(),
+    }
+}
+
+/// The commands of a channel, from its start until the first return, jump or infinite loop,
+/// without following calls.
+fn commands<G: Generation>(
+    rom: &[u8],
+    bank: u8,
+    (mut addr, mut channel): ChannelStart<G::ChannelType>,
+) -> Vec<(u16, G::Command)> {
+    let mut result = Vec::new();
+
+    for _ in 0..MAX_COMMANDS {
+        let Some(cmd) = G::parse(rom, bank, addr, channel) else {
+            break;
+        };
+        result.push((addr, cmd));
+
+        match G::flow(&cmd) {
+            Flow::Return | Flow::Jump(_) | Flow::Loop { count: 0, .. } => break,
+            Flow::Switch => channel = G::switch_channel(channel),
+            Flow::Next | Flow::Call(_) | Flow::Loop { .. } => {}
+        }
+
+        match addr.checked_add(G::len(&cmd) as u16) {
+            Some(next) if RomLocation::new(bank, next).is_mapped() => addr = next,
+            _ => break,
+        }
+    }
+
+    result
+}
+
+/// Diff two command lists with a longest common subsequence, pairing up removed and inserted
+/// commands of the same kind into `Changed` entries.
+fn diff_commands<C: PartialEq + fmt::Debug>(
+    a: &[(u16, C)],
+    b: &[(u16, C)],
+    same_kind: impl Fn(&C, &C) -> bool,
+) -> Vec<DiffEntry> {
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i].1 == b[j].1 {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = Vec::new();
+    let mut removed = Vec::new();
+    let mut inserted = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i].1 == b[j].1 {
+            flush(&mut result, &mut removed, &mut inserted, &same_kind);
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            removed.push(&a[i]);
+            i += 1;
+        } else {
+            inserted.push(&b[j]);
+            j += 1;
+        }
+    }
+
+    flush(&mut result, &mut removed, &mut inserted, &same_kind);
+
+    result
+}
+
+fn flush<C: fmt::Debug>(
+    result: &mut Vec<DiffEntry>,
+    removed: &mut Vec<&(u16, C)>,
+    inserted: &mut Vec<&(u16, C)>,
+    same_kind: impl Fn(&C, &C) -> bool,
+) {
+    let mut inserted = inserted.drain(..).peekable();
+
+    for (addr_a, before) in removed.drain(..) {
+        match inserted.next_if(|(_, after)| same_kind(before, after)) {
+            Some((addr_b, after)) => result.push(DiffEntry::Changed {
+                addr_a: *addr_a,
+                addr_b: *addr_b,
+                before: format!("{before:?}"),
+                after: format!("{after:?}"),
+            }),
+            None => result.push(DiffEntry::Removed {
+                addr: *addr_a,
+                command: format!("{before:?}"),
+            }),
+        }
+    }
+
+    for (addr, command) in inserted {
+        result.push(DiffEntry::Inserted {
+            addr: *addr,
+            command: format!("{command:?}"),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(commands: &[char]) -> Vec<(u16, char)> {
+        commands
+            .iter()
+            .enumerate()
+            .map(|(index, command)| (index as u16, *command))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_commands() {
+        let a = listing(&['a', 'b', 'c', 'd']);
+        let b = listing(&['a', 'C', 'd', 'e']);
+
+        let entries = diff_commands(&a, &b, |a, b| a.eq_ignore_ascii_case(b));
+
+        assert_eq!(
+            entries,
+            [
+                DiffEntry::Removed {
+                    addr: 1,
+                    command: String::from("'b'")
+                },
+                DiffEntry::Changed {
+                    addr_a: 2,
+                    addr_b: 1,
+                    before: String::from("'c'"),
+                    after: String::from("'C'")
+                },
+                DiffEntry::Inserted {
+                    addr: 3,
+                    command: String::from("'e'")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_commands_equal() {
+        let a = listing(&['a', 'b']);
+        assert!(diff_commands(&a, &a, |_, _| true).is_empty());
+    }
+}
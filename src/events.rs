@@ -0,0 +1,51 @@
+/// One of the four channels of the Game Boy APU.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Voice {
+    Pulse1,
+    Pulse2,
+    Wave,
+    Noise,
+}
+
+impl Voice {
+    /// Short name of the voice, e.g. `P1` for the first pulse channel.
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            Voice::Pulse1 => "P1",
+            Voice::Pulse2 => "P2",
+            Voice::Wave => "W",
+            Voice::Noise => "N",
+        }
+    }
+}
+
+/// The command that triggered a `NoteEvent`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EventKind {
+    /// A music note, with the frequency given by its pitch and octave.
+    Note,
+    /// A square note of a sound effect, with an explicit frequency.
+    SquareNote,
+    /// A noise note of a sound effect.
+    NoiseNote,
+    /// A noise note of a drum played by the music noise channel.
+    Drum,
+}
+
+/// A note played by one of the channels, with timestamps in samples at `SOURCE_SAMPLE_RATE`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct NoteEvent {
+    pub voice: Voice,
+    /// First sample of the note.
+    pub start: usize,
+    /// Number of samples until the next note, or until the channel ends.
+    pub len: usize,
+    /// The frequency register value, or the noise parameters for the noise channel, with the
+    /// pitch of the sound applied.
+    pub freq: u16,
+    pub volume: u8,
+    pub fade: i8,
+    pub kind: EventKind,
+}
@@ -0,0 +1,271 @@
+//! Standalone copies of sounds, for sharing them without distributing the ROM.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+use crate::generation::{Flow, Generation, MAX_COMMANDS};
+use crate::header;
+use crate::{HeaderError, RomLocation};
+
+/// Version of the `SoundBlob` format.
+pub const SOUND_BLOB_VERSION: u8 = 1;
+
+/// Error returned by `sound_blob` and `synthesize_blob`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BlobError {
+    /// The sound header isn't valid, see `Sound::try_new`.
+    Header(HeaderError),
+    /// The channel data runs past the end of the ROM, or out of the bank of the sound.
+    OutOfBounds,
+    /// The sound and its channel data are larger than a bank.
+    TooLarge,
+    /// The blob is from another generation than the one playing it.
+    OtherGeneration { generation: u8 },
+    /// The blob is from a version of the format that isn't supported, see `SOUND_BLOB_VERSION`.
+    UnsupportedVersion { version: u8 },
+}
+
+impl fmt::Display for BlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobError::Header(err) => err.fmt(f),
+            BlobError::OutOfBounds => write!(f, "Channel data runs out of its bank"),
+            BlobError::TooLarge => write!(f, "Sound is too large to extract"),
+            BlobError::OtherGeneration { generation } => {
+                write!(f, "Sound blob is from generation {}", generation)
+            }
+            BlobError::UnsupportedVersion { version } => {
+                write!(f, "Unsupported sound blob version: {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BlobError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BlobError::Header(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<HeaderError> for BlobError {
+    fn from(err: HeaderError) -> Self {
+        BlobError::Header(err)
+    }
+}
+
+/// A sound header together with all the channel data it can reach, with every pointer
+/// rewritten to point into the blob instead of into the ROM.
+///
+/// Created with `gen1::sound_blob` or `gen2::sound_blob`, and played back with the
+/// `synthesize_blob` function of the same generation.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SoundBlob {
+    version: u8,
+    generation: u8,
+    /// Offset of the sound header in `data`.
+    header: u16,
+    data: Vec<u8>,
+}
+
+impl SoundBlob {
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// The generation of the game that the sound was extracted from.
+    pub fn generation(&self) -> u8 {
+        self.generation
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(crate) fn header(&self) -> u16 {
+        self.header
+    }
+
+    /// Check that the blob can be played by the given generation.
+    pub(crate) fn check(&self, generation: u8) -> Result<(), BlobError> {
+        if self.generation != generation {
+            return Err(BlobError::OtherGeneration {
+                generation: self.generation,
+            });
+        }
+
+        if self.version != SOUND_BLOB_VERSION {
+            return Err(BlobError::UnsupportedVersion {
+                version: self.version,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Copy the given address ranges of a bank into a blob, and rewrite the little endian
+    /// pointers at the given addresses. Every pointer must point into one of the ranges, and the
+    /// header must be part of one of them.
+    pub(crate) fn build(
+        generation: u8,
+        rom: &[u8],
+        bank: u8,
+        header: u16,
+        mut ranges: Vec<Range<u16>>,
+        pointers: &[u16],
+    ) -> Result<SoundBlob, BlobError> {
+        ranges.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<Range<u16>> = Vec::new();
+
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+
+        let position = |addr: u16| ((bank as usize) * 0x4000) + ((addr as usize) & 0x3fff);
+        let mut data = Vec::new();
+        let mut layout = Vec::new();
+
+        for range in merged {
+            let start = position(range.start);
+            let bytes = rom
+                .get(start..(start + range.len()))
+                .ok_or(BlobError::OutOfBounds)?;
+
+            layout.push((range, data.len() as u16));
+            data.extend_from_slice(bytes);
+        }
+
+        if data.len() > 0x4000 {
+            return Err(BlobError::TooLarge);
+        }
+
+        // every pointer target has been walked, and is therefore part of one of the ranges
+        let offset = |addr: u16| {
+            layout
+                .iter()
+                .find(|(range, _)| range.contains(&addr))
+                .map(|(range, offset)| offset + (addr - range.start))
+        };
+
+        for &pointer in pointers {
+            let pos = position(pointer);
+            let Some(&[low, high]) = rom.get(pos..(pos + 2)) else {
+                return Err(BlobError::OutOfBounds);
+            };
+            let target = u16::from_le_bytes([low, high]);
+
+            if let (Some(at), Some(target)) = (offset(pointer), offset(target)) {
+                let at = at as usize;
+
+                if let Some(slot) = data.get_mut(at..(at + 2)) {
+                    slot.copy_from_slice(&target.to_le_bytes());
+                }
+            }
+        }
+
+        Ok(SoundBlob {
+            version: SOUND_BLOB_VERSION,
+            generation,
+            header: offset(header).ok_or(BlobError::OutOfBounds)?,
+            data,
+        })
+    }
+}
+
+/// Copy the sound at the given location, and all channel data that it can reach through loops,
+/// calls and jumps, into a standalone blob.
+pub(crate) fn sound_blob<G: Generation>(
+    rom: &[u8],
+    location: RomLocation,
+) -> Result<SoundBlob, BlobError> {
+    if !location.is_mapped() {
+        return Err(BlobError::OutOfBounds);
+    }
+
+    let channel_count = header::try_read(rom, location)?.len() as u16;
+
+    let mut ranges = Vec::new();
+    ranges.push(location.addr..(location.addr + channel_count * 3));
+
+    let mut pointers: Vec<u16> = (0..channel_count)
+        .map(|index| location.addr + index * 3 + 1)
+        .collect();
+
+    let mut visited = HashSet::new();
+    let mut queue: Vec<_> = G::channels(rom, location)
+        .into_iter()
+        .filter_map(|(_, channel)| channel)
+        .collect();
+
+    while let Some((mut addr, mut channel)) = queue.pop() {
+        for _ in 0..MAX_COMMANDS {
+            if !RomLocation::new(location.bank, addr).is_mapped() {
+                return Err(BlobError::OutOfBounds);
+            }
+
+            if !visited.insert((addr, channel)) {
+                break;
+            }
+
+            let Some(cmd) = G::parse(rom, location.bank, addr, channel) else {
+                break;
+            };
+
+            let Some(next) = addr
+                .checked_add(G::len(&cmd) as u16)
+                .filter(|&next| RomLocation::new(location.bank, next - 1).is_mapped())
+            else {
+                return Err(BlobError::OutOfBounds);
+            };
+            ranges.push(addr..next);
+
+            match G::flow(&cmd) {
+                Flow::Next => {}
+                Flow::Switch => channel = G::switch_channel(channel),
+                Flow::Loop {
+                    count,
+                    addr: target,
+                } => {
+                    pointers.push(addr + 2);
+                    queue.push((target, channel));
+
+                    if count == 0 {
+                        break;
+                    }
+                }
+                Flow::Call(target) => {
+                    pointers.push(addr + 1);
+                    queue.push((target, channel));
+                }
+                Flow::Jump(target) => {
+                    pointers.push(addr + 1);
+                    queue.push((target, channel));
+                    break;
+                }
+                Flow::Return => break,
+            }
+
+            addr = next;
+        }
+    }
+
+    pointers.sort_unstable();
+    pointers.dedup();
+
+    SoundBlob::build(
+        G::NUMBER,
+        rom,
+        location.bank,
+        location.addr,
+        ranges,
+        &pointers,
+    )
+}
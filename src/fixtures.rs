@@ -0,0 +1,281 @@
+//! Reference renders of the cries in `expected/`, shared by the tests and the `check-accuracy`
+//! binary. This is not part of the public API, and is only built for the tests and with the
+//! `fixtures` feature.
+
+use std::io;
+use std::path::PathBuf;
+
+use crate::{gen1, gen2, save, PcmBuffer, SaveError, WavOptions};
+
+pub const WAVE_HEADER_LEN: usize = 44;
+
+/// Maximum difference, in 8-bit sample values, between a render and its reference.
+pub const TOLERANCE: i32 = 1;
+
+/// A cry with a reference render in `expected/`.
+#[derive(Debug, Clone, Copy)]
+pub struct Fixture {
+    /// Name of the reference render, without the extension.
+    pub name: &'static str,
+    pub generation: u8,
+    pub bank: u8,
+    pub addr: u16,
+    pub pitch: i16,
+    pub length: u16,
+}
+
+/// Every fixture, both generations are checked against the same reference renders. The renders
+/// of the first generation come first, and are the ones written when updating the references.
+pub const FIXTURES: [Fixture; 14] = [
+    fixture("bulbasaur-cry", 1, 0x02, 0x40c3, -128, 1),
+    fixture("diglett-cry", 1, 0x02, 0x409f, -86, 1),
+    fixture("jigglypuff-cry", 1, 0x02, 0x40ba, -1, 53),
+    fixture("snorlax-cry", 1, 0x02, 0x4069, 85, 1),
+    fixture("aerodactyl-cry", 1, 0x02, 0x4177, 32, 240),
+    fixture("pikachu-cry", 1, 0x02, 0x40c3, -18, 1),
+    fixture("slowpoke-cry", 1, 0x02, 0x404e, 0, 128),
+    fixture("bulbasaur-cry", 2, 0x3c, 0x7504, 128, 129),
+    fixture("diglett-cry", 2, 0x3c, 0x74e0, 170, 129),
+    fixture("jigglypuff-cry", 2, 0x3c, 0x74fb, 255, 181),
+    fixture("snorlax-cry", 2, 0x3c, 0x74aa, 85, 129),
+    fixture("aerodactyl-cry", 2, 0x3c, 0x75b8, 32, 368),
+    fixture("pikachu-cry", 2, 0x3c, 0x7504, 238, 129),
+    fixture("slowpoke-cry", 2, 0x3c, 0x748f, 0, 256),
+];
+
+const fn fixture(
+    name: &'static str,
+    generation: u8,
+    bank: u8,
+    addr: u16,
+    pitch: i16,
+    length: u16,
+) -> Fixture {
+    Fixture {
+        name,
+        generation,
+        bank,
+        addr,
+        pitch,
+        length,
+    }
+}
+
+impl Fixture {
+    /// Name including the generation, e.g. `gen1/pikachu-cry`.
+    pub fn id(&self) -> String {
+        format!("gen{}/{}", self.generation, self.name)
+    }
+
+    /// Path of the ROM, relative to the root of the crate.
+    pub fn rom_path(&self) -> PathBuf {
+        match self.generation {
+            1 => root().join("roms/pokeyellow.gbc"),
+            _ => root().join("roms/pokecrystal.gbc"),
+        }
+    }
+
+    pub fn expected_path(&self) -> PathBuf {
+        root().join("expected").join(format!("{}.wav", self.name))
+    }
+
+    /// Render the cry in the same format as the reference renders.
+    pub fn render(&self, rom: &[u8]) -> io::Result<Vec<u8>> {
+        let buffer = match self.generation {
+            1 => gen1::synthesis(
+                rom,
+                self.bank,
+                self.addr,
+                self.pitch as i8,
+                self.length as u8,
+            )
+            .to_buffer(),
+            _ => gen2::synthesis(rom, self.bank, self.addr, self.pitch, self.length).to_buffer(),
+        };
+
+        match buffer {
+            Some(buffer) => to_wav(&buffer),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                SaveError::Infinite,
+            )),
+        }
+    }
+}
+
+fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Sample rate of the reference renders.
+pub const SAMPLE_RATE: u32 = 48000;
+
+/// The format of the reference renders, 8-bit samples at 48 kHz.
+pub fn wav_options() -> WavOptions {
+    WavOptions::new().sample_rate(SAMPLE_RATE).bits(8)
+}
+
+/// Encode a mono render in the format of the reference renders. The references were resampled
+/// by the old fixture encoder, which weighted each of the two nearest samples by its distance
+/// instead of its closeness, so that is done here as well rather than with `WavOptions`.
+pub fn to_wav(buffer: &PcmBuffer) -> io::Result<Vec<u8>> {
+    let data = buffer.data();
+    let ratio = buffer.sample_rate() as f64 / SAMPLE_RATE as f64;
+    let len = (data.len() as f64 / ratio).ceil() as usize;
+
+    let samples: Vec<f32> = (0..len)
+        .filter_map(|index| {
+            let position = index as f64 * ratio;
+            let prev_index = position.floor() as usize;
+            let prev = *data.get(prev_index)?;
+            let fraction = position - prev_index as f64;
+
+            Some(match data.get(prev_index + 1) {
+                Some(&next) if fraction > 0.0 => {
+                    (fraction * prev as f64 + (1.0 - fraction) * next as f64) as f32
+                }
+                _ => prev,
+            })
+        })
+        .collect();
+
+    let mut wav = Vec::new();
+    let len = samples.len();
+    save::write_wav(
+        &mut wav,
+        samples.into_iter(),
+        len,
+        1,
+        SAMPLE_RATE,
+        wav_options(),
+    )?;
+    Ok(wav)
+}
+
+/// How far a render is from its reference, see `compare`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Comparison {
+    /// Largest difference between two samples, in 8-bit sample values.
+    pub max_deviation: i32,
+    /// Index, in samples, of the largest difference.
+    pub max_deviation_at: usize,
+    /// Root mean square of the difference, in 8-bit sample values.
+    pub rms_error: f64,
+    /// Difference in length, in samples, positive when the render is longer.
+    pub length_delta: i64,
+    pub header_matches: bool,
+}
+
+impl Comparison {
+    pub fn is_within_tolerance(&self) -> bool {
+        self.header_matches && self.length_delta == 0 && self.max_deviation <= TOLERANCE
+    }
+}
+
+/// Bring a render into the layout of its reference, if the reference was written by the old
+/// fixture encoder. That encoder wrote the length of the data into the RIFF size field, and
+/// dropped the first sample while still counting it in the data chunk.
+///
+/// The references in `expected/` still have that layout, so the first sample of a render isn't
+/// compared until they are regenerated with `check-accuracy --update`.
+pub fn to_reference_layout(actual: &[u8], expected: &[u8]) -> Vec<u8> {
+    let is_legacy = expected.len() >= WAVE_HEADER_LEN && expected[4..8] == expected[40..44];
+
+    if !is_legacy || actual.len() <= WAVE_HEADER_LEN {
+        return actual.to_vec();
+    }
+
+    let mut result = actual[..WAVE_HEADER_LEN].to_vec();
+    result.copy_within(40..44, 4);
+    result.extend_from_slice(&actual[(WAVE_HEADER_LEN + 1)..]);
+    result
+}
+
+/// Compare two WAV files in the format of `wav_options`, sample by sample, see
+/// `to_reference_layout`.
+pub fn compare(actual: &[u8], expected: &[u8]) -> Comparison {
+    let actual = &to_reference_layout(actual, expected)[..];
+    let header_len = WAVE_HEADER_LEN.min(actual.len()).min(expected.len());
+    let actual_samples = &actual[header_len..];
+    let expected_samples = &expected[header_len..];
+
+    let mut max_deviation = 0;
+    let mut max_deviation_at = 0;
+    let mut squares = 0.0;
+
+    for (index, (actual, expected)) in actual_samples.iter().zip(expected_samples).enumerate() {
+        let deviation = (*actual as i32 - *expected as i32).abs();
+        squares += (deviation * deviation) as f64;
+
+        if deviation > max_deviation {
+            max_deviation = deviation;
+            max_deviation_at = index;
+        }
+    }
+
+    let count = actual_samples.len().min(expected_samples.len()).max(1);
+
+    Comparison {
+        max_deviation,
+        max_deviation_at,
+        rms_error: (squares / count as f64).sqrt(),
+        length_delta: actual_samples.len() as i64 - expected_samples.len() as i64,
+        header_matches: actual[..header_len] == expected[..header_len]
+            && header_len == WAVE_HEADER_LEN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert_to_wav(data: &[f32]) -> Vec<u8> {
+        let mut wav = Vec::new();
+        save::write_wav(
+            &mut wav,
+            data.iter().copied(),
+            data.len(),
+            1,
+            48000,
+            wav_options(),
+        )
+        .unwrap();
+        wav
+    }
+
+    #[test]
+    fn test_compare() {
+        let data = [0.0, 0.5, -0.5, 0.25];
+        let wav = convert_to_wav(&data);
+
+        let same = compare(&wav, &wav);
+        assert_eq!(same.max_deviation, 0);
+        assert_eq!(same.rms_error, 0.0);
+        assert!(same.is_within_tolerance());
+
+        let mut off_by_two = wav.clone();
+        off_by_two[WAVE_HEADER_LEN + 2] += 2;
+        let comparison = compare(&off_by_two, &wav);
+        assert_eq!(comparison.max_deviation, 2);
+        assert_eq!(comparison.max_deviation_at, 2);
+        assert!(!comparison.is_within_tolerance());
+
+        let shorter = convert_to_wav(&data[..2]);
+        assert_eq!(compare(&shorter, &wav).length_delta, -2);
+    }
+
+    #[test]
+    fn test_reference_layout() {
+        let data = [0.0, 0.5, -0.5, 0.25];
+        let wav = convert_to_wav(&data);
+        assert_eq!(to_reference_layout(&wav, &wav), wav);
+
+        // the old encoder: the data length as the RIFF size, and without the first sample
+        let mut legacy = wav.clone();
+        legacy[4..8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        legacy.remove(WAVE_HEADER_LEN);
+
+        assert_eq!(to_reference_layout(&wav, &legacy), legacy);
+        assert!(compare(&wav, &legacy).is_within_tolerance());
+    }
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+
+/// A retail release of one of the supported games.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Game {
+    Red,
+    Blue,
+    Yellow,
+    Gold,
+    Silver,
+    Crystal,
+}
+
+impl Game {
+    /// Detect the game from the title in the cartridge header, returns `None` for ROMs that
+    /// aren't one of the supported games.
+    pub fn detect(rom: &[u8]) -> Option<Game> {
+        let title = rom.get(0x134..0x144)?;
+        let title = title.split(|&byte| byte == 0).next()?;
+
+        [
+            (&b"POKEMON RED"[..], Game::Red),
+            (b"POKEMON BLUE", Game::Blue),
+            (b"POKEMON YELLOW", Game::Yellow),
+            (b"POKEMON_GLD", Game::Gold),
+            (b"POKEMON_SLV", Game::Silver),
+            (b"PM_CRYSTAL", Game::Crystal),
+        ]
+        .into_iter()
+        .find(|(prefix, _)| title.starts_with(prefix))
+        .map(|(_, game)| game)
+    }
+
+    /// The generation of the game, which decides whether the `gen1` or `gen2` module should be
+    /// used to synthesize its sounds.
+    pub fn generation(&self) -> u8 {
+        match self {
+            Game::Red | Game::Blue | Game::Yellow => 1,
+            Game::Gold | Game::Silver | Game::Crystal => 2,
+        }
+    }
+
+    /// The banks that start with a sound header table, see `gen1::SoundTable`. The three audio
+    /// banks of the first generation have the same sound effects, with the music split between
+    /// them. The second generation finds its sounds through pointer tables instead, so it has
+    /// none.
+    pub fn sound_banks(&self) -> &'static [u8] {
+        match self {
+            Game::Red | Game::Blue | Game::Yellow => &[0x02, 0x08, 0x1f],
+            Game::Gold | Game::Silver | Game::Crystal => &[],
+        }
+    }
+}
+
+/// Error returned by `detect_rom`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DetectError {
+    /// The ROM is too short to have a cartridge header.
+    Truncated,
+    /// The header checksum doesn't match the header, so it probably isn't a Game Boy ROM.
+    HeaderChecksum { expected: u8, actual: u8 },
+}
+
+impl fmt::Display for DetectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DetectError::Truncated => write!(f, "The ROM is too short to have a header"),
+            DetectError::HeaderChecksum { expected, actual } => write!(
+                f,
+                "Header checksum mismatch: expected {:02x}, found {:02x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DetectError {}
+
+/// The cartridge header of a ROM, see `detect_rom`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RomInfo {
+    pub title: String,
+    /// The supported game that the ROM is, if any.
+    pub game: Option<Game>,
+    /// Whether the ROM supports the Game Boy Color.
+    pub cgb: bool,
+    /// Size of the ROM in bytes, as given by the header.
+    pub rom_size: usize,
+    /// Sum of every byte of the ROM except for the checksum itself, as given by the header. The
+    /// console doesn't check it, so it can be wrong in ROM hacks.
+    pub global_checksum: u16,
+}
+
+impl RomInfo {
+    /// The banks that start with a sound header table, see `Game::sound_banks`.
+    pub fn sound_banks(&self) -> &'static [u8] {
+        self.game.map_or(&[], |game| game.sound_banks())
+    }
+}
+
+/// Read the cartridge header of a ROM, and detect which of the supported games it is.
+///
+/// Returns an error if the ROM doesn't have a valid header. ROMs of other games are detected
+/// with `game` set to `None`.
+pub fn detect_rom(rom: &[u8]) -> Result<RomInfo, DetectError> {
+    let header = rom.get(0x134..0x150).ok_or(DetectError::Truncated)?;
+
+    let expected = header[0x19];
+    let actual = header[..0x19]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1));
+
+    if expected != actual {
+        return Err(DetectError::HeaderChecksum { expected, actual });
+    }
+
+    // the last byte of the title is the CGB flag in games that support it
+    let cgb = header[0x0f] & 0x80 != 0;
+    let title = if cgb {
+        &header[..0x0f]
+    } else {
+        &header[..0x10]
+    };
+    let title = title.split(|&byte| byte == 0).next().unwrap_or_default();
+
+    Ok(RomInfo {
+        title: String::from_utf8_lossy(title).into_owned(),
+        game: Game::detect(rom),
+        cgb,
+        rom_size: 0x8000 << header[0x14].min(8),
+        global_checksum: u16::from_be_bytes([header[0x1a], header[0x1b]]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ROM with a valid header with the given title and CGB flag.
+    fn rom(title: &[u8], cgb: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..(0x134 + title.len())].copy_from_slice(title);
+        rom[0x143] = cgb;
+        rom[0x148] = 0x05;
+        rom[0x14e..0x150].copy_from_slice(&[0x12, 0x34]);
+        rom[0x14d] = rom[0x134..0x14d]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1));
+        rom
+    }
+
+    #[test]
+    fn test_detect_rom() {
+        assert_eq!(
+            detect_rom(&rom(b"POKEMON YELLOW", 0x80)),
+            Ok(RomInfo {
+                title: String::from("POKEMON YELLOW"),
+                game: Some(Game::Yellow),
+                cgb: true,
+                rom_size: 0x100000,
+                global_checksum: 0x1234,
+            })
+        );
+
+        let info = detect_rom(&rom(b"POKEMON RED", 0x00)).unwrap();
+        assert_eq!(info.game, Some(Game::Red));
+        assert!(!info.cgb);
+        assert_eq!(info.sound_banks(), [0x02, 0x08, 0x1f]);
+
+        let info = detect_rom(&rom(b"TETRIS", 0x00)).unwrap();
+        assert_eq!(info.title, "TETRIS");
+        assert_eq!(info.game, None);
+        assert!(info.sound_banks().is_empty());
+    }
+
+    #[test]
+    fn test_detect_rom_errors() {
+        let rom = rom(b"POKEMON RED", 0x00);
+        assert_eq!(detect_rom(&rom[..0x14f]), Err(DetectError::Truncated));
+
+        assert_eq!(
+            detect_rom(&[0; 0x8000]),
+            Err(DetectError::HeaderChecksum {
+                expected: 0x00,
+                actual: 0xe7,
+            })
+        );
+    }
+}
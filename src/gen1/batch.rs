@@ -0,0 +1,56 @@
+use crate::{batch, header, PcmBuffer, RomLocation, SynthesisOptions};
+
+use super::synthesis_from_header;
+
+/// Render the same sound at several pitches, e.g. for exploring how a cry sounds across the
+/// species that share it. Each element equals the corresponding `synthesis_with_options` render.
+///
+/// The header is only parsed once, every render gets a buffer of the exact length up front, and
+/// the pitches are rendered in parallel. Returns `None` if the sound is infinitely long.
+pub fn render_batch(
+    rom: &[u8],
+    bank: u8,
+    addr: u16,
+    pitches: &[i8],
+    length: u8,
+    options: SynthesisOptions,
+) -> Option<Vec<PcmBuffer>> {
+    let header = header::read(rom, RomLocation::new(bank, addr));
+    let sounds = pitches
+        .iter()
+        .map(|&pitch| synthesis_from_header(rom, bank, &header, pitch, length, options).iter())
+        .collect();
+
+    batch::render_batch(sounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::synthesis;
+    use super::*;
+
+    #[test]
+    fn test_render_batch() {
+        // two pulse channels
+        let rom = [
+            0x44, 0x06, 0x00, 0x05, 0x0f, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0x2a, 0xa2, 0x80, 0x06,
+            0xff, 0x24, 0xf3, 0x40, 0x07, 0xff,
+        ];
+        let pitches = [-128, -64, -1, 0, 1, 64, 127];
+
+        let batch = render_batch(&rom, 0, 0, &pitches, 1, SynthesisOptions::new()).unwrap();
+        assert_eq!(batch.len(), pitches.len());
+
+        for (buffer, pitch) in batch.iter().zip(pitches) {
+            assert_eq!(
+                Some(buffer),
+                synthesis(&rom, 0, 0, pitch, 1).to_buffer().as_ref()
+            );
+        }
+
+        assert_eq!(
+            render_batch(&rom, 0, 0, &[], 1, SynthesisOptions::new()),
+            Some(Vec::new())
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use super::channel::ChannelType;
 
 trait FromI4 {
@@ -32,11 +34,64 @@ pub enum Note {
     BFlat,
 }
 
+/// Frequency register values of the notes in the lowest octave, the engine shifts them right once
+/// for every octave above it.
+const PITCHES: [u16; 12] = [
+    0xf82c, 0xf89d, 0xf907, 0xf96b, 0xf9ca, 0xfa23, 0xfa77, 0xfac7, 0xfb12, 0xfb58, 0xfb9b, 0xfbda,
+];
+
+impl Note {
+    /// The note with the given index in the octave, as used by `PitchSlide`.
+    pub fn from_index(index: u8) -> Option<Note> {
+        const NOTES: [Note; 12] = [
+            Note::CFlat,
+            Note::CSharp,
+            Note::DFlat,
+            Note::DSharp,
+            Note::EFlat,
+            Note::FFlat,
+            Note::FSharp,
+            Note::GFlat,
+            Note::GSharp,
+            Note::AFlat,
+            Note::ASharp,
+            Note::BFlat,
+        ];
+
+        NOTES.get(index as usize).copied()
+    }
+
+    /// Frequency register value of the note, `octave` is the value of the `Octave` command where
+    /// 7 is the lowest octave and 0 the highest.
+    pub fn freq(&self, octave: u8) -> u16 {
+        let shift = 7 - octave.min(7);
+        (((PITCHES[*self as usize] as i16) >> shift) as u16) & 0x7ff
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Note::CFlat => "C",
+            Note::CSharp => "C#",
+            Note::DFlat => "D",
+            Note::DSharp => "D#",
+            Note::EFlat => "E",
+            Note::FFlat => "F",
+            Note::FSharp => "F#",
+            Note::GFlat => "G",
+            Note::GSharp => "G#",
+            Note::AFlat => "A",
+            Note::ASharp => "A#",
+            Note::BFlat => "B",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Command {
     PitchSweep {
-        /// Length of time between pitch shifts. \
-        /// Sometimes used with a value >7 in which case the MSB is ignored.
+        /// Length of time between pitch shifts, 0 means that the sweep is disabled. \
+        /// Sometimes written with a value >7, the MSB is ignored by the hardware and masked off
+        /// when parsing.
         length: u8,
         /// Positive value means increase in pitch, negative value means decrease in pitch. \
         /// Small magnitude means quick change, large magnitude means slow change. \
@@ -78,6 +133,15 @@ pub enum Command {
         /// In signed magnitude representation, so a value of 8 is the same as (negative) 0.
         fade: i8,
     },
+    /// `NoteType` of a wave channel, where the second byte selects the wave instrument instead of
+    /// the volume fade.
+    WaveNoteType {
+        speed: u8,
+        /// Output level of the wave channel: 0 is muted, 1 is full volume, 2 is half and 3 is a
+        /// quarter.
+        volume: u8,
+        instrument: u8,
+    },
     DrumSpeed(u8),
     Octave(u8),
     /// When enabled, effective frequency used is incremented by 1.
@@ -95,10 +159,12 @@ pub enum Command {
         octave: u8,
         pitch: u8,
     },
+    /// Duty cycle used for every step, the engine only keeps the two least significant bits.
     DutyCycle(u8),
     /// Used to calculate note delay counters, so a smaller value means music plays faster. \
     /// Ideally should be set to $100 or less to guarantee no overflow. \
-    /// If larger than 0x100, large note speed or note length values might cause overflow. \
+    /// If larger than 0x100, large note speed or note length values overflow, and the note
+    /// length wraps around the same way as in the engine. \
     /// Stored in big endian.
     Tempo(u16),
     Volume {
@@ -116,23 +182,146 @@ pub enum Command {
     Return,
 }
 
+/// Error returned by `Command::parse`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub bank: u8,
+    pub addr: u16,
+    /// The kind of channel that the command was parsed for, commands differ between them.
+    pub channel: ChannelType,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let channel = channel_name(self.channel);
+
+        match self.kind {
+            ParseErrorKind::UnknownCommand(byte) => write!(
+                f,
+                "Unknown {} command: {:02x} at {:02x}:{:04x}",
+                channel, byte, self.bank, self.addr
+            ),
+            ParseErrorKind::OutOfBounds => write!(
+                f,
+                "The {} command at {:02x}:{:04x} runs past the end of the ROM",
+                channel, self.bank, self.addr
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParseErrorKind {
+    /// The byte isn't a command of the channel type.
+    UnknownCommand(u8),
+    /// The command, or some of its operands, is past the end of the ROM, e.g. because the ROM is
+    /// truncated or the bank doesn't exist. The channel iterators also return it for data that
+    /// runs past the end of its bank.
+    OutOfBounds,
+}
+
+/// Error returned by `Command::encode`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct EncodeError {
+    pub kind: EncodeErrorKind,
+    pub command: Command,
+    /// The kind of channel that the command was encoded for.
+    pub channel: ChannelType,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let channel = channel_name(self.channel);
+
+        match self.kind {
+            EncodeErrorKind::UnsupportedCommand => {
+                write!(f, "{:?} isn't a {} command", self.command, channel)
+            }
+            EncodeErrorKind::OperandOutOfRange => write!(
+                f,
+                "An operand of {:?} doesn't fit in the {} command",
+                self.command, channel
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EncodeErrorKind {
+    /// The command doesn't exist for the channel type.
+    UnsupportedCommand,
+    /// An operand doesn't fit in the bits that the command has for it, e.g. a note length above
+    /// 15 or a fade outside of -7 to 7.
+    OperandOutOfRange,
+}
+
+fn channel_name(channel: ChannelType) -> &'static str {
+    match channel {
+        ChannelType::MusicPulse => "music pulse",
+        ChannelType::MusicWave => "music wave",
+        ChannelType::MusicNoise => "music noise",
+        ChannelType::SfxPulse => "SFX pulse channel",
+        ChannelType::SfxWave => "SFX wave channel",
+        ChannelType::SfxNoise => "SFX noise channel",
+    }
+}
+
 impl Command {
-    pub fn parse(rom: &[u8], bank: u8, addr: u16, channel: ChannelType) -> Command {
+    /// Parse the command at `addr`, returns an error if the byte there isn't a command of the
+    /// given channel type, or if the command doesn't fit in the ROM.
+    pub fn parse(
+        rom: &[u8],
+        bank: u8,
+        addr: u16,
+        channel: ChannelType,
+    ) -> Result<Command, ParseError> {
         let pos = ((bank as usize) * 0x4000) + ((addr as usize) & 0x3fff);
+        let data = rom.get(pos..).unwrap_or_default();
+
+        let error = |kind| ParseError {
+            kind,
+            bank,
+            addr,
+            channel,
+        };
+
+        let Some(&byte) = data.first() else {
+            return Err(error(ParseErrorKind::OutOfBounds));
+        };
 
-        match channel {
-            ChannelType::MusicPulse => Command::parse_music_pulse(&rom[pos..]),
-            ChannelType::MusicWave => Command::parse_music_wave(&rom[pos..]),
-            ChannelType::MusicNoise => Command::parse_music_noise(&rom[pos..]),
-            ChannelType::SfxPulse => Command::parse_sfx_pulse(&rom[pos..]),
-            ChannelType::SfxWave => Command::parse_sfx_wave(&rom[pos..]),
-            ChannelType::SfxNoise => Command::parse_sfx_noise(&rom[pos..]),
+        // Commands are at most 4 bytes long, pad the data so that the operands can be read
+        // without checking the length, and check it once the command is known instead.
+        let mut padded = [0; 4];
+        let available = data.len().min(padded.len());
+        padded[..available].copy_from_slice(&data[..available]);
+
+        let data = &padded;
+        let unknown = error(ParseErrorKind::UnknownCommand(byte));
+
+        let cmd = match channel {
+            ChannelType::MusicPulse => Command::parse_music_pulse(data, unknown),
+            ChannelType::MusicWave => Command::parse_music_wave(data, unknown),
+            ChannelType::MusicNoise => Command::parse_music_noise(data, unknown),
+            ChannelType::SfxPulse => Command::parse_sfx_pulse(data, unknown),
+            ChannelType::SfxWave => Command::parse_sfx_wave(data, unknown),
+            ChannelType::SfxNoise => Command::parse_sfx_noise(data, unknown),
+        }?;
+
+        if cmd.len() > available {
+            return Err(error(ParseErrorKind::OutOfBounds));
         }
+
+        Ok(cmd)
     }
 
     #[rustfmt::skip]
-    fn parse_music_pulse(data: &[u8]) -> Command {
-        match data[0] {
+    fn parse_music_pulse(data: &[u8], unknown: ParseError) -> Result<Command, ParseError> {
+        Ok(match data[0] {
             0x00..=0x0f => Command::Note { pitch: Note::CFlat, length: (data[0] & 0x0f) },
             0x10..=0x1f => Command::Note { pitch: Note::CSharp, length: (data[0] & 0x0f) },
             0x20..=0x2f => Command::Note { pitch: Note::DFlat, length: (data[0] & 0x0f) },
@@ -146,12 +335,12 @@ impl Command {
             0xa0..=0xaf => Command::Note { pitch: Note::ASharp, length: (data[0] & 0x0f) },
             0xb0..=0xbf => Command::Note { pitch: Note::BFlat, length: (data[0] & 0x0f) },
             0xc0..=0xcf => Command::Rest(data[0] & 0x0f),
-            0xd0..=0xdf => Command::NoteType { speed: (data[0] & 0x0f), volume: (data[1] & 0x0f), fade: i8::from_i4(data[1]) },
+            0xd0..=0xdf => Command::NoteType { speed: (data[0] & 0x0f), volume: (data[1] >> 4), fade: i8::from_i4(data[1]) },
             0xe0..=0xe7 => Command::Octave(data[0] & 0x0f),
             0xe8 => Command::TogglePerfectPitch,
             0xea => Command::Vibrato { delay: data[1], depth: (data[2] >> 4), rate: (data[2] & 0x0f) },
             0xeb => Command::PitchSlide { length: data[1], octave: (data[2] >> 4), pitch: (data[2] & 0x0f) },
-            0xec => Command::DutyCycle(data[1]),
+            0xec => Command::DutyCycle(data[1] & 0x03),
             0xed => Command::Tempo(u16::from_be_bytes([data[1], data[2]])),
             0xf0 => Command::Volume { left: (data[1] >> 4), right: (data[1] & 0x0f) },
             0xf8 => Command::ExecuteMusic,
@@ -159,13 +348,13 @@ impl Command {
             0xfd => Command::SoundCall(u16::from_le_bytes([data[1], data[2]])),
             0xfe => Command::Loop { count: data[1], addr: u16::from_le_bytes([data[2], data[3]]) },
             0xff => Command::Return,
-            byte => todo!("Unknown music pulse command: {:02x}", byte)
-        }
+            _ => return Err(unknown),
+        })
     }
 
     #[rustfmt::skip]
-    fn parse_music_wave(data: &[u8]) -> Command {
-        match data[0] {
+    fn parse_music_wave(data: &[u8], unknown: ParseError) -> Result<Command, ParseError> {
+        Ok(match data[0] {
             0x00..=0x0f => Command::Note { pitch: Note::CFlat, length: (data[0] & 0x0f) },
             0x10..=0x1f => Command::Note { pitch: Note::CSharp, length: (data[0] & 0x0f) },
             0x20..=0x2f => Command::Note { pitch: Note::DFlat, length: (data[0] & 0x0f) },
@@ -179,7 +368,7 @@ impl Command {
             0xa0..=0xaf => Command::Note { pitch: Note::ASharp, length: (data[0] & 0x0f) },
             0xb0..=0xbf => Command::Note { pitch: Note::BFlat, length: (data[0] & 0x0f) },
             0xc0..=0xcf => Command::Rest(data[0] & 0x0f),
-            0xd0..=0xdf => Command::NoteType { speed: (data[0] & 0x0f), volume: (data[1] & 0x0f), fade: i8::from_i4(data[1]) },
+            0xd0..=0xdf => Command::WaveNoteType { speed: (data[0] & 0x0f), volume: (data[1] >> 4) & 0x03, instrument: (data[1] & 0x0f) },
             0xe0..=0xe7 => Command::Octave(data[0] & 0x0f),
             0xe8 => Command::TogglePerfectPitch,
             0xea => Command::Vibrato { delay: data[1], depth: (data[2] >> 4), rate: (data[2] & 0x0f) },
@@ -189,60 +378,168 @@ impl Command {
             0xfd => Command::SoundCall(u16::from_le_bytes([data[1], data[2]])),
             0xfe => Command::Loop { count: data[1], addr: u16::from_le_bytes([data[2], data[3]]) },
             0xff => Command::Return,
-            byte => todo!("Unknown music wave command: {:02x}", byte)
-        }
+            _ => return Err(unknown),
+        })
     }
 
     #[rustfmt::skip]
-    fn parse_music_noise(data: &[u8]) -> Command {
-        match data[0] {
-            0xb0..=0xbf => Command::DrumNote { instrument: data[1], length: (data[0] >> 4) },
+    fn parse_music_noise(data: &[u8], unknown: ParseError) -> Result<Command, ParseError> {
+        Ok(match data[0] {
+            0xb0..=0xbf => Command::DrumNote { instrument: data[1], length: (data[0] & 0x0f) },
             0xc0..=0xcf => Command::Rest(data[0] & 0x0f),
             0xd0..=0xdf => Command::DrumSpeed(data[0] & 0x0f),
             0xfd => Command::SoundCall(u16::from_le_bytes([data[1], data[2]])),
             0xfe => Command::Loop { count: data[1], addr: u16::from_le_bytes([data[2], data[3]]) },
             0xff => Command::Return,
-            byte => todo!("Unknown music noise command: {:02x}", byte)
-        }
+            _ => return Err(unknown),
+        })
     }
 
     #[rustfmt::skip]
-    fn parse_sfx_pulse(data: &[u8]) -> Command {
-        match data[0] {
-            0x10 => Command::PitchSweep { length: (data[1] >> 4), change: i8::from_i4(data[1]) },
+    fn parse_sfx_pulse(data: &[u8], unknown: ParseError) -> Result<Command, ParseError> {
+        Ok(match data[0] {
+            0x10 => Command::PitchSweep { length: (data[1] >> 4) & 0x07, change: i8::from_i4(data[1]) },
             0x20..=0x2f => Command::SquareNote { length: data[0] & 0x0f, volume: data[1] >> 4, fade: i8::from_i4(data[1]), freq: u16::from_le_bytes([data[2], data[3]]) },
-            0xec => Command::DutyCycle(data[1]),
+            0xec => Command::DutyCycle(data[1] & 0x03),
             0xf8 => Command::ExecuteMusic,
             0xfc => Command::DutyCyclePattern(data[1] >> 6, (data[1] >> 4) & 0x03, (data[1] >> 2) & 0x03, data[1] & 0x03),
             0xfd => Command::SoundCall(u16::from_le_bytes([data[1], data[2]])),
             0xfe => Command::Loop { count: data[1], addr: u16::from_le_bytes([data[2], data[3]]) },
             0xff => Command::Return,
-            byte => todo!("Unknown SFX pulse channel command: {:02x}", byte)
-        }
+            _ => return Err(unknown),
+        })
     }
 
     #[rustfmt::skip]
-    fn parse_sfx_wave(data: &[u8]) -> Command {
-        match data[0] {
+    fn parse_sfx_wave(data: &[u8], unknown: ParseError) -> Result<Command, ParseError> {
+        Ok(match data[0] {
             0xf8 => Command::ExecuteMusic,
-            byte => todo!("Unknown SFX wave channel command: {:02x}", byte),
-        }
+            _ => return Err(unknown),
+        })
     }
 
     #[rustfmt::skip]
-    fn parse_sfx_noise(data: &[u8]) -> Command {
-        match data[0] {
+    fn parse_sfx_noise(data: &[u8], unknown: ParseError) -> Result<Command, ParseError> {
+        Ok(match data[0] {
             0x20..=0x2f => Command::NoiseNote { length: data[0] & 0x0f, volume: data[1] >> 4, fade: i8::from_i4(data[1]), value: data[2] },
-            0xec => Command::DutyCycle(data[1]),
+            0xec => Command::DutyCycle(data[1] & 0x03),
             0xfc => Command::DutyCyclePattern(data[1] >> 6, (data[1] >> 4) & 0x03, (data[1] >> 2) & 0x03, data[1] & 0x03),
             0xfd => Command::SoundCall(u16::from_le_bytes([data[1], data[2]])),
             0xfe => Command::Loop { count: data[1], addr: u16::from_le_bytes([data[2], data[3]]) },
             0xff => Command::Return,
-            byte => todo!("Unknown SFX noise channel command: {:02x}", byte),
+            _ => return Err(unknown),
+        })
+    }
+
+    /// Encode the command as the bytes that `parse` reads it from for the given channel type.
+    ///
+    /// Returns an error if the command doesn't exist for the channel type, or if an operand is
+    /// larger than the bits that the command has for it. Fades are encoded in signed magnitude,
+    /// and the tempo in big endian.
+    pub fn encode(&self, channel: ChannelType) -> Result<Vec<u8>, EncodeError> {
+        let error = |kind| EncodeError {
+            kind,
+            command: *self,
+            channel,
+        };
+
+        let bits = |value: u8, count: u32| match value >> count {
+            0 => Ok(value),
+            _ => Err(error(EncodeErrorKind::OperandOutOfRange)),
+        };
+        let nibble = |value: u8| bits(value, 4);
+        let fade = |fade: i8| match fade {
+            0..=7 => Ok(fade as u8),
+            -7..=-1 => Ok(0b1000 | fade.unsigned_abs()),
+            _ => Err(error(EncodeErrorKind::OperandOutOfRange)),
+        };
+
+        let bytes = match *self {
+            Command::PitchSweep { length, change } => {
+                vec![0x10, (bits(length, 3)? << 4) | fade(change)?]
+            }
+            Command::SquareNote {
+                length,
+                volume,
+                fade: value,
+                freq,
+            } => {
+                let [low, high] = freq.to_le_bytes();
+                vec![
+                    0x20 | nibble(length)?,
+                    (nibble(volume)? << 4) | fade(value)?,
+                    low,
+                    high,
+                ]
+            }
+            Command::NoiseNote {
+                length,
+                volume,
+                fade: value,
+                value: params,
+            } => vec![
+                0x20 | nibble(length)?,
+                (nibble(volume)? << 4) | fade(value)?,
+                params,
+            ],
+            Command::Note { pitch, length } => vec![((pitch as u8) << 4) | nibble(length)?],
+            Command::DrumNote { instrument, length } => vec![0xb0 | nibble(length)?, instrument],
+            Command::Rest(length) => vec![0xc0 | nibble(length)?],
+            Command::NoteType {
+                speed,
+                volume,
+                fade: value,
+            } => vec![0xd0 | nibble(speed)?, (nibble(volume)? << 4) | fade(value)?],
+            Command::WaveNoteType {
+                speed,
+                volume,
+                instrument,
+            } => vec![
+                0xd0 | nibble(speed)?,
+                (bits(volume, 2)? << 4) | nibble(instrument)?,
+            ],
+            Command::DrumSpeed(speed) => vec![0xd0 | nibble(speed)?],
+            Command::Octave(octave) => vec![0xe0 | bits(octave, 3)?],
+            Command::TogglePerfectPitch => vec![0xe8],
+            Command::Vibrato { delay, depth, rate } => {
+                vec![0xea, delay, (nibble(depth)? << 4) | nibble(rate)?]
+            }
+            Command::PitchSlide {
+                length,
+                octave,
+                pitch,
+            } => vec![0xeb, length, (nibble(octave)? << 4) | nibble(pitch)?],
+            Command::DutyCycle(duty) => vec![0xec, bits(duty, 2)?],
+            Command::Tempo(tempo) => {
+                let [high, low] = tempo.to_be_bytes();
+                vec![0xed, high, low]
+            }
+            Command::Volume { left, right } => vec![0xf0, (nibble(left)? << 4) | nibble(right)?],
+            Command::ExecuteMusic => vec![0xf8],
+            Command::DutyCyclePattern(a, b, c, d) => vec![
+                0xfc,
+                (bits(a, 2)? << 6) | (bits(b, 2)? << 4) | (bits(c, 2)? << 2) | bits(d, 2)?,
+            ],
+            Command::SoundCall(addr) => {
+                let [low, high] = addr.to_le_bytes();
+                vec![0xfd, low, high]
+            }
+            Command::Loop { count, addr } => {
+                let [low, high] = addr.to_le_bytes();
+                vec![0xfe, count, low, high]
+            }
+            Command::Return => vec![0xff],
+        };
+
+        // the opcodes are the same for every channel type, but not every channel type has every
+        // command, and some opcodes mean different commands depending on the channel type
+        match Command::parse(&bytes, 0, 0, channel) {
+            Ok(cmd) if cmd == *self => Ok(bytes),
+            _ => Err(error(EncodeErrorKind::UnsupportedCommand)),
         }
     }
 
-    pub fn len(&self) -> usize {
+    pub(super) fn len(&self) -> usize {
         match self {
             Command::PitchSweep { .. } => 2,
             Command::SquareNote { .. } => 4,
@@ -251,6 +548,7 @@ impl Command {
             Command::DrumNote { .. } => 2,
             Command::Rest(_) => 1,
             Command::NoteType { .. } => 2,
+            Command::WaveNoteType { .. } => 2,
             Command::DrumSpeed(_) => 1,
             Command::Octave(_) => 1,
             Command::TogglePerfectPitch => 1,
@@ -267,3 +565,168 @@ impl Command {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pitch_sweep_msb_is_ignored() {
+        for byte in 0x80..=0xf7 {
+            let cmd = Command::parse(&[0x10, byte], 0, 0, ChannelType::SfxPulse).unwrap();
+
+            match cmd {
+                Command::PitchSweep { length, change } => {
+                    assert_eq!(length, (byte >> 4) - 8, "byte: {byte:02x}");
+                    assert_eq!(change, i8::from_i4(byte), "byte: {byte:02x}");
+                }
+                cmd => panic!("Unexpected command: {cmd:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_duty_cycle_operand() {
+        for byte in [0x00, 0x01, 0x02, 0x03, 0x40, 0x82] {
+            let cmd = Command::parse(&[0xec, byte], 0, 0, ChannelType::SfxPulse).unwrap();
+            assert_eq!(cmd, Command::DutyCycle(byte & 0x03), "byte: {byte:02x}");
+        }
+    }
+
+    const CHANNEL_TYPES: [ChannelType; 6] = [
+        ChannelType::MusicPulse,
+        ChannelType::MusicWave,
+        ChannelType::MusicNoise,
+        ChannelType::SfxPulse,
+        ChannelType::SfxWave,
+        ChannelType::SfxNoise,
+    ];
+
+    #[test]
+    fn test_encode_round_trip() {
+        for channel in CHANNEL_TYPES {
+            for opcode in 0x00..=0xff {
+                for operand in 0x00..=0xff {
+                    let data = [opcode, operand, 0x34, 0x12];
+                    let Ok(cmd) = Command::parse(&data, 0, 0, channel) else {
+                        continue;
+                    };
+
+                    let bytes = cmd.encode(channel).unwrap();
+                    assert_eq!(bytes.len(), cmd.len());
+                    assert_eq!(
+                        Command::parse(&bytes, 0, 0, channel),
+                        Ok(cmd),
+                        "{data:02x?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_bytes() {
+        #[rustfmt::skip]
+        let sounds: [(ChannelType, &[u8]); 4] = [
+            // pitch_sweep, duty_cycle_pattern, square_note, duty_cycle, loop, call, ret
+            (ChannelType::SfxPulse, &[
+                0x10, 0x2d, 0xfc, 0xe4, 0x2f, 0xa9, 0x80, 0x06, 0xec, 0x02,
+                0xfe, 0x03, 0x02, 0x40, 0xfd, 0x10, 0x40, 0xff,
+            ]),
+            // noise_note with a fade in, ret
+            (ChannelType::SfxNoise, &[0x21, 0x5b, 0x31, 0xff]),
+            // tempo, volume, vibrato, note_type, octave, pitch_slide, toggle_perfect_pitch,
+            // notes and a rest
+            (ChannelType::MusicPulse, &[
+                0xed, 0x00, 0xc0, 0xf0, 0x77, 0xea, 0x06, 0x23, 0xdc, 0xa7, 0xe3,
+                0xeb, 0x02, 0x4a, 0xe8, 0x41, 0xb7, 0xc1, 0xff,
+            ]),
+            // drum_speed, drum_note, rest, loop forever
+            (ChannelType::MusicNoise, &[0xdc, 0xb1, 0x0c, 0xc3, 0xfe, 0x00, 0x00, 0x40]),
+        ];
+
+        for (channel, data) in sounds {
+            let mut addr = 0;
+            let mut encoded = Vec::new();
+
+            while addr < data.len() {
+                let cmd = Command::parse(data, 0, addr as u16, channel).unwrap();
+                encoded.extend(cmd.encode(channel).unwrap());
+                addr += cmd.len();
+            }
+
+            assert_eq!(encoded, data);
+        }
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        let note = Command::Note {
+            pitch: Note::CFlat,
+            length: 3,
+        };
+        let err = note.encode(ChannelType::SfxPulse).unwrap_err();
+        assert_eq!(err.kind, EncodeErrorKind::UnsupportedCommand);
+        assert_eq!(
+            err.to_string(),
+            "Note { pitch: CFlat, length: 3 } isn't a SFX pulse channel command"
+        );
+
+        let rest = Command::Rest(16);
+        let err = rest.encode(ChannelType::MusicPulse).unwrap_err();
+        assert_eq!(err.kind, EncodeErrorKind::OperandOutOfRange);
+
+        let note_type = Command::NoteType {
+            speed: 12,
+            volume: 10,
+            fade: -8,
+        };
+        let err = note_type.encode(ChannelType::MusicPulse).unwrap_err();
+        assert_eq!(err.kind, EncodeErrorKind::OperandOutOfRange);
+
+        // the opcode of an octave above 7 is another command
+        let err = Command::Octave(8)
+            .encode(ChannelType::MusicPulse)
+            .unwrap_err();
+        assert_eq!(err.kind, EncodeErrorKind::OperandOutOfRange);
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let rom = [0x00, 0x00];
+        let err = Command::parse(&rom, 0, 0x4001, ChannelType::SfxNoise).unwrap_err();
+
+        assert_eq!(
+            err,
+            ParseError {
+                kind: ParseErrorKind::UnknownCommand(0x00),
+                bank: 0,
+                addr: 0x4001,
+                channel: ChannelType::SfxNoise,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Unknown SFX noise channel command: 00 at 00:4001"
+        );
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        // a square note without its frequency
+        let err = Command::parse(&[0x20, 0xf1], 0, 0, ChannelType::SfxPulse).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::OutOfBounds);
+
+        // a bank past the end of the ROM
+        let err = Command::parse(&[0xff], 1, 0x4000, ChannelType::SfxPulse).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::OutOfBounds);
+        assert_eq!(
+            err.to_string(),
+            "The SFX pulse channel command at 01:4000 runs past the end of the ROM"
+        );
+
+        // the last byte of the ROM
+        let cmd = Command::parse(&[0xff], 0, 0, ChannelType::SfxPulse);
+        assert_eq!(cmd, Ok(Command::Return));
+    }
+}
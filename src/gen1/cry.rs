@@ -0,0 +1,198 @@
+use std::fmt;
+
+use crate::RomLocation;
+
+use super::{synthesis, Pcm};
+
+/// Bank of the sound headers of the cries, the first audio bank.
+const CRY_BANK: u8 = 0x02;
+
+/// Sound ID of the first cry, every cry has three channels.
+const CRY_SFX_START: u16 = 0x14;
+
+/// Number of species in the internal species order, including MissingNo.
+const SPECIES_COUNT: usize = 190;
+
+/// The code of `GetCryData` that indexes `CryData` with three bytes per species, starting at
+/// `dec a`. `None` is the address and bank of the table.
+const GET_CRY_DATA: [Option<u8>; 12] = [
+    Some(0x3d), // dec a
+    Some(0x4f), // ld c, a
+    Some(0x06), // ld b, 0
+    Some(0x00),
+    Some(0x21), // ld hl, CryData
+    None,
+    None,
+    Some(0x09), // add hl, bc
+    Some(0x09), // add hl, bc
+    Some(0x09), // add hl, bc
+    Some(0x3e), // ld a, BANK(CryData)
+    None,
+];
+
+/// The code of `IndexToPokedex` that looks up the Pokédex number of a species in
+/// `PokedexOrder`, starting at `dec a`. The table is in the same bank as the code.
+const INDEX_TO_POKEDEX: [Option<u8>; 10] = [
+    Some(0x3d), // dec a
+    Some(0x21), // ld hl, PokedexOrder
+    None,
+    None,
+    Some(0x06), // ld b, 0
+    Some(0x00),
+    Some(0x4f), // ld c, a
+    Some(0x09), // add hl, bc
+    Some(0x7e), // ld a, [hl]
+    Some(0xea), // ld [wPokedexNum], a
+];
+
+/// Error returned by `lookup_cry` and `synthesis_cry`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CryError {
+    /// The cry tables couldn't be found, e.g. because the ROM isn't Red, Blue or Yellow.
+    TablesNotFound,
+    /// No species has the given Pokédex number.
+    UnknownSpecies(u8),
+}
+
+impl fmt::Display for CryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryError::TablesNotFound => write!(f, "The cry tables couldn't be found in the ROM"),
+            CryError::UnknownSpecies(species) => {
+                write!(f, "No species has the Pokédex number {}", species)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CryError {}
+
+/// The cry of a species, with the arguments to `synthesis`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Cry {
+    pub location: RomLocation,
+    pub pitch: i8,
+    pub length: u8,
+}
+
+/// Look up the cry of the species with the given Pokédex number.
+///
+/// The Pokédex order and the cry data tables are found through the code that reads them, so
+/// their addresses don't have to be known for each release. This works the same for Red, Blue
+/// and Yellow, whose tables are at different addresses, and for ROM hacks that move them.
+pub fn lookup_cry(rom: &[u8], species: u8) -> Result<Cry, CryError> {
+    let pokedex_order = find(rom, &INDEX_TO_POKEDEX)
+        .and_then(|pos| {
+            Some(RomLocation::new(
+                (pos / 0x4000) as u8,
+                read_addr(rom, pos + 2)?,
+            ))
+        })
+        .ok_or(CryError::TablesNotFound)?;
+
+    let cry_data = find(&rom[..rom.len().min(0x4000)], &GET_CRY_DATA)
+        .and_then(|pos| {
+            Some(RomLocation::new(
+                *rom.get(pos + 11)?,
+                read_addr(rom, pos + 5)?,
+            ))
+        })
+        .ok_or(CryError::TablesNotFound)?;
+
+    let order = rom
+        .get(pokedex_order.offset()..(pokedex_order.offset() + SPECIES_COUNT))
+        .ok_or(CryError::TablesNotFound)?;
+
+    // the species are stored in their internal order, starting at 1
+    let index = order
+        .iter()
+        .position(|&number| species != 0 && number == species)
+        .ok_or(CryError::UnknownSpecies(species))?;
+
+    let pos = cry_data.offset() + index * 3;
+    let entry = rom.get(pos..(pos + 3)).ok_or(CryError::TablesNotFound)?;
+
+    // every entry is the base cry, the pitch and the length
+    let id = CRY_SFX_START + 3 * (entry[0] as u16);
+
+    Ok(Cry {
+        location: RomLocation::new(CRY_BANK, 0x4000 + id * 3),
+        pitch: entry[1] as i8,
+        length: entry[2],
+    })
+}
+
+/// Synthesize the cry of the species with the given Pokédex number, see `lookup_cry`.
+pub fn synthesis_cry(rom: &[u8], species: u8) -> Result<Pcm<'_>, CryError> {
+    let cry = lookup_cry(rom, species)?;
+
+    Ok(synthesis(
+        rom,
+        cry.location.bank,
+        cry.location.addr,
+        cry.pitch,
+        cry.length,
+    ))
+}
+
+/// Offset of the first match of `pattern` in `data`, where `None` matches any byte.
+fn find(data: &[u8], pattern: &[Option<u8>]) -> Option<usize> {
+    data.windows(pattern.len()).position(|window| {
+        window
+            .iter()
+            .zip(pattern)
+            .all(|(byte, expected)| expected.map_or(true, |expected| *byte == expected))
+    })
+}
+
+fn read_addr(rom: &[u8], pos: usize) -> Option<u16> {
+    match rom.get(pos..(pos + 2))? {
+        &[low, high] => Some(u16::from_le_bytes([low, high])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ROM with the code and tables for a single species, Bulbasaur, which is species 0x99 in
+    /// the internal order.
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 3 * 0x4000];
+
+        #[rustfmt::skip]
+        rom[0x1000..0x100c].copy_from_slice(&[
+            0x3d, 0x4f, 0x06, 0x00, 0x21, 0x00, 0x50, 0x09, 0x09, 0x09, 0x3e, 0x01,
+        ]);
+        #[rustfmt::skip]
+        rom[0x4100..0x410a].copy_from_slice(&[
+            0x3d, 0x21, 0x00, 0x60, 0x06, 0x00, 0x4f, 0x09, 0x7e, 0xea,
+        ]);
+
+        rom[0x6000 + 0x98] = 1;
+        rom[(0x5000 + 0x98 * 3)..(0x5000 + 0x99 * 3)].copy_from_slice(&[0x0f, 0x80, 0x01]);
+
+        rom
+    }
+
+    #[test]
+    fn test_lookup_cry() {
+        assert_eq!(
+            lookup_cry(&rom(), 1),
+            Ok(Cry {
+                location: RomLocation::new(0x02, 0x40c3),
+                pitch: -128,
+                length: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_lookup_cry_errors() {
+        assert_eq!(lookup_cry(&rom(), 2), Err(CryError::UnknownSpecies(2)));
+        assert_eq!(lookup_cry(&rom(), 0), Err(CryError::UnknownSpecies(0)));
+        assert_eq!(lookup_cry(&[0; 0x8000], 1), Err(CryError::TablesNotFound));
+        assert_eq!(lookup_cry(&[], 1), Err(CryError::TablesNotFound));
+    }
+}
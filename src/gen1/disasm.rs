@@ -0,0 +1,134 @@
+use crate::Voice;
+
+use super::channel::ChannelType;
+use super::command::{Command, ParseError};
+use super::sound::Sound;
+
+/// Upper bound on the number of commands read from a single channel, in case of malformed data.
+const MAX_COMMANDS: usize = 0x10000;
+
+/// Commands with their addresses, in the order they are stored.
+pub type Listing = Vec<(u16, Command)>;
+
+/// Disassemble the commands starting at the given address, returning each of them with its
+/// address.
+///
+/// The commands are listed in the order they are stored, so the body of a finite loop is only
+/// listed once. The listing ends after the first `Return` or infinite loop, sound calls are
+/// listed but not followed. At most 0x10000 commands are returned, in case the stream never
+/// ends.
+pub fn disassemble(
+    rom: &[u8],
+    bank: u8,
+    addr: u16,
+    channel: ChannelType,
+) -> Result<Listing, ParseError> {
+    let mut result = Vec::new();
+    let mut addr = addr;
+
+    for _ in 0..MAX_COMMANDS {
+        let cmd = Command::parse(rom, bank, addr, channel)?;
+        result.push((addr, cmd));
+
+        if matches!(cmd, Command::Return | Command::Loop { count: 0, .. }) {
+            break;
+        }
+
+        addr = addr.wrapping_add(cmd.len() as u16);
+    }
+
+    Ok(result)
+}
+
+/// Disassemble every channel of the sound at the given address, see `disassemble`.
+pub fn disassemble_sound(
+    rom: &[u8],
+    bank: u8,
+    addr: u16,
+) -> Result<Vec<(Voice, Listing)>, ParseError> {
+    Sound::new(rom, bank, addr)
+        .channels()
+        .into_iter()
+        .filter_map(|(voice, channel)| {
+            let channel = channel?;
+            let commands = disassemble(rom, bank, channel.addr, channel.channel);
+            Some(commands.map(|commands| (voice, commands)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gen1::ParseErrorKind;
+
+    #[rustfmt::skip]
+    const ROM: [u8; 25] = [
+        // two channels
+        0x44, 0x06, 0x00, 0x07, 0x13, 0x00,
+        // square_note, loop twice back to it, sound_call, return
+        0x21, 0xf1, 0x00, 0x07,
+        0xfe, 0x02, 0x06, 0x00,
+        0xfd, 0x12, 0x00,
+        0xff,
+        // subroutine: return
+        0xff,
+        // noise_note, loop forever
+        0x20, 0xa2, 0x31,
+        0xfe, 0x00, 0x13,
+    ];
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(
+            disassemble(&ROM, 0, 6, ChannelType::SfxPulse),
+            Ok(vec![
+                (
+                    0x06,
+                    Command::SquareNote {
+                        length: 1,
+                        volume: 15,
+                        fade: 1,
+                        freq: 0x700,
+                    }
+                ),
+                (
+                    0x0a,
+                    Command::Loop {
+                        count: 2,
+                        addr: 0x06,
+                    }
+                ),
+                (0x0e, Command::SoundCall(0x12)),
+                (0x11, Command::Return),
+            ])
+        );
+
+        // the infinite loop ends the listing, but its address is cut off
+        let error = disassemble(&ROM, 0, 0x13, ChannelType::SfxNoise).unwrap_err();
+        assert_eq!(error.kind, ParseErrorKind::OutOfBounds);
+        assert_eq!(error.addr, 0x16);
+    }
+
+    #[test]
+    fn test_disassemble_sound() {
+        let mut rom = ROM.to_vec();
+        rom.push(0x00);
+
+        let channels = disassemble_sound(&rom, 0, 0).unwrap();
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].0, Voice::Pulse1);
+        assert_eq!(channels[0].1.len(), 4);
+        assert_eq!(channels[1].0, Voice::Noise);
+        assert_eq!(
+            channels[1].1.last(),
+            Some(&(
+                0x16,
+                Command::Loop {
+                    count: 0,
+                    addr: 0x13,
+                }
+            ))
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use crate::extract::{self, BlobError, SoundBlob};
+use crate::{RomLocation, SynthesisOptions};
+
+use super::{synthesis_with_options, Gen1, Pcm};
+
+/// Copy the sound at the given location, and all channel data that it can reach through loops
+/// and calls, into a standalone blob.
+pub fn sound_blob(rom: &[u8], location: RomLocation) -> Result<SoundBlob, BlobError> {
+    extract::sound_blob::<Gen1>(rom, location)
+}
+
+/// Synthesize a sound from a blob created with `sound_blob`.
+///
+/// Returns an error if the blob is from another generation, or from an unsupported version.
+pub fn synthesize_blob(blob: &SoundBlob, pitch: i8, length: u8) -> Result<Pcm<'_>, BlobError> {
+    blob.check(1)?;
+
+    Ok(synthesis_with_options(
+        blob.data(),
+        0,
+        blob.header(),
+        pitch,
+        length,
+        SynthesisOptions::default(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut rom = vec![0xaa; 0x8000];
+
+        // sound header in bank 1, with the channel data placed apart from it
+        rom[0x4100..0x4103].copy_from_slice(&[0x04, 0x00, 0x42]);
+        // square_note, loop 3 times back to it, square_note, return
+        #[rustfmt::skip]
+        rom[0x4200..0x420d].copy_from_slice(&[
+            0x21, 0xf1, 0x00, 0x07,
+            0xfe, 0x03, 0x00, 0x42,
+            0x21, 0xa1, 0x80, 0x06,
+            0xff,
+        ]);
+
+        let expected: Vec<f32> = super::super::synthesis(&rom, 0x01, 0x4100, 0, 0)
+            .iter()
+            .collect();
+        let blob = sound_blob(&rom, RomLocation::new(0x01, 0x4100)).unwrap();
+        drop(rom);
+
+        assert_eq!(blob.data().len(), 3 + 13);
+        assert_eq!(
+            synthesize_blob(&blob, 0, 0)
+                .unwrap()
+                .iter()
+                .collect::<Vec<f32>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_pointers_are_rewritten() {
+        let mut rom = vec![0xaa; 0x8000];
+
+        rom[0x4100..0x4103].copy_from_slice(&[0x04, 0x00, 0x42]);
+        // square_note, loop 3 times back to it, call, return
+        #[rustfmt::skip]
+        rom[0x4200..0x420c].copy_from_slice(&[
+            0x21, 0xf1, 0x00, 0x07,
+            0xfe, 0x03, 0x00, 0x42,
+            0xfd, 0x00, 0x43,
+            0xff,
+        ]);
+        // subroutine: square_note, return
+        rom[0x4300..0x4305].copy_from_slice(&[0x21, 0xa1, 0x80, 0x06, 0xff]);
+
+        let blob = sound_blob(&rom, RomLocation::new(0x01, 0x4100)).unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(
+            blob.data(),
+            [
+                0x04, 0x03, 0x00,
+                0x21, 0xf1, 0x00, 0x07,
+                0xfe, 0x03, 0x03, 0x00,
+                0xfd, 0x0f, 0x00,
+                0xff,
+                0x21, 0xa1, 0x80, 0x06, 0xff,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_other_generation() {
+        let mut rom = vec![0xff; 0x8000];
+        rom[0x4100..0x4103].copy_from_slice(&[0x04, 0x03, 0x41]);
+
+        let blob = crate::gen2::sound_blob(&rom, RomLocation::new(0x01, 0x4100)).unwrap();
+        let err = synthesize_blob(&blob, 0, 0).map(|_| ());
+        assert_eq!(err, Err(BlobError::OtherGeneration { generation: 2 }));
+    }
+}
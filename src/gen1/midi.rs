@@ -0,0 +1,340 @@
+use crate::{Voice, SAMPLES_PER_FRAME, SOURCE_SAMPLE_RATE};
+
+use super::channel::Channel;
+use super::command::Command;
+use super::sound::Sound;
+use super::transcription::{initial_tempo, walk};
+
+/// Ticks per quarter note, which makes one tick one step of the note speed, with a note speed of
+/// 12 as the normal speed.
+const TICKS_PER_QUARTER: u16 = 48;
+
+/// General MIDI percussion keys of the drum instruments, by instrument, 0 for no drum. The
+/// snares become snare drums, the triangles hi-hats, the cymbals crash and ride cymbals, and the
+/// muted snares side sticks.
+const DRUM_KEYS: [u8; 20] = [
+    0, 38, 38, 38, 38, 38, 42, 42, 38, 38, 38, 38, 49, 57, 51, 37, 42, 37, 37, 37,
+];
+
+/// Export the music commands of every channel of the sound at the given address as a type 1
+/// Standard MIDI File.
+///
+/// The first track holds the tempo changes and is followed by one track per channel. The pulse
+/// and wave channels play on MIDI channels 1 to 3, and the drums of the noise channel on the
+/// percussion channel 10. Finite loops are unrolled, and a song ends where it would start
+/// looping forever. Vibrato, pitch slides and SFX commands are ignored.
+pub fn export_midi(rom: &[u8], bank: u8, addr: u16) -> Vec<u8> {
+    let sound = Sound::new(rom, bank, addr);
+    let channels = sound.channels();
+
+    let mut tempos = vec![(0, initial_tempo(&channels))];
+    let mut tracks = Vec::new();
+
+    for (voice, channel) in channels {
+        if let Some(channel) = channel {
+            tracks.push(channel_track(&channel, voice, &mut tempos));
+        }
+    }
+
+    // the tempo is shared between the channels, so the changes of all of them are merged
+    tempos.sort_by_key(|(time, _)| *time);
+
+    let mut tempo_track = Track::new();
+    let mut current = None;
+
+    for (time, tempo) in tempos {
+        if current != Some(tempo) {
+            let micros = quarter_note_micros(tempo).to_be_bytes();
+            tempo_track.event(time, &[0xff, 0x51, 0x03, micros[1], micros[2], micros[3]]);
+            current = Some(tempo);
+        }
+    }
+
+    let mut result = Vec::new();
+    result.extend_from_slice(b"MThd");
+    result.extend_from_slice(&6u32.to_be_bytes());
+    result.extend_from_slice(&1u16.to_be_bytes());
+    result.extend_from_slice(&(tracks.len() as u16 + 1).to_be_bytes());
+    result.extend_from_slice(&TICKS_PER_QUARTER.to_be_bytes());
+
+    let end = tempo_track.time;
+    tempo_track.write(end, &mut result);
+
+    for (track, end) in tracks {
+        track.write(end, &mut result);
+    }
+
+    result
+}
+
+/// Length of a quarter note in microseconds, at the given tempo.
+fn quarter_note_micros(tempo: u16) -> u32 {
+    // every tick is `tempo / 0x100` frames long
+    let micros =
+        (TICKS_PER_QUARTER as u64) * (tempo as u64) * (SAMPLES_PER_FRAME as u64) * 1_000_000
+            / (0x100 * SOURCE_SAMPLE_RATE as u64);
+
+    // the tempo meta event only has room for 24 bits
+    micros.min(0xff_ffff) as u32
+}
+
+/// The notes of a channel and the time that it ends, the tempo changes are added to `tempos`
+/// instead.
+fn channel_track(channel: &Channel, voice: Voice, tempos: &mut Vec<(u32, u16)>) -> (Track, u32) {
+    let midi_channel = match voice {
+        Voice::Pulse1 => 0,
+        Voice::Pulse2 => 1,
+        Voice::Wave => 2,
+        Voice::Noise => 9,
+    };
+
+    let mut track = Track::new();
+    let name = voice.short_name().as_bytes();
+    track.event(0, &[&[0xff, 0x03, name.len() as u8], name].concat());
+
+    let mut time = 0;
+    let mut speed = 1;
+    let mut octave = 0;
+    let mut velocity = 0;
+
+    let note = |track: &mut Track, time: u32, len: u32, key: u8, velocity: u8| {
+        if velocity > 0 {
+            track.event(time, &[0x90 | midi_channel, key, velocity]);
+            track.event(time + len, &[0x80 | midi_channel, key, 0]);
+        }
+    };
+
+    walk(channel, true, |cmd| match cmd {
+        Command::Note { pitch, length } => {
+            let len = ticks(length, speed);
+
+            // octave 7 starts at C2, and the wave channel plays an octave below the pulse channels
+            let mut key = 12 * (10 - octave as u32) + pitch as u32;
+            if voice == Voice::Wave {
+                key -= 12;
+            }
+
+            note(&mut track, time, len, key.min(127) as u8, velocity);
+            time += len;
+        }
+
+        Command::DrumNote { instrument, length } => {
+            let len = ticks(length, speed);
+            let key = DRUM_KEYS.get(instrument as usize).copied().unwrap_or(38);
+
+            if key != 0 {
+                note(&mut track, time, len, key, 100);
+            }
+            time += len;
+        }
+
+        Command::Rest(length) => time += ticks(length, speed),
+
+        Command::NoteType {
+            speed: value,
+            volume,
+            fade,
+        } => {
+            speed = value;
+            velocity = match volume {
+                // a note that starts silent is only heard if it fades in
+                0 if fade >= 0 => 0,
+                _ => ((volume as u32) * 127 / 15).max(1) as u8,
+            };
+        }
+        Command::WaveNoteType {
+            speed: value,
+            volume,
+            ..
+        } => {
+            speed = value;
+            velocity = [0, 127, 64, 32][volume as usize & 0x03];
+        }
+        Command::DrumSpeed(value) => speed = value,
+        Command::Octave(value) => octave = value.min(7),
+        Command::Tempo(value) => tempos.push((time, value)),
+
+        _ => {}
+    });
+
+    (track, time)
+}
+
+/// Length of a note in ticks.
+fn ticks(length: u8, speed: u8) -> u32 {
+    (length as u32 + 1) * (speed as u32)
+}
+
+/// The events of a track, with their delta times.
+struct Track {
+    data: Vec<u8>,
+    /// Time of the last event, in ticks.
+    time: u32,
+}
+
+impl Track {
+    fn new() -> Track {
+        Track {
+            data: Vec::new(),
+            time: 0,
+        }
+    }
+
+    /// Add an event at the given time, which can't be before the last event.
+    fn event(&mut self, time: u32, event: &[u8]) {
+        write_var_len(&mut self.data, time - self.time);
+        self.data.extend_from_slice(event);
+        self.time = time;
+    }
+
+    /// Write the track chunk, ending the track at the given time, or at the last event if that is
+    /// later.
+    fn write(mut self, end: u32, out: &mut Vec<u8>) {
+        self.event(end.max(self.time), &[0xff, 0x2f, 0x00]);
+
+        out.extend_from_slice(b"MTrk");
+        out.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.data);
+    }
+}
+
+/// Write a variable length quantity, seven bits per byte with the most significant byte first.
+fn write_var_len(out: &mut Vec<u8>, value: u32) {
+    let mut shift = 28;
+    while shift > 0 && (value >> shift) == 0 {
+        shift -= 7;
+    }
+
+    while shift > 0 {
+        out.push(0x80 | ((value >> shift) & 0x7f) as u8);
+        shift -= 7;
+    }
+
+    out.push((value & 0x7f) as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The events of a track chunk as delta times and event bytes, assuming that every event is
+    /// either a three byte channel event or a meta event.
+    fn events(data: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let mut result = Vec::new();
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let mut delta = 0;
+            loop {
+                delta = (delta << 7) | (data[pos] & 0x7f) as u32;
+                pos += 1;
+                if data[pos - 1] & 0x80 == 0 {
+                    break;
+                }
+            }
+
+            let len = match data[pos] {
+                0xff => 3 + data[pos + 2] as usize,
+                _ => 3,
+            };
+            result.push((delta, data[pos..(pos + len)].to_vec()));
+            pos += len;
+        }
+
+        result
+    }
+
+    /// The track chunks of a MIDI file.
+    fn tracks(midi: &[u8]) -> Vec<&[u8]> {
+        let mut result = Vec::new();
+        let mut rest = &midi[14..];
+
+        while !rest.is_empty() {
+            assert_eq!(&rest[..4], b"MTrk");
+            let len = u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            result.push(&rest[8..(8 + len)]);
+            rest = &rest[(8 + len)..];
+        }
+
+        result
+    }
+
+    #[test]
+    fn test_export_midi() {
+        #[rustfmt::skip]
+        let rom = [
+            // music channel 1
+            0x00, 0x03, 0x00,
+            // tempo 0x100, note_type 12, 10, 1, octave 5
+            0xed, 0x01, 0x00, 0xdc, 0xa1, 0xe3,
+            // E 2, G 2, octave 6, C 4, rest 2
+            0x41, 0x71, 0xe2, 0x03, 0xc1,
+            // loop 2 times back to the first note
+            0xfe, 0x02, 0x09, 0x00,
+            0xff,
+        ];
+
+        let midi = export_midi(&rom, 0, 0);
+        assert_eq!(
+            midi[..14],
+            [b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 1, 0, 2, 0, 48]
+        );
+
+        let tracks = tracks(&midi);
+        assert_eq!(tracks.len(), 2);
+
+        // a quarter note is 48 frames long at tempo 0x100
+        let micros = quarter_note_micros(0x100);
+        assert_eq!(micros, 803_649);
+        assert_eq!(
+            events(tracks[0]),
+            [
+                (0, vec![0xff, 0x51, 0x03, 0x0c, 0x43, 0x41]),
+                (0, vec![0xff, 0x2f, 0x00]),
+            ]
+        );
+
+        let notes: Vec<_> = events(tracks[1])
+            .into_iter()
+            .scan(0, |time, (delta, event)| {
+                *time += delta;
+                Some((*time, event))
+            })
+            .filter(|(_, event)| event[0] & 0xf0 == 0x90)
+            .map(|(time, event)| (time, event[1], event[2]))
+            .collect();
+
+        // E6, G6 and C7 with a volume of 10, then the loop again from E7
+        assert_eq!(
+            notes,
+            [
+                (0, 88, 84),
+                (24, 91, 84),
+                (48, 96, 84),
+                (120, 100, 84),
+                (144, 103, 84),
+                (168, 96, 84),
+            ]
+        );
+
+        let events = events(tracks[1]);
+        assert_eq!(events[0], (0, b"\xff\x03\x02P1".to_vec()));
+        assert_eq!(events[2], (24, vec![0x80, 88, 0]));
+        assert_eq!(events.last(), Some(&(24, vec![0xff, 0x2f, 0x00])));
+    }
+
+    #[test]
+    fn test_write_var_len() {
+        for (value, expected) in [
+            (0, vec![0x00]),
+            (0x7f, vec![0x7f]),
+            (0x80, vec![0x81, 0x00]),
+            (0x3fff, vec![0xff, 0x7f]),
+            (0x0fff_ffff, vec![0xff, 0xff, 0xff, 0x7f]),
+        ] {
+            let mut out = Vec::new();
+            write_var_len(&mut out, value);
+            assert_eq!(out, expected);
+        }
+    }
+}
@@ -1,19 +1,144 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
 use std::time::Duration;
 
-use channel::SOURCE_SAMPLE_RATE;
-use sound::Sound;
-
-pub use sound::SoundIterator;
-
+use crate::generation::{ChannelStart, Flow, Generation};
+use crate::mix::Stems;
+use crate::{
+    duration_to_frames, header, save, HeaderError, Metadata, NoteEvent, Pcm64, PcmBuffer,
+    RomLocation, SaveError, SaveOptions, SynthesisOptions, Voice, WavOptions, SAMPLES_PER_FRAME,
+};
+
+pub use batch::render_batch;
+pub use channel::ChannelType;
+pub use command::{Command, EncodeError, EncodeErrorKind, Note, ParseError, ParseErrorKind};
+pub use cry::{lookup_cry, synthesis_cry, Cry, CryError};
+pub use diff::{diff, diff_roms};
+pub use disasm::{disassemble, disassemble_sound, Listing};
+pub use extract::{sound_blob, synthesize_blob};
+pub use midi::export_midi;
+pub use session::RenderSession;
+pub use sound::{ChunkIter, FiniteSoundIterator, Sound, SoundIterator};
+pub use table::{list_sounds, SoundEntry, SoundTable};
+pub use transcription::{
+    transcribe, NoteDuration, Transcription, TranscriptionItem, TranscriptionOptions,
+};
+
+mod batch;
 mod channel;
 mod command;
+mod cry;
+mod diff;
+mod disasm;
+mod extract;
+mod midi;
+mod session;
 mod sound;
+mod table;
+mod transcription;
+mod wave;
+
+/// Error returned by `try_synthesis`.
+///
+/// No public function of the crate panics, whatever the ROM and the arguments contain. Functions
+/// that return a `Result` report malformed input as an error, and the others play or list what
+/// can be read of it, e.g. `synthesis` plays a header that isn't valid as described at
+/// `Sound::new`. Arguments out of range are either reported the same way, e.g. a sample rate of
+/// 0 for `PcmBuffer::new`, or clamped, e.g. a chunk size of 0 for `SoundIterator::chunks`. The
+/// adversarial-input tests in this module throw such input at every entry point.
+///
+/// Running out of memory is the exception, e.g. for a chunk size of `usize::MAX`, which aborts
+/// like any other allocation that is too large.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SynthesisError {
+    Header(HeaderError),
+    Parse(ParseError),
+}
+
+impl fmt::Display for SynthesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SynthesisError::Header(err) => err.fmt(f),
+            SynthesisError::Parse(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SynthesisError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SynthesisError::Header(err) => Some(err),
+            SynthesisError::Parse(err) => Some(err),
+        }
+    }
+}
+
+impl From<HeaderError> for SynthesisError {
+    fn from(err: HeaderError) -> Self {
+        SynthesisError::Header(err)
+    }
+}
+
+impl From<ParseError> for SynthesisError {
+    fn from(err: ParseError) -> Self {
+        SynthesisError::Parse(err)
+    }
+}
+
+/// The command set of the first generation, for the tools shared with the second one.
+pub(crate) struct Gen1;
+
+impl Generation for Gen1 {
+    const NUMBER: u8 = 1;
+
+    type ChannelType = ChannelType;
+    type Command = Command;
+
+    fn channels(
+        rom: &[u8],
+        location: RomLocation,
+    ) -> [(Voice, Option<ChannelStart<ChannelType>>); 4] {
+        Sound::new(rom, location.bank, location.addr)
+            .channels()
+            .map(|(voice, channel)| {
+                (
+                    voice,
+                    channel.map(|channel| (channel.addr, channel.channel)),
+                )
+            })
+    }
+
+    fn parse(rom: &[u8], bank: u8, addr: u16, channel: ChannelType) -> Option<Command> {
+        Command::parse(rom, bank, addr, channel).ok()
+    }
+
+    fn len(cmd: &Command) -> usize {
+        cmd.len()
+    }
+
+    fn flow(cmd: &Command) -> Flow {
+        match *cmd {
+            Command::ExecuteMusic => Flow::Switch,
+            Command::SoundCall(addr) => Flow::Call(addr),
+            Command::Loop { count, addr } => Flow::Loop { count, addr },
+            Command::Return => Flow::Return,
+            _ => Flow::Next,
+        }
+    }
+
+    fn switch_channel(channel: ChannelType) -> ChannelType {
+        channel.to_muisc()
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Pcm<'a> {
     pitch: i8,
     length: u16,
     sound: Sound<'a>,
+    options: SynthesisOptions,
+    metadata: Metadata,
 }
 
 impl<'a> Pcm<'a> {
@@ -22,11 +147,21 @@ impl<'a> Pcm<'a> {
     }
 
     pub fn sample_rate(&self) -> u32 {
-        SOURCE_SAMPLE_RATE as u32
+        self.options.sample_rate()
     }
 
+    /// The length of the sound, `None` if it's infinitely long.
+    ///
+    /// This steps through the commands of every channel a frame at a time, including the fade-out
+    /// at the end, so it is exact. Only the per-frame work of the engine is done: the commands,
+    /// envelopes and pitch sweeps. The duty cycle, wave and noise LFSR aren't clocked for the
+    /// samples of a frame, but it is still a pass over the whole sound, or up to `cut_at`, and
+    /// the result isn't kept between calls.
     pub fn total_duration(&self) -> Option<Duration> {
-        let len = self.sound.pcm(self.pitch, self.length).count();
+        let len = self
+            .sound
+            .pcm::<f32>(self.pitch, self.length, self.options)
+            .count();
 
         if len == usize::MAX {
             None
@@ -38,75 +173,242 @@ impl<'a> Pcm<'a> {
     }
 
     pub fn iter(&self) -> SoundIterator<'a> {
-        self.sound.pcm(self.pitch, self.length)
+        self.sound.pcm(self.pitch, self.length, self.options)
+    }
+
+    /// Iterate over the sound with an exact length, which implements `ExactSizeIterator`.
+    /// Returns `None` if the sound is infinitely long.
+    pub fn iter_exact(&self) -> Option<FiniteSoundIterator<'a>> {
+        self.iter().exact()
+    }
+
+    /// The same sound at another sample rate, e.g. 48000 Hz for playback. Each output sample is
+    /// the average of the source samples it covers, the same way as `SynthesisOptions::preview`,
+    /// which filters out more of the aliasing than interpolating between two source samples.
+    pub fn resample(&self, rate: u32) -> Pcm<'a> {
+        Pcm {
+            options: self.options.preview(rate),
+            ..self.clone()
+        }
+    }
+
+    /// Render the whole sound into a buffer, returns `None` if the sound is infinitely long.
+    pub fn to_buffer(&self) -> Option<PcmBuffer> {
+        self.total_duration()?;
+        Some(PcmBuffer::rendered(
+            self.iter().collect(),
+            self.sample_rate(),
+        ))
+    }
+
+    /// Render the whole sound as 16-bit integers, clamped, scaled and rounded the same way as
+    /// `PcmBuffer::iter_i16`. Returns `None` if the sound is infinitely long.
+    pub fn to_i16(&self) -> Option<Vec<i16>> {
+        Some(self.to_buffer()?.iter_i16().collect())
+    }
+
+    /// Render every channel into its own buffer, at the full sample rate regardless of
+    /// `SynthesisOptions::preview`, see `Stems`. Returns `None` if the sound is infinitely long.
+    pub fn stems(&self) -> Option<Stems> {
+        self.iter().into_stems()
+    }
+
+    /// Iterate over the samples, mixed with `f64` precision.
+    pub fn iter_f64(&self) -> SoundIterator<'a, f64> {
+        self.sound.pcm(self.pitch, self.length, self.options)
+    }
+
+    /// Render the whole sound into a buffer mixed with `f64` precision, returns `None` if the
+    /// sound is infinitely long.
+    pub fn render_f64(&self) -> Option<Pcm64> {
+        self.total_duration()?;
+        Some(PcmBuffer::rendered(
+            self.iter_f64().collect(),
+            self.sample_rate(),
+        ))
+    }
+
+    /// Render the whole sound and write it to a file, in the format given by the extension of
+    /// `path`: `wav` or `raw`, in any case. Samples are written as 16-bit integers.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        self.save_with_options(path, SaveOptions::new())
+    }
+
+    /// Same as `save`, with the given sample rate and bit depth.
+    pub fn save_with_options(
+        &self,
+        path: impl AsRef<Path>,
+        options: SaveOptions,
+    ) -> Result<(), SaveError> {
+        save::save(path.as_ref(), options, |rate| match rate {
+            Some(rate) => self.resample(rate).to_buffer(),
+            None => self.to_buffer(),
+        })
+    }
+
+    /// Write the sound to `w` as a RIFF WAVE file, see `WavOptions`. The samples are streamed
+    /// to `w` as they are rendered, after a first pass that finds the length of the sound. Fails
+    /// with `InvalidInput` if the sound is infinitely long or the bit depth isn't supported.
+    pub fn write_wav(&self, w: impl Write, options: WavOptions) -> io::Result<()> {
+        let len = self.iter().count();
+
+        if len == usize::MAX {
+            let err = SaveError::Infinite;
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+        }
+
+        save::write_wav(w, self.iter(), len, 1, self.sample_rate(), options)
+    }
+
+    /// Start rendering the sound in small chunks, see `RenderSession`.
+    pub fn session(&self) -> RenderSession<'a> {
+        RenderSession::new(self.iter())
+    }
+
+    /// Step through the sound without synthesizing it, returns the first command that a channel
+    /// couldn't parse. Such a channel is silenced instead of panicking, see
+    /// `SoundIterator::error`.
+    pub fn check(&self) -> Result<(), ParseError> {
+        self.iter().check()
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.metadata
+    }
+
+    /// The register writes that the sound engine makes while playing the sound, at most a minute
+    /// of them, see `apu::register_writes`.
+    #[cfg(feature = "apu-ref")]
+    #[doc(hidden)]
+    pub fn register_writes(&self) -> Vec<crate::apu::RegisterWrite> {
+        let states = self
+            .sound
+            .frame_states(self.pitch, self.length, crate::apu::MAX_FRAMES);
+        crate::apu::register_writes(&states)
+    }
+
+    /// The notes played by every channel, sorted by start time, see `NoteEvent`. \
+    /// Infinite loops are followed once, or as set by `LoopBehavior::Iterations`, and notes after
+    /// `cut_at` or `max_duration` are left out.
+    pub fn note_events(&self) -> Vec<NoteEvent> {
+        let loops = self.options.loop_iterations();
+        let mut result = self
+            .sound
+            .note_events_with_loops(self.pitch, self.length, loops);
+
+        if let Some(cut_at) = self.options.end() {
+            let cut_at = (duration_to_frames(cut_at) as usize) * SAMPLES_PER_FRAME;
+
+            result.retain(|event| event.start < cut_at);
+
+            for event in &mut result {
+                event.len = event.len.min(cut_at - event.start);
+            }
+        }
+
+        result
     }
 }
 
 pub fn synthesis(rom: &[u8], bank: u8, addr: u16, pitch: i8, length: u8) -> Pcm {
+    synthesis_with_options(rom, bank, addr, pitch, length, SynthesisOptions::default())
+}
+
+/// Same as `synthesis`, but returns an error if the header isn't valid, see `Sound::try_new`, or
+/// if the sound runs into a command that can't be parsed, see `Pcm::check`.
+pub fn try_synthesis(
+    rom: &[u8],
+    bank: u8,
+    addr: u16,
+    pitch: i8,
+    length: u8,
+) -> Result<Pcm<'_>, SynthesisError> {
+    try_synthesis_with_options(rom, bank, addr, pitch, length, SynthesisOptions::default())
+}
+
+/// Same as `synthesis_with_options`, but returns an error for a sound that can't be played as
+/// it is stored, see `try_synthesis`.
+pub fn try_synthesis_with_options(
+    rom: &[u8],
+    bank: u8,
+    addr: u16,
+    pitch: i8,
+    length: u8,
+    options: SynthesisOptions,
+) -> Result<Pcm<'_>, SynthesisError> {
+    let header = header::try_read(rom, RomLocation::new(bank, addr))?;
+    let pcm = synthesis_from_header(rom, bank, &header, pitch, length, options);
+    pcm.check()?;
+    Ok(pcm)
+}
+
+pub fn synthesis_with_options(
+    rom: &[u8],
+    bank: u8,
+    addr: u16,
+    pitch: i8,
+    length: u8,
+    options: SynthesisOptions,
+) -> Pcm<'_> {
+    let header = header::read(rom, RomLocation::new(bank, addr));
+    synthesis_from_header(rom, bank, &header, pitch, length, options)
+}
+
+/// Same as `synthesis_with_options`, with an already parsed header.
+pub(crate) fn synthesis_from_header<'a>(
+    rom: &'a [u8],
+    bank: u8,
+    header: &[(u8, u16)],
+    pitch: i8,
+    length: u8,
+    options: SynthesisOptions,
+) -> Pcm<'a> {
+    let sound = Sound::from_header(rom, bank, header);
+    synthesis_from_sound(sound, pitch, length, options)
+}
+
+/// Same as `synthesis_with_options`, with an already parsed sound.
+pub(crate) fn synthesis_from_sound(
+    sound: Sound<'_>,
+    pitch: i8,
+    length: u8,
+    options: SynthesisOptions,
+) -> Pcm<'_> {
+    let (pitch, length) = options.apply_variation(pitch as i16, length as u16);
+    let pitch = pitch.clamp(i8::MIN as i16, i8::MAX as i16) as i8;
+    let length = length.min(u8::MAX as u16) as u8;
+
     Pcm {
-        sound: Sound::new(rom, bank, addr),
+        sound,
+        options,
         pitch,
         length: (length as u16) + 0x80,
+        metadata: Metadata {
+            pitch: pitch as i16,
+            length: length as u16,
+        },
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::sound::tests::LOOPING_SQUARE_NOTE;
     use super::*;
+    use crate::fixtures::{self, FIXTURES, TOLERANCE, WAVE_HEADER_LEN};
+    use crate::{detect_rom, RomLocation, Synthesizer, SOURCE_SAMPLE_RATE};
 
+    /// The cry headers in bank 0x02 starts at 0x403c, directly after the 19 noise instrument
+    /// headers, with each cry header being 9 bytes long (three channels).
     const POKEYELLOW: &[u8] = include_bytes!("../../roms/pokeyellow.gbc");
-    const WAVE_HEADER_LEN: usize = 44;
 
     fn convert_to_wav(input: &Pcm) -> Vec<u8> {
         assert_eq!(input.channels(), 1);
 
-        let data: Vec<f32> = input.iter().collect();
-
-        let resample_rate_ratio = input.sample_rate() as f64 / 48000.0;
-        let resampled_length = (data.len() as f64 / resample_rate_ratio).ceil() as usize;
-        let mut output = Vec::with_capacity(WAVE_HEADER_LEN + resampled_length);
-
-        output.extend(b"RIFF");
-        output.extend((resampled_length as u32).to_le_bytes());
-        output.extend(b"WAVEfmt ");
-        output.extend(16u32.to_le_bytes()); // remaining header size
-        output.extend(1u16.to_le_bytes()); // PCM type
-        output.extend(1u16.to_le_bytes()); // channels
-        output.extend(48000u32.to_le_bytes()); // sample rate
-        output.extend(48000u32.to_le_bytes()); // byte rate
-        output.extend(1u16.to_le_bytes()); // block align
-        output.extend(8u16.to_le_bytes()); // bits per sample
-        output.extend(b"data");
-        output.extend((resampled_length as u32).to_le_bytes());
-
-        assert_eq!(output.len(), WAVE_HEADER_LEN);
-
-        fn to_u8(value: f32) -> u8 {
-            (value * 127.0 + 128.0) as u8
-        }
-
-        for resampled_index in 1..resampled_length {
-            let prev_index = (resampled_index as f64 * resample_rate_ratio).floor() as usize;
-            let next_index = (resampled_index as f64 * resample_rate_ratio).ceil() as usize;
-
-            if prev_index == next_index {
-                output.push(to_u8(data[prev_index]));
-                continue;
-            }
-
-            let prev_fraction = resampled_index as f64 * resample_rate_ratio - prev_index as f64;
-            let next_fraction = 1.0 - prev_fraction;
-
-            output.push(to_u8(
-                ((prev_fraction * (data[prev_index] as f64))
-                    + (next_fraction * (data[next_index] as f64))) as f32,
-            ));
-        }
-
-        output
+        fixtures::to_wav(&input.to_buffer().unwrap()).unwrap()
     }
 
     fn assert_wav_almost_equal(actual: &[u8], expected: &[u8]) {
+        let actual = &fixtures::to_reference_layout(actual, expected)[..];
         assert_eq!(actual.len(), expected.len());
 
         assert_eq!(&actual[..WAVE_HEADER_LEN], &expected[..WAVE_HEADER_LEN],);
@@ -118,14 +420,109 @@ mod tests {
             .enumerate()
         {
             assert!(
-                (*actual as i32 - *expected as i32).abs() <= 1,
+                (*actual as i32 - *expected as i32).abs() <= TOLERANCE,
                 "actual: {actual}, expected: {expected}, at index: {index}",
             );
         }
     }
 
+    #[test]
+    fn test_infinite_sound() {
+        let pcm = synthesis(&LOOPING_SQUARE_NOTE, 0, 0, 0, 0);
+        assert_eq!(pcm.total_duration(), None);
+        assert!(pcm.to_buffer().is_none());
+        assert!(pcm.to_i16().is_none());
+        assert!(pcm.stems().is_none());
+
+        let options = SynthesisOptions::new().cut_at(Some(Duration::from_secs(1)));
+        let pcm = synthesis_with_options(&LOOPING_SQUARE_NOTE, 0, 0, 0, 0, options);
+        let buffer = pcm.to_buffer().unwrap();
+        let samples = pcm.to_i16().unwrap();
+        let stems = pcm.stems().unwrap();
+        assert_eq!(stems.pulse1.unwrap().frames(), buffer.frames());
+        assert_eq!(samples.len(), buffer.frames());
+        assert!(samples.iter().any(|&sample| sample > 0));
+        assert_eq!(
+            buffer.frames(),
+            duration_to_frames(Duration::from_secs(1)) as usize * SAMPLES_PER_FRAME
+        );
+    }
+
+    #[test]
+    fn test_resample() {
+        // a single pulse channel with an 8 frame long square_note at volume 0, which is a
+        // constant level out of the DAC
+        let rom = [0x04, 0x03, 0x00, 0x2f, 0x00, 0x00, 0x07, 0xff];
+
+        let options = SynthesisOptions::new().quantize_dac(true);
+        let pcm = synthesis_with_options(&rom, 0, 0, 0, 0, options);
+        let len = pcm.iter().count();
+        assert_eq!(len, 8 * SAMPLES_PER_FRAME);
+
+        let resampled = pcm.resample(48000);
+        assert_eq!(resampled.sample_rate(), 48000);
+
+        let samples = resampled.to_buffer().unwrap();
+        let expected_len = (len * 48000 + SOURCE_SAMPLE_RATE - 1) / SOURCE_SAMPLE_RATE;
+        assert_eq!(samples.frames(), expected_len);
+
+        // a constant input stays constant
+        let first = samples.data()[0];
+        assert!(first > 0.1);
+        assert!(samples.iter().all(|sample| (sample - first).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_truncated_rom() {
+        let rom = &POKEYELLOW[..0x8002];
+
+        // the first header of bank 2 is cut off, and plays as silence
+        let pcm = synthesis(rom, 0x02, 0x4000, 0, 0);
+        assert_eq!(pcm.total_duration(), Some(Duration::ZERO));
+
+        let err = try_synthesis(rom, 0x02, 0x4000, 0, 0).unwrap_err();
+        assert_eq!(err, SynthesisError::Header(HeaderError::OutOfBounds));
+
+        // a bank past the end of the ROM
+        let pcm = synthesis(rom, 0x20, 0x4000, 0, 0);
+        assert_eq!(pcm.total_duration(), Some(Duration::ZERO));
+
+        // a square note at the very end of bank 1, with its frequency cut off
+        let mut rom = rom.to_vec();
+        rom[0x7ff0..0x7ff3].copy_from_slice(&[0x04, 0xff, 0x7f]);
+        rom[0x7fff] = 0x2f;
+
+        match try_synthesis(&rom, 0x01, 0x7ff0, 0, 0) {
+            Err(SynthesisError::Parse(err)) => {
+                assert_eq!(err.kind, ParseErrorKind::OutOfBounds);
+                assert_eq!(err.addr, 0x7fff);
+            }
+            result => panic!("Unexpected result: {:?}", result.map(|pcm| pcm.metadata())),
+        }
+    }
+
+    #[test]
+    fn test_cry_lengths() {
+        // the length is stepped through without synthesizing, and matches what is rendered
+        for fixture in FIXTURES.iter().filter(|fixture| fixture.generation == 1) {
+            let pcm = synthesis(
+                POKEYELLOW,
+                fixture.bank,
+                fixture.addr,
+                fixture.pitch as i8,
+                fixture.length as u8,
+            );
+            let rendered = pcm.iter().collect::<Vec<f32>>().len();
+
+            let size_hint = pcm.iter().size_hint();
+            assert_eq!(size_hint, (rendered, Some(rendered)), "{}", fixture.id());
+            assert_eq!(pcm.iter().count(), rendered, "{}", fixture.id());
+        }
+    }
+
     #[test]
     fn test_bulbasaur_cry() {
+        // SFX_Cry0F
         let pcm = synthesis(POKEYELLOW, 0x02, 0x40c3, -128, 1);
 
         assert_wav_almost_equal(
@@ -134,8 +531,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_synthesis_cry() {
+        let pcm = synthesis_cry(POKEYELLOW, 1).unwrap();
+
+        assert_wav_almost_equal(
+            &convert_to_wav(&pcm),
+            include_bytes!("../../expected/bulbasaur-cry.wav"),
+        );
+    }
+
     #[test]
     fn test_diglett_cry() {
+        // SFX_Cry0B
         let pcm = synthesis(POKEYELLOW, 0x02, 0x409f, -86, 1);
 
         assert_wav_almost_equal(
@@ -146,6 +554,7 @@ mod tests {
 
     #[test]
     fn test_jigglypuff_cry() {
+        // SFX_Cry0E
         let pcm = synthesis(POKEYELLOW, 0x02, 0x40ba, -1, 53);
 
         assert_wav_almost_equal(
@@ -156,6 +565,7 @@ mod tests {
 
     #[test]
     fn test_snorlax_cry() {
+        // SFX_Cry05
         let pcm = synthesis(POKEYELLOW, 0x02, 0x4069, 85, 1);
 
         assert_wav_almost_equal(
@@ -166,6 +576,7 @@ mod tests {
 
     #[test]
     fn test_aerodactyl_cry() {
+        // SFX_Cry23
         let pcm = synthesis(POKEYELLOW, 0x02, 0x4177, 32, 240);
 
         assert_wav_almost_equal(
@@ -176,6 +587,7 @@ mod tests {
 
     #[test]
     fn test_pikachu_cry() {
+        // SFX_Cry0F
         let pcm = synthesis(POKEYELLOW, 0x02, 0x40c3, -18, 1);
 
         assert_wav_almost_equal(
@@ -186,6 +598,7 @@ mod tests {
 
     #[test]
     fn test_slowpoke_cry() {
+        // SFX_Cry02
         let pcm = synthesis(POKEYELLOW, 0x02, 0x404e, 0, 128);
 
         assert_wav_almost_equal(
@@ -193,4 +606,250 @@ mod tests {
             include_bytes!("../../expected/slowpoke-cry.wav"),
         );
     }
+
+    #[test]
+    fn test_cry_variation() {
+        let render = |options| {
+            let pcm = synthesis_with_options(POKEYELLOW, 0x02, 0x40c3, -128, 1, options);
+            (pcm.metadata(), pcm.iter().collect::<Vec<f32>>())
+        };
+
+        let (metadata, original) = render(SynthesisOptions::new());
+        assert_eq!(
+            metadata,
+            Metadata {
+                pitch: -128,
+                length: 1
+            }
+        );
+        assert_eq!(
+            render(SynthesisOptions::new().variation(0, 8, 8)).1,
+            original
+        );
+        assert_eq!(
+            render(SynthesisOptions::new().variation(42, 0, 0)).1,
+            original
+        );
+
+        let a = render(SynthesisOptions::new().variation(1, 8, 8));
+        let b = render(SynthesisOptions::new().variation(2, 8, 8));
+        assert_ne!(a, b);
+        assert_eq!(render(SynthesisOptions::new().variation(1, 8, 8)), a);
+    }
+
+    #[test]
+    fn test_no_analog_drift() {
+        let options = SynthesisOptions::new().analog_drift(0.0);
+        let pcm = synthesis_with_options(POKEYELLOW, 0x02, 0x40c3, -128, 1, options);
+
+        assert_wav_almost_equal(
+            &convert_to_wav(&pcm),
+            include_bytes!("../../expected/bulbasaur-cry.wav"),
+        );
+    }
+
+    #[test]
+    fn test_sound_table() {
+        let table = SoundTable::read(POKEYELLOW, 0x02);
+
+        assert_eq!(table.get(0x14), Some(RomLocation::new(0x02, 0x403c)));
+        assert_eq!(table.get(0x23), Some(RomLocation::new(0x02, 0x4069)));
+        assert_eq!(table.get(0x35), Some(RomLocation::new(0x02, 0x409f)));
+        assert_eq!(table.get(0x3e), Some(RomLocation::new(0x02, 0x40ba)));
+        assert_eq!(table.get(0x41), Some(RomLocation::new(0x02, 0x40c3)));
+        assert_eq!(table.get(0x15), None);
+    }
+
+    #[test]
+    fn test_detect_rom() {
+        let info = crate::detect_rom(POKEYELLOW).unwrap();
+
+        assert_eq!(info.game, Some(crate::Game::Yellow));
+        assert_eq!(info.title, "POKEMON YELLOW");
+        assert!(info.cgb);
+        assert_eq!(info.rom_size, POKEYELLOW.len());
+        assert!(crate::detect_rom(&POKEYELLOW[..0x140]).is_err());
+    }
+
+    /// Red and Blue aren't bundled with the tests, set `POKERED_ROM` to the path of either of
+    /// them to run this test.
+    #[test]
+    fn test_red_blue_cry() {
+        let Some(path) = std::env::var_os("POKERED_ROM") else {
+            return;
+        };
+
+        let rom = std::fs::read(path).unwrap();
+        let game = crate::detect_rom(&rom).unwrap().game;
+        assert!(matches!(game, Some(crate::Game::Red | crate::Game::Blue)));
+
+        // Bulbasaur, with the same cry as in Yellow
+        let cry = lookup_cry(&rom, 1).unwrap();
+        assert_eq!(cry.location, RomLocation::new(0x02, 0x40c3));
+
+        let duration = synthesis_cry(&rom, 1).unwrap().total_duration().unwrap();
+        assert!(duration > Duration::from_millis(200), "{duration:?}");
+        assert!(duration < Duration::from_secs(3), "{duration:?}");
+    }
+
+    #[test]
+    fn test_list_sounds() {
+        let sounds = list_sounds(POKEYELLOW, 0x02).unwrap();
+        assert_eq!(sounds.len(), SoundTable::read(POKEYELLOW, 0x02).len());
+
+        // SFX_Cry00 and SFX_Cry0F
+        for (index, addr) in [(0x14, 0x403c), (0x41, 0x40c3)] {
+            let entry = sounds.iter().find(|entry| entry.index == index).unwrap();
+            assert_eq!(entry.addr, addr);
+            assert_eq!(entry.channel_count, 3);
+            assert_eq!(entry.channels.len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_audacity_labels() {
+        let pcm = synthesis(POKEYELLOW, 0x02, 0x40c3, -128, 1);
+        let events = pcm.note_events();
+
+        let mut output = Vec::new();
+        crate::labels::write_audacity_labels(&mut output, &events, 48000, None).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(output.lines().count(), events.len());
+
+        let pulse1 = output.lines().find(|line| line.contains("\tP1: ")).unwrap();
+        assert!(pulse1.starts_with("0.000000\t"));
+    }
+
+    #[test]
+    fn test_sound_blob() {
+        let blob = sound_blob(POKEYELLOW, RomLocation::new(0x02, 0x40c3)).unwrap();
+        let pcm = synthesize_blob(&blob, -128, 1).unwrap();
+
+        assert_wav_almost_equal(
+            &convert_to_wav(&pcm),
+            include_bytes!("../../expected/bulbasaur-cry.wav"),
+        );
+    }
+
+    #[test]
+    fn test_preview() {
+        let full = synthesis(POKEYELLOW, 0x02, 0x40c3, -128, 1);
+        let full: Vec<f32> = full.iter().collect();
+
+        let options = SynthesisOptions::new().preview(8000);
+        let pcm = synthesis_with_options(POKEYELLOW, 0x02, 0x40c3, -128, 1, options);
+        let preview: Vec<f32> = pcm.iter().collect();
+
+        assert_eq!(pcm.sample_rate(), 8000);
+        assert_eq!(
+            preview.len(),
+            (full.len() * 8000 + SOURCE_SAMPLE_RATE - 1) / SOURCE_SAMPLE_RATE
+        );
+
+        // downsample the full quality render independently, and check that they match up
+        let downsampled: Vec<f64> = (0..preview.len())
+            .map(|i| {
+                let start = i * SOURCE_SAMPLE_RATE / 8000;
+                let end = ((i + 1) * SOURCE_SAMPLE_RATE / 8000).min(full.len());
+                full[start..end].iter().map(|&x| x as f64).sum::<f64>() / ((end - start) as f64)
+            })
+            .collect();
+
+        let mean = |data: &[f64]| data.iter().sum::<f64>() / (data.len() as f64);
+        let preview: Vec<f64> = preview.iter().map(|&x| x as f64).collect();
+        let (mean_a, mean_b) = (mean(&preview), mean(&downsampled));
+
+        let mut covariance = 0.0;
+        let mut variance_a = 0.0;
+        let mut variance_b = 0.0;
+
+        for (a, b) in preview.iter().zip(&downsampled) {
+            covariance += (a - mean_a) * (b - mean_b);
+            variance_a += (a - mean_a).powi(2);
+            variance_b += (b - mean_b).powi(2);
+        }
+
+        assert!(covariance / (variance_a * variance_b).sqrt() > 0.9);
+    }
+
+    /// ROMs that are malformed in the ways that every entry point has to handle, with the
+    /// location of the sound to read from them.
+    fn adversarial_roms() -> Vec<(Vec<u8>, RomLocation)> {
+        // a header with four channels at the last byte of bank 1
+        let mut last_byte = vec![0; 0x8000];
+        last_byte[0x7fff] = 0xc4;
+
+        vec![
+            (Vec::new(), RomLocation::new(0x00, 0x0000)),
+            (vec![0x00], RomLocation::new(0x00, 0x0000)),
+            (vec![0xff; 0x8000], RomLocation::new(0x01, 0x4000)),
+            (vec![0x00; 0x8000], RomLocation::new(0x01, 0x4000)),
+            (last_byte, RomLocation::new(0x01, 0x7fff)),
+            (vec![0xff; 0x8000], RomLocation::new(0xff, 0xffff)),
+        ]
+    }
+
+    #[test]
+    fn test_adversarial_synthesis() {
+        for (rom, RomLocation { bank, addr }) in adversarial_roms() {
+            assert!(try_synthesis(&rom, bank, addr, i8::MAX, u8::MAX).is_err());
+
+            // the lenient entry points play what can be played of the sound, which is nothing
+            let pcm = synthesis(&rom, bank, addr, i8::MIN, u8::MAX);
+            assert!(pcm.check().is_err() || pcm.iter().all(|sample| sample == 0.0));
+
+            let pitches = [i8::MIN, i8::MAX];
+            let options = SynthesisOptions::default();
+            render_batch(&rom, bank, addr, &pitches, u8::MAX, options);
+        }
+    }
+
+    #[test]
+    fn test_adversarial_tools() {
+        for (rom, location) in adversarial_roms() {
+            let RomLocation { bank, addr } = location;
+
+            assert!(detect_rom(&rom).is_err());
+            assert!(Synthesizer::new(rom.clone()).is_err());
+            assert!(sound_blob(&rom, location).is_err());
+            assert!(lookup_cry(&rom, u8::MAX).is_err());
+            assert!(synthesis_cry(&rom, u8::MAX).is_err());
+
+            // these list what can be read of the sound, and only have to return
+            let _ = list_sounds(&rom, bank);
+            SoundTable::read(&rom, bank);
+            let _ = disassemble(&rom, bank, addr, ChannelType::SfxPulse);
+            let _ = disassemble_sound(&rom, bank, addr);
+            diff_roms(&rom, &rom, location);
+            transcribe(&rom, bank, addr, TranscriptionOptions::default());
+            export_midi(&rom, bank, addr);
+        }
+    }
+
+    #[test]
+    fn test_adversarial_arguments() {
+        // a single pulse channel with a 16 frame long square_note
+        let rom = [0x04, 0x03, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0xff];
+        let pcm = synthesis(&rom, 0, 0, 0, 0);
+        let len = pcm.iter().count();
+
+        let chunk = pcm.iter().chunks(0).next().map(|chunk| chunk.len());
+        assert_eq!(chunk, Some(1));
+
+        let mut session = pcm.session();
+        for ms in [f32::INFINITY, f32::NAN, -1.0] {
+            assert!(session.render_ms(ms).is_empty());
+        }
+        assert_eq!(session.render_ms(f32::MAX).len(), len);
+
+        let err = PcmBuffer::new(vec![0.0; len], 0);
+        assert_eq!(err, Err(crate::BufferError::NoSampleRate));
+
+        for rate in [0, u32::MAX] {
+            let options = WavOptions::new().sample_rate(rate);
+            let err = pcm.write_wav(io::sink(), options).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
 }
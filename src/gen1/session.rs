@@ -0,0 +1,45 @@
+use super::SoundIterator;
+
+/// Renders a sound a few milliseconds at a time, created with `Pcm::session`.
+pub type RenderSession<'a> = crate::session::RenderSession<SoundIterator<'a>>;
+
+#[cfg(test)]
+mod tests {
+    use super::super::synthesis;
+
+    #[test]
+    fn test_render_ms() {
+        // two pulse channels
+        let rom = [
+            0x44, 0x06, 0x00, 0x05, 0x0f, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0x2a, 0xa2, 0x80, 0x06,
+            0xff, 0x24, 0xf3, 0x40, 0x07, 0xff,
+        ];
+        let pcm = synthesis(&rom, 0, 0, 0, 0);
+
+        let expected: Vec<f32> = pcm.iter().collect();
+        let mut session = pcm.session();
+        let mut result = Vec::new();
+
+        assert_eq!(session.progress(), Some(0.0));
+
+        while !session.is_done() {
+            result.extend_from_slice(session.render_ms(10.0));
+        }
+
+        assert_eq!(result, expected);
+        assert_eq!(session.progress(), Some(1.0));
+        assert!(session.render_ms(10.0).is_empty());
+
+        // a request past the end is cut short without allocating for all of it
+        let mut session = pcm.session();
+        assert_eq!(session.render_ms(1e30), expected);
+        assert!(session.is_done());
+
+        let mut session = pcm.session();
+        for ms in [f32::INFINITY, f32::NAN, -10.0] {
+            assert!(session.render_ms(ms).is_empty());
+        }
+        assert!(!session.is_done());
+        assert_eq!(session.progress(), Some(0.0));
+    }
+}
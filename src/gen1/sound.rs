@@ -1,7 +1,18 @@
-use super::channel::{
-    Channel, ChannelIterator, ChannelType, SAMPLES_PER_FRAME, SOURCE_SAMPLE_RATE,
+use crate::{
+    duration_to_frames, header, ChannelMask, HeaderError, NoteEvent, RomLocation, Sample,
+    SynthesisOptions, Voice, SAMPLES_PER_FRAME, SOURCE_SAMPLE_RATE,
 };
 
+use super::channel::{Channel, ChannelIterator, ChannelType};
+use super::command::ParseError;
+#[cfg(test)]
+use super::command::ParseErrorKind;
+#[cfg(feature = "apu-ref")]
+use crate::apu::FrameState;
+use crate::generation::Render;
+use crate::mix::Stems;
+use crate::timing::LengthCache;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Sound<'a> {
     pulse1: Option<Channel<'a>>,
@@ -11,7 +22,23 @@ pub struct Sound<'a> {
 }
 
 impl<'a> Sound<'a> {
+    /// Create the sound with its header at the given address.
+    ///
+    /// A header that isn't valid plays what can be played of it: a header past the end of the
+    /// ROM plays as silence, entries with a channel ID above 8 are skipped, and a hardware
+    /// channel that is listed twice plays its last entry. Use `try_new` to reject such headers.
     pub fn new(rom: &'a [u8], bank: u8, addr: u16) -> Sound<'a> {
+        Sound::from_header(rom, bank, &header::read(rom, RomLocation::new(bank, addr)))
+    }
+
+    /// Same as `new`, but returns an error if the header isn't a valid sound header.
+    pub fn try_new(rom: &'a [u8], bank: u8, addr: u16) -> Result<Sound<'a>, HeaderError> {
+        let header = header::try_read(rom, RomLocation::new(bank, addr))?;
+        Ok(Sound::from_header(rom, bank, &header))
+    }
+
+    /// Create a sound from an already parsed header, see `new` for headers that aren't valid.
+    pub(crate) fn from_header(rom: &'a [u8], bank: u8, header: &[(u8, u16)]) -> Sound<'a> {
         let mut result = Sound {
             pulse1: None,
             pulse2: None,
@@ -19,82 +46,211 @@ impl<'a> Sound<'a> {
             noise: None,
         };
 
-        let mut pos = ((bank as usize) * 0x4000) + ((addr as usize) & 0x3fff);
-        let channel_count = (rom[pos] >> 6) + 1;
-
-        for _ in 0..channel_count {
-            let id = (rom[pos] & 0xf) + 1;
-            pos += 1;
-
-            let ptr = u16::from_le_bytes([rom[pos], rom[pos + 1]]);
-            pos += 2;
-
+        for &(id, ptr) in header {
             match id {
-                1 => assert!(result
-                    .pulse1
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::MusicPulse))
-                    .is_none()),
-                2 => assert!(result
-                    .pulse2
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::MusicPulse))
-                    .is_none()),
-                3 => assert!(result
-                    .wave
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::MusicWave))
-                    .is_none()),
-                4 => assert!(result
-                    .noise
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::MusicNoise))
-                    .is_none()),
-                5 => assert!(result
-                    .pulse1
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::SfxPulse))
-                    .is_none()),
-                6 => assert!(result
-                    .pulse2
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::SfxPulse))
-                    .is_none()),
-                7 => assert!(result
-                    .wave
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::SfxWave))
-                    .is_none()),
-                8 => assert!(result
-                    .noise
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::SfxNoise))
-                    .is_none()),
-                _ => panic!("Invalid SFX channel: {}", id),
+                1 => result.pulse1 = Some(Channel::new(rom, bank, ptr, ChannelType::MusicPulse)),
+                2 => result.pulse2 = Some(Channel::new(rom, bank, ptr, ChannelType::MusicPulse)),
+                3 => result.wave = Some(Channel::new(rom, bank, ptr, ChannelType::MusicWave)),
+                4 => result.noise = Some(Channel::new(rom, bank, ptr, ChannelType::MusicNoise)),
+                5 => result.pulse1 = Some(Channel::new(rom, bank, ptr, ChannelType::SfxPulse)),
+                6 => result.pulse2 = Some(Channel::new(rom, bank, ptr, ChannelType::SfxPulse)),
+                7 => result.wave = Some(Channel::new(rom, bank, ptr, ChannelType::SfxWave)),
+                8 => result.noise = Some(Channel::new(rom, bank, ptr, ChannelType::SfxNoise)),
+                _ => {}
             }
         }
 
         result
     }
 
-    pub fn pcm(self, pitch: i8, length: u16) -> SoundIterator<'a> {
-        SoundIterator::new(self, pitch, length)
+    pub fn pcm<S: Sample>(
+        self,
+        pitch: i8,
+        length: u16,
+        options: SynthesisOptions,
+    ) -> SoundIterator<'a, S> {
+        SoundIterator::new(self, pitch, length, options)
+    }
+
+    /// Render the whole sound, synthesizing every channel on a thread of its own and mixing them
+    /// afterwards. The result is identical to collecting `pcm`. Returns `None` if the sound is
+    /// infinitely long.
+    ///
+    /// The channels affect each other through the tempo, the master volume and the pitch reset of
+    /// the noise channel, so these are worked out first, by stepping through the commands of all
+    /// channels together without synthesizing anything. Previews are rendered on the current
+    /// thread.
+    #[cfg(feature = "parallel")]
+    pub fn render(self, pitch: i8, length: u16, options: SynthesisOptions) -> Option<Vec<f32>> {
+        let mut iter = self.pcm::<f32>(pitch, length, options);
+
+        match options.preview {
+            Some(_) => (iter.clone().count() != usize::MAX).then(|| iter.collect()),
+            None => iter.render_parallel(),
+        }
+    }
+
+    pub(super) fn channels(&self) -> [(Voice, Option<Channel<'a>>); 4] {
+        [
+            (Voice::Pulse1, self.pulse1),
+            (Voice::Pulse2, self.pulse2),
+            (Voice::Wave, self.wave),
+            (Voice::Noise, self.noise),
+        ]
+    }
+
+    /// What the engine does to each channel every frame, for at most `max_frames` frames. The
+    /// wave channel is left out, since it can't be synthesized yet.
+    #[cfg(feature = "apu-ref")]
+    pub(crate) fn frame_states(
+        self,
+        pitch: i8,
+        length: u16,
+        max_frames: usize,
+    ) -> [Option<Vec<FrameState>>; 4] {
+        let channels = [
+            self.pulse1.map(|c| c.pcm(pitch, length)),
+            self.pulse2.map(|c| c.pcm(pitch, length)),
+            None,
+            self.noise.map(|c| c.pcm(pitch, 0x100)),
+        ];
+
+        channels.map(|channel| {
+            let mut channel = channel?;
+            Some(
+                std::iter::from_fn(|| channel.frame_state())
+                    .take(max_frames)
+                    .collect(),
+            )
+        })
+    }
+
+    /// The notes played by every channel, sorted by start time.
+    pub fn note_events(self, pitch: i8, length: u16) -> Vec<NoteEvent> {
+        self.note_events_with_loops(pitch, length, None)
+    }
+
+    /// Same as `note_events`, with the infinite loops played the given number of times, see
+    /// `ChannelIterator::loop_iterations`.
+    pub(super) fn note_events_with_loops(
+        self,
+        pitch: i8,
+        length: u16,
+        loops: Option<u32>,
+    ) -> Vec<NoteEvent> {
+        let channels = [
+            (
+                Voice::Pulse1,
+                self.pulse1
+                    .map(|c| c.pcm(pitch, length).loop_iterations(loops)),
+            ),
+            (
+                Voice::Pulse2,
+                self.pulse2
+                    .map(|c| c.pcm(pitch, length).loop_iterations(loops)),
+            ),
+            (
+                Voice::Wave,
+                self.wave
+                    .map(|c| c.pcm(pitch, length).loop_iterations(loops)),
+            ),
+            (
+                Voice::Noise,
+                self.noise
+                    .map(|c| c.pcm(pitch, 0x100).loop_iterations(loops)),
+            ),
+        ];
+
+        let mut result: Vec<NoteEvent> = channels
+            .into_iter()
+            .filter_map(|(voice, channel)| Some(channel?.note_events(voice)))
+            .flatten()
+            .collect();
+
+        result.sort_by_key(|event| (event.start, event.voice));
+        result
     }
 }
 
+/// The tempo that was passed on to the other channels after each of pulse1, pulse2 and wave,
+/// see `SoundIterator::share_state`.
+type SharedTempos = [Option<(ChannelType, u16)>; 3];
+
 #[derive(Debug, Clone)]
-pub struct SoundIterator<'a> {
+pub struct SoundIterator<'a, S = f32> {
     pulse1: Option<ChannelIterator<'a>>,
     pulse2: Option<ChannelIterator<'a>>,
     wave: Option<ChannelIterator<'a>>,
     noise: Option<ChannelIterator<'a>>,
     index: usize,
-    buffer: [f32; SAMPLES_PER_FRAME],
+    cut_at: Option<usize>,
+    buffer: [S; SAMPLES_PER_FRAME],
+    /// Channels that are mixed into the buffer, the others are only advanced.
+    mask: ChannelMask,
+    preview: Option<u32>,
+    /// Number of samples returned so far when rendering a preview.
+    preview_index: usize,
     pitch_has_been_reset: bool,
+    /// Left and right master volume, from 0 to 7, as set by the `Volume` command.
+    master_volume: (u8, u8),
+    /// Length of the whole sound at the output sample rate, see `remaining_len`.
+    total_len: LengthCache,
 }
 
-impl<'a> SoundIterator<'a> {
-    pub fn new(sound: Sound<'a>, pitch: i8, length: u16) -> SoundIterator<'a> {
+impl<'a, S: Sample> SoundIterator<'a, S> {
+    pub fn new(
+        sound: Sound<'a>,
+        pitch: i8,
+        length: u16,
+        options: SynthesisOptions,
+    ) -> SoundIterator<'a, S> {
+        let detune = options.analog_drift / 2.0;
+        let limit = options.max_commands();
+        let loops = options.loop_iterations();
+        let dac = options.quantize_dac;
+        let [pulse1_phase, pulse2_phase, wave_phase, _] = options.channel_phase;
+
         SoundIterator {
-            pulse1: sound.pulse1.as_ref().map(|c| c.pcm(pitch, length)),
-            pulse2: sound.pulse2.as_ref().map(|c| c.pcm(pitch, length)),
-            wave: sound.wave.as_ref().map(|c| c.pcm(pitch, length)),
-            noise: sound.noise.as_ref().map(|c| c.pcm(pitch, 0x100)),
+            pulse1: sound.pulse1.as_ref().map(|c| {
+                c.pcm(pitch, length)
+                    .detune(detune)
+                    .phase(pulse1_phase)
+                    .command_limit(limit)
+                    .loop_iterations(loops)
+                    .quantize_dac(dac)
+            }),
+            pulse2: sound.pulse2.as_ref().map(|c| {
+                c.pcm(pitch, length)
+                    .detune(-detune)
+                    .phase(pulse2_phase)
+                    .command_limit(limit)
+                    .loop_iterations(loops)
+                    .quantize_dac(dac)
+            }),
+            wave: sound.wave.as_ref().map(|c| {
+                c.pcm(pitch, length)
+                    .phase(wave_phase)
+                    .command_limit(limit)
+                    .loop_iterations(loops)
+                    .quantize_dac(dac)
+            }),
+            noise: sound.noise.as_ref().map(|c| {
+                c.pcm(pitch, 0x100)
+                    .command_limit(limit)
+                    .loop_iterations(loops)
+                    .quantize_dac(dac)
+            }),
             index: 0,
-            buffer: [0.0; SAMPLES_PER_FRAME],
+            cut_at: options
+                .end()
+                .map(|cut_at| (duration_to_frames(cut_at) as usize) * SAMPLES_PER_FRAME),
+            buffer: [S::default(); SAMPLES_PER_FRAME],
+            mask: options.channel_mask,
+            preview: options.preview,
+            preview_index: 0,
             pitch_has_been_reset: false,
+            master_volume: (7, 7),
+            total_len: LengthCache::default(),
         }
     }
 
@@ -103,22 +259,215 @@ impl<'a> SoundIterator<'a> {
     }
 
     pub fn sample_rate(&self) -> u32 {
-        SOURCE_SAMPLE_RATE as u32
+        self.preview.unwrap_or(SOURCE_SAMPLE_RATE as u32)
     }
-}
 
-impl<'a> Iterator for SoundIterator<'a> {
-    type Item = f32;
+    /// Whether any channel was stopped because it executed too many commands in a row without
+    /// playing anything, see `SynthesisOptions::command_limit`.
+    pub fn is_stuck(&self) -> bool {
+        [&self.pulse1, &self.pulse2, &self.wave, &self.noise]
+            .into_iter()
+            .flatten()
+            .any(|channel| channel.is_stuck())
+    }
+
+    /// The command that stopped a channel because it couldn't be parsed, if any. The channel is
+    /// silent from there on, while the other channels keep playing.
+    pub fn error(&self) -> Option<ParseError> {
+        [&self.pulse1, &self.pulse2, &self.wave, &self.noise]
+            .into_iter()
+            .flatten()
+            .find_map(|channel| channel.error())
+    }
+
+    /// Step through the whole sound without synthesizing it, and return the first command that
+    /// couldn't be parsed. Infinite sounds are only checked until they are known to loop.
+    pub(super) fn check(mut self) -> Result<(), ParseError> {
+        self.source_len();
+        self.error().map_or(Ok(()), Err)
+    }
+
+    /// Fill `out` with the next samples of the sound, copying whole runs out of the frame buffer. \
+    /// Returns the number of samples written, which is only less than `out.len()` at the end of the
+    /// sound.
+    pub fn fill(&mut self, out: &mut [S]) -> usize {
+        if self.preview.is_some() {
+            return out
+                .iter_mut()
+                .zip(self.by_ref())
+                .map(|(out, sample)| *out = sample)
+                .count();
+        }
+
+        let mut written = 0;
+
+        while written < out.len() {
+            let offset = self.index % SAMPLES_PER_FRAME;
+            let mut available = SAMPLES_PER_FRAME - offset;
+
+            if let Some(cut_at) = self.cut_at {
+                available = available.min(cut_at.saturating_sub(self.index));
+            }
+
+            if available == 0 || (offset == 0 && !self.render_frame()) {
+                break;
+            }
+
+            let count = available.min(out.len() - written);
+            out[written..(written + count)].copy_from_slice(&self.buffer[offset..(offset + count)]);
+            written += count;
+            self.index += count;
+        }
+
+        written
+    }
+
+    /// Render the next frame and return all of its samples, or `None` at the end of the sound.
+    ///
+    /// This is the cheapest way of pulling the whole sound, since the samples are handed out
+    /// straight from the frame buffer. The rest of a frame that was partly read with `next` or
+    /// `fill` is skipped. Previews have no frames at their sample rate, and always return `None`.
+    pub fn next_frame(&mut self) -> Option<&[S; SAMPLES_PER_FRAME]> {
+        if self.preview.is_some() {
+            return None;
+        }
+
+        let offset = self.index % SAMPLES_PER_FRAME;
+        if offset != 0 {
+            self.index += SAMPLES_PER_FRAME - offset;
+        }
+
+        if self.cut_at == Some(self.index) || !self.render_frame() {
+            return None;
+        }
+
+        self.index += SAMPLES_PER_FRAME;
+        Some(&self.buffer)
+    }
+
+    /// Skip the next `n` samples, returns the number of samples skipped, which is only less than
+    /// `n` at the end of the sound.
+    ///
+    /// Whole frames are skipped without synthesizing them, while still stepping every channel
+    /// through exactly the same state, so the samples after the skip are the same as when
+    /// calling `next` `n` times. Previews are skipped sample by sample.
+    pub fn skip_samples(&mut self, n: usize) -> usize {
+        if self.preview.is_some() {
+            return self.by_ref().take(n).count();
+        }
+
+        let mut skipped = 0;
+
+        while skipped < n {
+            let offset = self.index % SAMPLES_PER_FRAME;
+            let mut available = SAMPLES_PER_FRAME - offset;
+
+            if let Some(cut_at) = self.cut_at {
+                available = available.min(cut_at.saturating_sub(self.index));
+            }
+
+            if available == 0 {
+                break;
+            }
+
+            let count = available.min(n - skipped);
+
+            // only a frame that is partly skipped has to be synthesized
+            if offset == 0 {
+                let playing = match count == SAMPLES_PER_FRAME {
+                    true => self.advance_frame(),
+                    false => self.render_frame(),
+                };
 
-    fn count(mut self) -> usize {
+                if !playing {
+                    break;
+                }
+            }
+
+            skipped += count;
+            self.index += count;
+        }
+
+        skipped
+    }
+
+    /// Split the sound into chunks of `size` samples. The last chunk may be shorter. A `size` of 0
+    /// is treated as 1.
+    pub fn chunks(self, size: usize) -> ChunkIter<'a, S> {
+        ChunkIter {
+            iter: self,
+            size: size.max(1),
+        }
+    }
+
+    /// Only iterate over the sound if it has a known length, see `FiniteSoundIterator`. Returns
+    /// `None` if any channel loops forever, and the sound isn't cut short by the options.
+    pub fn exact(self) -> Option<FiniteSoundIterator<'a, S>> {
+        let remaining = self.remaining_len()?;
+        Some(FiniteSoundIterator {
+            iter: self,
+            remaining,
+        })
+    }
+
+    /// Number of samples left at the output sample rate, or `None` if the sound is infinitely
+    /// long.
+    ///
+    /// The length of the whole sound is only stepped through once, and then kept for every copy
+    /// of the iterator. The samples that are left follow from how far it has come.
+    fn remaining_len(&self) -> Option<usize> {
+        let total_len = self.total_len.get_or_init(|| self.total_len())?;
+
+        Some(match self.preview {
+            Some(_) => total_len.saturating_sub(self.preview_index),
+            None => total_len.saturating_sub(self.index),
+        })
+    }
+
+    /// Number of samples of the whole sound at the output sample rate, found by stepping through
+    /// the rest of it, or `None` if it is infinitely long.
+    fn total_len(&self) -> Option<usize> {
+        // the rest of the current frame is already in the buffer
+        let offset = self.index % SAMPLES_PER_FRAME;
+        let buffered = match offset {
+            0 => 0,
+            _ => SAMPLES_PER_FRAME - offset,
+        };
+
+        let mut rest = self.clone();
+        let start = self.index + buffered;
+        rest.cut_at = self.cut_at.map(|cut_at| cut_at.saturating_sub(start));
+
+        let source_len = match rest.source_len() {
+            usize::MAX => return None,
+            len => start + len,
+        };
+
+        match self.preview {
+            Some(rate) => {
+                let len = (source_len as u64) * (rate as u64);
+                let source_rate = SOURCE_SAMPLE_RATE as u64;
+                Some(((len + source_rate - 1) / source_rate) as usize)
+            }
+            None => Some(source_len),
+        }
+    }
+
+    /// Number of samples at `SOURCE_SAMPLE_RATE`, or `usize::MAX` if the sound is infinitely long.
+    fn source_len(&mut self) -> usize {
         let mut result = 0;
+        let infinite_len = self.cut_at.unwrap_or(usize::MAX);
 
         loop {
+            if self.cut_at == Some(result) {
+                return result;
+            }
+
             let mut done = true;
             let mut fadeout = true;
 
             if let Some(pulse1) = &mut self.pulse1 {
-                if pulse1.next().is_some() {
+                if pulse1.skip_frame() {
                     done = false;
 
                     if !pulse1.only_fadeout_left() {
@@ -127,12 +476,14 @@ impl<'a> Iterator for SoundIterator<'a> {
                 }
 
                 if pulse1.is_infinite() == Some(true) {
-                    return usize::MAX;
+                    return infinite_len;
                 }
             }
 
+            self.share_state();
+
             if let Some(pulse2) = &mut self.pulse2 {
-                if pulse2.next().is_some() {
+                if pulse2.skip_frame() {
                     done = false;
 
                     if !pulse2.only_fadeout_left() {
@@ -141,32 +492,36 @@ impl<'a> Iterator for SoundIterator<'a> {
                 }
 
                 if pulse2.is_infinite() == Some(true) {
-                    return usize::MAX;
+                    return infinite_len;
                 }
             }
 
+            self.share_state();
+
             if let Some(wave) = &mut self.wave {
-                if wave.next().is_some() {
+                if wave.skip_frame() {
                     done = false;
                 }
 
                 if wave.is_infinite() == Some(true) {
-                    return usize::MAX;
+                    return infinite_len;
                 }
             }
 
+            self.share_state();
+
             if let Some(noise) = &mut self.noise {
                 if fadeout && !self.pitch_has_been_reset {
                     self.pitch_has_been_reset = true;
                     noise.reset_pitch();
                 }
 
-                if noise.next().is_some() {
+                if noise.skip_frame() {
                     done = false;
                 }
 
                 if noise.is_infinite() == Some(true) {
-                    return usize::MAX;
+                    return infinite_len;
                 }
             }
 
@@ -178,73 +533,1254 @@ impl<'a> Iterator for SoundIterator<'a> {
         }
     }
 
-    fn next(&mut self) -> Option<f32> {
-        if self.index % SAMPLES_PER_FRAME == 0 {
-            self.buffer.fill(0.0);
+    /// Step through the sound without synthesizing it, and record how the channels affect each
+    /// other, see `Sound::render`. Returns `None` if the sound is infinitely long.
+    #[cfg(feature = "parallel")]
+    fn shared_state(&mut self) -> Option<SharedState> {
+        let mut shared = SharedState::default();
 
-            let mut done = true;
-            let mut fadeout = true;
+        while self.cut_at != Some(shared.master_volumes.len() * SAMPLES_PER_FRAME) {
+            let frame = shared.master_volumes.len();
+            let pitch_has_been_reset = self.pitch_has_been_reset;
+            let mut tempos = [None; 3];
+            let frames =
+                self.step_channels(&mut tempos, |channel| channel.skip_frame().then_some(()));
 
-            if let Some(pulse1) = &mut self.pulse1 {
-                if let Some(data) = pulse1.next() {
-                    for (i, data) in data.iter().enumerate() {
-                        self.buffer[i] += data / 3.0;
-                    }
+            if frames.iter().all(Option::is_none) {
+                break;
+            }
 
-                    done = false;
+            let is_infinite = [&self.pulse1, &self.pulse2, &self.wave, &self.noise]
+                .into_iter()
+                .flatten()
+                .any(|channel| channel.is_infinite() == Some(true));
 
-                    if !pulse1.only_fadeout_left() {
-                        fadeout = false;
-                    }
+            if is_infinite && self.cut_at.is_none() {
+                return None;
+            }
+
+            for (after, tempo) in tempos.into_iter().enumerate() {
+                if let Some((channel_type, tempo)) = tempo {
+                    shared.tempos.push((frame, after, channel_type, tempo));
                 }
             }
 
-            if let Some(pulse2) = &mut self.pulse2 {
-                if let Some(data) = pulse2.next() {
-                    for (i, data) in data.iter().enumerate() {
-                        self.buffer[i] += data / 3.0;
-                    }
+            if self.pitch_has_been_reset && !pitch_has_been_reset {
+                shared.pitch_reset = Some(frame);
+            }
 
-                    done = false;
+            shared.master_volumes.push(self.master_volume);
+        }
 
-                    if !pulse2.only_fadeout_left() {
-                        fadeout = false;
-                    }
-                }
+        Some(shared)
+    }
+
+    /// Render every channel on a thread of its own, and mix them, see `Sound::render`. The sound
+    /// must not have started playing. Returns `None` if it is infinitely long.
+    #[cfg(feature = "parallel")]
+    fn render_parallel(&mut self) -> Option<Vec<S>> {
+        let channels = [
+            self.pulse1.clone(),
+            self.pulse2.clone(),
+            self.wave.clone(),
+            self.noise.clone(),
+        ];
+
+        let shared = self.shared_state()?;
+        let shared = &shared;
+
+        let rendered: Vec<Vec<[f32; SAMPLES_PER_FRAME]>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = channels
+                .into_iter()
+                .enumerate()
+                .map(|(index, channel)| {
+                    channel
+                        .map(|channel| scope.spawn(move || render_channel(channel, index, shared)))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| match handle {
+                    Some(handle) => handle
+                        .join()
+                        .unwrap_or_else(|err| std::panic::resume_unwind(err)),
+                    None => Vec::new(),
+                })
+                .collect()
+        });
+
+        let mut result = Vec::with_capacity(shared.master_volumes.len() * SAMPLES_PER_FRAME);
+
+        for (frame, master_volume) in shared.master_volumes.iter().enumerate() {
+            let frames = [0, 1, 2, 3].map(|index| rendered[index].get(frame));
+            mix_frame(&mut self.buffer, frames, self.mask, *master_volume);
+            result.extend_from_slice(&self.buffer);
+        }
+
+        Some(result)
+    }
+
+    /// Pass a tempo set by one channel on to the other channels, and pick up changes to the
+    /// master volume. The engine processes the channels in order, so this is called after each
+    /// of them. Returns the tempo that was passed on, if any.
+    fn share_state(&mut self) -> Option<(ChannelType, u16)> {
+        let mut channels = [
+            &mut self.pulse1,
+            &mut self.pulse2,
+            &mut self.wave,
+            &mut self.noise,
+        ];
+
+        let changed = channels
+            .iter_mut()
+            .flat_map(|channel| channel.as_mut())
+            .find_map(|channel| Some((channel.channel_type(), channel.take_tempo()?)));
+
+        if let Some((channel_type, tempo)) = changed {
+            for channel in channels.iter_mut().flat_map(|channel| channel.as_mut()) {
+                channel.set_tempo(channel_type, tempo);
             }
+        }
 
-            if let Some(wave) = &mut self.wave {
-                if let Some(data) = wave.next() {
-                    for (i, data) in data.iter().enumerate() {
-                        self.buffer[i] += data / 3.0;
-                    }
+        if let Some(volume) = channels
+            .iter_mut()
+            .flat_map(|channel| channel.as_mut())
+            .find_map(|channel| channel.take_master_volume())
+        {
+            self.master_volume = volume;
+        }
 
-                    done = false;
-                }
+        changed
+    }
+
+    /// Average the source samples covered by the next output sample at the preview `rate`.
+    fn next_preview(&mut self, rate: u32) -> Option<S> {
+        let mut end = (((self.preview_index as u64) + 1) * (SOURCE_SAMPLE_RATE as u64)
+            / (rate as u64)) as usize;
+
+        if let Some(cut_at) = self.cut_at {
+            end = end.min(cut_at);
+        }
+
+        let mut sum = S::default();
+        let mut count = 0;
+
+        while self.index < end {
+            let offset = self.index % SAMPLES_PER_FRAME;
+
+            if offset == 0 && !self.render_frame() {
+                break;
             }
 
-            if let Some(noise) = &mut self.noise {
-                if fadeout && !self.pitch_has_been_reset {
-                    self.pitch_has_been_reset = true;
-                    noise.reset_pitch();
-                }
+            let len = (SAMPLES_PER_FRAME - offset).min(end - self.index);
 
-                if let Some(data) = noise.next() {
-                    for (i, data) in data.iter().enumerate() {
-                        self.buffer[i] += data / 3.0;
-                    }
+            for sample in &self.buffer[offset..(offset + len)] {
+                sum += *sample;
+            }
 
-                    done = false;
-                }
+            count += len;
+            self.index += len;
+        }
+
+        if count == 0 {
+            return None;
+        }
+
+        self.preview_index += 1;
+        Some(sum / S::from_f32(count as f32))
+    }
+
+    /// Render every channel into its own buffer, at the full sample rate. The channels are still
+    /// stepped in lockstep, so they affect each other the same way as in the mix. Returns `None`
+    /// if the sound is infinitely long.
+    pub(super) fn into_stems(mut self) -> Option<Stems> {
+        if self.clone().count() == usize::MAX {
+            return None;
+        }
+
+        let mut stems: [Option<Vec<f32>>; 4] =
+            [&self.pulse1, &self.pulse2, &self.wave, &self.noise]
+                .map(|channel| channel.as_ref().map(|_| Vec::new()));
+
+        while self.cut_at != Some(self.index) {
+            let frames = self.next_frames();
+
+            if frames.iter().all(Option::is_none) {
+                break;
             }
 
-            if done {
+            let is_infinite = [&self.pulse1, &self.pulse2, &self.wave, &self.noise]
+                .into_iter()
+                .flatten()
+                .any(|channel| channel.is_infinite() == Some(true));
+
+            if is_infinite && self.cut_at.is_none() {
                 return None;
             }
+
+            let scale = master_volume_scale(self.master_volume);
+
+            for (stem, frame) in stems.iter_mut().zip(&frames) {
+                if let Some(stem) = stem {
+                    let frame = frame.unwrap_or([0.0; SAMPLES_PER_FRAME]);
+                    stem.extend(frame.iter().map(|sample| sample * scale));
+                }
+            }
+
+            self.index += SAMPLES_PER_FRAME;
+        }
+
+        Some(Stems::new(stems, SOURCE_SAMPLE_RATE as u32))
+    }
+
+    /// Step every channel to its next frame, in the order pulse1, pulse2, wave and noise. A
+    /// channel that isn't playing has no frame.
+    fn next_frames(&mut self) -> [Option<[f32; SAMPLES_PER_FRAME]>; 4] {
+        self.step_channels(&mut [None; 3], |channel| channel.next())
+    }
+
+    /// Step every channel over its next frame like `next_frames`, without synthesizing it.
+    /// Returns `false` once all channels are done.
+    fn advance_frame(&mut self) -> bool {
+        let frames = self.step_channels(&mut [None; 3], |channel| {
+            channel.advance_frame().then_some(())
+        });
+        frames.iter().any(Option::is_some)
+    }
+
+    /// Step every channel with `step`, in the order the engine processes them. The tempo that was
+    /// passed on after each of pulse1, pulse2 and wave is stored in `tempos`.
+    fn step_channels<T>(
+        &mut self,
+        tempos: &mut SharedTempos,
+        mut step: impl FnMut(&mut ChannelIterator<'a>) -> Option<T>,
+    ) -> [Option<T>; 4] {
+        let mut frames = [None, None, None, None];
+        let mut fadeout = true;
+
+        if let Some(pulse1) = &mut self.pulse1 {
+            frames[0] = step(pulse1);
+
+            if frames[0].is_some() && !pulse1.only_fadeout_left() {
+                fadeout = false;
+            }
+        }
+
+        tempos[0] = self.share_state();
+
+        if let Some(pulse2) = &mut self.pulse2 {
+            frames[1] = step(pulse2);
+
+            if frames[1].is_some() && !pulse2.only_fadeout_left() {
+                fadeout = false;
+            }
+        }
+
+        tempos[1] = self.share_state();
+
+        if let Some(wave) = &mut self.wave {
+            frames[2] = step(wave);
+        }
+
+        tempos[2] = self.share_state();
+
+        if let Some(noise) = &mut self.noise {
+            if fadeout && !self.pitch_has_been_reset {
+                self.pitch_has_been_reset = true;
+                noise.reset_pitch();
+            }
+
+            frames[3] = step(noise);
+        }
+
+        frames
+    }
+
+    /// Mix the next frame of every channel into the buffer. Returns `false` once all channels are
+    /// done.
+    fn render_frame(&mut self) -> bool {
+        let frames = self.next_frames();
+        let frames = [&frames[0], &frames[1], &frames[2], &frames[3]].map(Option::as_ref);
+        mix_frame(&mut self.buffer, frames, self.mask, self.master_volume)
+    }
+}
+
+/// How the channels affect each other while they play, see `Sound::render`.
+#[cfg(feature = "parallel")]
+#[derive(Debug, Default)]
+struct SharedState {
+    /// Tempos passed on between the channels, as the frame, the index of the channel that it was
+    /// passed on after, the type of the channel that set it, and the tempo.
+    tempos: Vec<(usize, usize, ChannelType, u16)>,
+    /// The master volume of every frame, one for each frame of the sound.
+    master_volumes: Vec<(u8, u8)>,
+    /// The frame that the pitch of the noise channel was reset on, if any.
+    pitch_reset: Option<usize>,
+}
+
+/// Render a single channel on its own, passing it what the other channels did at the same points
+/// as when all channels are stepped together, see `SoundIterator::step_channels`.
+#[cfg(feature = "parallel")]
+fn render_channel(
+    mut channel: ChannelIterator,
+    index: usize,
+    shared: &SharedState,
+) -> Vec<[f32; SAMPLES_PER_FRAME]> {
+    let mut result = Vec::new();
+    let mut tempos = shared.tempos.iter().peekable();
+
+    for frame in 0..shared.master_volumes.len() {
+        // the channels before this one have already passed on their tempo in this frame
+        while let Some((_, _, channel_type, tempo)) =
+            tempos.next_if(|(at, after, ..)| *at == frame && *after < index)
+        {
+            channel.set_tempo(*channel_type, *tempo);
+        }
+
+        if index == 3 && shared.pitch_reset == Some(frame) {
+            channel.reset_pitch();
+        }
+
+        match channel.next() {
+            Some(data) => result.push(data),
+            None => break,
+        }
+
+        while let Some((_, _, channel_type, tempo)) = tempos.next_if(|(at, ..)| *at == frame) {
+            channel.set_tempo(*channel_type, *tempo);
+        }
+    }
+
+    result
+}
+
+/// Mix the frames of the channels that are playing into `buffer`. Returns `false` if no channel
+/// is playing.
+fn mix_frame<S: Sample>(
+    buffer: &mut [S; SAMPLES_PER_FRAME],
+    frames: [Option<&[f32; SAMPLES_PER_FRAME]>; 4],
+    mask: ChannelMask,
+    master_volume: (u8, u8),
+) -> bool {
+    let mask = [mask.pulse1, mask.pulse2, mask.wave, mask.noise];
+
+    let mut done = true;
+    let mut mixed = false;
+
+    for (data, enabled) in frames.into_iter().zip(mask) {
+        if let Some(data) = data {
+            if enabled {
+                mix_into(buffer, data, !mixed);
+                mixed = true;
+            }
+
+            done = false;
+        }
+    }
+
+    if !done && master_volume != (7, 7) {
+        let scale = S::from_f32(master_volume_scale(master_volume));
+
+        for out in buffer.iter_mut() {
+            *out = *out * scale;
+        }
+    }
+
+    if !done && !mixed {
+        buffer.fill(S::default());
+    }
+
+    !done
+}
+
+/// Factor that the mix is scaled by at the given master volume. The hardware scales each of the
+/// left and right outputs by `(volume + 1) / 8`, the mono output is the average of the two.
+fn master_volume_scale((left, right): (u8, u8)) -> f32 {
+    (left + right + 2) as f32 / 16.0
+}
+
+/// Add a channel frame to the mix, scaled so that three channels at full volume peaks at 1.0. \
+/// The first channel mixed into a frame overwrites the buffer instead, which saves clearing it.
+fn mix_into<S: Sample>(
+    buffer: &mut [S; SAMPLES_PER_FRAME],
+    data: &[f32; SAMPLES_PER_FRAME],
+    first: bool,
+) {
+    let scale = S::from_f32(1.0) / S::from_f32(3.0);
+
+    if first {
+        for (out, data) in buffer.iter_mut().zip(data) {
+            *out = S::from_f32(*data) * scale;
+        }
+    } else {
+        for (out, data) in buffer.iter_mut().zip(data) {
+            *out += S::from_f32(*data) * scale;
+        }
+    }
+}
+
+/// Iterator over fixed-size chunks of a sound, created by [`SoundIterator::chunks`].
+#[derive(Debug, Clone)]
+pub struct ChunkIter<'a, S = f32> {
+    iter: SoundIterator<'a, S>,
+    size: usize,
+}
+
+impl<'a, S: Sample> Iterator for ChunkIter<'a, S> {
+    type Item = Vec<S>;
+
+    fn next(&mut self) -> Option<Vec<S>> {
+        let mut chunk = vec![S::default(); self.size];
+        let len = self.iter.fill(&mut chunk);
+
+        if len == 0 {
+            return None;
+        }
+
+        chunk.truncate(len);
+        Some(chunk)
+    }
+}
+
+/// A sound with a known length, created by [`SoundIterator::exact`] or `Pcm::iter_exact`.
+#[derive(Debug, Clone)]
+pub struct FiniteSoundIterator<'a, S = f32> {
+    iter: SoundIterator<'a, S>,
+    remaining: usize,
+}
+
+impl<'a, S: Sample> Iterator for FiniteSoundIterator<'a, S> {
+    type Item = S;
+
+    fn next(&mut self) -> Option<S> {
+        let sample = self.iter.next()?;
+        self.remaining = self.remaining.saturating_sub(1);
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, S: Sample> ExactSizeIterator for FiniteSoundIterator<'a, S> {}
+
+impl<'a, S: Sample> Iterator for SoundIterator<'a, S> {
+    type Item = S;
+
+    fn count(self) -> usize {
+        self.remaining_len().unwrap_or(usize::MAX)
+    }
+
+    /// The exact number of samples left when the sound is finite. The first call takes a pass
+    /// through the rest of the sound without synthesizing it, later ones are free.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.remaining_len() {
+            Some(len) => (len, Some(len)),
+            None => (0, None),
+        }
+    }
+
+    fn next(&mut self) -> Option<S> {
+        if let Some(rate) = self.preview {
+            return self.next_preview(rate);
+        }
+
+        if self.cut_at == Some(self.index) {
+            return None;
+        }
+
+        if self.index % SAMPLES_PER_FRAME == 0 && !self.render_frame() {
+            return None;
         }
 
         let result = self.buffer[self.index % SAMPLES_PER_FRAME];
         self.index += 1;
         Some(result)
     }
+
+    fn nth(&mut self, n: usize) -> Option<S> {
+        if self.skip_samples(n) < n {
+            return None;
+        }
+
+        self.next()
+    }
+}
+
+impl Render for SoundIterator<'_> {
+    fn sample_rate(&self) -> u32 {
+        SoundIterator::sample_rate(self)
+    }
+
+    fn fill(&mut self, out: &mut [f32]) -> usize {
+        SoundIterator::fill(self, out)
+    }
+}
+
+#[cfg(feature = "rodio")]
+impl rodio::Source for SoundIterator<'_> {
+    /// The mix is rendered a frame at a time, except for previews.
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.preview {
+            Some(_) => None,
+            None => Some(SAMPLES_PER_FRAME - self.index % SAMPLES_PER_FRAME),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        SoundIterator::channels(self)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SoundIterator::sample_rate(self)
+    }
+
+    /// The length of the whole sound, `None` if it's infinitely long or once playback has started.
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        if self.index != 0 || self.preview_index != 0 {
+            return None;
+        }
+
+        let len = self.clone().count();
+
+        (len != usize::MAX).then(|| {
+            std::time::Duration::from_secs_f64(len as f64 / SoundIterator::sample_rate(self) as f64)
+        })
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::LoopBehavior;
+
+    /// A single pulse channel with a 16 frame long square_note, fading out.
+    const SQUARE_NOTE: [u8; 8] = [0x04, 0x03, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0xff];
+
+    /// A single pulse channel looping a square_note forever.
+    pub(crate) const LOOPING_SQUARE_NOTE: [u8; 11] = [
+        0x04, 0x03, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0xfe, 0x00, 0x03, 0x00,
+    ];
+
+    #[test]
+    fn test_f64_precision() {
+        // a single pulse channel with a long square_note, slowly fading out
+        let rom = [0x04, 0x03, 0x00, 0x2f, 0xf7, 0x00, 0x07, 0xff];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let single: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+        let double: Vec<f64> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+
+        assert_eq!(single.len(), double.len());
+        assert!(single.len() > 100 * SAMPLES_PER_FRAME);
+
+        for (single, double) in single.iter().zip(double) {
+            assert!((*single as f64 - double).abs() <= f32::EPSILON as f64 * double.abs());
+        }
+    }
+
+    #[test]
+    fn test_channel_phase() {
+        // two pulse channels playing the same duty_cycle 2 note
+        let rom = [
+            0x44, 0x06, 0x00, 0x05, 0x06, 0x00, 0xec, 0x02, 0x2f, 0xf1, 0x00, 0x07, 0xff,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let rms = |phase: [f32; 4]| {
+            let options = SynthesisOptions::new().channel_phase(phase);
+            let data: Vec<f32> = sound.pcm(0, 0x100, options).collect();
+            let mean = data.iter().sum::<f32>() / data.len() as f32;
+            (data.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / data.len() as f32).sqrt()
+        };
+
+        let in_phase = rms([0.0, 0.0, 0.0, 0.0]);
+        let out_of_phase = rms([0.0, 0.5, 0.0, 0.0]);
+
+        assert!(
+            out_of_phase < in_phase * 0.5,
+            "{out_of_phase} vs {in_phase}"
+        );
+    }
+
+    #[test]
+    fn test_channel_mask() {
+        // two pulse channels, the second one an octave lower with another duty cycle
+        let rom = [
+            0x44, 0x06, 0x00, 0x05, 0x0b, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0xff, 0xec, 0x02, 0x2f,
+            0xf1, 0x00, 0x06, 0xff,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let render = |mask: ChannelMask| -> Vec<f32> {
+            let options = SynthesisOptions::new().channel_mask(mask);
+            sound.pcm(0, 0x100, options).collect()
+        };
+
+        let full = render(ChannelMask::ALL);
+        let pulse1 = render(ChannelMask::solo(Voice::Pulse1));
+        let pulse2 = render(ChannelMask::solo(Voice::Pulse2));
+        let none = render(ChannelMask::NONE);
+
+        assert_eq!(pulse1.len(), full.len());
+        assert_eq!(pulse2.len(), full.len());
+        assert_eq!(none.len(), full.len());
+
+        assert!(pulse2.iter().any(|&sample| sample != 0.0));
+        assert_ne!(pulse2, full);
+        assert!(none.iter().all(|&sample| sample == 0.0));
+
+        for ((full, pulse1), pulse2) in full.iter().zip(&pulse1).zip(&pulse2) {
+            assert_eq!(*full, pulse1 + pulse2);
+        }
+    }
+
+    #[test]
+    fn test_stems() {
+        // two pulse channels, the second one an octave lower with another duty cycle
+        let rom = [
+            0x44, 0x06, 0x00, 0x05, 0x0b, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0xff, 0xec, 0x02, 0x2f,
+            0xf1, 0x00, 0x06, 0xff,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let full: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+        let stems = sound
+            .pcm::<f32>(0, 0x100, SynthesisOptions::new())
+            .into_stems()
+            .unwrap();
+
+        assert!(stems.wave.is_none());
+        assert!(stems.noise.is_none());
+
+        let pulse2 = stems.pulse2.as_ref().unwrap();
+        assert_eq!(pulse2.frames(), full.len());
+        assert_eq!(pulse2.sample_rate(), SOURCE_SAMPLE_RATE as u32);
+
+        let mix = stems.mix();
+        assert_eq!(mix.frames(), full.len());
+
+        for (mix, full) in mix.iter().zip(&full) {
+            assert!((mix - full).abs() < 1e-6);
+        }
+    }
+
+    #[cfg(feature = "rodio")]
+    #[test]
+    fn test_rodio_source() {
+        use rodio::Source;
+
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+
+        let mut iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        let len = iter.clone().count();
+        assert_eq!(Source::sample_rate(&iter), SOURCE_SAMPLE_RATE as u32);
+        assert_eq!(
+            iter.total_duration(),
+            Some(Duration::from_secs_f64(
+                len as f64 / SOURCE_SAMPLE_RATE as f64
+            ))
+        );
+
+        assert_eq!(iter.current_frame_len(), Some(SAMPLES_PER_FRAME));
+        iter.nth(99);
+        assert_eq!(iter.current_frame_len(), Some(SAMPLES_PER_FRAME - 100));
+
+        let sound = Sound::new(&LOOPING_SQUARE_NOTE, 0, 0);
+        let iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        assert_eq!(iter.total_duration(), None);
+    }
+
+    #[test]
+    fn test_quantize_dac() {
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+
+        let levels = |options: SynthesisOptions| {
+            let mut data: Vec<u32> = sound
+                .pcm::<f32>(0, 0x100, options)
+                .map(f32::to_bits)
+                .collect();
+            data.sort_unstable();
+            data.dedup();
+            data.len()
+        };
+
+        assert!(levels(SynthesisOptions::new()) > 16);
+        assert!(levels(SynthesisOptions::new().quantize_dac(true)) <= 16);
+    }
+
+    #[test]
+    fn test_cut_at() {
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+
+        let uncut: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+
+        // 0.1 seconds is a bit less than 6 frames
+        let options = SynthesisOptions::new().cut_at(Some(Duration::from_millis(100)));
+        let cut: Vec<f32> = sound.pcm(0, 0x100, options).collect();
+
+        assert_eq!(cut.len(), 6 * SAMPLES_PER_FRAME);
+        assert_eq!(cut, uncut[..cut.len()]);
+        assert_eq!(sound.pcm::<f32>(0, 0x100, options).count(), cut.len());
+    }
+
+    #[test]
+    fn test_max_duration() {
+        let sound = Sound::new(&LOOPING_SQUARE_NOTE, 0, 0);
+
+        let max_duration = Duration::from_secs(1);
+        let options = SynthesisOptions::new().max_duration(Some(max_duration));
+        let samples: Vec<f32> = sound.pcm(0, 0x100, options).collect();
+
+        // one second is a bit less than 60 frames, and the last one is rendered in full
+        let frames = (max_duration.as_secs_f64() * SOURCE_SAMPLE_RATE as f64
+            / SAMPLES_PER_FRAME as f64)
+            .ceil() as usize;
+        assert_eq!(frames, 60);
+        assert_eq!(samples.len(), frames * SAMPLES_PER_FRAME);
+
+        // the same as cutting the sound, which wins when it is earlier
+        let cut = options.cut_at(Some(Duration::from_millis(100)));
+        assert_eq!(
+            sound.pcm::<f32>(0, 0x100, cut).count(),
+            6 * SAMPLES_PER_FRAME
+        );
+
+        let pcm = crate::gen1::synthesis_with_options(&LOOPING_SQUARE_NOTE, 0, 0, 0, 0x80, options);
+        let duration = pcm.total_duration().unwrap();
+        assert_eq!(
+            duration.as_micros(),
+            crate::frames_to_duration(frames as u64).as_micros()
+        );
+    }
+
+    #[test]
+    fn test_skip_samples() {
+        #[rustfmt::skip]
+        let rom = [
+            // pulse1 and noise
+            0x44, 0x06, 0x00, 0x07, 0x0e, 0x00,
+            // pitch_sweep 1, -2, square_note 15, 15, 3, 0x600
+            0x10, 0x1a, 0x2f, 0xf3, 0x00, 0x06, 0xff,
+            // noise_note 15, 15, 2, 0x39
+            0x2f, 0xf2, 0x39, 0xff,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+        let samples: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+        assert!(samples.len() > 10 * SAMPLES_PER_FRAME);
+
+        for n in [
+            0,
+            17,
+            SAMPLES_PER_FRAME,
+            3 * SAMPLES_PER_FRAME + 5,
+            7 * SAMPLES_PER_FRAME - 1,
+        ] {
+            let mut iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+            assert_eq!(iter.skip_samples(n), n);
+            assert_eq!(
+                iter.by_ref().take(SAMPLES_PER_FRAME).collect::<Vec<_>>(),
+                samples[n..(n + SAMPLES_PER_FRAME)]
+            );
+
+            let mut iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+            assert_eq!(iter.nth(n), Some(samples[n]));
+        }
+
+        // skipping past the end stops at the end
+        let mut iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        assert_eq!(iter.skip_samples(usize::MAX), samples.len());
+        assert_eq!(iter.next(), None);
+    }
+
+    /// Check that `Sound::render` gives exactly the same samples as `Sound::pcm`.
+    #[cfg(feature = "parallel")]
+    fn assert_render_parallel(rom: &[u8], pitch: i8, length: u16, options: SynthesisOptions) {
+        let sound = Sound::new(rom, 0, 0);
+
+        let expected: Vec<f32> = sound.pcm(pitch, length, options).collect();
+        assert!(!expected.is_empty());
+
+        let rendered = sound.render(pitch, length, options).unwrap();
+        assert_eq!(rendered.len(), expected.len());
+        assert!(rendered
+            .iter()
+            .zip(&expected)
+            .all(|(rendered, expected)| rendered.to_bits() == expected.to_bits()));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_render_parallel() {
+        #[rustfmt::skip]
+        let music = [
+            // music pulse1 and pulse2
+            0x40, 0x06, 0x00, 0x01, 0x17, 0x00,
+            // tempo 0x100, volume 5, 3, note_type 12, 10, 1, octave 5, E 2, G 2
+            0xed, 0x01, 0x00, 0xf0, 0x53, 0xdc, 0xa1, 0xe3, 0x41, 0x71,
+            // tempo 0xc0, octave 6, C 4, rest 2
+            0xed, 0x00, 0xc0, 0xe2, 0x03, 0xc1, 0xff,
+            // note_type 12, 8, 2, octave 4, D 2, F 2, A 4, in step with pulse1
+            0xdc, 0x82, 0xe4, 0x31, 0x51, 0x83, 0xff,
+        ];
+        #[rustfmt::skip]
+        let sfx = [
+            // pulse1, pulse2 and noise, where the noise pitch is reset before its last notes, once
+            // the pulses fade out
+            0x84, 0x09, 0x00, 0x05, 0x16, 0x00, 0x07, 0x1f, 0x00,
+            0x2f, 0xf2, 0x00, 0x07, 0x2f, 0xf2, 0x80, 0x06, 0x2f, 0xa4, 0x40, 0x07, 0xff,
+            0x2f, 0xc3, 0x20, 0x07, 0x2f, 0xc3, 0xa0, 0x06, 0xff,
+            0x2f, 0xf4, 0x44, 0x2f, 0xf4, 0x45, 0x2f, 0xf4, 0x46, 0x2f, 0xf4, 0x47, 0x2f, 0xf4, 0x48,
+            0x2f, 0xf4, 0x49, 0x2f, 0xf4, 0x4a, 0x2f, 0xf4, 0x4b, 0xff,
+        ];
+
+        assert_render_parallel(&music, 0, 0x100, SynthesisOptions::new());
+        assert_render_parallel(&sfx, -20, 0x180, SynthesisOptions::new());
+
+        // cut short, and muted
+        let options = SynthesisOptions::new()
+            .cut_at(Some(Duration::from_millis(150)))
+            .channel_mask(ChannelMask::solo(Voice::Pulse2));
+        assert_render_parallel(&music, 0, 0x100, options);
+        assert_render_parallel(&sfx, -20, 0x180, options);
+
+        // a sound that loops forever can't be rendered in full
+        assert_eq!(
+            Sound::new(&LOOPING_SQUARE_NOTE, 0, 0).render(0, 0x100, SynthesisOptions::new()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_next_frame() {
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+        let samples: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+
+        let mut iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        let mut frames = Vec::new();
+        while let Some(frame) = iter.next_frame() {
+            frames.extend_from_slice(frame);
+        }
+        assert_eq!(frames, samples);
+
+        // the rest of a partly read frame is skipped
+        let mut iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        iter.next();
+        assert_eq!(
+            iter.next_frame().map(|frame| frame.to_vec()),
+            Some(samples[SAMPLES_PER_FRAME..(2 * SAMPLES_PER_FRAME)].to_vec())
+        );
+        assert_eq!(iter.next(), Some(samples[2 * SAMPLES_PER_FRAME]));
+
+        let options = SynthesisOptions::new().preview(8000);
+        assert!(sound.pcm::<f32>(0, 0x100, options).next_frame().is_none());
+    }
+
+    #[test]
+    fn test_size_hint() {
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+        let options = SynthesisOptions::new();
+
+        let mut iter = sound.pcm::<f32>(0, 0x100, options);
+        let len = iter.clone().count();
+        assert_eq!(iter.size_hint(), (len, Some(len)));
+
+        // the hint keeps up with the samples that have been taken
+        iter.skip_samples(SAMPLES_PER_FRAME + 100);
+        let rest = len - SAMPLES_PER_FRAME - 100;
+        assert_eq!(iter.size_hint(), (rest, Some(rest)));
+        assert_eq!(iter.clone().count(), rest);
+
+        let mut exact = iter.exact().unwrap();
+        assert_eq!(exact.len(), rest);
+        exact.next();
+        assert_eq!(exact.len(), rest - 1);
+        assert_eq!(exact.count(), rest - 1);
+    }
+
+    #[test]
+    fn test_size_hint_after_reading() {
+        let mut iter = Sound::new(&SQUARE_NOTE, 0, 0).pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        let len = iter.size_hint().0;
+
+        // the length is only stepped through once, and then follows every way of reading
+        iter.next();
+        iter.fill(&mut [0.0; 1000]);
+        iter.skip_samples(SAMPLES_PER_FRAME);
+        iter.next_frame();
+
+        let rest = len - 3 * SAMPLES_PER_FRAME;
+        assert_eq!(iter.size_hint(), (rest, Some(rest)));
+        assert_eq!(iter.clone().map(|_| ()).count(), rest);
+    }
+
+    #[test]
+    fn test_size_hint_preview() {
+        let options = SynthesisOptions::new().preview(8000);
+
+        let preview = Sound::new(&SQUARE_NOTE, 0, 0).pcm::<f32>(0, 0x100, options);
+        assert_eq!(preview.size_hint().0, preview.clone().count());
+        assert_eq!(preview.clone().exact().unwrap().len(), preview.count());
+    }
+
+    #[test]
+    fn test_size_hint_infinite() {
+        let sound = Sound::new(&LOOPING_SQUARE_NOTE, 0, 0);
+
+        let iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        assert_eq!(iter.size_hint(), (0, None));
+        assert!(iter.exact().is_none());
+
+        // cutting the sound short gives it a length
+        let options = SynthesisOptions::new().max_duration(Some(Duration::from_millis(100)));
+        let iter = sound.pcm::<f32>(0, 0x100, options);
+        let len = 6 * SAMPLES_PER_FRAME;
+        assert_eq!(iter.size_hint(), (len, Some(len)));
+        assert_eq!(iter.exact().map(|iter| iter.len()), Some(len));
+    }
+
+    #[test]
+    fn test_loop_iterations() {
+        // a single pulse channel with a square_note intro, then looping a square_note forever
+        let rom = [
+            0x04, 0x03, 0x00, 0x23, 0xf1, 0x00, 0x07, 0x21, 0xf1, 0x00, 0x06, 0xfe, 0x00, 0x07,
+            0x00, 0xff,
+        ];
+        let count = |iterations| {
+            let options =
+                SynthesisOptions::new().loop_behavior(LoopBehavior::Iterations(iterations));
+            Sound::new(&rom, 0, 0).pcm::<f32>(0, 0x100, options).count()
+        };
+
+        let once = count(1);
+        assert_eq!(count(0), once);
+
+        // every pass adds the two frames of the loop body, on top of the intro and the fade-out
+        let body = 2 * SAMPLES_PER_FRAME;
+        let intro = once - body;
+        assert_eq!(count(2) - intro, 2 * body);
+        assert_eq!(count(5) - intro, 5 * body);
+    }
+
+    #[test]
+    fn test_cut_at_infinite() {
+        let sound = Sound::new(&LOOPING_SQUARE_NOTE, 0, 0);
+
+        let options = SynthesisOptions::new();
+        assert_eq!(sound.pcm::<f32>(0, 0x100, options).count(), usize::MAX);
+
+        let options = options.cut_at(Some(Duration::from_millis(100)));
+        assert_eq!(
+            sound.pcm::<f32>(0, 0x100, options).count(),
+            6 * SAMPLES_PER_FRAME
+        );
+        assert_eq!(
+            sound
+                .pcm::<f32>(0, 0x100, options)
+                .collect::<Vec<_>>()
+                .len(),
+            6 * SAMPLES_PER_FRAME
+        );
+    }
+
+    #[test]
+    fn test_chunks() {
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+
+        let expected: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+
+        // sizes that are smaller than, larger than, and not aligned with a frame
+        for size in [
+            1000,
+            SAMPLES_PER_FRAME - 1,
+            SAMPLES_PER_FRAME,
+            3 * SAMPLES_PER_FRAME + 7,
+        ] {
+            let chunks: Vec<Vec<f32>> = sound
+                .pcm(0, 0x100, SynthesisOptions::new())
+                .chunks(size)
+                .collect();
+
+            assert!(chunks[..(chunks.len() - 1)].iter().all(|c| c.len() == size));
+            assert!(!chunks[chunks.len() - 1].is_empty());
+            assert_eq!(chunks.concat(), expected);
+        }
+    }
+
+    #[test]
+    fn test_fill_cut_at() {
+        let sound = Sound::new(&LOOPING_SQUARE_NOTE, 0, 0);
+
+        let options = SynthesisOptions::new().cut_at(Some(Duration::from_millis(100)));
+        let expected: Vec<f32> = sound.pcm(0, 0x100, options).collect();
+
+        let mut iter = sound.pcm(0, 0x100, options);
+        let mut buffer = vec![0.0; 5000];
+        let mut result = Vec::new();
+
+        loop {
+            let len = iter.fill(&mut buffer);
+            result.extend_from_slice(&buffer[..len]);
+
+            if len < buffer.len() {
+                break;
+            }
+        }
+
+        assert_eq!(result, expected);
+        assert_eq!(iter.fill(&mut buffer), 0);
+    }
+
+    #[test]
+    fn test_preview() {
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+
+        let full: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+
+        let options = SynthesisOptions::new().preview(8000);
+        let iter = sound.pcm::<f32>(0, 0x100, options);
+        assert_eq!(iter.sample_rate(), 8000);
+
+        let expected_len = (full.len() * 8000 + SOURCE_SAMPLE_RATE - 1) / SOURCE_SAMPLE_RATE;
+        assert_eq!(iter.clone().count(), expected_len);
+
+        let preview: Vec<f32> = iter.collect();
+        assert_eq!(preview.len(), expected_len);
+
+        for (i, sample) in preview.iter().enumerate() {
+            let start = i * SOURCE_SAMPLE_RATE / 8000;
+            let end = ((i + 1) * SOURCE_SAMPLE_RATE / 8000).min(full.len());
+            let average = full[start..end].iter().sum::<f32>() / ((end - start) as f32);
+            assert!((sample - average).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_preview_cut_at() {
+        let sound = Sound::new(&LOOPING_SQUARE_NOTE, 0, 0);
+
+        let options = SynthesisOptions::new()
+            .preview(8000)
+            .cut_at(Some(Duration::from_millis(100)));
+        let expected_len =
+            (6 * SAMPLES_PER_FRAME * 8000 + SOURCE_SAMPLE_RATE - 1) / SOURCE_SAMPLE_RATE;
+
+        assert_eq!(sound.pcm::<f32>(0, 0x100, options).count(), expected_len);
+        assert_eq!(
+            sound
+                .pcm::<f32>(0, 0x100, options)
+                .collect::<Vec<_>>()
+                .len(),
+            expected_len
+        );
+
+        let mut buffer = vec![0.0; 1000];
+        assert_eq!(sound.pcm(0, 0x100, options).fill(&mut buffer), expected_len);
+    }
+
+    #[test]
+    fn test_partial_drains() {
+        // two pulse channels, with fading notes that change every few frames
+        let rom = [
+            0x44, 0x06, 0x00, 0x05, 0x0f, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0x2a, 0xa2, 0x80, 0x06,
+            0xff, 0x24, 0xf3, 0x40, 0x07, 0xff,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let expected: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+
+        // drain the samples in uneven bursts, mixing single samples with bulk copies
+        let mut iter = sound.pcm(0, 0x100, SynthesisOptions::new());
+        let mut buffer = vec![0.0; 7919];
+        let mut result = Vec::new();
+
+        for burst in 0.. {
+            for _ in 0..(burst % 5) {
+                result.extend(iter.next());
+            }
+
+            let size = burst * 1237 % buffer.len();
+            let len = iter.fill(&mut buffer[..size]);
+            result.extend_from_slice(&buffer[..len]);
+
+            if len < size {
+                break;
+            }
+        }
+
+        assert_eq!(result, expected);
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_stuck_channel() {
+        // a pulse channel with a 16 frame long square_note, and a noise channel with an infinite
+        // loop that jumps to itself
+        let rom = [
+            0x44, 0x06, 0x00, 0x07, 0x0b, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0xff, 0xfe, 0x00, 0x0b,
+            0x00,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        // the same pulse channel on its own
+        let expected: Vec<f32> = Sound::new(&SQUARE_NOTE, 0, 0)
+            .pcm(0, 0x100, SynthesisOptions::new())
+            .collect();
+
+        let mut iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        assert_eq!(iter.clone().count(), expected.len());
+        assert_eq!(iter.by_ref().collect::<Vec<_>>(), expected);
+        assert!(iter.is_stuck());
+    }
+
+    #[test]
+    fn test_runaway_channel() {
+        // a pulse channel of one frame long square_notes that never loops or returns, and runs off
+        // the end of bank 0 after 4096 notes
+        let mut rom = vec![0x20; 0x4004];
+        rom[..3].copy_from_slice(&[0x04, 0x03, 0x00]);
+
+        let error = ParseError {
+            kind: ParseErrorKind::OutOfBounds,
+            bank: 0,
+            addr: 0x4003,
+            channel: ChannelType::SfxPulse,
+        };
+
+        let iter = Sound::new(&rom, 0, 0).pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        assert_eq!(iter.clone().count(), 4096 * SAMPLES_PER_FRAME);
+        assert_eq!(iter.check(), Err(error));
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        // the same pulse channel as above, and a noise channel starting with an unknown command
+        let rom = [
+            0x44, 0x06, 0x00, 0x07, 0x0b, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0xff, 0x00,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let expected: Vec<f32> = Sound::new(&SQUARE_NOTE, 0, 0)
+            .pcm(0, 0x100, SynthesisOptions::new())
+            .collect();
+
+        let error = ParseError {
+            kind: ParseErrorKind::UnknownCommand(0x00),
+            bank: 0,
+            addr: 0x0b,
+            channel: ChannelType::SfxNoise,
+        };
+
+        let mut iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        assert_eq!(iter.clone().check(), Err(error));
+        assert_eq!(iter.error(), None);
+        assert_eq!(iter.by_ref().collect::<Vec<_>>(), expected);
+        assert_eq!(iter.error(), Some(error));
+        assert!(!iter.is_stuck());
+    }
+
+    #[test]
+    fn test_try_new() {
+        let rom = [0x04, 0x03, 0x00, 0xff];
+        assert!(Sound::try_new(&rom, 0, 0).is_ok());
+
+        // the first pulse channel, as both a music and an SFX channel
+        let rom = [0x40, 0x06, 0x00, 0x04, 0x06, 0x00, 0xff];
+        let err = Sound::try_new(&rom, 0, 0).unwrap_err();
+        assert_eq!(err, HeaderError::DuplicateChannel { id: 5 });
+    }
+
+    #[test]
+    fn test_missing_pulse_channels() {
+        // a noise channel on its own, its pitch is reset right away since there are no pulse
+        // channels to wait for
+        let rom = [0x07, 0x03, 0x00, 0x2f, 0xf1, 0x33, 0xff];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let expected: Vec<f32> = sound.pcm(0, 0x80, SynthesisOptions::new()).collect();
+        let actual: Vec<f32> = sound.pcm(0x40, 0x80, SynthesisOptions::new()).collect();
+        assert!(!expected.is_empty());
+        assert_eq!(actual, expected);
+
+        // only the second pulse channel
+        let rom = [0x05, 0x03, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0xff];
+        let iter = Sound::new(&rom, 0, 0).pcm::<f32>(0, 0x80, SynthesisOptions::new());
+        assert!(iter.count() > 0);
+
+        // a pulse channel that loops forever, which means that the noise pitch is never reset
+        let rom = [
+            0x44, 0x06, 0x00, 0x07, 0x0e, 0x00, 0x2f, 0xf1, 0x00, 0x07, 0xfe, 0x00, 0x06, 0x00,
+            0x2f, 0xf1, 0x33, 0xff,
+        ];
+        let iter = Sound::new(&rom, 0, 0).pcm::<f32>(0x40, 0x80, SynthesisOptions::new());
+        assert_eq!(iter.clone().count(), usize::MAX);
+        assert_eq!(
+            iter.take(SAMPLES_PER_FRAME * 64).count(),
+            SAMPLES_PER_FRAME * 64
+        );
+    }
+
+    #[test]
+    fn test_music_tempo() {
+        let count = |rom: &[u8]| {
+            Sound::new(rom, 0, 0)
+                .pcm::<f32>(0, 0x100, SynthesisOptions::new())
+                .count()
+        };
+
+        // the first channel sets the tempo to half a frame, which the second channel plays at
+        // as well
+        let rom = [
+            0x40, 0x06, 0x00, 0x01, 0x0f, 0x00, 0xed, 0x00, 0x80, 0xd1, 0xf0, 0xe4, 0x07, 0xc7,
+            0xff, 0xd1, 0xf0, 0xe4, 0x07, 0xc7, 0xff,
+        ];
+        assert_eq!(count(&rom), 8 * SAMPLES_PER_FRAME);
+
+        let pulse1 = [
+            0x00, 0x03, 0x00, 0xed, 0x00, 0x80, 0xd1, 0xf0, 0xe4, 0x07, 0xc7, 0xff,
+        ];
+        assert_eq!(count(&pulse1), 8 * SAMPLES_PER_FRAME);
+
+        // on its own, the second channel is played at the tempo given to the sound
+        let pulse2 = [0x01, 0x03, 0x00, 0xd1, 0xf0, 0xe4, 0x07, 0xc7, 0xff];
+        assert_eq!(count(&pulse2), 16 * SAMPLES_PER_FRAME);
+    }
+
+    #[test]
+    fn test_master_volume() {
+        let samples = |volume: &[u8]| -> Vec<f32> {
+            // note_type 1, 15, 0; octave 4; C_ 8; rest 8
+            let rom = [
+                &[0x00, 0x03, 0x00][..],
+                volume,
+                &[0xd1, 0xf0, 0xe4, 0x07, 0xc7, 0xff],
+            ];
+            Sound::new(&rom.concat(), 0, 0)
+                .pcm(0, 0x100, SynthesisOptions::new())
+                .collect()
+        };
+
+        let full = samples(&[0xf0, 0x77]);
+        assert_eq!(samples(&[]), full);
+
+        // both terminals at 1 play at a quarter of the amplitude
+        let quiet = samples(&[0xf0, 0x11]);
+        assert_eq!(quiet.len(), full.len());
+        assert!(full.iter().any(|&sample| sample != 0.0));
+        assert!(quiet
+            .iter()
+            .zip(&full)
+            .all(|(&quiet, &full)| quiet == full / 4.0));
+
+        // the mono output is the average of the left and the right terminal
+        let left = samples(&[0xf0, 0x70]);
+        assert!(left
+            .iter()
+            .zip(&full)
+            .all(|(&left, &full)| (left - full * 9.0 / 16.0).abs() < 1e-6));
+    }
 }
@@ -0,0 +1,172 @@
+use crate::{header, HeaderError, RomLocation};
+
+/// The sound header table at the start of a sound bank.
+///
+/// Every sound bank starts with a table of 3-byte channel headers, where the first entry is
+/// padding. A sound ID indexes into this table, and points at the first of the sound's channel
+/// headers, so IDs of consecutive sounds are spaced by their channel count. The table ends where
+/// the first channel data begins.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SoundTable {
+    sounds: Vec<(u8, RomLocation)>,
+}
+
+impl SoundTable {
+    pub fn read(rom: &[u8], bank: u8) -> SoundTable {
+        let mut sounds = Vec::new();
+        let mut end = 0x8000;
+        let mut id = 1usize;
+
+        while id <= (u8::MAX as usize) {
+            let location = RomLocation::new(bank, 0x4000 + (id as u16) * 3);
+            let pos = location.offset();
+
+            let channel_count = match rom.get(pos) {
+                Some(byte) => ((byte >> 6) + 1) as usize,
+                None => break,
+            };
+
+            if (location.addr as usize) + channel_count * 3 > end {
+                break;
+            }
+
+            let Some(headers) = rom.get(pos..(pos + channel_count * 3)) else {
+                break;
+            };
+
+            for header in headers.chunks(3) {
+                let ptr = u16::from_le_bytes([header[1], header[2]]) as usize;
+
+                if (0x4000..0x8000).contains(&ptr) {
+                    end = end.min(ptr);
+                }
+            }
+
+            sounds.push((id as u8, location));
+            id += channel_count;
+        }
+
+        SoundTable { sounds }
+    }
+
+    /// Location of the sound with the given ID, returns `None` if the ID doesn't point at the
+    /// start of a sound.
+    pub fn get(&self, id: u8) -> Option<RomLocation> {
+        self.sounds
+            .binary_search_by_key(&id, |(id, _)| *id)
+            .ok()
+            .map(|index| self.sounds[index].1)
+    }
+
+    /// Number of sounds in the table.
+    pub fn len(&self) -> usize {
+        self.sounds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sounds.is_empty()
+    }
+
+    /// Iterate over the IDs and locations of every sound in the table.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, RomLocation)> + '_ {
+        self.sounds.iter().copied()
+    }
+}
+
+/// A sound in the sound header table of a bank, see `list_sounds`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SoundEntry {
+    /// The sound ID, the index of its first channel header in the table.
+    pub index: usize,
+    pub addr: u16,
+    pub channel_count: u8,
+    /// The ID from 1 to 8 and the address of the data of every channel.
+    pub channels: Vec<(u8, u16)>,
+}
+
+/// List every sound in the sound header table of the given bank, see `SoundTable`.
+///
+/// Returns an error if the header of any of the sounds can't be played, see `Sound::try_new`.
+pub fn list_sounds(rom: &[u8], bank: u8) -> Result<Vec<SoundEntry>, HeaderError> {
+    SoundTable::read(rom, bank)
+        .iter()
+        .map(|(id, location)| {
+            let channels = header::try_read(rom, location)?;
+
+            Ok(SoundEntry {
+                index: id as usize,
+                addr: location.addr,
+                channel_count: channels.len() as u8,
+                channels,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let mut rom = vec![0; 0x8000];
+
+        // padding, a one channel sound, a three channel sound, and then the channel data
+        rom[0x4000..0x4018].copy_from_slice(&[
+            0xff, 0xff, 0xff, 0x07, 0x15, 0x40, 0x84, 0x15, 0x40, 0x05, 0x15, 0x40, 0x07, 0x15,
+            0x40, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ]);
+
+        let table = SoundTable::read(&rom, 0x01);
+
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(0), None);
+        assert_eq!(table.get(1), Some(RomLocation::new(0x01, 0x4003)));
+        assert_eq!(table.get(2), Some(RomLocation::new(0x01, 0x4006)));
+        assert_eq!(table.get(3), None);
+        assert_eq!(table.get(5), None);
+        assert_eq!(table.iter().map(|(id, _)| id).collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn test_list_sounds() {
+        let mut rom = vec![0; 0x8000];
+
+        // padding, a one channel sound, a two channel sound, and then the channel data
+        rom[0x4000..0x400f].copy_from_slice(&[
+            0xff, 0xff, 0xff, 0x07, 0x0c, 0x40, 0x44, 0x0c, 0x40, 0x05, 0x0d, 0x40, 0xff, 0xff,
+            0xff,
+        ]);
+
+        assert_eq!(
+            list_sounds(&rom, 0x01),
+            Ok(vec![
+                SoundEntry {
+                    index: 1,
+                    addr: 0x4003,
+                    channel_count: 1,
+                    channels: vec![(8, 0x400c)],
+                },
+                SoundEntry {
+                    index: 2,
+                    addr: 0x4006,
+                    channel_count: 2,
+                    channels: vec![(5, 0x400c), (6, 0x400d)],
+                },
+            ])
+        );
+
+        // the second channel of the second sound is the first pulse channel again
+        rom[0x4009] = 0x04;
+        assert_eq!(
+            list_sounds(&rom, 0x01),
+            Err(HeaderError::DuplicateChannel { id: 5 })
+        );
+    }
+
+    #[test]
+    fn test_read_out_of_bounds() {
+        assert!(SoundTable::read(&[], 0x02).is_empty());
+        assert!(SoundTable::read(&[0; 0x8004], 0x02).is_empty());
+    }
+}
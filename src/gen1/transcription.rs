@@ -0,0 +1,363 @@
+use std::fmt;
+
+use crate::Voice;
+
+use super::channel::Channel;
+use super::command::Command;
+use super::sound::Sound;
+
+/// Upper bound on the number of commands read from a single channel, in case of malformed data.
+const MAX_COMMANDS: usize = 0x10000;
+
+/// Options for `transcribe`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct TranscriptionOptions {
+    unroll_loops: bool,
+}
+
+impl TranscriptionOptions {
+    pub fn new() -> TranscriptionOptions {
+        TranscriptionOptions::default()
+    }
+
+    /// Repeat the body of finite loops instead of annotating them. Infinite loops are always
+    /// annotated. Defaults to false.
+    pub fn unroll_loops(mut self, unroll_loops: bool) -> Self {
+        self.unroll_loops = unroll_loops;
+        self
+    }
+}
+
+/// Duration of a note, both exact and as an approximate musical fraction.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct NoteDuration {
+    /// Number of frames, taking the tempo and the carry between notes into account.
+    pub frames: usize,
+    /// Fraction of a whole note, assuming that a note speed of 12 is the normal speed.
+    pub fraction: (u32, u32),
+}
+
+impl fmt::Display for NoteDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.fraction.0, self.fraction.1)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TranscriptionItem {
+    Note {
+        name: &'static str,
+        octave: u8,
+        duration: NoteDuration,
+    },
+    Drum {
+        instrument: u8,
+        duration: NoteDuration,
+    },
+    Rest(NoteDuration),
+    /// A loop back to `addr`, `count` times in total, or forever if 0.
+    Loop {
+        count: u8,
+        addr: u16,
+    },
+}
+
+impl fmt::Display for TranscriptionItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscriptionItem::Note {
+                name,
+                octave,
+                duration,
+            } => write!(f, "{name}{octave} {duration}"),
+            TranscriptionItem::Drum {
+                instrument,
+                duration,
+            } => write!(f, "drum {instrument} {duration}"),
+            TranscriptionItem::Rest(duration) => write!(f, "rest {duration}"),
+            TranscriptionItem::Loop { count: 0, addr } => write!(f, "| loop forever to {addr:04x}"),
+            TranscriptionItem::Loop { count, addr } => write!(f, "| loop x{count} to {addr:04x}"),
+        }
+    }
+}
+
+/// The notes of the music channels of a sound.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Transcription {
+    channels: Vec<(Voice, Vec<TranscriptionItem>)>,
+}
+
+impl Transcription {
+    pub fn channel(&self, voice: Voice) -> Option<&[TranscriptionItem]> {
+        self.channels
+            .iter()
+            .find(|(v, _)| *v == voice)
+            .map(|(_, items)| items.as_slice())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Voice, &[TranscriptionItem])> {
+        self.channels
+            .iter()
+            .map(|(voice, items)| (*voice, items.as_slice()))
+    }
+}
+
+impl fmt::Display for Transcription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (voice, items) in &self.channels {
+            write!(f, "{}:", voice.short_name())?;
+
+            for (index, item) in items.iter().enumerate() {
+                let after_loop =
+                    index > 0 && matches!(items[index - 1], TranscriptionItem::Loop { .. });
+
+                let separator = match item {
+                    _ if index == 0 || after_loop => " ",
+                    TranscriptionItem::Loop { .. } => " ",
+                    _ => ", ",
+                };
+
+                write!(f, "{separator}{item}")?;
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Transcribe the music commands of every channel of the sound at the given address.
+///
+/// The tempo is shared between the channels, as in the engine, and the first tempo command of
+/// the sound is used from the start. SFX commands are not transcribed.
+pub fn transcribe(rom: &[u8], bank: u8, addr: u16, options: TranscriptionOptions) -> Transcription {
+    let sound = Sound::new(rom, bank, addr);
+    let channels = sound.channels();
+
+    let tempo = initial_tempo(&channels);
+
+    Transcription {
+        channels: channels
+            .into_iter()
+            .filter_map(|(voice, channel)| {
+                Some((voice, transcribe_channel(&channel?, tempo, options)))
+            })
+            .collect(),
+    }
+}
+
+/// The tempo that the sound starts with, the first tempo command of any of its channels.
+pub(super) fn initial_tempo(channels: &[(Voice, Option<Channel>)]) -> u16 {
+    channels
+        .iter()
+        .filter_map(|(_, channel)| channel.as_ref())
+        .find_map(|channel| {
+            commands(channel).find_map(|cmd| match cmd {
+                Command::Tempo(tempo) => Some(tempo),
+                _ => None,
+            })
+        })
+        .unwrap_or(0x100)
+}
+
+/// The commands of a channel in the order they are stored, until the first return.
+fn commands<'a>(channel: &Channel<'a>) -> impl Iterator<Item = Command> + 'a {
+    let Channel {
+        rom,
+        bank,
+        mut addr,
+        channel,
+    } = *channel;
+
+    (0..MAX_COMMANDS).map_while(move |_| {
+        let cmd = Command::parse(rom, bank, addr, channel).ok()?;
+        addr = addr.checked_add(cmd.len() as u16)?;
+        (cmd != Command::Return).then_some(cmd)
+    })
+}
+
+fn transcribe_channel(
+    channel: &Channel,
+    mut tempo: u16,
+    options: TranscriptionOptions,
+) -> Vec<TranscriptionItem> {
+    let mut result = Vec::new();
+
+    let mut speed = 1;
+    let mut octave = 0;
+    let mut carry = 0;
+
+    let mut duration = |length: u8, speed: u8, tempo: u16| {
+        let ticks = (length as usize + 1) * (speed as usize);
+        let delay = ticks * (tempo as usize) + carry;
+        carry = delay & 0xff;
+
+        let gcd = gcd(ticks as u32, 16 * 12);
+        NoteDuration {
+            frames: delay >> 8,
+            fraction: ((ticks as u32) / gcd, (16 * 12) / gcd),
+        }
+    };
+
+    walk(channel, options.unroll_loops, |cmd| match cmd {
+        Command::Note { pitch, length } => result.push(TranscriptionItem::Note {
+            name: pitch.name(),
+            octave,
+            duration: duration(length, speed, tempo),
+        }),
+
+        Command::DrumNote { instrument, length } => result.push(TranscriptionItem::Drum {
+            instrument,
+            duration: duration(length, speed, tempo),
+        }),
+
+        Command::Rest(length) => {
+            result.push(TranscriptionItem::Rest(duration(length, speed, tempo)))
+        }
+
+        Command::NoteType { speed: value, .. }
+        | Command::WaveNoteType { speed: value, .. }
+        | Command::DrumSpeed(value) => speed = value,
+        Command::Octave(value) => octave = 8 - value,
+        Command::Tempo(value) => tempo = value,
+
+        Command::Loop {
+            count,
+            addr: target,
+        } => result.push(TranscriptionItem::Loop {
+            count,
+            addr: target,
+        }),
+
+        _ => {}
+    });
+
+    result
+}
+
+/// Step through the commands of a channel in the order they are executed, following sound calls,
+/// until it returns or loops forever.
+///
+/// Every command except the sound calls and returns is passed to `f`. Loops are passed as well,
+/// unless `unroll_loops` is set, in which case finite loops are followed instead. The infinite
+/// loop that ends a channel is always passed, as the last command.
+pub(super) fn walk(channel: &Channel, unroll_loops: bool, mut f: impl FnMut(Command)) {
+    let mut addr = channel.addr;
+    let mut return_addr = None;
+    let mut loop_counter = 1;
+
+    for _ in 0..MAX_COMMANDS {
+        let Ok(cmd) = Command::parse(channel.rom, channel.bank, addr, channel.channel) else {
+            break;
+        };
+
+        match cmd {
+            Command::SoundCall(target) => {
+                return_addr = addr.checked_add(cmd.len() as u16);
+                addr = target;
+                continue;
+            }
+
+            Command::Loop {
+                count,
+                addr: target,
+            } => {
+                if count == 0 {
+                    f(cmd);
+                    break;
+                }
+
+                if !unroll_loops {
+                    f(cmd);
+                } else if loop_counter < count {
+                    loop_counter += 1;
+                    addr = target;
+                    continue;
+                } else {
+                    loop_counter = 1;
+                }
+            }
+
+            Command::Return => match return_addr.take() {
+                Some(target) => {
+                    addr = target;
+                    continue;
+                }
+                None => break,
+            },
+
+            _ => f(cmd),
+        }
+
+        match addr.checked_add(cmd.len() as u16) {
+            Some(next) => addr = next,
+            None => break,
+        }
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcribe() {
+        #[rustfmt::skip]
+        let rom = [
+            // music channel 1
+            0x00, 0x03, 0x00,
+            // tempo 0x100, note_type 12, 10, 1, octave 5
+            0xed, 0x01, 0x00, 0xdc, 0xa1, 0xe3,
+            // E 2, G 2, octave 6, C 4, rest 2
+            0x41, 0x71, 0xe2, 0x03, 0xc1,
+            // loop 2 times back to the first note
+            0xfe, 0x02, 0x09, 0x00,
+            0xff,
+        ];
+
+        let transcription = transcribe(&rom, 0, 0, TranscriptionOptions::new());
+        let items = transcription.channel(Voice::Pulse1).unwrap();
+
+        let eighth = NoteDuration {
+            frames: 24,
+            fraction: (1, 8),
+        };
+
+        assert_eq!(
+            items[..2],
+            [
+                TranscriptionItem::Note {
+                    name: "E",
+                    octave: 5,
+                    duration: eighth,
+                },
+                TranscriptionItem::Note {
+                    name: "G",
+                    octave: 5,
+                    duration: eighth,
+                },
+            ]
+        );
+        assert_eq!(items.len(), 5);
+        assert_eq!(
+            transcription.to_string(),
+            "P1: E5 1/8, G5 1/8, C6 1/4, rest 1/8 | loop x2 to 0009\n"
+        );
+
+        let options = TranscriptionOptions::new().unroll_loops(true);
+        let transcription = transcribe(&rom, 0, 0, options);
+        assert_eq!(
+            transcription.to_string(),
+            "P1: E5 1/8, G5 1/8, C6 1/4, rest 1/8, E6 1/8, G6 1/8, C6 1/4, rest 1/8\n"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Wave instruments of the wave channel, which every audio engine of the first generation keeps
+//! in a table in the same bank as its sound headers.
+
+/// Number of leading pointers in the table that have to point at consecutive waves.
+const MIN_INSTRUMENTS: usize = 5;
+
+/// Number of bytes of wave RAM, holding 32 4-bit samples.
+pub(super) const WAVE_LEN: usize = 16;
+
+/// Find the table of wave instrument pointers in the given bank.
+///
+/// The table isn't at the same address in every game, so it is found by its shape instead: a list
+/// of pointers to 16 byte waves, where the waves directly follow the list. At least the first
+/// `MIN_INSTRUMENTS` waves have to be stored in order.
+pub(super) fn find_table(rom: &[u8], bank: u8) -> Option<u16> {
+    let start = (bank as usize) * 0x4000;
+    let data = rom.get(start..)?;
+    let data = &data[..data.len().min(0x4000)];
+    let window = if bank == 0 { 0x0000 } else { 0x4000 };
+
+    let pointer = |offset: usize| {
+        data.get(offset..(offset + 2))
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+
+    (0..data.len()).find_map(|offset| {
+        let first = pointer(offset)?;
+
+        if (first & 0xc000) != window {
+            return None;
+        }
+
+        let waves = (first & 0x3fff) as usize;
+        let count = waves.checked_sub(offset)? / 2;
+
+        let is_table = (MIN_INSTRUMENTS..=16).contains(&count)
+            && waves == offset + count * 2
+            && waves + MIN_INSTRUMENTS * WAVE_LEN <= data.len()
+            && (1..MIN_INSTRUMENTS).all(|index| {
+                pointer(offset + index * 2) == Some(first + (index * WAVE_LEN) as u16)
+            });
+
+        is_table.then_some(window | offset as u16)
+    })
+}
+
+/// The wave RAM of the given instrument, two samples per byte with the high nibble first.
+pub(super) fn pattern(rom: &[u8], bank: u8, table: u16, instrument: u8) -> Option<[u8; WAVE_LEN]> {
+    let offset = |addr: u16| ((bank as usize) * 0x4000) + ((addr as usize) & 0x3fff);
+
+    let entry = offset(table) + (instrument as usize) * 2;
+    let addr = u16::from_le_bytes(rom.get(entry..(entry + 2))?.try_into().ok()?);
+
+    rom.get(offset(addr)..(offset(addr) + WAVE_LEN))?
+        .try_into()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bank with some padding, followed by a table of six wave instruments.
+    fn bank() -> Vec<u8> {
+        let mut data = vec![0xff; 0x20];
+
+        for index in 0..6u16 {
+            data.extend((0x4020 + 12 + index * 16).to_le_bytes());
+        }
+
+        for index in 0..6u8 {
+            data.extend([index << 4 | index; WAVE_LEN]);
+        }
+
+        data
+    }
+
+    #[test]
+    fn test_find_table() {
+        let mut rom = vec![0; 0x4000];
+        rom.extend(bank());
+
+        assert_eq!(find_table(&rom, 1), Some(0x4020));
+        assert_eq!(find_table(&rom, 0), None);
+        assert_eq!(find_table(&rom, 2), None);
+
+        // a table with too few waves in order
+        rom[0x4020 + 6] = 0;
+        assert_eq!(find_table(&rom, 1), None);
+    }
+
+    #[test]
+    fn test_pattern() {
+        let mut rom = vec![0; 0x4000];
+        rom.extend(bank());
+
+        assert_eq!(pattern(&rom, 1, 0x4020, 0), Some([0x00; WAVE_LEN]));
+        assert_eq!(pattern(&rom, 1, 0x4020, 5), Some([0x55; WAVE_LEN]));
+
+        // the pointer of an instrument past the table is read anyway, like the engine does
+        assert_eq!(pattern(&rom, 1, 0x4020, 6), Some([0xff; WAVE_LEN]));
+        assert_eq!(pattern(&rom, 1, 0x4020, 0xff), None);
+    }
+}
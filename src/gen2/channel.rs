@@ -1,23 +1,63 @@
-use super::command::Command;
+use std::collections::{HashMap, VecDeque};
 
-pub const SAMPLES_PER_FRAME: usize = 17556;
-pub const SOURCE_SAMPLE_RATE: usize = 1048576;
+#[cfg(feature = "apu-ref")]
+use crate::apu::FrameState;
+use crate::options::DEFAULT_COMMAND_LIMIT;
+use crate::{EventKind, NoteEvent, RomLocation, Voice, SAMPLES_PER_FRAME, SOURCE_SAMPLE_RATE};
 
-fn calc_duty(duty: u8, period_count: f64) -> bool {
+use super::command::{Command, ParseError, ParseErrorKind};
+
+/// Number of entries in the cache of decoded commands.
+const COMMAND_CACHE_SIZE: usize = 64;
+
+/// Recently decoded commands, direct-mapped by address. ROM data never changes, so an entry only
+/// has to be checked against the address and the channel type it was decoded for.
+type CommandCache = [Option<(u16, ChannelType, Command)>; COMMAND_CACHE_SIZE];
+
+/// Output of the pulse channel at the given step, out of eight, of the duty cycle.
+fn calc_duty(duty: u8, step: usize) -> bool {
     match duty {
-        0 => (0.5..0.625).contains(&period_count),
-        1 => (0.5..0.75).contains(&period_count),
-        2 => (0.5..0.875).contains(&period_count),
-        3 => !(0.5..0.875).contains(&period_count),
-        _ => panic!("Invalid duty cycle: {}", duty),
+        0 => step == 4,
+        1 => (4..6).contains(&step),
+        2 => (4..7).contains(&step),
+        // the duty cycle is masked to two bits
+        _ => !(4..7).contains(&step),
+    }
+}
+
+/// Number of samples between each clock of the noise channel's LFSR. \
+/// Returns `None` for a clock shift of 14 or 15, since the LFSR then receives no clocks at all
+/// and keeps holding its current output.
+fn noise_clock_period(shift: u8, divider: u8) -> Option<usize> {
+    match (shift, divider) {
+        (0xe.., _) => None,
+        (_, 0) => Some(1 << (shift + 1)),
+        (_, _) => Some((divider as usize) << (shift + 2)),
     }
 }
 
+/// Apply a detune in cents to a frequency register value, rounded to the nearest register value.
+fn detune(freq: usize, cents: f32) -> usize {
+    if cents == 0.0 {
+        return freq;
+    }
+
+    let period = (2048 - freq) as f64 / 2f64.powf(cents as f64 / 1200.0);
+    2048 - (period.round() as usize).clamp(1, 2048)
+}
+
 fn sample(bin: isize, volume: isize) -> f32 {
     (((2 * bin) - 1) as f32) * (((volume as f32) * -1.0) / 16.0)
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// Exact output level of the DAC for the given 4-bit digital value, on the same scale as
+/// `sample`. Unlike `sample`, the level isn't centered around zero, so a channel carries the DC
+/// offset that moves with its volume envelope.
+fn dac_sample(bin: isize, volume: isize) -> f32 {
+    (15 - 2 * bin * volume) as f32 / 16.0
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum ChannelType {
     MusicPulse,
     MusicWave,
@@ -28,24 +68,32 @@ pub enum ChannelType {
 }
 
 impl ChannelType {
-    fn to_muisc(self) -> ChannelType {
+    /// The channel type that notes are interpreted as after a `ToggleSfx`.
+    pub(super) fn toggle_sfx(self) -> ChannelType {
         match self {
-            ChannelType::MusicPulse => ChannelType::MusicPulse,
-            ChannelType::MusicWave => ChannelType::MusicWave,
-            ChannelType::MusicNoise => ChannelType::MusicNoise,
+            ChannelType::MusicPulse => ChannelType::SfxPulse,
+            ChannelType::MusicWave => ChannelType::SfxWave,
+            ChannelType::MusicNoise => ChannelType::SfxNoise,
             ChannelType::SfxPulse => ChannelType::MusicPulse,
             ChannelType::SfxWave => ChannelType::MusicWave,
             ChannelType::SfxNoise => ChannelType::MusicNoise,
         }
     }
+
+    pub(super) fn is_music(self) -> bool {
+        matches!(
+            self,
+            ChannelType::MusicPulse | ChannelType::MusicWave | ChannelType::MusicNoise
+        )
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Channel<'a> {
-    rom: &'a [u8],
-    bank: u8,
-    addr: u16,
-    channel: ChannelType,
+    pub(super) rom: &'a [u8],
+    pub(super) bank: u8,
+    pub(super) addr: u16,
+    pub(super) channel: ChannelType,
 }
 
 impl<'a> Channel<'a> {
@@ -70,15 +118,34 @@ pub struct ChannelIterator<'a> {
     addr: u16,
     channel: ChannelType,
 
+    /// Tempo of the channel, the number of 1/256 frames in each unit of note length.
     length: usize,
+    /// Tempo set by the last `Tempo` command, until it is passed on with `take_tempo`.
+    new_tempo: Option<u16>,
 
     pitch: i16,
     pitch_sweep: i8,
     pitch_sweep_delay: u8,
     pitch_sweep_period: u8,
+    detune: f32,
+    quantize_dac: bool,
 
-    loop_counter: u8,
+    /// Number of times each loop has been played, by the address of the loop command.
+    loop_counters: HashMap<u16, u32>,
+    /// Number of times to play the body of an infinite loop, `None` loops forever.
+    loop_iterations: Option<u32>,
+    /// Address to continue at when returning from a `SoundCall`.
+    return_addr: Option<u16>,
+    /// Value of the last `SetCondition`, checked by `SoundJumpIf`.
+    condition: u8,
     note_delay: usize,
+    /// Note speed of music notes, multiplied with their length.
+    note_speed: u8,
+    /// Value of the last `Octave` command, 7 is the lowest octave.
+    octave: u8,
+    /// Volume and fade of music notes, as given by the last `NoteType`.
+    note_volume: u8,
+    note_fade: i8,
 
     duty: u8,
     volume: u8,
@@ -89,10 +156,32 @@ pub struct ChannelIterator<'a> {
     noise_params: u8,
     noise_buffer: u16,
 
-    period_count: f64,
+    /// Current step, out of eight, of the duty cycle.
+    duty_step: usize,
+    /// Number of samples spent in the current step of the duty cycle.
+    duty_timer: usize,
+    /// Fraction of a step of the initial phase, applied once the length of a step is known.
+    phase_remainder: f32,
+    is_disabled: bool,
     is_done: bool,
 
     is_infinite: Option<bool>,
+    /// Maximum number of commands to execute in a row without playing anything.
+    command_limit: usize,
+    /// Whether the channel was stopped because it hit the `command_limit`.
+    is_stuck: bool,
+    error: Option<ParseError>,
+
+    /// Number of frames that has been generated.
+    frame: usize,
+    /// Notes triggered since the last call to `take_events`, when recording.
+    events: Option<(Voice, Vec<NoteEvent>)>,
+    /// Music loops revisit the same few commands over and over, `None` disables the cache.
+    command_cache: Option<CommandCache>,
+    /// Number of commands that has been decoded, used by tests to make sure that commands
+    /// aren't decoded more often than needed.
+    #[cfg(debug_assertions)]
+    decoded: usize,
 }
 
 impl<'a> ChannelIterator<'a> {
@@ -104,14 +193,24 @@ impl<'a> ChannelIterator<'a> {
             channel: channel.channel,
 
             length: length as usize,
+            new_tempo: None,
 
             pitch,
             pitch_sweep: 0,
             pitch_sweep_delay: 0,
             pitch_sweep_period: 0,
+            detune: 0.0,
+            quantize_dac: false,
 
-            loop_counter: 1,
+            loop_counters: HashMap::new(),
+            loop_iterations: None,
+            return_addr: None,
+            condition: 0,
             note_delay: 0,
+            note_speed: 1,
+            octave: 0,
+            note_volume: 0,
+            note_fade: 0,
 
             duty: 0,
             volume: 0,
@@ -122,143 +221,249 @@ impl<'a> ChannelIterator<'a> {
             noise_params: 0,
             noise_buffer: 0x7fff,
 
-            period_count: 0.0,
+            duty_step: 0,
+            duty_timer: 0,
+            phase_remainder: 0.0,
+            is_disabled: false,
             is_done: false,
 
             is_infinite: None,
+            command_limit: DEFAULT_COMMAND_LIMIT,
+            is_stuck: false,
+            error: None,
+
+            frame: 0,
+            events: None,
+            command_cache: Some([None; COMMAND_CACHE_SIZE]),
+            #[cfg(debug_assertions)]
+            decoded: 0,
+        }
+    }
+
+    /// Detune the pulse output by the given amount of cents.
+    pub fn detune(mut self, cents: f32) -> Self {
+        self.detune = cents;
+        self
+    }
+
+    /// Output the exact levels of the DAC instead of levels centered around zero.
+    pub fn quantize_dac(mut self, enabled: bool) -> Self {
+        self.quantize_dac = enabled;
+        self
+    }
+
+    /// Set the initial phase of the channel, as a fraction of a period.
+    pub fn phase(mut self, phase: f32) -> Self {
+        let steps = phase.rem_euclid(1.0) * 8.0;
+        self.duty_step = (steps as usize) % 8;
+        self.phase_remainder = steps.fract();
+        self
+    }
+
+    pub(super) fn channel_type(&self) -> ChannelType {
+        self.channel
+    }
+
+    /// The tempo set by a `Tempo` command since the last call, if any.
+    pub(super) fn take_tempo(&mut self) -> Option<u16> {
+        self.new_tempo.take()
+    }
+
+    /// Apply a tempo set by another channel, the engine keeps a single tempo for all music
+    /// channels and another one for all SFX channels.
+    pub(super) fn set_tempo(&mut self, channel: ChannelType, tempo: u16) {
+        if channel.is_music() == self.channel.is_music() {
+            self.length = tempo as usize;
+            self.note_delay &= !0xff;
         }
     }
 
     pub fn is_infinite(&self) -> Option<bool> {
         self.is_infinite
     }
-}
 
-impl Iterator for ChannelIterator<'_> {
-    type Item = [f32; SAMPLES_PER_FRAME];
+    /// Stop the channel after executing `limit` commands in a row without playing anything.
+    pub fn command_limit(mut self, limit: usize) -> Self {
+        self.command_limit = limit;
+        self
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if self.is_done {
-                return None;
+    /// Play the body of an infinite loop `iterations` times in total and then move on past it,
+    /// instead of looping forever. `None` loops forever, like the engine.
+    pub fn loop_iterations(mut self, iterations: Option<u32>) -> Self {
+        self.loop_iterations = iterations;
+        self
+    }
+
+    /// Whether the channel was stopped because it executed too many commands in a row without
+    /// playing anything, e.g. a loop that jumps to itself.
+    pub fn is_stuck(&self) -> bool {
+        self.is_stuck
+    }
+
+    /// The command that stopped the channel because it couldn't be parsed, if any.
+    pub fn error(&self) -> Option<ParseError> {
+        self.error
+    }
+
+    /// Iterate over the notes that the channel plays.
+    pub fn note_events(mut self, voice: Voice) -> NoteEvents<'a> {
+        self.events = Some((voice, Vec::new()));
+
+        NoteEvents {
+            channel: self,
+            pending: VecDeque::new(),
+            end: None,
+        }
+    }
+
+    /// Record a note being triggered, only the first pass of an infinite loop is recorded.
+    fn record_event(&mut self, freq: u16, kind: EventKind) {
+        if self.is_infinite == Some(true) {
+            return;
+        }
+
+        if let Some((voice, events)) = &mut self.events {
+            events.push(NoteEvent {
+                voice: *voice,
+                start: self.frame * SAMPLES_PER_FRAME,
+                len: 0,
+                freq,
+                volume: self.volume,
+                fade: self.volume_fade,
+                kind,
+            });
+        }
+    }
+
+    /// Decode the command at the current address, going through the cache.
+    fn decode(&mut self) -> Result<Command, ParseError> {
+        // the data of a channel never continues past the end of its bank, a command outside of
+        // the bank ends the channel with an `OutOfBounds` error at the address it was read from
+        if !RomLocation::new(self.bank, self.addr).is_mapped() {
+            return Err(self.error_at(ParseErrorKind::OutOfBounds));
+        }
+
+        let slot = (self.addr as usize) % COMMAND_CACHE_SIZE;
+        let key = (self.addr, self.channel);
+
+        if let Some((addr, channel, cmd)) =
+            self.command_cache.as_ref().and_then(|cache| cache[slot])
+        {
+            if (addr, channel) == key {
+                return Ok(cmd);
             }
+        }
 
-            // Generate sound data
-            if self.note_delay > 0xff {
-                let mut result = [0.0; SAMPLES_PER_FRAME];
-
-                match self.channel {
-                    ChannelType::SfxPulse => {
-                        // number of samples for a single period of the note's pitch
-                        let period = SOURCE_SAMPLE_RATE
-                            * (2048
-                                - ((self.freq as usize + ((self.pitch as u16) as usize)) & 0x7ff))
-                            / 131072;
-
-                        // apply this note
-                        for data in result.iter_mut() {
-                            let enabled = calc_duty(self.duty & 0b11, self.period_count);
-                            *data = sample(enabled as isize, self.volume as isize);
-
-                            self.period_count += 1.0 / (period as f64);
-
-                            if self.period_count >= 1.0 {
-                                self.period_count -= 1.0;
-                            }
-                        }
-
-                        // once per frame, adjust duty
-                        self.duty = self.duty.rotate_left(2);
-                    }
+        let cmd = Command::parse(self.rom, self.bank, self.addr, self.channel)?;
 
-                    ChannelType::SfxNoise => {
-                        let shift = self.noise_params >> 4;
-                        let shift = if shift > 0xd { shift & 0xd } else { shift }; // not sure how to deal with E or F, but its so low you can hardly notice it anyway
-
-                        let divider = self.noise_params & 0x7;
-                        let width = (self.noise_params & 0x8) == 0x8;
-
-                        for (index, data) in result.iter_mut().enumerate() {
-                            let bit0 = self.noise_buffer & 1;
-                            *data = sample((1 ^ bit0) as isize, self.volume as isize);
-
-                            // according to params, update buffer
-                            if index
-                                % ((2.0
-                                    * (if divider == 0 { 0.5 } else { divider as f64 })
-                                    * (1 << (shift + 1)) as f64)
-                                    as usize)
-                                == 0
-                            {
-                                let bit1 = (self.noise_buffer >> 1) & 1;
-                                self.noise_buffer =
-                                    (self.noise_buffer >> 1) | ((bit0 ^ bit1) << 14);
-                                if width {
-                                    self.noise_buffer =
-                                        (self.noise_buffer >> 1) | ((bit0 ^ bit1) << 6);
-                                }
-                            }
-                        }
-                    }
+        #[cfg(debug_assertions)]
+        {
+            self.decoded += 1;
+        }
 
-                    channel => todo!("Channel {:?}", channel),
-                }
+        if let Some(cache) = &mut self.command_cache {
+            cache[slot] = Some((self.addr, self.channel, cmd));
+        }
 
-                if self.note_delay >= 0x100 {
-                    self.note_delay -= 0x100;
-                }
+        Ok(cmd)
+    }
+
+    /// The frequency register value that a note of the given frequency is played at. The engine
+    /// adds the full 16-bit pitch offset to the frequency when the note is triggered, carrying
+    /// into the high byte, but only the lower 11 bits of the result ends up in NR13/NR14.
+    fn pitched(&self, freq: u16) -> u16 {
+        freq.wrapping_add(self.pitch as u16) & 0x7ff
+    }
+}
 
-                // once per frame * fadeamount, adjust volume
-                match self.volume_fade_delay {
-                    0 => {}
-                    1 => {
-                        self.volume_fade_delay = (self.volume_fade & 0b111) as u8;
+impl<'a> ChannelIterator<'a> {
+    /// Advance one frame without synthesizing any samples, returns `false` once the channel is
+    /// done. This steps through exactly the same logic as `next`, only skipping the audio, so it
+    /// is a cheap way of measuring how long a channel plays.
+    pub fn skip_frame(&mut self) -> bool {
+        if !self.start_frame() {
+            return false;
+        }
 
-                        if self.volume_fade < 0 && self.volume < 15 {
-                            self.volume += 1;
-                        } else if self.volume_fade > 0 && self.volume > 0 {
-                            self.volume -= 1;
-                        }
-                    }
-                    _ => {
-                        self.volume_fade_delay -= 1;
-                    }
-                }
+        self.end_frame();
+        true
+    }
 
-                // once per frame * fadeamount, adjust pitch
-                match self.pitch_sweep_delay {
-                    0 => {}
-                    1 => {
-                        self.pitch_sweep_delay = self.pitch_sweep_period;
-                        let offset = self.freq >> self.pitch_sweep.unsigned_abs();
+    /// Advance one frame the same way as `next`, including the state of the oscillators, but
+    /// without writing out the samples. Returns `false` once the channel is done.
+    pub fn advance_frame(&mut self) -> bool {
+        if !self.start_frame() {
+            return false;
+        }
 
-                        if self.pitch_sweep < 0 {
-                            self.freq = self.freq.wrapping_sub(offset);
-                        } else {
-                            self.freq = self.freq.wrapping_add(offset);
-                        }
-                    }
-                    _ => {
-                        self.pitch_sweep_delay -= 1;
-                    }
-                }
+        self.advance_oscillators();
+        self.end_frame();
+        true
+    }
 
-                return Some(result);
+    /// Process commands until there is something to play for the next frame, returns `false`
+    /// once the channel is done.
+    fn start_frame(&mut self) -> bool {
+        if self.is_stuck || self.error.is_some() {
+            return false;
+        }
+
+        let mut commands = 0;
+
+        loop {
+            if self.is_done {
+                return false;
+            }
+
+            if self.note_delay > 0xff {
+                return true;
             }
 
             // Read and process next command
 
-            let cmd = Command::parse(self.rom, self.bank, self.addr, self.channel);
+            if commands == self.command_limit {
+                self.is_stuck = true;
+                self.is_done = true;
+                self.is_infinite = Some(false);
+                return false;
+            }
+
+            commands += 1;
+            let cmd = match self.decode() {
+                Ok(cmd) => cmd,
+                Err(err) => {
+                    self.stop(err);
+                    return false;
+                }
+            };
+
+            // the wave channel needs the wave instruments of the engine, which aren't supported
+            // yet, so it stops at its first note
+            if self.is_wave() && matches!(cmd, Command::Note { .. } | Command::SquareNote { .. }) {
+                self.stop(self.error_at(ParseErrorKind::Unsupported));
+                return false;
+            }
+
+            let Some(next) = self.addr.checked_add(cmd.len() as u16) else {
+                self.stop(self.error_at(ParseErrorKind::OutOfBounds));
+                return false;
+            };
 
             match cmd {
                 Command::Return => {
+                    if let Some(addr) = self.return_addr.take() {
+                        self.addr = addr;
+                        continue;
+                    }
+
                     self.is_done = true;
                     self.is_infinite = Some(false);
                     continue;
                 }
 
-                Command::ExecuteMusic => {
-                    self.channel = self.channel.to_muisc();
+                Command::ToggleSfx => {
+                    self.channel = self.channel.toggle_sfx();
                 }
 
                 Command::DutyCycle(a) => {
@@ -275,18 +480,102 @@ impl Iterator for ChannelIterator<'_> {
                     self.pitch_sweep_period = length;
                 }
 
+                Command::NoteType {
+                    speed,
+                    volume,
+                    fade,
+                } => {
+                    self.note_speed = speed;
+                    self.note_volume = volume;
+                    self.note_fade = fade;
+                }
+
+                Command::Tempo(tempo) => {
+                    // the fractional part of the note delay is reset for every channel
+                    self.length = tempo as usize;
+                    self.note_delay &= !0xff;
+                    self.new_tempo = Some(tempo);
+                }
+
+                Command::Octave(octave) => {
+                    self.octave = octave;
+                }
+
+                Command::Note { pitch, length } => {
+                    self.note_delay = self.note_duration(length);
+
+                    self.freq = self.pitched(pitch.freq(self.octave));
+                    self.volume = self.note_volume;
+                    self.volume_fade = self.note_fade;
+                    self.volume_fade_delay = (self.note_fade & 0b111) as u8;
+                    self.record_event(self.freq, EventKind::Note);
+                }
+
+                Command::Rest(length) => {
+                    self.note_delay = self.note_duration(length);
+
+                    // the engine sets the volume of the channel to zero
+                    self.volume = 0;
+                    self.volume_fade = 0;
+                    self.volume_fade_delay = 0;
+                }
+
+                Command::VolumeEnvelope { volume, fade } => {
+                    self.note_volume = volume;
+                    self.note_fade = fade;
+                }
+
+                Command::DrumSpeed(speed) => {
+                    self.note_speed = speed;
+                }
+
+                // the output is mono, and only a single sound is played at a time
+                Command::StereoPanning(_)
+                | Command::ForceStereoPanning(_)
+                | Command::SfxPriorityOn
+                | Command::SfxPriorityOff => {}
+
+                Command::SoundJump(addr) => {
+                    // jumping backwards plays the same commands again, forever
+                    if addr <= self.addr {
+                        self.is_infinite = Some(true);
+                    }
+
+                    self.addr = addr;
+                    continue;
+                }
+
+                Command::SoundCall(addr) => {
+                    // The engine only keeps a single return address, a nested call overwrites it
+                    self.return_addr = Some(next);
+                    self.addr = addr;
+                    continue;
+                }
+
                 Command::Loop { count, addr } => {
+                    let count = match (count, self.loop_iterations) {
+                        (0, Some(iterations)) => iterations.max(1),
+                        (count, _) => count as u32,
+                    };
+
                     if count == 0 {
                         self.addr = addr;
                         self.is_infinite = Some(true);
                         continue;
                     }
 
-                    if self.loop_counter < count {
-                        self.loop_counter += 1;
+                    // The engine keeps a single counter per channel, which is reset once the
+                    // loop is done. Counting every loop separately plays back-to-back loops the
+                    // same way, and makes nested loops work as well.
+                    let counter = self.loop_counters.entry(self.addr).or_insert(1);
+
+                    if *counter < count {
+                        *counter += 1;
                         self.addr = addr;
                         continue;
                     }
+
+                    self.loop_counters.remove(&self.addr);
                 }
 
                 Command::SquareNote {
@@ -295,14 +584,20 @@ impl Iterator for ChannelIterator<'_> {
                     fade,
                     freq,
                 } => {
-                    // number of samples for this single note
-                    self.note_delay =
-                        self.length * (length as usize + 1) + (self.note_delay & 0xff);
+                    self.note_delay = self.note_duration(length);
 
                     self.volume = volume;
                     self.volume_fade = fade;
                     self.volume_fade_delay = (fade & 0b111) as u8;
-                    self.freq = freq;
+                    self.freq = self.pitched(freq);
+                    self.record_event(self.freq, EventKind::SquareNote);
+
+                    // on trigger, the hardware performs the overflow check whenever the sweep
+                    // shift is non-zero, even if the sweep period is 0
+                    self.is_disabled = self.pitch_sweep > 0 && {
+                        let freq = self.freq as usize;
+                        freq + (freq >> self.pitch_sweep) > 0x7ff
+                    };
                 }
 
                 Command::NoiseNote {
@@ -311,25 +606,876 @@ impl Iterator for ChannelIterator<'_> {
                     fade,
                     value,
                 } => {
-                    // number of samples for this single note
-                    self.note_delay =
-                        self.length * (length as usize + 1) + (self.note_delay & 0xff);
+                    self.note_delay = self.note_duration(length);
 
                     self.volume = volume;
                     self.volume_fade = fade;
                     self.volume_fade_delay = (fade & 0b111) as u8;
+                    // the pitch offset is added to the full 16-bit frequency as well, but only
+                    // the low byte is written to NR43, so the carry into the high byte is lost
                     self.noise_params = value.wrapping_add(self.pitch as u8);
                     self.noise_buffer = 0x7fff;
+                    self.record_event(self.noise_params as u16, EventKind::NoiseNote);
                 }
 
                 Command::PitchOffset(offset) => {
                     self.pitch = offset;
                 }
 
-                _ => todo!("PCM data of {:?}", cmd),
+                Command::TempoRelative(offset) => {
+                    let tempo = (self.length as u16).wrapping_add(offset as u16);
+                    self.length = tempo as usize;
+                    self.note_delay &= !0xff;
+                    self.new_tempo = Some(tempo);
+                }
+
+                Command::SetCondition(condition) => {
+                    self.condition = condition;
+                }
+
+                Command::SoundJumpIf { condition, addr } if condition == self.condition => {
+                    self.addr = addr;
+                    continue;
+                }
+
+                Command::SoundJumpIf { .. } => {}
+
+                // Transposition, vibrato, pitch slides, the master volume, drum kits, wave
+                // instruments and commands that start other sounds.
+                Command::Transpose { .. }
+                | Command::Vibrato { .. }
+                | Command::PitchSlide { .. }
+                | Command::Volume { .. }
+                | Command::DrumNote { .. }
+                | Command::ToggleNoise(_)
+                | Command::SfxToggleNoise(_)
+                | Command::WaveNoteType { .. }
+                | Command::WaveVolumeEnvelope { .. }
+                | Command::RestartChannel(_)
+                | Command::NewSong(_) => {
+                    self.stop(self.error_at(ParseErrorKind::Unsupported));
+                    return false;
+                }
+            }
+
+            self.addr = next;
+        }
+    }
+
+    /// Stop the channel because of the given error, the same way as when it gets stuck.
+    fn stop(&mut self, err: ParseError) {
+        self.error = Some(err);
+        self.is_done = true;
+        self.is_infinite = Some(false);
+    }
+
+    /// An error of the given kind for the command at the current address.
+    fn error_at(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            kind,
+            bank: self.bank,
+            addr: self.addr,
+            channel: self.channel,
+        }
+    }
+
+    fn is_wave(&self) -> bool {
+        matches!(self.channel, ChannelType::SfxWave | ChannelType::MusicWave)
+    }
+
+    /// Number of 1/256 frames of a note of the given length, including the fraction left over
+    /// from the previous note. The cry length is the tempo of the pulse and wave channels.
+    ///
+    /// The engine multiplies the length with the note speed in 8 bits, and that with the tempo
+    /// in 16 bits, so a tempo above 0x100 makes long notes wrap around. The engine moves on to
+    /// the next note once less than two frames are left, so a note of 0 frames lasts one frame.
+    fn note_duration(&self, length: u8) -> usize {
+        let units = (self.note_speed as usize * (length as usize + 1)) & 0xff;
+        let delay = (units * self.length + (self.note_delay & 0xff)) & 0xffff;
+
+        match delay {
+            0..=0xff => delay + 0x100,
+            _ => delay,
+        }
+    }
+
+    /// Output level for the given bit of the waveform at the current volume.
+    fn output(&self, bin: isize) -> f32 {
+        match self.quantize_dac {
+            true => dac_sample(bin, self.volume as isize),
+            false => sample(bin, self.volume as isize),
+        }
+    }
+
+    /// Synthesize the samples of the current frame.
+    fn render_frame(&mut self) -> [f32; SAMPLES_PER_FRAME] {
+        let mut result = [0.0; SAMPLES_PER_FRAME];
+
+        match self.channel {
+            ChannelType::SfxPulse | ChannelType::MusicPulse => {
+                // number of samples for each of the eight steps of the duty cycle, the
+                // period is always an exact number of samples at the source sample rate
+                let freq = detune(self.freq as usize, self.detune);
+                let step_len = SOURCE_SAMPLE_RATE * (2048 - freq) / 131072 / 8;
+
+                if self.phase_remainder > 0.0 {
+                    self.duty_timer = (self.phase_remainder * step_len as f32) as usize;
+                    self.phase_remainder = 0.0;
+                }
+
+                // apply this note, a disabled channel stays silent until the next trigger
+                for data in result.iter_mut().filter(|_| !self.is_disabled) {
+                    let enabled = calc_duty(self.duty & 0b11, self.duty_step);
+                    *data = self.output(enabled as isize);
+
+                    self.duty_timer += 1;
+
+                    if self.duty_timer >= step_len {
+                        self.duty_timer = 0;
+                        self.duty_step = (self.duty_step + 1) % 8;
+                    }
+                }
             }
 
-            self.addr += cmd.len() as u16;
+            ChannelType::SfxNoise | ChannelType::MusicNoise => {
+                let shift = self.noise_params >> 4;
+                let divider = self.noise_params & 0x7;
+                let width = (self.noise_params & 0x8) == 0x8;
+                let clock_period = noise_clock_period(shift, divider);
+
+                for (index, data) in result.iter_mut().enumerate() {
+                    let bit0 = self.noise_buffer & 1;
+                    *data = self.output((1 ^ bit0) as isize);
+
+                    // according to params, update buffer
+                    if matches!(clock_period, Some(period) if index % period == 0) {
+                        self.clock_noise(width);
+                    }
+                }
+            }
+
+            // the wave channel stops at its first note, so it only ever rests
+            ChannelType::SfxWave | ChannelType::MusicWave => {}
+        }
+
+        result
+    }
+
+    /// Step the oscillators over the current frame without synthesizing it, leaving them in the
+    /// same state as `render_frame` does.
+    fn advance_oscillators(&mut self) {
+        match self.channel {
+            ChannelType::SfxPulse | ChannelType::MusicPulse => {
+                let freq = detune(self.freq as usize, self.detune);
+                let step_len = SOURCE_SAMPLE_RATE * (2048 - freq) / 131072 / 8;
+
+                if self.phase_remainder > 0.0 {
+                    self.duty_timer = (self.phase_remainder * step_len as f32) as usize;
+                    self.phase_remainder = 0.0;
+                }
+
+                if self.is_disabled {
+                    return;
+                }
+
+                // a timer that was left past the end of a step by a higher note wraps on the
+                // first sample, after that every step is exactly `step_len` samples long
+                let mut samples = SAMPLES_PER_FRAME;
+                if self.duty_timer >= step_len {
+                    self.duty_timer = 0;
+                    self.duty_step = (self.duty_step + 1) % 8;
+                    samples -= 1;
+                }
+
+                let step_len = step_len.max(1);
+                let timer = self.duty_timer + samples;
+                self.duty_step = (self.duty_step + timer / step_len) % 8;
+                self.duty_timer = timer % step_len;
+            }
+
+            ChannelType::SfxNoise | ChannelType::MusicNoise => {
+                let shift = self.noise_params >> 4;
+                let width = (self.noise_params & 0x8) == 0x8;
+
+                if let Some(period) = noise_clock_period(shift, self.noise_params & 0x7) {
+                    // clocked on every sample whose index is a multiple of the period
+                    for _ in 0..((SAMPLES_PER_FRAME - 1) / period + 1) {
+                        self.clock_noise(width);
+                    }
+                }
+            }
+
+            ChannelType::SfxWave | ChannelType::MusicWave => {}
+        }
+    }
+
+    /// Shift the noise LFSR by one step, in 7-bit mode when `width` is set.
+    fn clock_noise(&mut self, width: bool) {
+        let feedback = (self.noise_buffer & 1) ^ ((self.noise_buffer >> 1) & 1);
+        self.noise_buffer = (self.noise_buffer >> 1) | (feedback << 14);
+
+        // in 7-bit mode, the feedback is written to bit 6 as well
+        if width {
+            self.noise_buffer = (self.noise_buffer & !0x40) | (feedback << 6);
+        }
+    }
+
+    /// Update the duty cycle, envelope and pitch sweep, which the engine does once per frame.
+    fn end_frame(&mut self) {
+        // once per frame, adjust duty
+        self.duty = self.duty.rotate_left(2);
+
+        if self.note_delay >= 0x100 {
+            self.note_delay -= 0x100;
+        }
+
+        // once per frame * fadeamount, adjust volume
+        match self.volume_fade_delay {
+            0 => {}
+            1 => {
+                self.volume_fade_delay = (self.volume_fade & 0b111) as u8;
+
+                if self.volume_fade < 0 && self.volume < 15 {
+                    self.volume += 1;
+                } else if self.volume_fade > 0 && self.volume > 0 {
+                    self.volume -= 1;
+                }
+            }
+            _ => {
+                self.volume_fade_delay -= 1;
+            }
+        }
+
+        // once per frame * fadeamount, adjust pitch
+        match self.pitch_sweep_delay {
+            0 => {}
+            1 => {
+                self.pitch_sweep_delay = self.pitch_sweep_period;
+                let offset = self.freq >> self.pitch_sweep.unsigned_abs();
+
+                // with a shift of 0 the new frequency is calculated, but never written back
+                if self.pitch_sweep < 0 {
+                    self.freq -= offset;
+                } else if self.pitch_sweep > 0 {
+                    self.freq = (self.freq + offset) & 0x7ff;
+                }
+            }
+            _ => {
+                self.pitch_sweep_delay -= 1;
+            }
+        }
+
+        self.frame += 1;
+    }
+}
+
+#[cfg(feature = "apu-ref")]
+impl ChannelIterator<'_> {
+    /// Advance one frame, like `skip_frame`, and return what the engine wrote to the channel
+    /// during it. Notes are only reported for the first pass of an infinite loop.
+    pub(crate) fn frame_state(&mut self) -> Option<FrameState> {
+        if self.events.is_none() {
+            self.events = Some((Voice::Pulse1, Vec::new()));
+        }
+
+        if !self.skip_frame() {
+            return None;
+        }
+
+        let trigger = self
+            .events
+            .as_mut()
+            .and_then(|(_, events)| events.drain(..).next_back());
+
+        Some(FrameState {
+            trigger,
+            // the duty cycle is rotated at the end of every frame
+            duty: self.duty.rotate_right(2) & 0b11,
+            sweep: (self.pitch_sweep_period << 4) | ((self.pitch_sweep as u8) & 0x0f),
+        })
+    }
+}
+
+impl Iterator for ChannelIterator<'_> {
+    type Item = [f32; SAMPLES_PER_FRAME];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.start_frame() {
+            return None;
+        }
+
+        let result = self.render_frame();
+        self.end_frame();
+        Some(result)
+    }
+}
+
+/// Iterator over the notes of a channel, see `ChannelIterator::note_events`.
+#[derive(Debug, Clone)]
+pub struct NoteEvents<'a> {
+    channel: ChannelIterator<'a>,
+    pending: VecDeque<NoteEvent>,
+    end: Option<usize>,
+}
+
+impl Iterator for NoteEvents<'_> {
+    type Item = NoteEvent;
+
+    fn next(&mut self) -> Option<NoteEvent> {
+        loop {
+            // a note lasts until the next note is triggered, or until the channel ends
+            if let Some(next_start) = self.pending.get(1).map(|event| event.start) {
+                let mut event = self.pending.pop_front()?;
+                event.len = next_start - event.start;
+                return Some(event);
+            }
+
+            if let Some(end) = self.end {
+                let mut event = self.pending.pop_front()?;
+                event.len = end - event.start;
+                return Some(event);
+            }
+
+            let has_frame = self.channel.skip_frame();
+
+            let (_, events) = self.channel.events.as_mut()?;
+            self.pending.extend(events.drain(..));
+
+            if !has_frame {
+                self.end = Some(self.channel.frame * SAMPLES_PER_FRAME);
+            } else if self.channel.is_infinite == Some(true) {
+                // the loop was taken before generating the frame that was just returned
+                self.end = Some((self.channel.frame - 1) * SAMPLES_PER_FRAME);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::command::Note;
+    use super::*;
+
+    fn measure_frequency(channel: ChannelIterator, frames: usize) -> f64 {
+        let samples: Vec<f32> = channel.take(frames).flatten().collect();
+        let edges: Vec<usize> = (1..samples.len())
+            .filter(|&i| samples[i - 1] < samples[i])
+            .collect();
+
+        let periods = (edges.len() - 1) as f64;
+        let duration = (edges[edges.len() - 1] - edges[0]) as f64;
+        SOURCE_SAMPLE_RATE as f64 * periods / duration
+    }
+
+    const NOISE_NOTE: u8 = 0x00;
+
+    #[test]
+    fn test_pitch_sweep_period_zero() {
+        // pitch_sweep 0, 1 followed by a two frame long square_note
+        let rom = [0xdd, 0x01, 0x01, 0xf0, 0x00, 0x02, 0xff];
+        let mut channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        assert!(channel.next().unwrap().iter().any(|&data| data != 0.0));
+        assert!(channel.next().unwrap().iter().any(|&data| data != 0.0));
+        assert_eq!(channel.freq, 0x200);
+    }
+
+    #[test]
+    fn test_pitch_sweep_overflow_on_trigger() {
+        // pitch_sweep 0, 1 followed by a square_note where freq + (freq >> 1) overflows
+        let rom = [0xdd, 0x01, 0x01, 0xf0, 0x00, 0x07, 0xff];
+        let mut channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        assert!(channel.next().unwrap().iter().all(|&data| data == 0.0));
+        assert!(channel.is_disabled);
+
+        // pitch_sweep 0, 8 has a shift of 0, which never overflows
+        let rom = [0xdd, 0x08, 0x01, 0xf0, 0x00, 0x07, 0xff];
+        let mut channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        assert!(channel.next().unwrap().iter().any(|&data| data != 0.0));
+        assert!(!channel.is_disabled);
+    }
+
+    #[test]
+    fn test_pitch_sweep_after_pitch_offset() {
+        // pitch_sweep 1, 1; square_note 4, 15, 0, 0x400
+        let rom = [0xdd, 0x11, 0x04, 0xf0, 0x00, 0x04, 0xff];
+        let mut channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0x100, 0x100);
+
+        // the cry pitch is added when the note is triggered, and the hardware sweeps the
+        // frequency that was written to it
+        assert!(channel.skip_frame());
+        assert_eq!(channel.freq, 0x500 + (0x500 >> 1));
+    }
+
+    #[test]
+    fn test_short_notes() {
+        // square_note 0, 15, 0, 0x700; square_note 0, 15, 0, 0x700
+        let rom = [0x00, 0xf0, 0x00, 0x07, 0x00, 0xf0, 0x00, 0x07, 0xff];
+        let frames = |length| {
+            Channel::new(&rom, 0, 0, ChannelType::SfxPulse)
+                .pcm(0, length)
+                .count()
+        };
+
+        // a note shorter than a frame lasts a single frame, the fraction is still carried over
+        assert_eq!(frames(0x100), 2);
+        assert_eq!(frames(0x80), 2);
+        assert_eq!(frames(0xc0), 2);
+        assert_eq!(frames(0), 2);
+
+        // the length of the cry scales the tempo
+        assert_eq!(frames(0x180), 3);
+        assert_eq!(frames(0x300), 6);
+    }
+
+    #[test]
+    fn test_noise_clock_period() {
+        for shift in 0..=0xf {
+            for divider in 0..=0x7 {
+                // frequency = 262144 / (r * 2^s) Hz, with r = 0 being treated as r = 0.5
+                let r = if divider == 0 { 0.5 } else { divider as f64 };
+                let frequency = 262144.0 / (r * (1 << shift) as f64);
+
+                let expected = match shift {
+                    0xe | 0xf => None,
+                    _ => Some((SOURCE_SAMPLE_RATE as f64 / frequency) as usize),
+                };
+
+                assert_eq!(noise_clock_period(shift, divider), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_noise_frozen_lfsr() {
+        // noise_note with a clock shift of 15
+        let rom = [NOISE_NOTE, 0xf0, 0xf0, 0xff];
+        let mut channel = Channel::new(&rom, 0, 0, ChannelType::SfxNoise).pcm(0, 0x100);
+
+        let frame = channel.next().unwrap();
+        assert!(frame.iter().all(|&data| data == frame[0] && data != 0.0));
+        assert_eq!(channel.noise_buffer, 0x7fff);
+    }
+
+    #[test]
+    fn test_noise_7bit_lfsr() {
+        // the hardware shifts once per clock, and in 7-bit mode also copies the feedback to bit 6
+        let mut lfsr: u16 = 0x7fff;
+        let expected: Vec<bool> = (0..300)
+            .map(|_| {
+                let feedback = (lfsr ^ (lfsr >> 1)) & 1;
+                lfsr = (lfsr >> 1) | (feedback << 14);
+                lfsr = (lfsr & !0x40) | (feedback << 6);
+                lfsr & 1 == 1
+            })
+            .collect();
+
+        // the 7-bit sequence repeats every 127 clocks
+        assert!((0..(300 - 127)).all(|i| expected[i] == expected[i + 127]));
+        assert!((0..(300 - 63)).any(|i| expected[i] != expected[i + 63]));
+
+        // noise_note in 7-bit mode, clocked every other sample
+        let rom = [NOISE_NOTE, 0xf0, 0x08, 0xff];
+        let frame = Channel::new(&rom, 0, 0, ChannelType::SfxNoise)
+            .pcm(0, 0x100)
+            .next()
+            .unwrap();
+
+        // the output is high while bit 0 is set, and is clocked at the start of every period
+        let actual: Vec<bool> = (0..300).map(|i| frame[2 * i + 1] > 0.0).collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_pitch_offset_carry() {
+        let render = |freq: u16, pitch: i16| {
+            let [lo, hi] = freq.to_le_bytes();
+            let rom = [0x01, 0xf0, lo, hi, 0xff];
+            let mut channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(pitch, 0x100);
+            let frame = channel.next().unwrap();
+            (channel.freq, frame)
+        };
+
+        let (freq, frame) = render(0x0120, 0x0300);
+        assert_eq!(freq, 0x0420);
+        assert_eq!(frame, render(0x0420, 0).1);
+
+        let (freq, frame) = render(0x0120, -0x0200);
+        assert_eq!(freq, 0x0720);
+        assert_eq!(frame, render(0x0720, 0).1);
+
+        let (freq, frame) = render(0x0700, 0x7fff);
+        assert_eq!(freq, 0x06ff);
+        assert_eq!(frame, render(0x06ff, 0).1);
+    }
+
+    #[test]
+    fn test_detune() {
+        // a long square_note with freq 0x100
+        let rom = [0x0f, 0xf0, 0x00, 0x01, 0xff];
+        let channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse);
+
+        let original: Vec<_> = channel.pcm(0, 0xf00).take(4).collect();
+        let detuned: Vec<_> = channel.pcm(0, 0xf00).detune(0.0).take(4).collect();
+        assert_eq!(original, detuned);
+
+        let pulse1 = measure_frequency(channel.pcm(0, 0xf00).detune(5.0), 120);
+        let pulse2 = measure_frequency(channel.pcm(0, 0xf00).detune(-5.0), 120);
+
+        let base = 131072.0 / (2048.0 - 256.0);
+        let expected = base * (2f64.powf(5.0 / 1200.0) - 2f64.powf(-5.0 / 1200.0));
+        let beat = pulse1 - pulse2;
+
+        assert!(
+            (beat - expected).abs() < expected * 0.1,
+            "beat: {beat}, expected: {expected}"
+        );
+    }
+
+    #[test]
+    fn test_phase() {
+        // duty_cycle 2 followed by a square_note with a period of 2048 samples
+        let rom = [0xdb, 0x02, 0x00, 0xf0, 0x00, 0x07, 0xff];
+        let channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse);
+
+        let original = channel.pcm(0, 0x100).next().unwrap();
+        let shifted = channel.pcm(0, 0x100).phase(0.5).next().unwrap();
+
+        assert_ne!(original, shifted);
+        assert_eq!(original[1024..], shifted[..SAMPLES_PER_FRAME - 1024]);
+    }
+
+    #[test]
+    fn test_note_events() {
+        // a two frame long square_note, followed by a three frame long square_note
+        let rom = [0x01, 0xf1, 0x00, 0x07, 0x02, 0xa1, 0x80, 0x06, 0xff];
+        let channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        let frames = channel.clone().count();
+        let events: Vec<NoteEvent> = channel.note_events(Voice::Pulse2).collect();
+
+        assert_eq!(
+            events,
+            [
+                NoteEvent {
+                    voice: Voice::Pulse2,
+                    start: 0,
+                    len: 2 * SAMPLES_PER_FRAME,
+                    freq: 0x700,
+                    volume: 15,
+                    fade: 1,
+                    kind: EventKind::SquareNote,
+                },
+                NoteEvent {
+                    voice: Voice::Pulse2,
+                    start: 2 * SAMPLES_PER_FRAME,
+                    len: (frames - 2) * SAMPLES_PER_FRAME,
+                    freq: 0x680,
+                    volume: 10,
+                    fade: 1,
+                    kind: EventKind::SquareNote,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_note_events_infinite() {
+        // a two frame long square_note, followed by an infinite loop back to it
+        let rom = [0x01, 0xf1, 0x00, 0x07, 0xfd, 0x00, 0x00, 0x00];
+        let channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        let events: Vec<NoteEvent> = channel.note_events(Voice::Pulse1).collect();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].start, events[0].len), (0, 2 * SAMPLES_PER_FRAME));
+    }
+
+    #[test]
+    fn test_constant_tone_is_exact() {
+        // duty_cycle 2 followed by an infinitely looped square_note at exactly 512 Hz
+        let rom = [0xdb, 0x02, 0x0f, 0xf0, 0x00, 0x07, 0xfd, 0x00, 0x02, 0x00];
+        let channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        // the first sample is counted as a change as well
+        let mut previous = None;
+        let crossings = channel
+            .flatten()
+            .take(60 * SOURCE_SAMPLE_RATE)
+            .filter(|&sample| previous.replace(sample) != Some(sample))
+            .count()
+            - 1;
+
+        // two crossings per period, for 60 seconds of 512 periods per second
+        assert_eq!(crossings, 2 * 512 * 60);
+    }
+
+    #[test]
+    fn test_skip_frame() {
+        let roms = [
+            // two square_notes, the second one fading out
+            vec![0x01, 0xf1, 0x00, 0x07, 0x02, 0xa1, 0x80, 0x06, 0xff],
+            // a square_note looped three times, with a pitch sweep
+            vec![
+                0xdd, 0x21, 0x02, 0xf2, 0x00, 0x04, 0xfd, 0x03, 0x02, 0x00, 0xff,
+            ],
+            // a noise_note that fades out slowly
+            vec![0x0f, 0xf7, 0x44, 0xff],
+        ];
+
+        for (rom, channel) in roms.iter().zip([
+            ChannelType::SfxPulse,
+            ChannelType::SfxPulse,
+            ChannelType::SfxNoise,
+        ]) {
+            let channel = Channel::new(rom, 0, 0, channel).pcm(0, 0x100);
+
+            let mut skipped = channel.clone();
+            let mut frames = 0;
+
+            while skipped.skip_frame() {
+                frames += 1;
+            }
+
+            assert!(frames > 0);
+            assert_eq!(frames, channel.count());
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_decoded_once() {
+        // two square_notes looped three times
+        let rom = [
+            0x01, 0xf1, 0x00, 0x07, 0x02, 0xa1, 0x80, 0x06, 0xfd, 0x03, 0x00, 0x00, 0xff,
+        ];
+        let mut channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        while channel.next().is_some() {}
+
+        // the two notes and the loop are only decoded on the first pass, followed by the return
+        assert_eq!(channel.decoded, 3 + 1);
+
+        let mut channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+        channel.command_cache = None;
+
+        while channel.next().is_some() {}
+
+        // without the cache, every command is decoded exactly once each time it is executed
+        assert_eq!(channel.decoded, 3 * 3 + 1);
+    }
+
+    #[test]
+    fn test_command_cache() {
+        // duty_cycle, square_note, pitch_sweep and another square_note, looped four times
+        let rom = [
+            0xdb, 0x01, 0x01, 0xf1, 0x00, 0x07, 0xdd, 0x21, 0x02, 0xa2, 0x80, 0x06, 0xfd, 0x04,
+            0x00, 0x00, 0xff,
+        ];
+        let channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        let mut uncached = channel.clone();
+        uncached.command_cache = None;
+
+        assert!(channel.clone().count() > 0);
+        assert!(channel.eq(uncached));
+    }
+
+    #[test]
+    fn test_command_limit() {
+        // an infinite loop that jumps to itself
+        let rom = [0xfd, 0x00, 0x00, 0x00];
+        let mut channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        assert_eq!(channel.next(), None);
+        assert!(channel.is_stuck());
+        assert_eq!(channel.is_infinite(), Some(false));
+        assert_eq!(channel.next(), None);
+
+        // ten duty_cycle commands followed by a square_note
+        let mut rom = [0xdb, 0x01].repeat(10);
+        rom.extend([0x01, 0xf1, 0x00, 0x07, 0xff]);
+        let channel = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        let mut limited = channel.clone().command_limit(10);
+        assert_eq!(limited.next(), None);
+        assert!(limited.is_stuck());
+
+        let mut unlimited = channel.command_limit(11);
+        assert!(unlimited.next().is_some());
+        assert!(!unlimited.is_stuck());
+    }
+
+    #[test]
+    fn test_bank_end() {
+        let end = |rom: &[u8], addr| {
+            let mut channel = Channel::new(rom, 1, addr, ChannelType::MusicPulse).pcm(0, 0x100);
+            assert!(channel.by_ref().count() < 0x10000);
+            channel.error().map(|err| (err.kind, err.addr))
+        };
+        let out_of_bounds = |addr| Some((ParseErrorKind::OutOfBounds, addr));
+
+        // notes up to the end of the bank, the next command is read just past its end
+        let mut rom = vec![0; 0x8000];
+        assert_eq!(end(&rom, 0x7ffc), out_of_bounds(0x8000));
+
+        // a sound_call in the last bytes of the bank returns past its end
+        rom[0x7000] = 0xff;
+        rom[0x7ffd..].copy_from_slice(&[0xfe, 0x00, 0x70]);
+        assert_eq!(end(&rom, 0x7ffd), out_of_bounds(0x8000));
+
+        // a jump to an address outside of the bank
+        rom[0x7000..0x7003].copy_from_slice(&[0xfc, 0x00, 0x01]);
+        assert_eq!(end(&rom, 0x7000), out_of_bounds(0x0100));
+    }
+
+    #[test]
+    fn test_sound_call() {
+        // a call to a square_note, followed by another square_note
+        let rom = [
+            0xfe, 0x08, 0x00, 0x0f, 0xf1, 0x00, 0x07, 0xff, 0x01, 0xf1, 0x00, 0x06, 0xff,
+        ];
+        let called = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        // the same square_notes without the call
+        let rom = [0x01, 0xf1, 0x00, 0x06, 0x0f, 0xf1, 0x00, 0x07, 0xff];
+        let inline = Channel::new(&rom, 0, 0, ChannelType::SfxPulse).pcm(0, 0x100);
+
+        assert_eq!(called.clone().count(), inline.clone().count());
+        assert_eq!(
+            called.note_events(Voice::Pulse1).collect::<Vec<_>>(),
+            inline.note_events(Voice::Pulse1).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        let count = |rom: &[u8], freq: u16| {
+            Channel::new(rom, 0, 0, ChannelType::SfxPulse)
+                .pcm(0, 0x100)
+                .note_events(Voice::Pulse1)
+                .filter(|event| event.freq == freq)
+                .count()
+        };
+
+        // a square_note looped three times, followed by another one looped twice
+        let rom = [
+            0x00, 0xf1, 0x00, 0x07, 0xfd, 0x03, 0x00, 0x00, 0x00, 0xf1, 0x80, 0x06, 0xfd, 0x02,
+            0x08, 0x00, 0xff,
+        ];
+        assert_eq!(count(&rom, 0x700), 3);
+        assert_eq!(count(&rom, 0x680), 2);
+
+        // a square_note looped twice inside of a loop that is played three times
+        let rom = [
+            0x00, 0xf1, 0x00, 0x07, 0xfd, 0x02, 0x00, 0x00, 0x00, 0xf1, 0x80, 0x06, 0xfd, 0x03,
+            0x00, 0x00, 0xff,
+        ];
+        assert_eq!(count(&rom, 0x700), 6);
+        assert_eq!(count(&rom, 0x680), 3);
+    }
+
+    #[test]
+    fn test_music_notes() {
+        // note_type 2, 10, 3; octave 4; C_ 2; A# 3; rest 1
+        let rom = [0xd8, 0x02, 0xa3, 0xd4, 0x11, 0xb2, 0x00, 0xff];
+        let channel = Channel::new(&rom, 0, 0, ChannelType::MusicPulse).pcm(0, 0x100);
+
+        let events: Vec<_> = channel.clone().note_events(Voice::Pulse1).collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].start, 0);
+        assert_eq!(events[0].freq, 0x705);
+        assert_eq!(events[0].volume, 10);
+        assert_eq!(events[0].fade, 3);
+        assert_eq!(events[1].start, 4 * SAMPLES_PER_FRAME);
+        assert_eq!(events[1].freq, Note::ASharp.freq(4));
+
+        // the note plays at the pitch of the frequency register, and the rest is silent
+        let frames: Vec<_> = channel.clone().collect();
+        assert_eq!(frames.len(), 4 + 6 + 2);
+        assert!(frames[10..].iter().flatten().all(|&sample| sample == 0.0));
+
+        let freq = measure_frequency(channel, 4);
+        let expected = 131072.0 / (2048 - 0x705) as f64;
+        assert!((freq - expected).abs() < 0.5, "{freq} != {expected}");
+    }
+
+    #[test]
+    fn test_music_tempo() {
+        let frames = |rom: &[u8]| {
+            Channel::new(rom, 0, 0, ChannelType::MusicPulse)
+                .pcm(0, 0x100)
+                .count()
+        };
+
+        // note_type 1, 15, 0; octave 4; C_ 8; rest 8
+        assert_eq!(frames(&[0xd8, 0x01, 0xf0, 0xd4, 0x17, 0x07, 0xff]), 16);
+
+        // the same, after setting the tempo to half a frame
+        let rom = [0xda, 0x00, 0x80, 0xd8, 0x01, 0xf0, 0xd4, 0x17, 0x07, 0xff];
+        assert_eq!(frames(&rom), 8);
+    }
+
+    #[test]
+    fn test_music_effects() {
+        let frames = |rom: &[u8]| {
+            let mut channel = Channel::new(rom, 0, 0, ChannelType::MusicPulse).pcm(0, 0x100);
+            let frames = channel.by_ref().count();
+            (frames, channel.error().map(|err| err.kind))
+        };
+
+        // tempo_relative -128 halves the default tempo of 0x100
+        let rom = [0xe9, 0x80, 0xd8, 0x01, 0xf0, 0xd4, 0x17, 0xff];
+        assert_eq!(frames(&rom), (4, None));
+
+        // set_condition 1; sound_jump_if 1 past the first note, sound_jump_if 2 is not taken
+        #[rustfmt::skip]
+        let rom = [
+            0xd8, 0x01, 0xf0, 0xd4, 0xfa, 0x01,
+            0xfb, 0x01, 0x0b, 0x00, 0x17, 0xfb, 0x02, 0x00, 0x00, 0x13, 0xff,
+        ];
+        assert_eq!(frames(&rom), (4, None));
+
+        // new_song stops the channel, after the note before it
+        let rom = [0xd8, 0x01, 0xf0, 0xd4, 0x17, 0xeb, 0x00, 0x00, 0xff];
+        assert_eq!(frames(&rom), (8, Some(ParseErrorKind::Unsupported)));
+    }
+
+    #[test]
+    fn test_unsupported_channels() {
+        let stop = |rom: &[u8], channel| {
+            let mut channel = Channel::new(rom, 0, 0, channel).pcm(0, 0x100);
+            let frames = channel.by_ref().count();
+            (frames, channel.error().map(|err| (err.kind, err.addr)))
+        };
+        let unsupported = |addr| Some((ParseErrorKind::Unsupported, addr));
+
+        // drum_speed 1; rest 2; drum note 1, 1
+        let rom = [0xd8, 0x01, 0x01, 0x10, 0xff];
+        assert_eq!(stop(&rom, ChannelType::MusicNoise), (2, unsupported(3)));
+
+        // note_type 1, 2, 1 of the wave channel; octave 4; rest 1; C_ 1
+        let rom = [0xd8, 0x01, 0x21, 0xd4, 0x00, 0x10, 0xff];
+        assert_eq!(stop(&rom, ChannelType::MusicWave), (0, unsupported(0)));
+        assert_eq!(stop(&rom[3..], ChannelType::MusicWave), (1, unsupported(2)));
+
+        // square_note 1, 15, 0, 1792 on the SFX wave channel
+        let rom = [0x01, 0xf0, 0x00, 0x07, 0xff];
+        assert_eq!(stop(&rom, ChannelType::SfxWave), (0, unsupported(0)));
+
+        // note_type 1, 15, 0; octave 4; C_ 8; followed by one of vibrato 2, 3, 4; transpose 1, 2;
+        // pitch_slide 1, 4, C_ or volume 7, 7, which stop the channel after the first note
+        for effect in [
+            &[0xe1, 0x02, 0x34][..],
+            &[0xd9, 0x12],
+            &[0xe0, 0x01, 0x41],
+            &[0xe5, 0x77],
+        ] {
+            let rom = [&[0xd8, 0x01, 0xf0, 0xd4, 0x17][..], effect, &[0x17, 0xff]].concat();
+            let channel = ChannelType::MusicPulse;
+            assert_eq!(stop(&rom, channel), (8, unsupported(5)));
         }
     }
 }
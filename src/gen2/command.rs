@@ -1,3 +1,5 @@
+use std::fmt;
+
 use super::channel::ChannelType;
 
 trait FromI4 {
@@ -32,11 +34,47 @@ pub enum Note {
     BFlat,
 }
 
+/// Frequency register values of the notes in the lowest octave, the engine shifts them right once
+/// for every octave above it.
+const PITCHES: [u16; 12] = [
+    0xf82c, 0xf89d, 0xf907, 0xf96b, 0xf9ca, 0xfa23, 0xfa77, 0xfac7, 0xfb12, 0xfb58, 0xfb9b, 0xfbda,
+];
+
+impl Note {
+    /// The note with the given index in the octave.
+    pub fn from_index(index: u8) -> Option<Note> {
+        const NOTES: [Note; 12] = [
+            Note::CFlat,
+            Note::CSharp,
+            Note::DFlat,
+            Note::DSharp,
+            Note::EFlat,
+            Note::FFlat,
+            Note::FSharp,
+            Note::GFlat,
+            Note::GSharp,
+            Note::AFlat,
+            Note::ASharp,
+            Note::BFlat,
+        ];
+
+        NOTES.get(index as usize).copied()
+    }
+
+    /// Frequency register value of the note, `octave` is the value of the `Octave` command where
+    /// 7 is the lowest octave and 0 the highest.
+    pub fn freq(&self, octave: u8) -> u16 {
+        let shift = 7 - octave.min(7);
+        (((PITCHES[*self as usize] as i16) >> shift) as u16) & 0x7ff
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Command {
     PitchSweep {
-        /// Length of time between pitch shifts. \
-        /// Sometimes used with a value >7 in which case the MSB is ignored.
+        /// Length of time between pitch shifts, 0 means that the sweep is disabled. \
+        /// Sometimes written with a value >7, the MSB is ignored by the hardware and masked off
+        /// when parsing.
         length: u8,
         /// Positive value means increase in pitch, negative value means decrease in pitch. \
         /// Small magnitude means quick change, large magnitude means slow change. \
@@ -65,6 +103,8 @@ pub enum Command {
         pitch: Note,
         length: u8,
     },
+    /// Drum note of the music noise channel, `instrument` is the index in the drum kit selected
+    /// by `ToggleNoise`, starting at 1.
     DrumNote {
         instrument: u8,
         length: u8,
@@ -78,8 +118,33 @@ pub enum Command {
         /// In signed magnitude representation, so a value of 8 is the same as (negative) 0.
         fade: i8,
     },
+    /// The `NoteType` of the wave channel, which picks a wave instead of a fade.
+    WaveNoteType {
+        speed: u8,
+        volume: u8,
+        instrument: u8,
+    },
+    /// The `NoteType` of the music noise channel, which only takes the speed.
     DrumSpeed(u8),
     Octave(u8),
+    /// Shift the following notes up by a number of octaves and pitches.
+    Transpose {
+        octaves: u8,
+        pitches: u8,
+    },
+    /// Volume and fade of the following music notes, also known as intensity.
+    VolumeEnvelope {
+        volume: u8,
+        /// Positive value means decrease in volume, negative value means increase in volume.
+        /// Small magnitude means quick change, large magnitude means slow change.
+        /// In signed magnitude representation, so a value of 8 is the same as (negative) 0.
+        fade: i8,
+    },
+    /// The `VolumeEnvelope` of the wave channel, which picks a wave instead of a fade.
+    WaveVolumeEnvelope {
+        volume: u8,
+        instrument: u8,
+    },
     PitchOffset(i16),
     Vibrato {
         /// Time delay until vibrato effect begins.
@@ -94,19 +159,44 @@ pub enum Command {
         octave: u8,
         pitch: u8,
     },
+    /// Duty cycle used for every step, the engine only keeps the two least significant bits.
     DutyCycle(u8),
     /// Used to calculate note delay counters, so a smaller value means music plays faster. \
     /// Ideally should be set to $100 or less to guarantee no overflow. \
     /// If larger than 0x100, large note speed or note length values might cause overflow. \
     /// Stored in big endian.
     Tempo(u16),
+    /// Added to the tempo of the channel.
+    TempoRelative(i8),
     Volume {
         left: u8,
         right: u8,
     },
-    /// When enabled, the sfx data is interpreted as music data.
-    ExecuteMusic,
+    /// Which of the left and right terminals the channel is output to, the engine only keeps
+    /// the bits of NR51 that belong to the channel.
+    StereoPanning(u8),
+    /// Like `StereoPanning`, but also when the stereo option is turned off.
+    ForceStereoPanning(u8),
+    /// Toggles whether notes are interpreted as SFX notes or as music notes.
+    ToggleSfx,
+    /// Turns on the drum kit with the given id for the notes of the music noise channel. \
+    /// The engine only reads the id when turning it on, which depends on the state of the
+    /// channel, it is always parsed here since that is how the command is used.
+    ToggleNoise(u8),
+    /// Like `ToggleNoise`, but for the SFX noise channel.
+    SfxToggleNoise(u8),
+    SfxPriorityOn,
+    SfxPriorityOff,
+    /// Restart the channel at the given address.
+    RestartChannel(u16),
+    NewSong(u16),
     DutyCyclePattern(u8, u8, u8, u8),
+    SetCondition(u8),
+    SoundJumpIf {
+        condition: u8,
+        addr: u16,
+    },
+    SoundJump(u16),
     SoundCall(u16),
     Loop {
         count: u8,
@@ -115,127 +205,180 @@ pub enum Command {
     Return,
 }
 
-impl Command {
-    pub fn parse(rom: &[u8], bank: u8, addr: u16, channel: ChannelType) -> Command {
-        let pos = ((bank as usize) * 0x4000) + ((addr as usize) & 0x3fff);
+/// Error returned by `Command::parse`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub bank: u8,
+    pub addr: u16,
+    /// The kind of channel that the command was parsed for, commands differ between them.
+    pub channel: ChannelType,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let channel = match self.channel {
+            ChannelType::MusicPulse => "music pulse",
+            ChannelType::MusicWave => "music wave",
+            ChannelType::MusicNoise => "music noise",
+            ChannelType::SfxPulse => "SFX pulse channel",
+            ChannelType::SfxWave => "SFX wave channel",
+            ChannelType::SfxNoise => "SFX noise channel",
+        };
 
-        match channel {
-            ChannelType::MusicPulse => Command::parse_music_pulse(&rom[pos..]),
-            ChannelType::MusicWave => Command::parse_music_wave(&rom[pos..]),
-            ChannelType::MusicNoise => Command::parse_music_noise(&rom[pos..]),
-            ChannelType::SfxPulse => Command::parse_sfx_pulse(&rom[pos..]),
-            ChannelType::SfxWave => Command::parse_sfx_wave(&rom[pos..]),
-            ChannelType::SfxNoise => Command::parse_sfx_noise(&rom[pos..]),
+        match self.kind {
+            ParseErrorKind::UnknownCommand(byte) => write!(
+                f,
+                "Unknown {} command: {:02x} at {:02x}:{:04x}",
+                channel, byte, self.bank, self.addr
+            ),
+            ParseErrorKind::OutOfBounds => write!(
+                f,
+                "The {} command at {:02x}:{:04x} runs past the end of the ROM",
+                channel, self.bank, self.addr
+            ),
+            ParseErrorKind::Unsupported => write!(
+                f,
+                "The {} command at {:02x}:{:04x} isn't supported yet",
+                channel, self.bank, self.addr
+            ),
         }
     }
+}
 
-    #[rustfmt::skip]
-    fn parse_music_pulse(data: &[u8]) -> Command {
-        match data[0] {
-            0x00..=0x0f => Command::Note { pitch: Note::CFlat, length: (data[0] & 0x0f) },
-            0x10..=0x1f => Command::Note { pitch: Note::CSharp, length: (data[0] & 0x0f) },
-            0x20..=0x2f => Command::Note { pitch: Note::DFlat, length: (data[0] & 0x0f) },
-            0x30..=0x3f => Command::Note { pitch: Note::DSharp, length: (data[0] & 0x0f) },
-            0x40..=0x4f => Command::Note { pitch: Note::EFlat, length: (data[0] & 0x0f) },
-            0x50..=0x5f => Command::Note { pitch: Note::FFlat, length: (data[0] & 0x0f) },
-            0x60..=0x6f => Command::Note { pitch: Note::FSharp, length: (data[0] & 0x0f) },
-            0x70..=0x7f => Command::Note { pitch: Note::GFlat, length: (data[0] & 0x0f) },
-            0x80..=0x8f => Command::Note { pitch: Note::GSharp, length: (data[0] & 0x0f) },
-            0x90..=0x9f => Command::Note { pitch: Note::AFlat, length: (data[0] & 0x0f) },
-            0xa0..=0xaf => Command::Note { pitch: Note::ASharp, length: (data[0] & 0x0f) },
-            0xb0..=0xbf => Command::Note { pitch: Note::BFlat, length: (data[0] & 0x0f) },
-            0xc0..=0xcf => Command::Rest(data[0] & 0x0f),
-            0xd0..=0xdf => Command::NoteType { speed: (data[0] & 0x0f), volume: (data[1] & 0x0f), fade: i8::from_i4(data[1]) },
-            0xe0..=0xe7 => Command::Octave(data[0] & 0x0f),
-            0xea => Command::Vibrato { delay: data[1], depth: (data[2] >> 4), rate: (data[2] & 0x0f) },
-            0xeb => Command::PitchSlide { length: data[1], octave: (data[2] >> 4), pitch: (data[2] & 0x0f) },
-            0xec => Command::DutyCycle(data[1]),
-            0xed => Command::Tempo(u16::from_be_bytes([data[1], data[2]])),
-            0xf0 => Command::Volume { left: (data[1] >> 4), right: (data[1] & 0x0f) },
-            0xf8 => Command::ExecuteMusic,
-            0xfc => Command::DutyCyclePattern(data[1] >> 6, (data[1] >> 4) & 0x03, (data[1] >> 2) & 0x03, data[1] & 0x03),
-            0xfd => Command::SoundCall(u16::from_le_bytes([data[1], data[2]])),
-            0xfe => Command::Loop { count: data[1], addr: u16::from_le_bytes([data[2], data[3]]) },
-            0xff => Command::Return,
-            byte => todo!("Unknown music pulse command: {:02x}", byte)
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ParseErrorKind {
+    /// The byte isn't a command of the channel type.
+    UnknownCommand(u8),
+    /// The command, or some of its operands, is past the end of the ROM, e.g. because the ROM is
+    /// truncated or the bank doesn't exist. The channel iterators also return it for data that
+    /// runs past the end of its bank.
+    OutOfBounds,
+    /// The command is valid, but playing it isn't supported yet, e.g. a drum note. Returned by
+    /// the channel iterator, never by `Command::parse`.
+    Unsupported,
+}
+
+impl Command {
+    /// Parse the command at `addr`, returns an error if the byte there isn't a command of the
+    /// given channel type, or if the command doesn't fit in the ROM.
+    pub fn parse(
+        rom: &[u8],
+        bank: u8,
+        addr: u16,
+        channel: ChannelType,
+    ) -> Result<Command, ParseError> {
+        let pos = ((bank as usize) * 0x4000) + ((addr as usize) & 0x3fff);
+        let data = rom.get(pos..).unwrap_or_default();
+
+        let error = |kind| ParseError {
+            kind,
+            bank,
+            addr,
+            channel,
+        };
+
+        let Some(&byte) = data.first() else {
+            return Err(error(ParseErrorKind::OutOfBounds));
+        };
+
+        // Commands are at most 4 bytes long, pad the data so that the operands can be read
+        // without checking the length, and check it once the command is known instead.
+        let mut padded = [0; 4];
+        let available = data.len().min(padded.len());
+        padded[..available].copy_from_slice(&data[..available]);
+
+        let data = &padded;
+        let unknown = error(ParseErrorKind::UnknownCommand(byte));
+
+        // every channel shares the same commands, only the notes differ
+        let cmd = match (data[0], channel) {
+            (0xd0.., _) => Command::parse_command(data, channel, unknown),
+            (_, ChannelType::MusicPulse | ChannelType::MusicWave) => Ok(Command::parse_note(data)),
+            (_, ChannelType::MusicNoise) => Ok(Command::parse_drum_note(data)),
+            (_, ChannelType::SfxPulse | ChannelType::SfxWave) => {
+                Ok(Command::parse_square_note(data))
+            }
+            (_, ChannelType::SfxNoise) => Ok(Command::parse_noise_note(data)),
+        }?;
+
+        if cmd.len() > available {
+            return Err(error(ParseErrorKind::OutOfBounds));
         }
+
+        Ok(cmd)
     }
 
-    #[rustfmt::skip]
-    fn parse_music_wave(data: &[u8]) -> Command {
-        match data[0] {
-            0x00..=0x0f => Command::Note { pitch: Note::CFlat, length: (data[0] & 0x0f) },
-            0x10..=0x1f => Command::Note { pitch: Note::CSharp, length: (data[0] & 0x0f) },
-            0x20..=0x2f => Command::Note { pitch: Note::DFlat, length: (data[0] & 0x0f) },
-            0x30..=0x3f => Command::Note { pitch: Note::DSharp, length: (data[0] & 0x0f) },
-            0x40..=0x4f => Command::Note { pitch: Note::EFlat, length: (data[0] & 0x0f) },
-            0x50..=0x5f => Command::Note { pitch: Note::FFlat, length: (data[0] & 0x0f) },
-            0x60..=0x6f => Command::Note { pitch: Note::FSharp, length: (data[0] & 0x0f) },
-            0x70..=0x7f => Command::Note { pitch: Note::GFlat, length: (data[0] & 0x0f) },
-            0x80..=0x8f => Command::Note { pitch: Note::GSharp, length: (data[0] & 0x0f) },
-            0x90..=0x9f => Command::Note { pitch: Note::AFlat, length: (data[0] & 0x0f) },
-            0xa0..=0xaf => Command::Note { pitch: Note::ASharp, length: (data[0] & 0x0f) },
-            0xb0..=0xbf => Command::Note { pitch: Note::BFlat, length: (data[0] & 0x0f) },
-            0xc0..=0xcf => Command::Rest(data[0] & 0x0f),
-            0xd0..=0xdf => Command::NoteType { speed: (data[0] & 0x0f), volume: (data[1] & 0x0f), fade: i8::from_i4(data[1]) },
-            0xe0..=0xe7 => Command::Octave(data[0] & 0x0f),
-            0xea => Command::Vibrato { delay: data[1], depth: (data[2] >> 4), rate: (data[2] & 0x0f) },
-            0xeb => Command::PitchSlide { length: data[1], octave: (data[2] >> 4), pitch: (data[2] & 0x0f) },
-            0xed => Command::Tempo(u16::from_be_bytes([data[1], data[2]])),
-            0xf8 => Command::ExecuteMusic,
-            0xfd => Command::SoundCall(u16::from_le_bytes([data[1], data[2]])),
-            0xfe => Command::Loop { count: data[1], addr: u16::from_le_bytes([data[2], data[3]]) },
-            0xff => Command::Return,
-            byte => todo!("Unknown music wave command: {:02x}", byte)
+    fn parse_note(data: &[u8]) -> Command {
+        match Note::from_index((data[0] >> 4).wrapping_sub(1)) {
+            Some(pitch) => Command::Note {
+                pitch,
+                length: data[0] & 0x0f,
+            },
+            None => Command::Rest(data[0] & 0x0f),
         }
     }
 
-    #[rustfmt::skip]
-    fn parse_music_noise(data: &[u8]) -> Command {
-        match data[0] {
-            0xb0..=0xbf => Command::DrumNote { instrument: data[1], length: (data[0] >> 4) },
-            0xc0..=0xcf => Command::Rest(data[0] & 0x0f),
-            0xd0..=0xdf => Command::DrumSpeed(data[0] & 0x0f),
-            0xfd => Command::SoundCall(u16::from_le_bytes([data[1], data[2]])),
-            0xfe => Command::Loop { count: data[1], addr: u16::from_le_bytes([data[2], data[3]]) },
-            0xff => Command::Return,
-            byte => todo!("Unknown music noise command: {:02x}", byte)
+    fn parse_drum_note(data: &[u8]) -> Command {
+        match data[0] >> 4 {
+            0 => Command::Rest(data[0] & 0x0f),
+            instrument => Command::DrumNote {
+                instrument,
+                length: data[0] & 0x0f,
+            },
         }
     }
 
     #[rustfmt::skip]
-    fn parse_sfx_pulse(data: &[u8]) -> Command {
-        match data[0] {
-            0x00..=0xd0 => Command::SquareNote { length: data[0], volume: data[1] >> 4, fade: i8::from_i4(data[1]), freq: u16::from_le_bytes([data[2], data[3]]) },
-            0xdb => Command::DutyCycle(data[1]),
-            0xdd => Command::PitchSweep { length: (data[1] >> 4), change: i8::from_i4(data[1]) },
-            0xde => Command::DutyCyclePattern(data[1] >> 6, (data[1] >> 4) & 0x03, (data[1] >> 2) & 0x03, data[1] & 0x03),
-            0xe6 => Command::PitchOffset(i16::from_be_bytes([data[1], data[2]])),
-            0xfd => Command::Loop { count: data[1], addr: u16::from_le_bytes([data[2], data[3]]) },
-            0xfe => Command::SoundCall(u16::from_le_bytes([data[1], data[2]])),
-            0xff => Command::Return,
-            byte => todo!("Unknown SFX pulse channel command: {:02x}", byte)
-        }
+    fn parse_square_note(data: &[u8]) -> Command {
+        Command::SquareNote { length: data[0], volume: data[1] >> 4, fade: i8::from_i4(data[1]), freq: u16::from_le_bytes([data[2], data[3]]) }
     }
 
     #[rustfmt::skip]
-    fn parse_sfx_wave(data: &[u8]) -> Command {
-        match data[0] {
-            byte => todo!("Unknown SFX wave channel command: {:02x}", byte),
-        }
+    fn parse_noise_note(data: &[u8]) -> Command {
+        Command::NoiseNote { length: data[0], volume: data[1] >> 4, fade: i8::from_i4(data[1]), value: data[2] }
     }
 
     #[rustfmt::skip]
-    fn parse_sfx_noise(data: &[u8]) -> Command {
-        match data[0] {
-            0x00..=0xd0 => Command::NoiseNote { length: data[0], volume: data[1] >> 4, fade: i8::from_i4(data[1]), value: data[2] },
-            0xdb => Command::DutyCycle(data[1]),
+    fn parse_command(data: &[u8], channel: ChannelType, unknown: ParseError) -> Result<Command, ParseError> {
+        let is_wave = matches!(channel, ChannelType::MusicWave | ChannelType::SfxWave);
+
+        Ok(match data[0] {
+            0xd0..=0xd7 => Command::Octave(data[0] & 0x07),
+            0xd8 if channel == ChannelType::MusicNoise => Command::DrumSpeed(data[1]),
+            0xd8 if is_wave => Command::WaveNoteType { speed: data[1], volume: (data[2] >> 4), instrument: (data[2] & 0x0f) },
+            0xd8 => Command::NoteType { speed: data[1], volume: (data[2] >> 4), fade: i8::from_i4(data[2]) },
+            0xd9 => Command::Transpose { octaves: (data[1] >> 4), pitches: (data[1] & 0x0f) },
+            0xda => Command::Tempo(u16::from_be_bytes([data[1], data[2]])),
+            0xdb => Command::DutyCycle(data[1] & 0x03),
+            0xdc if is_wave => Command::WaveVolumeEnvelope { volume: (data[1] >> 4), instrument: (data[1] & 0x0f) },
+            0xdc => Command::VolumeEnvelope { volume: (data[1] >> 4), fade: i8::from_i4(data[1]) },
+            0xdd => Command::PitchSweep { length: (data[1] >> 4) & 0x07, change: i8::from_i4(data[1]) },
             0xde => Command::DutyCyclePattern(data[1] >> 6, (data[1] >> 4) & 0x03, (data[1] >> 2) & 0x03, data[1] & 0x03),
+            0xdf => Command::ToggleSfx,
+            0xe0 => Command::PitchSlide { length: data[1], octave: (data[2] >> 4), pitch: (data[2] & 0x0f) },
+            0xe1 => Command::Vibrato { delay: data[1], depth: (data[2] >> 4), rate: (data[2] & 0x0f) },
+            0xe3 => Command::ToggleNoise(data[1]),
+            0xe4 => Command::ForceStereoPanning(data[1]),
+            0xe5 => Command::Volume { left: (data[1] >> 4), right: (data[1] & 0x0f) },
+            0xe6 => Command::PitchOffset(i16::from_be_bytes([data[1], data[2]])),
+            0xe9 => Command::TempoRelative(data[1] as i8),
+            0xea => Command::RestartChannel(u16::from_le_bytes([data[1], data[2]])),
+            0xeb => Command::NewSong(u16::from_le_bytes([data[1], data[2]])),
+            0xec => Command::SfxPriorityOn,
+            0xed => Command::SfxPriorityOff,
+            0xef => Command::StereoPanning(data[1]),
+            0xf0 => Command::SfxToggleNoise(data[1]),
+            0xfa => Command::SetCondition(data[1]),
+            0xfb => Command::SoundJumpIf { condition: data[1], addr: u16::from_le_bytes([data[2], data[3]]) },
+            0xfc => Command::SoundJump(u16::from_le_bytes([data[1], data[2]])),
             0xfd => Command::Loop { count: data[1], addr: u16::from_le_bytes([data[2], data[3]]) },
             0xfe => Command::SoundCall(u16::from_le_bytes([data[1], data[2]])),
             0xff => Command::Return,
-            byte => todo!("Unknown SFX noise channel command: {:02x}", byte),
-        }
+            _ => return Err(unknown),
+        })
     }
 
     pub fn len(&self) -> usize {
@@ -244,22 +387,287 @@ impl Command {
             Command::SquareNote { .. } => 4,
             Command::NoiseNote { .. } => 3,
             Command::Note { .. } => 1,
-            Command::DrumNote { .. } => 2,
+            Command::DrumNote { .. } => 1,
             Command::Rest(_) => 1,
-            Command::NoteType { .. } => 2,
-            Command::DrumSpeed(_) => 1,
+            Command::NoteType { .. } => 3,
+            Command::WaveNoteType { .. } => 3,
+            Command::DrumSpeed(_) => 2,
             Command::Octave(_) => 1,
+            Command::Transpose { .. } => 2,
+            Command::VolumeEnvelope { .. } => 2,
+            Command::WaveVolumeEnvelope { .. } => 2,
             Command::PitchOffset(_) => 3,
             Command::Vibrato { .. } => 3,
             Command::PitchSlide { .. } => 3,
             Command::DutyCycle(_) => 2,
             Command::Tempo(_) => 3,
+            Command::TempoRelative(_) => 2,
             Command::Volume { .. } => 2,
-            Command::ExecuteMusic => 1,
+            Command::StereoPanning(_) => 2,
+            Command::ForceStereoPanning(_) => 2,
+            Command::ToggleSfx => 1,
+            Command::ToggleNoise(_) => 2,
+            Command::SfxToggleNoise(_) => 2,
+            Command::SfxPriorityOn => 1,
+            Command::SfxPriorityOff => 1,
+            Command::RestartChannel(_) => 3,
+            Command::NewSong(_) => 3,
             Command::DutyCyclePattern(_, _, _, _) => 2,
+            Command::SetCondition(_) => 2,
+            Command::SoundJumpIf { .. } => 4,
+            Command::SoundJump(_) => 3,
             Command::SoundCall(_) => 3,
             Command::Loop { .. } => 4,
             Command::Return => 1,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pitch_sweep_msb_is_ignored() {
+        for byte in 0x80..=0xf7 {
+            let cmd = Command::parse(&[0xdd, byte], 0, 0, ChannelType::SfxPulse).unwrap();
+
+            match cmd {
+                Command::PitchSweep { length, change } => {
+                    assert_eq!(length, (byte >> 4) - 8, "byte: {byte:02x}");
+                    assert_eq!(change, i8::from_i4(byte), "byte: {byte:02x}");
+                }
+                cmd => panic!("Unexpected command: {cmd:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_duty_cycle_operand() {
+        for byte in [0x00, 0x01, 0x02, 0x03, 0x40, 0x82] {
+            let cmd = Command::parse(&[0xdb, byte], 0, 0, ChannelType::SfxPulse).unwrap();
+            assert_eq!(cmd, Command::DutyCycle(byte & 0x03), "byte: {byte:02x}");
+        }
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let rom = [0x00, 0xe7];
+        let err = Command::parse(&rom, 0, 0x4001, ChannelType::SfxNoise).unwrap_err();
+
+        assert_eq!(
+            err,
+            ParseError {
+                kind: ParseErrorKind::UnknownCommand(0xe7),
+                bank: 0,
+                addr: 0x4001,
+                channel: ChannelType::SfxNoise,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Unknown SFX noise channel command: e7 at 00:4001"
+        );
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        // a square note without its frequency
+        let err = Command::parse(&[0x00, 0xf1], 0, 0, ChannelType::SfxPulse).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::OutOfBounds);
+
+        // a bank past the end of the ROM
+        let err = Command::parse(&[0xff], 1, 0x4000, ChannelType::SfxPulse).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::OutOfBounds);
+        assert_eq!(
+            err.to_string(),
+            "The SFX pulse channel command at 01:4000 runs past the end of the ROM"
+        );
+
+        // the last byte of the ROM
+        let cmd = Command::parse(&[0xff], 0, 0, ChannelType::SfxPulse);
+        assert_eq!(cmd, Ok(Command::Return));
+    }
+
+    fn parse_all(rom: &[u8], channel: ChannelType) -> Vec<Command> {
+        let mut result = Vec::new();
+        let mut addr = 0;
+
+        while (addr as usize) < rom.len() {
+            let cmd = Command::parse(rom, 0, addr, channel).unwrap();
+            addr += cmd.len() as u16;
+            result.push(cmd);
+        }
+
+        result
+    }
+
+    #[test]
+    fn test_cry() {
+        #[rustfmt::skip]
+        let rom = [
+            0xde, 0xa5,             // duty_cycle_pattern 2, 2, 1, 1
+            0x04, 0xf7, 0xa0, 0x07, // square_note 4, 15, 7, 1952
+            0xe6, 0x00, 0x10,       // pitch_offset 16
+            0x0c, 0xe9, 0xb0, 0x07, // square_note 12, 14, -1, 1968
+            0xdd, 0x1a,             // pitch_sweep 1, -2
+            0xff,                   // sound_ret
+        ];
+
+        assert_eq!(
+            parse_all(&rom, ChannelType::SfxPulse),
+            [
+                Command::DutyCyclePattern(2, 2, 1, 1),
+                Command::SquareNote {
+                    length: 4,
+                    volume: 15,
+                    fade: 7,
+                    freq: 1952
+                },
+                Command::PitchOffset(16),
+                Command::SquareNote {
+                    length: 12,
+                    volume: 14,
+                    fade: -1,
+                    freq: 1968
+                },
+                Command::PitchSweep {
+                    length: 1,
+                    change: -2
+                },
+                Command::Return,
+            ]
+        );
+
+        // noise_note 3, 10, 1, 68; noise_note 8, 9, 4, 85; sound_ret
+        let rom = [0x03, 0xa1, 0x44, 0x08, 0x94, 0x55, 0xff];
+
+        assert_eq!(
+            parse_all(&rom, ChannelType::SfxNoise),
+            [
+                Command::NoiseNote {
+                    length: 3,
+                    volume: 10,
+                    fade: 1,
+                    value: 0x44
+                },
+                Command::NoiseNote {
+                    length: 8,
+                    volume: 9,
+                    fade: 4,
+                    value: 0x55
+                },
+                Command::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_music() {
+        #[rustfmt::skip]
+        let rom = [
+            0xda, 0x00, 0x90,       // tempo 144
+            0xe5, 0x77,             // volume 7, 7
+            0xef, 0xf0,             // stereo_panning TRUE, FALSE
+            0xdb, 0x02,             // duty_cycle 2
+            0xe1, 0x08, 0x24,       // vibrato 8, 2, 4
+            0xd8, 0x0c, 0x92,       // note_type 12, 9, 2
+            0xd5,                   // octave 3
+            0x51,                   // note E_, 2
+            0x03,                   // rest 4
+            0xdc, 0xa7,             // volume_envelope 10, 7
+            0xd9, 0x12,             // transpose 1, 2
+            0xe0, 0x03, 0x41,       // pitch_slide 4, 4, C_
+            0xcf,                   // note B_, 16
+            0xdf,                   // toggle_sfx
+            0xfc, 0x00, 0x40,       // sound_jump $4000
+        ];
+
+        assert_eq!(
+            parse_all(&rom, ChannelType::MusicPulse),
+            [
+                Command::Tempo(144),
+                Command::Volume { left: 7, right: 7 },
+                Command::StereoPanning(0xf0),
+                Command::DutyCycle(2),
+                Command::Vibrato {
+                    delay: 8,
+                    depth: 2,
+                    rate: 4
+                },
+                Command::NoteType {
+                    speed: 12,
+                    volume: 9,
+                    fade: 2
+                },
+                Command::Octave(5),
+                Command::Note {
+                    pitch: Note::EFlat,
+                    length: 1
+                },
+                Command::Rest(3),
+                Command::VolumeEnvelope {
+                    volume: 10,
+                    fade: 7
+                },
+                Command::Transpose {
+                    octaves: 1,
+                    pitches: 2
+                },
+                Command::PitchSlide {
+                    length: 3,
+                    octave: 4,
+                    pitch: 1
+                },
+                Command::Note {
+                    pitch: Note::BFlat,
+                    length: 15
+                },
+                Command::ToggleSfx,
+                Command::SoundJump(0x4000),
+            ]
+        );
+
+        // note_type 12, 2, 5; volume_envelope 1, 3; note C_, 1
+        let rom = [0xd8, 0x0c, 0x25, 0xdc, 0x13, 0x10];
+
+        assert_eq!(
+            parse_all(&rom, ChannelType::MusicWave),
+            [
+                Command::WaveNoteType {
+                    speed: 12,
+                    volume: 2,
+                    instrument: 5
+                },
+                Command::WaveVolumeEnvelope {
+                    volume: 1,
+                    instrument: 3
+                },
+                Command::Note {
+                    pitch: Note::CFlat,
+                    length: 0
+                },
+            ]
+        );
+
+        // toggle_noise 3; drum_speed 12; drum_note 1, 4; rest 2; sound_loop 0, $4000
+        let rom = [0xe3, 0x03, 0xd8, 0x0c, 0x13, 0x01, 0xfd, 0x00, 0x00, 0x40];
+
+        assert_eq!(
+            parse_all(&rom, ChannelType::MusicNoise),
+            [
+                Command::ToggleNoise(3),
+                Command::DrumSpeed(12),
+                Command::DrumNote {
+                    instrument: 1,
+                    length: 3
+                },
+                Command::Rest(1),
+                Command::Loop {
+                    count: 0,
+                    addr: 0x4000
+                },
+            ]
+        );
+    }
+}
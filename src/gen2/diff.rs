@@ -0,0 +1,51 @@
+use crate::diff::{diff_sounds, SoundDiff};
+use crate::RomLocation;
+
+use super::Gen2;
+
+/// Diff the command streams of two sounds, channel by channel.
+///
+/// Each channel is read from its start until the first return, jump or infinite loop, without
+/// following calls.
+pub fn diff(rom_a: &[u8], a: RomLocation, rom_b: &[u8], b: RomLocation) -> SoundDiff {
+    diff_sounds::<Gen2>(rom_a, a, rom_b, b)
+}
+
+/// Diff the sound at the same location in two ROM images.
+pub fn diff_roms(rom_a: &[u8], rom_b: &[u8], location: RomLocation) -> SoundDiff {
+    diff(rom_a, location, rom_b, location)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::DiffEntry;
+    use crate::Voice;
+
+    #[test]
+    fn test_diff() {
+        #[rustfmt::skip]
+        let a = [
+            0x44, 0x06, 0x00, 0x05, 0x0e, 0x00,
+            0x01, 0xf1, 0x00, 0x07, 0x01, 0xa1, 0x80, 0x06, 0xff,
+            0x01, 0xf1, 0x00, 0x05, 0xff,
+        ];
+
+        let mut b = a;
+        b[11] = 0x91;
+
+        let location = RomLocation::new(0, 0);
+        let diff = diff_roms(&a, &b, location);
+
+        assert_eq!(diff.channels.len(), 2);
+        assert_eq!(diff.channels[0].voice, Voice::Pulse1);
+        assert!(diff.channels[1].entries.is_empty());
+
+        match diff.channels[0].entries[..] {
+            [DiffEntry::Changed { addr_a, addr_b, .. }] => assert_eq!((addr_a, addr_b), (10, 10)),
+            ref entries => panic!("Unexpected entries: {entries:?}"),
+        }
+
+        assert!(diff_roms(&a, &a, location).is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::header;
+use crate::{HeaderError, RomLocation};
+
+use super::channel::ChannelType;
+use super::command::Command;
+
+/// Upper bound on the number of commands read from a single block, in case of malformed data.
+const MAX_COMMANDS: usize = 0x10000;
+
+/// Disassemble the sound at the given address into the macro syntax of the pokecrystal
+/// project, using `label` as the name of the sound.
+///
+/// Every channel gets a `<label>_Ch<n>` label, loop targets gets local `.loop<n>` labels, and
+/// the targets of `sound_call` are emitted as `<label>_Sub<n>` blocks after the channels.
+/// Commands without a macro in the SFX command set are emitted as raw `db` bytes.
+///
+/// Returns an error if the header isn't valid, see `Sound::try_new`.
+pub fn disassemble(rom: &[u8], bank: u8, addr: u16, label: &str) -> Result<String, HeaderError> {
+    let mut output = String::new();
+    let mut subroutines = Vec::new();
+
+    let channels = header::try_read(rom, RomLocation::new(bank, addr))?;
+    let channel_count = channels.len();
+
+    // writing to a String can't fail
+    let _ = writeln!(output, "{label}:");
+    let _ = writeln!(output, "\tchannel_count {channel_count}");
+
+    for (id, _) in &channels {
+        let _ = writeln!(output, "\tchannel {id}, {label}_Ch{id}");
+    }
+
+    for (id, ptr) in channels {
+        let channel = match id {
+            1 | 2 => ChannelType::MusicPulse,
+            3 => ChannelType::MusicWave,
+            4 => ChannelType::MusicNoise,
+            5 | 6 => ChannelType::SfxPulse,
+            7 => ChannelType::SfxWave,
+            _ => ChannelType::SfxNoise,
+        };
+
+        let _ = writeln!(output);
+        let _ = writeln!(output, "{label}_Ch{id}:");
+        write_block(
+            &mut output,
+            rom,
+            bank,
+            ptr,
+            channel,
+            label,
+            &mut subroutines,
+        );
+    }
+
+    let mut index = 0;
+
+    while index < subroutines.len() {
+        let (ptr, channel) = subroutines[index];
+        index += 1;
+
+        let _ = writeln!(output);
+        let _ = writeln!(output, "{label}_Sub{index}:");
+        write_block(
+            &mut output,
+            rom,
+            bank,
+            ptr,
+            channel,
+            label,
+            &mut subroutines,
+        );
+    }
+
+    Ok(output)
+}
+
+fn write_block(
+    output: &mut String,
+    rom: &[u8],
+    bank: u8,
+    start: u16,
+    channel: ChannelType,
+    label: &str,
+    subroutines: &mut Vec<(u16, ChannelType)>,
+) {
+    let mut commands = Vec::new();
+    let mut addr = start;
+
+    for _ in 0..MAX_COMMANDS {
+        let Ok(cmd) = Command::parse(rom, bank, addr, channel) else {
+            break;
+        };
+        commands.push((addr, cmd));
+        addr = addr.wrapping_add(cmd.len() as u16);
+
+        if matches!(
+            cmd,
+            Command::Return | Command::Loop { count: 0, .. } | Command::SoundJump(_)
+        ) {
+            break;
+        }
+    }
+
+    let end = addr;
+    let mut loops = BTreeMap::new();
+
+    for (_, cmd) in &commands {
+        if let Command::Loop { addr, .. } = cmd {
+            if (start..end).contains(addr) {
+                loops.insert(*addr, String::new());
+            }
+        }
+    }
+
+    for (index, name) in loops.values_mut().enumerate() {
+        *name = format!(".loop{}", index + 1);
+    }
+
+    for (addr, cmd) in commands {
+        if let Some(name) = loops.get(&addr) {
+            let _ = writeln!(output, "{name}:");
+        }
+
+        let line = match cmd {
+            Command::SquareNote {
+                length,
+                volume,
+                fade,
+                freq,
+            } => format!("square_note {length}, {volume}, {fade}, {freq}"),
+            Command::NoiseNote {
+                length,
+                volume,
+                fade,
+                value,
+            } => format!("noise_note {length}, {volume}, {fade}, {value}"),
+            Command::DutyCycle(duty) => format!("duty_cycle {duty}"),
+            Command::DutyCyclePattern(a, b, c, d) => {
+                format!("duty_cycle_pattern {a}, {b}, {c}, {d}")
+            }
+            Command::PitchSweep { length, change } => format!("pitch_sweep {length}, {change}"),
+            Command::PitchOffset(offset) => format!("pitch_offset {}", offset as u16),
+            Command::Loop { count, addr } => match loops.get(&addr) {
+                Some(name) => format!("sound_loop {count}, {name}"),
+                None => format!("sound_loop {count}, ${addr:04x}"),
+            },
+            Command::SoundCall(addr) => {
+                let index = match subroutines.iter().position(|(ptr, _)| *ptr == addr) {
+                    Some(index) => index,
+                    None => {
+                        subroutines.push((addr, channel));
+                        subroutines.len() - 1
+                    }
+                };
+
+                format!("sound_call {label}_Sub{}", index + 1)
+            }
+            Command::Return => String::from("sound_ret"),
+            cmd => {
+                let pos = ((bank as usize) * 0x4000) + ((addr as usize) & 0x3fff);
+                let bytes: Vec<String> = rom
+                    .get(pos..(pos + cmd.len()))
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|byte| format!("${byte:02x}"))
+                    .collect();
+
+                format!("db {}", bytes.join(", "))
+            }
+        };
+
+        let _ = writeln!(output, "\t{line}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        #[rustfmt::skip]
+        let rom = [
+            // two channels
+            0x44, 0x06, 0x00, 0x07, 0x1b, 0x00,
+            // duty_cycle_pattern, square_note, pitch_offset, square_note, loop, call, ret
+            0xde, 0xe4,
+            0x04, 0xf1, 0x00, 0x07,
+            0xe6, 0x01, 0x00,
+            0x08, 0x9b, 0xc0, 0x06,
+            0xfd, 0x02, 0x0c, 0x00,
+            0xfe, 0x22, 0x00,
+            0xff,
+            // noise_note, loop forever
+            0x02, 0xa2, 0x31,
+            0xfd, 0x00, 0x1b, 0x00,
+            // subroutine: duty_cycle, ret
+            0xdb, 0x02,
+            0xff,
+        ];
+
+        assert_eq!(
+            disassemble(&rom, 0, 0, "Cry_Test").unwrap(),
+            "\
+Cry_Test:
+\tchannel_count 2
+\tchannel 5, Cry_Test_Ch5
+\tchannel 8, Cry_Test_Ch8
+
+Cry_Test_Ch5:
+\tduty_cycle_pattern 3, 2, 1, 0
+\tsquare_note 4, 15, 1, 1792
+.loop1:
+\tpitch_offset 256
+\tsquare_note 8, 9, -3, 1728
+\tsound_loop 2, .loop1
+\tsound_call Cry_Test_Sub1
+\tsound_ret
+
+Cry_Test_Ch8:
+.loop1:
+\tnoise_note 2, 10, 2, 49
+\tsound_loop 0, .loop1
+
+Cry_Test_Sub1:
+\tduty_cycle 2
+\tsound_ret
+"
+        );
+    }
+}
@@ -1,19 +1,135 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
 use std::time::Duration;
 
-use channel::SOURCE_SAMPLE_RATE;
-use sound::Sound;
-
-pub use sound::SoundIterator;
-
+use crate::generation::{ChannelStart, Flow, Generation};
+use crate::mix::Stems;
+use crate::{
+    duration_to_frames, header, save, HeaderError, Metadata, NoteEvent, Pcm64, PcmBuffer,
+    RomLocation, SaveError, SaveOptions, SynthesisOptions, Voice, WavOptions, SAMPLES_PER_FRAME,
+};
+use command::Command;
+
+pub use batch::render_batch;
+pub use channel::ChannelType;
+pub use command::{ParseError, ParseErrorKind};
+pub use diff::{diff, diff_roms};
+pub use disasm::disassemble;
+pub use extract::{sound_blob, synthesize_blob};
+pub use session::RenderSession;
+pub use sound::{ChunkIter, FiniteSoundIterator, Sound, SoundIterator};
+
+mod batch;
 mod channel;
 mod command;
+mod diff;
+mod disasm;
+mod extract;
+mod session;
 mod sound;
 
+/// Error returned by `try_synthesis`.
+///
+/// No public function of the crate panics, whatever the ROM and the arguments contain. Functions
+/// that return a `Result` report malformed input as an error, and the others play or list what
+/// can be read of it, e.g. `synthesis` plays a header that isn't valid as described at
+/// `Sound::new`. Arguments out of range are either reported the same way, e.g. a sample rate of
+/// 0 for `PcmBuffer::new`, or clamped, e.g. a chunk size of 0 for `SoundIterator::chunks`. The
+/// adversarial-input tests in this module throw such input at every entry point.
+///
+/// Running out of memory is the exception, e.g. for a chunk size of `usize::MAX`, which aborts
+/// like any other allocation that is too large.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SynthesisError {
+    Header(HeaderError),
+    Parse(ParseError),
+}
+
+impl fmt::Display for SynthesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SynthesisError::Header(err) => err.fmt(f),
+            SynthesisError::Parse(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SynthesisError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SynthesisError::Header(err) => Some(err),
+            SynthesisError::Parse(err) => Some(err),
+        }
+    }
+}
+
+impl From<HeaderError> for SynthesisError {
+    fn from(err: HeaderError) -> Self {
+        SynthesisError::Header(err)
+    }
+}
+
+impl From<ParseError> for SynthesisError {
+    fn from(err: ParseError) -> Self {
+        SynthesisError::Parse(err)
+    }
+}
+
+/// The command set of the second generation, for the tools shared with the first one.
+pub(crate) struct Gen2;
+
+impl Generation for Gen2 {
+    const NUMBER: u8 = 2;
+
+    type ChannelType = ChannelType;
+    type Command = Command;
+
+    fn channels(
+        rom: &[u8],
+        location: RomLocation,
+    ) -> [(Voice, Option<ChannelStart<ChannelType>>); 4] {
+        Sound::new(rom, location.bank, location.addr)
+            .channels()
+            .map(|(voice, channel)| {
+                (
+                    voice,
+                    channel.map(|channel| (channel.addr, channel.channel)),
+                )
+            })
+    }
+
+    fn parse(rom: &[u8], bank: u8, addr: u16, channel: ChannelType) -> Option<Command> {
+        Command::parse(rom, bank, addr, channel).ok()
+    }
+
+    fn len(cmd: &Command) -> usize {
+        cmd.len()
+    }
+
+    fn flow(cmd: &Command) -> Flow {
+        match *cmd {
+            Command::ToggleSfx => Flow::Switch,
+            Command::SoundCall(addr) => Flow::Call(addr),
+            Command::Loop { count, addr } => Flow::Loop { count, addr },
+            Command::SoundJump(addr) => Flow::Jump(addr),
+            Command::Return => Flow::Return,
+            _ => Flow::Next,
+        }
+    }
+
+    fn switch_channel(channel: ChannelType) -> ChannelType {
+        channel.toggle_sfx()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Pcm<'a> {
     pitch: i16,
     length: u16,
     sound: Sound<'a>,
+    options: SynthesisOptions,
+    metadata: Metadata,
 }
 
 impl<'a> Pcm<'a> {
@@ -22,11 +138,21 @@ impl<'a> Pcm<'a> {
     }
 
     pub fn sample_rate(&self) -> u32 {
-        SOURCE_SAMPLE_RATE as u32
+        self.options.sample_rate()
     }
 
+    /// The length of the sound, `None` if it's infinitely long.
+    ///
+    /// This steps through the channels a frame at a time in the order the engine runs them,
+    /// passing a tempo set by one channel on to the others, until every channel has ended or one
+    /// of them loops forever. A channel that reaches a command which isn't emulated ends there,
+    /// the same as when rendering, so the duration is that of the render. The oscillators aren't
+    /// clocked, but it is a pass over the whole sound, or up to `cut_at`, on every call.
     pub fn total_duration(&self) -> Option<Duration> {
-        let len = self.sound.pcm(self.pitch, self.length).count();
+        let len = self
+            .sound
+            .pcm::<f32>(self.pitch, self.length, self.options)
+            .count();
 
         if len == usize::MAX {
             None
@@ -38,75 +164,234 @@ impl<'a> Pcm<'a> {
     }
 
     pub fn iter(&self) -> SoundIterator<'a> {
-        self.sound.pcm(self.pitch, self.length)
+        self.sound.pcm(self.pitch, self.length, self.options)
+    }
+
+    /// Iterate over the sound with an exact length, which implements `ExactSizeIterator`.
+    /// Returns `None` if the sound is infinitely long.
+    pub fn iter_exact(&self) -> Option<FiniteSoundIterator<'a>> {
+        self.iter().exact()
+    }
+
+    /// The same sound at another sample rate, e.g. 48000 Hz for playback. Each output sample is
+    /// the average of the source samples it covers, the same way as `SynthesisOptions::preview`,
+    /// which filters out more of the aliasing than interpolating between two source samples.
+    pub fn resample(&self, rate: u32) -> Pcm<'a> {
+        Pcm {
+            options: self.options.preview(rate),
+            ..self.clone()
+        }
+    }
+
+    /// Render the whole sound into a buffer, returns `None` if the sound is infinitely long.
+    pub fn to_buffer(&self) -> Option<PcmBuffer> {
+        self.total_duration()?;
+        Some(PcmBuffer::rendered(
+            self.iter().collect(),
+            self.sample_rate(),
+        ))
+    }
+
+    /// Render the whole sound as 16-bit integers, clamped, scaled and rounded the same way as
+    /// `PcmBuffer::iter_i16`. Returns `None` if the sound is infinitely long.
+    pub fn to_i16(&self) -> Option<Vec<i16>> {
+        Some(self.to_buffer()?.iter_i16().collect())
+    }
+
+    /// Render every channel into its own buffer, at the full sample rate regardless of
+    /// `SynthesisOptions::preview`, see `Stems`. Returns `None` if the sound is infinitely long.
+    pub fn stems(&self) -> Option<Stems> {
+        self.iter().into_stems()
+    }
+
+    /// Iterate over the samples, mixed with `f64` precision.
+    pub fn iter_f64(&self) -> SoundIterator<'a, f64> {
+        self.sound.pcm(self.pitch, self.length, self.options)
+    }
+
+    /// Render the whole sound into a buffer mixed with `f64` precision, returns `None` if the
+    /// sound is infinitely long.
+    pub fn render_f64(&self) -> Option<Pcm64> {
+        self.total_duration()?;
+        Some(PcmBuffer::rendered(
+            self.iter_f64().collect(),
+            self.sample_rate(),
+        ))
+    }
+
+    /// Render the whole sound and write it to a file, in the format given by the extension of
+    /// `path`: `wav` or `raw`, in any case. Samples are written as 16-bit integers.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        self.save_with_options(path, SaveOptions::new())
+    }
+
+    /// Same as `save`, with the given sample rate and bit depth.
+    pub fn save_with_options(
+        &self,
+        path: impl AsRef<Path>,
+        options: SaveOptions,
+    ) -> Result<(), SaveError> {
+        save::save(path.as_ref(), options, |rate| match rate {
+            Some(rate) => self.resample(rate).to_buffer(),
+            None => self.to_buffer(),
+        })
+    }
+
+    /// Write the sound to `w` as a RIFF WAVE file, see `WavOptions`. The samples are streamed
+    /// to `w` as they are rendered, after a first pass that finds the length of the sound. Fails
+    /// with `InvalidInput` if the sound is infinitely long or the bit depth isn't supported.
+    pub fn write_wav(&self, w: impl Write, options: WavOptions) -> io::Result<()> {
+        let len = self.iter().count();
+
+        if len == usize::MAX {
+            let err = SaveError::Infinite;
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+        }
+
+        save::write_wav(w, self.iter(), len, 1, self.sample_rate(), options)
+    }
+
+    /// Start rendering the sound in small chunks, see `RenderSession`.
+    pub fn session(&self) -> RenderSession<'a> {
+        RenderSession::new(self.iter())
+    }
+
+    /// Step through the sound without synthesizing it, returns the first command that a channel
+    /// couldn't parse. Such a channel is silenced instead of panicking, see
+    /// `SoundIterator::error`.
+    pub fn check(&self) -> Result<(), ParseError> {
+        self.iter().check()
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.metadata
+    }
+
+    /// The register writes that the sound engine makes while playing the sound, at most a minute
+    /// of them, see `apu::register_writes`.
+    #[cfg(feature = "apu-ref")]
+    #[doc(hidden)]
+    pub fn register_writes(&self) -> Vec<crate::apu::RegisterWrite> {
+        let states = self
+            .sound
+            .frame_states(self.pitch, self.length, crate::apu::MAX_FRAMES);
+        crate::apu::register_writes(&states)
+    }
+
+    /// The notes played by every channel, sorted by start time, see `NoteEvent`. \
+    /// Infinite loops are followed once, or as set by `LoopBehavior::Iterations`, and notes after
+    /// `cut_at` or `max_duration` are left out.
+    pub fn note_events(&self) -> Vec<NoteEvent> {
+        let loops = self.options.loop_iterations();
+        let mut result = self
+            .sound
+            .note_events_with_loops(self.pitch, self.length, loops);
+
+        if let Some(cut_at) = self.options.end() {
+            let cut_at = (duration_to_frames(cut_at) as usize) * SAMPLES_PER_FRAME;
+
+            result.retain(|event| event.start < cut_at);
+
+            for event in &mut result {
+                event.len = event.len.min(cut_at - event.start);
+            }
+        }
+
+        result
     }
 }
 
 pub fn synthesis(rom: &[u8], bank: u8, addr: u16, pitch: i16, length: u16) -> Pcm {
+    synthesis_with_options(rom, bank, addr, pitch, length, SynthesisOptions::default())
+}
+
+/// Same as `synthesis`, but returns an error if the header isn't valid, see `Sound::try_new`, or
+/// if the sound runs into a command that can't be parsed, see `Pcm::check`.
+pub fn try_synthesis(
+    rom: &[u8],
+    bank: u8,
+    addr: u16,
+    pitch: i16,
+    length: u16,
+) -> Result<Pcm<'_>, SynthesisError> {
+    try_synthesis_with_options(rom, bank, addr, pitch, length, SynthesisOptions::default())
+}
+
+/// Same as `synthesis_with_options`, but returns an error for a sound that can't be played as
+/// it is stored, see `try_synthesis`.
+pub fn try_synthesis_with_options(
+    rom: &[u8],
+    bank: u8,
+    addr: u16,
+    pitch: i16,
+    length: u16,
+    options: SynthesisOptions,
+) -> Result<Pcm<'_>, SynthesisError> {
+    let header = header::try_read(rom, RomLocation::new(bank, addr))?;
+    let pcm = synthesis_from_header(rom, bank, &header, pitch, length, options);
+    pcm.check()?;
+    Ok(pcm)
+}
+
+pub fn synthesis_with_options(
+    rom: &[u8],
+    bank: u8,
+    addr: u16,
+    pitch: i16,
+    length: u16,
+    options: SynthesisOptions,
+) -> Pcm<'_> {
+    let header = header::read(rom, RomLocation::new(bank, addr));
+    synthesis_from_header(rom, bank, &header, pitch, length, options)
+}
+
+/// Same as `synthesis_with_options`, with an already parsed header.
+pub(crate) fn synthesis_from_header<'a>(
+    rom: &'a [u8],
+    bank: u8,
+    header: &[(u8, u16)],
+    pitch: i16,
+    length: u16,
+    options: SynthesisOptions,
+) -> Pcm<'a> {
+    let sound = Sound::from_header(rom, bank, header);
+    synthesis_from_sound(sound, pitch, length, options)
+}
+
+/// Same as `synthesis_with_options`, with an already parsed sound.
+pub(crate) fn synthesis_from_sound(
+    sound: Sound<'_>,
+    pitch: i16,
+    length: u16,
+    options: SynthesisOptions,
+) -> Pcm<'_> {
+    let (pitch, length) = options.apply_variation(pitch, length);
+
     Pcm {
-        sound: Sound::new(rom, bank, addr),
+        sound,
+        options,
         pitch,
         length,
+        metadata: Metadata { pitch, length },
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::sound::tests::LOOPING_SQUARE_NOTE;
     use super::*;
+    use crate::fixtures::{self, FIXTURES, TOLERANCE, WAVE_HEADER_LEN};
 
     const POKECRYSTAL: &[u8] = include_bytes!("../../roms/pokecrystal.gbc");
-    const WAVE_HEADER_LEN: usize = 44;
 
     fn convert_to_wav(input: &Pcm) -> Vec<u8> {
         assert_eq!(input.channels(), 1);
 
-        let data: Vec<f32> = input.iter().collect();
-
-        let resample_rate_ratio = input.sample_rate() as f64 / 48000.0;
-        let resampled_length = (data.len() as f64 / resample_rate_ratio).ceil() as usize;
-        let mut output = Vec::with_capacity(WAVE_HEADER_LEN + resampled_length);
-
-        output.extend(b"RIFF");
-        output.extend((resampled_length as u32).to_le_bytes());
-        output.extend(b"WAVEfmt ");
-        output.extend(16u32.to_le_bytes()); // remaining header size
-        output.extend(1u16.to_le_bytes()); // PCM type
-        output.extend(1u16.to_le_bytes()); // channels
-        output.extend(48000u32.to_le_bytes()); // sample rate
-        output.extend(48000u32.to_le_bytes()); // byte rate
-        output.extend(1u16.to_le_bytes()); // block align
-        output.extend(8u16.to_le_bytes()); // bits per sample
-        output.extend(b"data");
-        output.extend((resampled_length as u32).to_le_bytes());
-
-        assert_eq!(output.len(), WAVE_HEADER_LEN);
-
-        fn to_u8(value: f32) -> u8 {
-            (value * 127.0 + 128.0) as u8
-        }
-
-        for resampled_index in 1..resampled_length {
-            let prev_index = (resampled_index as f64 * resample_rate_ratio).floor() as usize;
-            let next_index = (resampled_index as f64 * resample_rate_ratio).ceil() as usize;
-
-            if prev_index == next_index {
-                output.push(to_u8(data[prev_index]));
-                continue;
-            }
-
-            let prev_fraction = resampled_index as f64 * resample_rate_ratio - prev_index as f64;
-            let next_fraction = 1.0 - prev_fraction;
-
-            output.push(to_u8(
-                ((prev_fraction * (data[prev_index] as f64))
-                    + (next_fraction * (data[next_index] as f64))) as f32,
-            ));
-        }
-
-        output
+        fixtures::to_wav(&input.to_buffer().unwrap()).unwrap()
     }
 
     fn assert_wav_almost_equal(actual: &[u8], expected: &[u8]) {
+        let actual = &fixtures::to_reference_layout(actual, expected)[..];
         assert_eq!(actual.len(), expected.len());
 
         assert_eq!(&actual[..WAVE_HEADER_LEN], &expected[..WAVE_HEADER_LEN],);
@@ -118,12 +403,31 @@ mod tests {
             .enumerate()
         {
             assert!(
-                (*actual as i32 - *expected as i32).abs() <= 1,
+                (*actual as i32 - *expected as i32).abs() <= TOLERANCE,
                 "actual: {actual}, expected: {expected}, at index: {index}",
             );
         }
     }
 
+    #[test]
+    fn test_cry_lengths() {
+        // the length is stepped through without synthesizing, and matches what is rendered
+        for fixture in FIXTURES.iter().filter(|fixture| fixture.generation == 2) {
+            let pcm = synthesis(
+                POKECRYSTAL,
+                fixture.bank,
+                fixture.addr,
+                fixture.pitch,
+                fixture.length,
+            );
+            let rendered = pcm.iter().collect::<Vec<f32>>().len();
+
+            let size_hint = pcm.iter().size_hint();
+            assert_eq!(size_hint, (rendered, Some(rendered)), "{}", fixture.id());
+            assert_eq!(pcm.iter().count(), rendered, "{}", fixture.id());
+        }
+    }
+
     #[test]
     fn test_bulbasaur_cry() {
         let pcm = synthesis(POKECRYSTAL, 0x3c, 0x7504, 128, 129);
@@ -193,4 +497,131 @@ mod tests {
             include_bytes!("../../expected/slowpoke-cry.wav"),
         );
     }
+
+    #[test]
+    fn test_infinite_sound() {
+        let pcm = synthesis(&LOOPING_SQUARE_NOTE, 0, 0, 0, 0x100);
+        assert_eq!(pcm.total_duration(), None);
+        assert!(pcm.to_buffer().is_none());
+        assert!(pcm.to_i16().is_none());
+        assert!(pcm.stems().is_none());
+
+        let options = SynthesisOptions::new().cut_at(Some(Duration::from_secs(1)));
+        let pcm = synthesis_with_options(&LOOPING_SQUARE_NOTE, 0, 0, 0, 0x100, options);
+        let buffer = pcm.to_buffer().unwrap();
+        let samples = pcm.to_i16().unwrap();
+        let stems = pcm.stems().unwrap();
+        assert_eq!(stems.pulse1.unwrap().frames(), buffer.frames());
+        assert_eq!(samples.len(), buffer.frames());
+        assert!(samples.iter().any(|&sample| sample > 0));
+        assert_eq!(
+            buffer.frames(),
+            duration_to_frames(Duration::from_secs(1)) as usize * SAMPLES_PER_FRAME
+        );
+    }
+
+    #[test]
+    fn test_large_pitch_offset() {
+        let base: Vec<f32> = synthesis(POKECRYSTAL, 0x3c, 0x7504, 128, 129)
+            .iter()
+            .collect();
+
+        // only the lower 11 bits of the frequency reaches the hardware, and the low byte is
+        // unchanged
+        let wrapped: Vec<f32> = synthesis(POKECRYSTAL, 0x3c, 0x7504, 128 + 0x800, 129)
+            .iter()
+            .collect();
+        assert_eq!(base, wrapped);
+
+        // carrying into the high byte changes the pulse channels
+        let shifted: Vec<f32> = synthesis(POKECRYSTAL, 0x3c, 0x7504, 128 + 0x100, 129)
+            .iter()
+            .collect();
+        assert_eq!(base.len(), shifted.len());
+        assert_ne!(base, shifted);
+    }
+
+    #[test]
+    fn test_disassemble_cry() {
+        let listing = disassemble(POKECRYSTAL, 0x3c, 0x7504, "Cry_0F").unwrap();
+
+        assert!(listing.starts_with(
+            "Cry_0F:\n\tchannel_count 3\n\tchannel 5, Cry_0F_Ch5\n\tchannel 6, Cry_0F_Ch6\n\tchannel 8, Cry_0F_Ch8\n"
+        ));
+
+        for block in listing.split("\n\n").skip(1) {
+            assert!(block.trim_end().ends_with("\tsound_ret"), "{block}");
+        }
+    }
+
+    /// ROMs that are malformed in the ways that every entry point has to handle, with the
+    /// location of the sound to read from them.
+    fn adversarial_roms() -> Vec<(Vec<u8>, RomLocation)> {
+        // a header with four channels at the last byte of bank 1
+        let mut last_byte = vec![0; 0x8000];
+        last_byte[0x7fff] = 0xc4;
+
+        vec![
+            (Vec::new(), RomLocation::new(0x00, 0x0000)),
+            (vec![0x00], RomLocation::new(0x00, 0x0000)),
+            (vec![0xff; 0x8000], RomLocation::new(0x01, 0x4000)),
+            (vec![0x00; 0x8000], RomLocation::new(0x01, 0x4000)),
+            (last_byte, RomLocation::new(0x01, 0x7fff)),
+            (vec![0xff; 0x8000], RomLocation::new(0xff, 0xffff)),
+        ]
+    }
+
+    #[test]
+    fn test_adversarial_synthesis() {
+        for (rom, RomLocation { bank, addr }) in adversarial_roms() {
+            assert!(try_synthesis(&rom, bank, addr, i16::MAX, u16::MAX).is_err());
+
+            // the lenient entry points play what can be played of the sound, which is nothing
+            let pcm = synthesis(&rom, bank, addr, i16::MIN, u16::MAX);
+            assert!(pcm.check().is_err() || pcm.iter().all(|sample| sample == 0.0));
+
+            let pitches = [i16::MIN, i16::MAX];
+            let options = SynthesisOptions::default();
+            render_batch(&rom, bank, addr, &pitches, u16::MAX, options);
+        }
+    }
+
+    #[test]
+    fn test_adversarial_tools() {
+        for (rom, location) in adversarial_roms() {
+            let RomLocation { bank, addr } = location;
+
+            assert!(sound_blob(&rom, location).is_err());
+
+            // these list what can be read of the sound, and only have to return
+            let _ = disassemble(&rom, bank, addr, "Adversarial");
+            diff_roms(&rom, &rom, location);
+        }
+    }
+
+    #[test]
+    fn test_adversarial_arguments() {
+        // a single pulse channel with a 16 frame long square_note
+        let rom = [0x04, 0x03, 0x00, 0x0f, 0xf1, 0x00, 0x07, 0xff];
+        let pcm = synthesis(&rom, 0, 0, 0, 0);
+        let len = pcm.iter().count();
+
+        let chunk = pcm.iter().chunks(0).next().map(|chunk| chunk.len());
+        assert_eq!(chunk, Some(1));
+
+        let mut session = pcm.session();
+        for ms in [f32::INFINITY, f32::NAN, -1.0] {
+            assert!(session.render_ms(ms).is_empty());
+        }
+        assert_eq!(session.render_ms(f32::MAX).len(), len);
+
+        let err = PcmBuffer::new(vec![0.0; len], 0);
+        assert_eq!(err, Err(crate::BufferError::NoSampleRate));
+
+        for rate in [0, u32::MAX] {
+            let options = WavOptions::new().sample_rate(rate);
+            let err = pcm.write_wav(io::sink(), options).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
 }
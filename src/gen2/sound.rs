@@ -1,7 +1,18 @@
-use super::channel::{
-    Channel, ChannelIterator, ChannelType, SAMPLES_PER_FRAME, SOURCE_SAMPLE_RATE,
+use crate::{
+    duration_to_frames, header, ChannelMask, HeaderError, NoteEvent, RomLocation, Sample,
+    SynthesisOptions, Voice, SAMPLES_PER_FRAME, SOURCE_SAMPLE_RATE,
 };
 
+use super::channel::{Channel, ChannelIterator, ChannelType};
+use super::command::ParseError;
+#[cfg(test)]
+use super::command::ParseErrorKind;
+#[cfg(feature = "apu-ref")]
+use crate::apu::FrameState;
+use crate::generation::Render;
+use crate::mix::Stems;
+use crate::timing::LengthCache;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Sound<'a> {
     pulse1: Option<Channel<'a>>,
@@ -11,7 +22,23 @@ pub struct Sound<'a> {
 }
 
 impl<'a> Sound<'a> {
+    /// Create the sound with its header at the given address.
+    ///
+    /// A header that isn't valid plays what can be played of it: a header past the end of the
+    /// ROM plays as silence, entries with a channel ID above 8 are skipped, and a hardware
+    /// channel that is listed twice plays its last entry. Use `try_new` to reject such headers.
     pub fn new(rom: &'a [u8], bank: u8, addr: u16) -> Sound<'a> {
+        Sound::from_header(rom, bank, &header::read(rom, RomLocation::new(bank, addr)))
+    }
+
+    /// Same as `new`, but returns an error if the header isn't a valid sound header.
+    pub fn try_new(rom: &'a [u8], bank: u8, addr: u16) -> Result<Sound<'a>, HeaderError> {
+        let header = header::try_read(rom, RomLocation::new(bank, addr))?;
+        Ok(Sound::from_header(rom, bank, &header))
+    }
+
+    /// Create a sound from an already parsed header, see `new` for headers that aren't valid.
+    pub(crate) fn from_header(rom: &'a [u8], bank: u8, header: &[(u8, u16)]) -> Sound<'a> {
         let mut result = Sound {
             pulse1: None,
             pulse2: None,
@@ -19,80 +46,204 @@ impl<'a> Sound<'a> {
             noise: None,
         };
 
-        let mut pos = ((bank as usize) * 0x4000) + ((addr as usize) & 0x3fff);
-        let channel_count = (rom[pos] >> 6) + 1;
-
-        for _ in 0..channel_count {
-            let id = (rom[pos] & 0xf) + 1;
-            pos += 1;
-
-            let ptr = u16::from_le_bytes([rom[pos], rom[pos + 1]]);
-            pos += 2;
-
+        for &(id, ptr) in header {
             match id {
-                1 => assert!(result
-                    .pulse1
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::MusicPulse))
-                    .is_none()),
-                2 => assert!(result
-                    .pulse2
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::MusicPulse))
-                    .is_none()),
-                3 => assert!(result
-                    .wave
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::MusicWave))
-                    .is_none()),
-                4 => assert!(result
-                    .noise
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::MusicNoise))
-                    .is_none()),
-                5 => assert!(result
-                    .pulse1
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::SfxPulse))
-                    .is_none()),
-                6 => assert!(result
-                    .pulse2
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::SfxPulse))
-                    .is_none()),
-                7 => assert!(result
-                    .wave
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::SfxWave))
-                    .is_none()),
-                8 => assert!(result
-                    .noise
-                    .replace(Channel::new(rom, bank, ptr, ChannelType::SfxNoise))
-                    .is_none()),
-                _ => panic!("Invalid SFX channel: {}", id),
+                1 => result.pulse1 = Some(Channel::new(rom, bank, ptr, ChannelType::MusicPulse)),
+                2 => result.pulse2 = Some(Channel::new(rom, bank, ptr, ChannelType::MusicPulse)),
+                3 => result.wave = Some(Channel::new(rom, bank, ptr, ChannelType::MusicWave)),
+                4 => result.noise = Some(Channel::new(rom, bank, ptr, ChannelType::MusicNoise)),
+                5 => result.pulse1 = Some(Channel::new(rom, bank, ptr, ChannelType::SfxPulse)),
+                6 => result.pulse2 = Some(Channel::new(rom, bank, ptr, ChannelType::SfxPulse)),
+                7 => result.wave = Some(Channel::new(rom, bank, ptr, ChannelType::SfxWave)),
+                8 => result.noise = Some(Channel::new(rom, bank, ptr, ChannelType::SfxNoise)),
+                _ => {}
             }
         }
 
         result
     }
 
-    pub fn pcm(self, pitch: i16, length: u16) -> SoundIterator<'a> {
-        SoundIterator::new(self, pitch, length)
+    pub fn pcm<S: Sample>(
+        self,
+        pitch: i16,
+        length: u16,
+        options: SynthesisOptions,
+    ) -> SoundIterator<'a, S> {
+        SoundIterator::new(self, pitch, length, options)
+    }
+
+    /// Render the whole sound, synthesizing every channel on a thread of its own and mixing them
+    /// afterwards. The result is identical to collecting `pcm`. Returns `None` if the sound is
+    /// infinitely long.
+    ///
+    /// The channels affect each other through the tempo, so this is worked out first, by stepping
+    /// through the commands of all channels together without synthesizing anything. Previews are
+    /// rendered on the current thread.
+    #[cfg(feature = "parallel")]
+    pub fn render(self, pitch: i16, length: u16, options: SynthesisOptions) -> Option<Vec<f32>> {
+        let mut iter = self.pcm::<f32>(pitch, length, options);
+
+        match options.preview {
+            Some(_) => (iter.clone().count() != usize::MAX).then(|| iter.collect()),
+            None => iter.render_parallel(),
+        }
+    }
+
+    pub(super) fn channels(&self) -> [(Voice, Option<Channel<'a>>); 4] {
+        [
+            (Voice::Pulse1, self.pulse1),
+            (Voice::Pulse2, self.pulse2),
+            (Voice::Wave, self.wave),
+            (Voice::Noise, self.noise),
+        ]
+    }
+
+    /// What the engine does to each channel every frame, for at most `max_frames` frames. The
+    /// wave channel is left out, since it can't be synthesized yet.
+    #[cfg(feature = "apu-ref")]
+    pub(crate) fn frame_states(
+        self,
+        pitch: i16,
+        length: u16,
+        max_frames: usize,
+    ) -> [Option<Vec<FrameState>>; 4] {
+        let channels = [
+            self.pulse1.map(|c| c.pcm(pitch, length)),
+            self.pulse2.map(|c| c.pcm(pitch, length)),
+            None,
+            self.noise.map(|c| c.pcm(pitch, 0x100)),
+        ];
+
+        channels.map(|channel| {
+            let mut channel = channel?;
+            Some(
+                std::iter::from_fn(|| channel.frame_state())
+                    .take(max_frames)
+                    .collect(),
+            )
+        })
+    }
+
+    /// The notes played by every channel, sorted by start time.
+    pub fn note_events(self, pitch: i16, length: u16) -> Vec<NoteEvent> {
+        self.note_events_with_loops(pitch, length, None)
+    }
+
+    /// Same as `note_events`, with the infinite loops played the given number of times, see
+    /// `ChannelIterator::loop_iterations`.
+    pub(super) fn note_events_with_loops(
+        self,
+        pitch: i16,
+        length: u16,
+        loops: Option<u32>,
+    ) -> Vec<NoteEvent> {
+        let channels = [
+            (
+                Voice::Pulse1,
+                self.pulse1
+                    .map(|c| c.pcm(pitch, length).loop_iterations(loops)),
+            ),
+            (
+                Voice::Pulse2,
+                self.pulse2
+                    .map(|c| c.pcm(pitch, length).loop_iterations(loops)),
+            ),
+            (
+                Voice::Wave,
+                self.wave
+                    .map(|c| c.pcm(pitch, length).loop_iterations(loops)),
+            ),
+            (
+                Voice::Noise,
+                self.noise
+                    .map(|c| c.pcm(pitch, 0x100).loop_iterations(loops)),
+            ),
+        ];
+
+        let mut result: Vec<NoteEvent> = channels
+            .into_iter()
+            .filter_map(|(voice, channel)| Some(channel?.note_events(voice)))
+            .flatten()
+            .collect();
+
+        result.sort_by_key(|event| (event.start, event.voice));
+        result
     }
 }
 
+/// The tempo that was passed on to the other channels after each of pulse1, pulse2 and wave,
+/// see `SoundIterator::share_state`.
+type SharedTempos = [Option<(ChannelType, u16)>; 3];
+
 #[derive(Debug, Clone)]
-pub struct SoundIterator<'a> {
+pub struct SoundIterator<'a, S = f32> {
     pulse1: Option<ChannelIterator<'a>>,
     pulse2: Option<ChannelIterator<'a>>,
     wave: Option<ChannelIterator<'a>>,
     noise: Option<ChannelIterator<'a>>,
     index: usize,
-    buffer: [f32; SAMPLES_PER_FRAME],
+    cut_at: Option<usize>,
+    buffer: [S; SAMPLES_PER_FRAME],
+    /// Channels that are mixed into the buffer, the others are only advanced.
+    mask: ChannelMask,
+    preview: Option<u32>,
+    /// Number of samples returned so far when rendering a preview.
+    preview_index: usize,
+    /// Length of the whole sound at the output sample rate, see `remaining_len`.
+    total_len: LengthCache,
 }
 
-impl<'a> SoundIterator<'a> {
-    pub fn new(sound: Sound<'a>, pitch: i16, length: u16) -> SoundIterator<'a> {
+impl<'a, S: Sample> SoundIterator<'a, S> {
+    pub fn new(
+        sound: Sound<'a>,
+        pitch: i16,
+        length: u16,
+        options: SynthesisOptions,
+    ) -> SoundIterator<'a, S> {
+        let detune = options.analog_drift / 2.0;
+        let limit = options.max_commands();
+        let loops = options.loop_iterations();
+        let dac = options.quantize_dac;
+        let [pulse1_phase, pulse2_phase, wave_phase, _] = options.channel_phase;
+
         SoundIterator {
-            pulse1: sound.pulse1.as_ref().map(|c| c.pcm(pitch, length)),
-            pulse2: sound.pulse2.as_ref().map(|c| c.pcm(pitch, length)),
-            wave: sound.wave.as_ref().map(|c| c.pcm(pitch, length)),
-            noise: sound.noise.as_ref().map(|c| c.pcm(pitch, 0x100)),
+            pulse1: sound.pulse1.as_ref().map(|c| {
+                c.pcm(pitch, length)
+                    .detune(detune)
+                    .phase(pulse1_phase)
+                    .command_limit(limit)
+                    .loop_iterations(loops)
+                    .quantize_dac(dac)
+            }),
+            pulse2: sound.pulse2.as_ref().map(|c| {
+                c.pcm(pitch, length)
+                    .detune(-detune)
+                    .phase(pulse2_phase)
+                    .command_limit(limit)
+                    .loop_iterations(loops)
+                    .quantize_dac(dac)
+            }),
+            wave: sound.wave.as_ref().map(|c| {
+                c.pcm(pitch, length)
+                    .phase(wave_phase)
+                    .command_limit(limit)
+                    .loop_iterations(loops)
+            }),
+            noise: sound.noise.as_ref().map(|c| {
+                c.pcm(pitch, 0x100)
+                    .command_limit(limit)
+                    .loop_iterations(loops)
+                    .quantize_dac(dac)
+            }),
             index: 0,
-            buffer: [0.0; SAMPLES_PER_FRAME],
+            cut_at: options
+                .end()
+                .map(|cut_at| (duration_to_frames(cut_at) as usize) * SAMPLES_PER_FRAME),
+            buffer: [S::default(); SAMPLES_PER_FRAME],
+            mask: options.channel_mask,
+            preview: options.preview,
+            preview_index: 0,
+            total_len: LengthCache::default(),
         }
     }
 
@@ -101,56 +252,255 @@ impl<'a> SoundIterator<'a> {
     }
 
     pub fn sample_rate(&self) -> u32 {
-        SOURCE_SAMPLE_RATE as u32
+        self.preview.unwrap_or(SOURCE_SAMPLE_RATE as u32)
     }
-}
 
-impl<'a> Iterator for SoundIterator<'a> {
-    type Item = f32;
+    /// Whether any channel was stopped because it executed too many commands in a row without
+    /// playing anything, see `SynthesisOptions::command_limit`.
+    pub fn is_stuck(&self) -> bool {
+        [&self.pulse1, &self.pulse2, &self.wave, &self.noise]
+            .into_iter()
+            .flatten()
+            .any(|channel| channel.is_stuck())
+    }
+
+    /// The command that stopped a channel because it couldn't be parsed, if any. The channel is
+    /// silent from there on, while the other channels keep playing.
+    pub fn error(&self) -> Option<ParseError> {
+        [&self.pulse1, &self.pulse2, &self.wave, &self.noise]
+            .into_iter()
+            .flatten()
+            .find_map(|channel| channel.error())
+    }
+
+    /// Step through the whole sound without synthesizing it, and return the first command that
+    /// couldn't be parsed. Infinite sounds are only checked until they are known to loop.
+    pub(super) fn check(mut self) -> Result<(), ParseError> {
+        self.source_len();
+        self.error().map_or(Ok(()), Err)
+    }
+
+    /// Fill `out` with the next samples of the sound, copying whole runs out of the frame buffer. \
+    /// Returns the number of samples written, which is only less than `out.len()` at the end of the
+    /// sound.
+    pub fn fill(&mut self, out: &mut [S]) -> usize {
+        if self.preview.is_some() {
+            return out
+                .iter_mut()
+                .zip(self.by_ref())
+                .map(|(out, sample)| *out = sample)
+                .count();
+        }
+
+        let mut written = 0;
+
+        while written < out.len() {
+            let offset = self.index % SAMPLES_PER_FRAME;
+            let mut available = SAMPLES_PER_FRAME - offset;
+
+            if let Some(cut_at) = self.cut_at {
+                available = available.min(cut_at.saturating_sub(self.index));
+            }
+
+            if available == 0 || (offset == 0 && !self.render_frame()) {
+                break;
+            }
+
+            let count = available.min(out.len() - written);
+            out[written..(written + count)].copy_from_slice(&self.buffer[offset..(offset + count)]);
+            written += count;
+            self.index += count;
+        }
+
+        written
+    }
+
+    /// Render the next frame and return all of its samples, or `None` at the end of the sound.
+    ///
+    /// This is the cheapest way of pulling the whole sound, since the samples are handed out
+    /// straight from the frame buffer. The rest of a frame that was partly read with `next` or
+    /// `fill` is skipped. Previews have no frames at their sample rate, and always return `None`.
+    pub fn next_frame(&mut self) -> Option<&[S; SAMPLES_PER_FRAME]> {
+        if self.preview.is_some() {
+            return None;
+        }
+
+        let offset = self.index % SAMPLES_PER_FRAME;
+        if offset != 0 {
+            self.index += SAMPLES_PER_FRAME - offset;
+        }
+
+        if self.cut_at == Some(self.index) || !self.render_frame() {
+            return None;
+        }
+
+        self.index += SAMPLES_PER_FRAME;
+        Some(&self.buffer)
+    }
+
+    /// Skip the next `n` samples, returns the number of samples skipped, which is only less than
+    /// `n` at the end of the sound.
+    ///
+    /// Whole frames are skipped without synthesizing them, while still stepping every channel
+    /// through exactly the same state, so the samples after the skip are the same as when
+    /// calling `next` `n` times. Previews are skipped sample by sample.
+    pub fn skip_samples(&mut self, n: usize) -> usize {
+        if self.preview.is_some() {
+            return self.by_ref().take(n).count();
+        }
+
+        let mut skipped = 0;
+
+        while skipped < n {
+            let offset = self.index % SAMPLES_PER_FRAME;
+            let mut available = SAMPLES_PER_FRAME - offset;
+
+            if let Some(cut_at) = self.cut_at {
+                available = available.min(cut_at.saturating_sub(self.index));
+            }
+
+            if available == 0 {
+                break;
+            }
+
+            let count = available.min(n - skipped);
+
+            // only a frame that is partly skipped has to be synthesized
+            if offset == 0 {
+                let playing = match count == SAMPLES_PER_FRAME {
+                    true => self.advance_frame(),
+                    false => self.render_frame(),
+                };
+
+                if !playing {
+                    break;
+                }
+            }
 
-    fn count(mut self) -> usize {
+            skipped += count;
+            self.index += count;
+        }
+
+        skipped
+    }
+
+    /// Split the sound into chunks of `size` samples. The last chunk may be shorter. A `size` of 0
+    /// is treated as 1.
+    pub fn chunks(self, size: usize) -> ChunkIter<'a, S> {
+        ChunkIter {
+            iter: self,
+            size: size.max(1),
+        }
+    }
+
+    /// Only iterate over the sound if it has a known length, see `FiniteSoundIterator`. Returns
+    /// `None` if any channel loops forever, and the sound isn't cut short by the options.
+    pub fn exact(self) -> Option<FiniteSoundIterator<'a, S>> {
+        let remaining = self.remaining_len()?;
+        Some(FiniteSoundIterator {
+            iter: self,
+            remaining,
+        })
+    }
+
+    /// Number of samples left at the output sample rate, or `None` if the sound is infinitely
+    /// long.
+    ///
+    /// The length of the whole sound is only stepped through once, and then kept for every copy
+    /// of the iterator. The samples that are left follow from how far it has come.
+    fn remaining_len(&self) -> Option<usize> {
+        let total_len = self.total_len.get_or_init(|| self.total_len())?;
+
+        Some(match self.preview {
+            Some(_) => total_len.saturating_sub(self.preview_index),
+            None => total_len.saturating_sub(self.index),
+        })
+    }
+
+    /// Number of samples of the whole sound at the output sample rate, found by stepping through
+    /// the rest of it, or `None` if it is infinitely long.
+    fn total_len(&self) -> Option<usize> {
+        // the rest of the current frame is already in the buffer
+        let offset = self.index % SAMPLES_PER_FRAME;
+        let buffered = match offset {
+            0 => 0,
+            _ => SAMPLES_PER_FRAME - offset,
+        };
+
+        let mut rest = self.clone();
+        let start = self.index + buffered;
+        rest.cut_at = self.cut_at.map(|cut_at| cut_at.saturating_sub(start));
+
+        let source_len = match rest.source_len() {
+            usize::MAX => return None,
+            len => start + len,
+        };
+
+        match self.preview {
+            Some(rate) => {
+                let len = (source_len as u64) * (rate as u64);
+                let source_rate = SOURCE_SAMPLE_RATE as u64;
+                Some(((len + source_rate - 1) / source_rate) as usize)
+            }
+            None => Some(source_len),
+        }
+    }
+
+    /// Number of samples at `SOURCE_SAMPLE_RATE`, or `usize::MAX` if the sound is infinitely long.
+    fn source_len(&mut self) -> usize {
         let mut result = 0;
+        let infinite_len = self.cut_at.unwrap_or(usize::MAX);
 
         loop {
+            if self.cut_at == Some(result) {
+                return result;
+            }
+
             let mut done = true;
 
             if let Some(pulse1) = &mut self.pulse1 {
-                if pulse1.next().is_some() {
+                if pulse1.skip_frame() {
                     done = false;
                 }
 
                 if pulse1.is_infinite() == Some(true) {
-                    return usize::MAX;
+                    return infinite_len;
                 }
             }
 
+            self.share_state();
+
             if let Some(pulse2) = &mut self.pulse2 {
-                if pulse2.next().is_some() {
+                if pulse2.skip_frame() {
                     done = false;
                 }
 
                 if pulse2.is_infinite() == Some(true) {
-                    return usize::MAX;
+                    return infinite_len;
                 }
             }
 
+            self.share_state();
+
             if let Some(wave) = &mut self.wave {
-                if wave.next().is_some() {
+                if wave.skip_frame() {
                     done = false;
                 }
 
                 if wave.is_infinite() == Some(true) {
-                    return usize::MAX;
+                    return infinite_len;
                 }
             }
 
+            self.share_state();
+
             if let Some(noise) = &mut self.noise {
-                if noise.next().is_some() {
+                if noise.skip_frame() {
                     done = false;
                 }
 
                 if noise.is_infinite() == Some(true) {
-                    return usize::MAX;
+                    return infinite_len;
                 }
             }
 
@@ -162,59 +512,910 @@ impl<'a> Iterator for SoundIterator<'a> {
         }
     }
 
-    fn next(&mut self) -> Option<f32> {
-        if self.index % SAMPLES_PER_FRAME == 0 {
-            self.buffer.fill(0.0);
+    /// Step through the sound without synthesizing it, and record how the channels affect each
+    /// other, see `Sound::render`. Returns `None` if the sound is infinitely long.
+    #[cfg(feature = "parallel")]
+    fn shared_state(&mut self) -> Option<SharedState> {
+        let mut shared = SharedState::default();
 
-            let mut done = true;
+        while self.cut_at != Some(shared.frames * SAMPLES_PER_FRAME) {
+            let frame = shared.frames;
+            let mut tempos = [None; 3];
+            let frames =
+                self.step_channels(&mut tempos, |channel| channel.skip_frame().then_some(()));
 
-            if let Some(pulse1) = &mut self.pulse1 {
-                if let Some(data) = pulse1.next() {
-                    for (i, data) in data.iter().enumerate() {
-                        self.buffer[i] += data / 3.0;
-                    }
+            if frames.iter().all(Option::is_none) {
+                break;
+            }
 
-                    done = false;
+            let is_infinite = [&self.pulse1, &self.pulse2, &self.wave, &self.noise]
+                .into_iter()
+                .flatten()
+                .any(|channel| channel.is_infinite() == Some(true));
+
+            if is_infinite && self.cut_at.is_none() {
+                return None;
+            }
+
+            for (after, tempo) in tempos.into_iter().enumerate() {
+                if let Some((channel_type, tempo)) = tempo {
+                    shared.tempos.push((frame, after, channel_type, tempo));
                 }
             }
 
-            if let Some(pulse2) = &mut self.pulse2 {
-                if let Some(data) = pulse2.next() {
-                    for (i, data) in data.iter().enumerate() {
-                        self.buffer[i] += data / 3.0;
-                    }
+            shared.frames += 1;
+        }
 
-                    done = false;
-                }
+        Some(shared)
+    }
+
+    /// Render every channel on a thread of its own, and mix them, see `Sound::render`. The sound
+    /// must not have started playing. Returns `None` if it is infinitely long.
+    #[cfg(feature = "parallel")]
+    fn render_parallel(&mut self) -> Option<Vec<S>> {
+        let channels = [
+            self.pulse1.clone(),
+            self.pulse2.clone(),
+            self.wave.clone(),
+            self.noise.clone(),
+        ];
+
+        let shared = self.shared_state()?;
+        let shared = &shared;
+
+        let rendered: Vec<Vec<[f32; SAMPLES_PER_FRAME]>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = channels
+                .into_iter()
+                .enumerate()
+                .map(|(index, channel)| {
+                    channel
+                        .map(|channel| scope.spawn(move || render_channel(channel, index, shared)))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| match handle {
+                    Some(handle) => handle
+                        .join()
+                        .unwrap_or_else(|err| std::panic::resume_unwind(err)),
+                    None => Vec::new(),
+                })
+                .collect()
+        });
+
+        let mut result = Vec::with_capacity(shared.frames * SAMPLES_PER_FRAME);
+
+        for frame in 0..shared.frames {
+            let frames = [0, 1, 2, 3].map(|index| rendered[index].get(frame));
+            mix_frame(&mut self.buffer, frames, self.mask);
+            result.extend_from_slice(&self.buffer);
+        }
+
+        Some(result)
+    }
+
+    /// Pass a tempo set by one channel on to the other channels. The engine processes the
+    /// channels in order, so this is called after each of them.
+    fn share_state(&mut self) -> Option<(ChannelType, u16)> {
+        let mut channels = [
+            &mut self.pulse1,
+            &mut self.pulse2,
+            &mut self.wave,
+            &mut self.noise,
+        ];
+
+        let changed = channels
+            .iter_mut()
+            .flat_map(|channel| channel.as_mut())
+            .find_map(|channel| Some((channel.channel_type(), channel.take_tempo()?)));
+
+        if let Some((channel_type, tempo)) = changed {
+            for channel in channels.iter_mut().flat_map(|channel| channel.as_mut()) {
+                channel.set_tempo(channel_type, tempo);
             }
+        }
 
-            if let Some(wave) = &mut self.wave {
-                if let Some(data) = wave.next() {
-                    for (i, data) in data.iter().enumerate() {
-                        self.buffer[i] += data / 3.0;
-                    }
+        changed
+    }
 
-                    done = false;
-                }
+    /// Average the source samples covered by the next output sample at the preview `rate`.
+    fn next_preview(&mut self, rate: u32) -> Option<S> {
+        let mut end = (((self.preview_index as u64) + 1) * (SOURCE_SAMPLE_RATE as u64)
+            / (rate as u64)) as usize;
+
+        if let Some(cut_at) = self.cut_at {
+            end = end.min(cut_at);
+        }
+
+        let mut sum = S::default();
+        let mut count = 0;
+
+        while self.index < end {
+            let offset = self.index % SAMPLES_PER_FRAME;
+
+            if offset == 0 && !self.render_frame() {
+                break;
             }
 
-            if let Some(noise) = &mut self.noise {
-                if let Some(data) = noise.next() {
-                    for (i, data) in data.iter().enumerate() {
-                        self.buffer[i] += data / 3.0;
-                    }
+            let len = (SAMPLES_PER_FRAME - offset).min(end - self.index);
 
-                    done = false;
-                }
+            for sample in &self.buffer[offset..(offset + len)] {
+                sum += *sample;
             }
 
-            if done {
+            count += len;
+            self.index += len;
+        }
+
+        if count == 0 {
+            return None;
+        }
+
+        self.preview_index += 1;
+        Some(sum / S::from_f32(count as f32))
+    }
+
+    /// Render every channel into its own buffer, at the full sample rate. The channels are still
+    /// stepped in lockstep, so they affect each other the same way as in the mix. Returns `None`
+    /// if the sound is infinitely long.
+    pub(super) fn into_stems(mut self) -> Option<Stems> {
+        if self.clone().count() == usize::MAX {
+            return None;
+        }
+
+        let mut stems: [Option<Vec<f32>>; 4] =
+            [&self.pulse1, &self.pulse2, &self.wave, &self.noise]
+                .map(|channel| channel.as_ref().map(|_| Vec::new()));
+
+        while self.cut_at != Some(self.index) {
+            let frames = self.next_frames();
+
+            if frames.iter().all(Option::is_none) {
+                break;
+            }
+
+            let is_infinite = [&self.pulse1, &self.pulse2, &self.wave, &self.noise]
+                .into_iter()
+                .flatten()
+                .any(|channel| channel.is_infinite() == Some(true));
+
+            if is_infinite && self.cut_at.is_none() {
                 return None;
             }
+
+            for (stem, frame) in stems.iter_mut().zip(&frames) {
+                if let Some(stem) = stem {
+                    stem.extend(frame.unwrap_or([0.0; SAMPLES_PER_FRAME]));
+                }
+            }
+
+            self.index += SAMPLES_PER_FRAME;
+        }
+
+        Some(Stems::new(stems, SOURCE_SAMPLE_RATE as u32))
+    }
+
+    /// Step every channel to its next frame, in the order pulse1, pulse2, wave and noise. A
+    /// channel that isn't playing has no frame.
+    fn next_frames(&mut self) -> [Option<[f32; SAMPLES_PER_FRAME]>; 4] {
+        self.step_channels(&mut [None; 3], |channel| channel.next())
+    }
+
+    /// Step every channel over its next frame like `next_frames`, without synthesizing it.
+    /// Returns `false` once all channels are done.
+    fn advance_frame(&mut self) -> bool {
+        let frames = self.step_channels(&mut [None; 3], |channel| {
+            channel.advance_frame().then_some(())
+        });
+        frames.iter().any(Option::is_some)
+    }
+
+    /// Step every channel with `step`, in the order the engine processes them. The tempo that was
+    /// passed on after each of pulse1, pulse2 and wave is stored in `tempos`.
+    fn step_channels<T>(
+        &mut self,
+        tempos: &mut SharedTempos,
+        mut step: impl FnMut(&mut ChannelIterator<'a>) -> Option<T>,
+    ) -> [Option<T>; 4] {
+        let mut frames = [None, None, None, None];
+
+        if let Some(pulse1) = &mut self.pulse1 {
+            frames[0] = step(pulse1);
+        }
+
+        tempos[0] = self.share_state();
+
+        if let Some(pulse2) = &mut self.pulse2 {
+            frames[1] = step(pulse2);
+        }
+
+        tempos[1] = self.share_state();
+
+        if let Some(wave) = &mut self.wave {
+            frames[2] = step(wave);
+        }
+
+        tempos[2] = self.share_state();
+
+        if let Some(noise) = &mut self.noise {
+            frames[3] = step(noise);
+        }
+
+        frames
+    }
+
+    /// Mix the next frame of every channel into the buffer. Returns `false` once all channels are
+    /// done.
+    fn render_frame(&mut self) -> bool {
+        let frames = self.next_frames();
+        let frames = [&frames[0], &frames[1], &frames[2], &frames[3]].map(Option::as_ref);
+        mix_frame(&mut self.buffer, frames, self.mask)
+    }
+}
+
+/// How the channels affect each other while they play, see `Sound::render`.
+#[cfg(feature = "parallel")]
+#[derive(Debug, Default)]
+struct SharedState {
+    /// Tempos passed on between the channels, as the frame, the index of the channel that it was
+    /// passed on after, the type of the channel that set it, and the tempo.
+    tempos: Vec<(usize, usize, ChannelType, u16)>,
+    /// Number of frames of the sound.
+    frames: usize,
+}
+
+/// Render a single channel on its own, passing it what the other channels did at the same points
+/// as when all channels are stepped together, see `SoundIterator::step_channels`.
+#[cfg(feature = "parallel")]
+fn render_channel(
+    mut channel: ChannelIterator,
+    index: usize,
+    shared: &SharedState,
+) -> Vec<[f32; SAMPLES_PER_FRAME]> {
+    let mut result = Vec::new();
+    let mut tempos = shared.tempos.iter().peekable();
+
+    for frame in 0..shared.frames {
+        // the channels before this one have already passed on their tempo in this frame
+        while let Some((_, _, channel_type, tempo)) =
+            tempos.next_if(|(at, after, ..)| *at == frame && *after < index)
+        {
+            channel.set_tempo(*channel_type, *tempo);
+        }
+
+        match channel.next() {
+            Some(data) => result.push(data),
+            None => break,
+        }
+
+        while let Some((_, _, channel_type, tempo)) = tempos.next_if(|(at, ..)| *at == frame) {
+            channel.set_tempo(*channel_type, *tempo);
+        }
+    }
+
+    result
+}
+
+/// Mix the frames of the channels that are playing into `buffer`. Returns `false` if no channel
+/// is playing.
+fn mix_frame<S: Sample>(
+    buffer: &mut [S; SAMPLES_PER_FRAME],
+    frames: [Option<&[f32; SAMPLES_PER_FRAME]>; 4],
+    mask: ChannelMask,
+) -> bool {
+    let mask = [mask.pulse1, mask.pulse2, mask.wave, mask.noise];
+
+    let mut done = true;
+    let mut mixed = false;
+
+    for (data, enabled) in frames.into_iter().zip(mask) {
+        if let Some(data) = data {
+            if enabled {
+                mix_into(buffer, data, !mixed);
+                mixed = true;
+            }
+
+            done = false;
+        }
+    }
+
+    if !done && !mixed {
+        buffer.fill(S::default());
+    }
+
+    !done
+}
+
+/// Add a channel frame to the mix, scaled so that three channels at full volume peaks at 1.0. \
+/// The first channel mixed into a frame overwrites the buffer instead, which saves clearing it.
+fn mix_into<S: Sample>(
+    buffer: &mut [S; SAMPLES_PER_FRAME],
+    data: &[f32; SAMPLES_PER_FRAME],
+    first: bool,
+) {
+    let scale = S::from_f32(1.0) / S::from_f32(3.0);
+
+    if first {
+        for (out, data) in buffer.iter_mut().zip(data) {
+            *out = S::from_f32(*data) * scale;
+        }
+    } else {
+        for (out, data) in buffer.iter_mut().zip(data) {
+            *out += S::from_f32(*data) * scale;
+        }
+    }
+}
+
+/// Iterator over fixed-size chunks of a sound, created by [`SoundIterator::chunks`].
+#[derive(Debug, Clone)]
+pub struct ChunkIter<'a, S = f32> {
+    iter: SoundIterator<'a, S>,
+    size: usize,
+}
+
+impl<'a, S: Sample> Iterator for ChunkIter<'a, S> {
+    type Item = Vec<S>;
+
+    fn next(&mut self) -> Option<Vec<S>> {
+        let mut chunk = vec![S::default(); self.size];
+        let len = self.iter.fill(&mut chunk);
+
+        if len == 0 {
+            return None;
+        }
+
+        chunk.truncate(len);
+        Some(chunk)
+    }
+}
+
+/// A sound with a known length, created by [`SoundIterator::exact`] or `Pcm::iter_exact`.
+#[derive(Debug, Clone)]
+pub struct FiniteSoundIterator<'a, S = f32> {
+    iter: SoundIterator<'a, S>,
+    remaining: usize,
+}
+
+impl<'a, S: Sample> Iterator for FiniteSoundIterator<'a, S> {
+    type Item = S;
+
+    fn next(&mut self) -> Option<S> {
+        let sample = self.iter.next()?;
+        self.remaining = self.remaining.saturating_sub(1);
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, S: Sample> ExactSizeIterator for FiniteSoundIterator<'a, S> {}
+
+impl<'a, S: Sample> Iterator for SoundIterator<'a, S> {
+    type Item = S;
+
+    fn count(self) -> usize {
+        self.remaining_len().unwrap_or(usize::MAX)
+    }
+
+    /// The exact number of samples left when the sound is finite. The first call takes a pass
+    /// through the rest of the sound without synthesizing it, later ones are free.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.remaining_len() {
+            Some(len) => (len, Some(len)),
+            None => (0, None),
+        }
+    }
+
+    fn next(&mut self) -> Option<S> {
+        if let Some(rate) = self.preview {
+            return self.next_preview(rate);
+        }
+
+        if self.cut_at == Some(self.index) {
+            return None;
+        }
+
+        if self.index % SAMPLES_PER_FRAME == 0 && !self.render_frame() {
+            return None;
         }
 
         let result = self.buffer[self.index % SAMPLES_PER_FRAME];
         self.index += 1;
         Some(result)
     }
+
+    fn nth(&mut self, n: usize) -> Option<S> {
+        if self.skip_samples(n) < n {
+            return None;
+        }
+
+        self.next()
+    }
+}
+
+impl Render for SoundIterator<'_> {
+    fn sample_rate(&self) -> u32 {
+        SoundIterator::sample_rate(self)
+    }
+
+    fn fill(&mut self, out: &mut [f32]) -> usize {
+        SoundIterator::fill(self, out)
+    }
+}
+
+#[cfg(feature = "rodio")]
+impl rodio::Source for SoundIterator<'_> {
+    /// The mix is rendered a frame at a time, except for previews.
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.preview {
+            Some(_) => None,
+            None => Some(SAMPLES_PER_FRAME - self.index % SAMPLES_PER_FRAME),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        SoundIterator::channels(self)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SoundIterator::sample_rate(self)
+    }
+
+    /// The length of the whole sound, `None` if it's infinitely long or once playback has started.
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        if self.index != 0 || self.preview_index != 0 {
+            return None;
+        }
+
+        let len = self.clone().count();
+
+        (len != usize::MAX).then(|| {
+            std::time::Duration::from_secs_f64(len as f64 / SoundIterator::sample_rate(self) as f64)
+        })
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A single pulse channel with a 16 frame long square_note, fading out.
+    const SQUARE_NOTE: [u8; 8] = [0x04, 0x03, 0x00, 0x0f, 0xf1, 0x00, 0x07, 0xff];
+
+    /// A single pulse channel looping a square_note forever.
+    pub(crate) const LOOPING_SQUARE_NOTE: [u8; 11] = [
+        0x04, 0x03, 0x00, 0x0f, 0xf1, 0x00, 0x07, 0xfd, 0x00, 0x03, 0x00,
+    ];
+
+    #[test]
+    fn test_f64_precision() {
+        // a single pulse channel with a long square_note, slowly fading out
+        let rom = [0x04, 0x03, 0x00, 0xc0, 0xf7, 0x00, 0x07, 0xff];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let single: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+        let double: Vec<f64> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+
+        assert_eq!(single.len(), double.len());
+        assert!(single.len() > 100 * SAMPLES_PER_FRAME);
+
+        for (single, double) in single.iter().zip(double) {
+            assert!((*single as f64 - double).abs() <= f32::EPSILON as f64 * double.abs());
+        }
+    }
+
+    #[test]
+    fn test_channel_phase() {
+        // two pulse channels playing the same duty_cycle 2 note
+        let rom = [
+            0x44, 0x06, 0x00, 0x05, 0x06, 0x00, 0xdb, 0x02, 0x0f, 0xf1, 0x00, 0x07, 0xff,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let rms = |phase: [f32; 4]| {
+            let options = SynthesisOptions::new().channel_phase(phase);
+            let data: Vec<f32> = sound.pcm(0, 0x100, options).collect();
+            let mean = data.iter().sum::<f32>() / data.len() as f32;
+            (data.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / data.len() as f32).sqrt()
+        };
+
+        let in_phase = rms([0.0, 0.0, 0.0, 0.0]);
+        let out_of_phase = rms([0.0, 0.5, 0.0, 0.0]);
+
+        assert!(
+            out_of_phase < in_phase * 0.5,
+            "{out_of_phase} vs {in_phase}"
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_render_parallel() {
+        // two pulse channels, the second with a duty_cycle
+        let rom = [
+            0x44, 0x06, 0x00, 0x05, 0x0b, 0x00, 0x0f, 0xf1, 0x00, 0x07, 0xff, 0xdb, 0x02, 0x0f,
+            0xf1, 0x00, 0x06, 0xff,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let expected: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+        let rendered = sound.render(0, 0x100, SynthesisOptions::new()).unwrap();
+        assert!(!expected.is_empty());
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn test_channel_mask() {
+        // two pulse channels, the second one an octave lower with another duty cycle
+        let rom = [
+            0x44, 0x06, 0x00, 0x05, 0x0b, 0x00, 0x0f, 0xf1, 0x00, 0x07, 0xff, 0xdb, 0x02, 0x0f,
+            0xf1, 0x00, 0x06, 0xff,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let render = |mask: ChannelMask| -> Vec<f32> {
+            let options = SynthesisOptions::new().channel_mask(mask);
+            sound.pcm(0, 0x100, options).collect()
+        };
+
+        let full = render(ChannelMask::ALL);
+        let pulse1 = render(ChannelMask::solo(Voice::Pulse1));
+        let pulse2 = render(ChannelMask::solo(Voice::Pulse2));
+        let none = render(ChannelMask::NONE);
+
+        assert_eq!(pulse1.len(), full.len());
+        assert_eq!(pulse2.len(), full.len());
+        assert_eq!(none.len(), full.len());
+
+        assert!(pulse2.iter().any(|&sample| sample != 0.0));
+        assert_ne!(pulse2, full);
+        assert!(none.iter().all(|&sample| sample == 0.0));
+
+        for ((full, pulse1), pulse2) in full.iter().zip(&pulse1).zip(&pulse2) {
+            assert_eq!(*full, pulse1 + pulse2);
+        }
+    }
+
+    #[test]
+    fn test_stems() {
+        // two pulse channels, the second one an octave lower with another duty cycle
+        let rom = [
+            0x44, 0x06, 0x00, 0x05, 0x0b, 0x00, 0x0f, 0xf1, 0x00, 0x07, 0xff, 0xdb, 0x02, 0x0f,
+            0xf1, 0x00, 0x06, 0xff,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let full: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+        let stems = sound
+            .pcm::<f32>(0, 0x100, SynthesisOptions::new())
+            .into_stems()
+            .unwrap();
+
+        assert!(stems.wave.is_none());
+        assert!(stems.noise.is_none());
+
+        let pulse2 = stems.pulse2.as_ref().unwrap();
+        assert_eq!(pulse2.frames(), full.len());
+        assert_eq!(pulse2.sample_rate(), SOURCE_SAMPLE_RATE as u32);
+
+        let mix = stems.mix();
+        assert_eq!(mix.frames(), full.len());
+
+        for (mix, full) in mix.iter().zip(&full) {
+            assert!((mix - full).abs() < 1e-6);
+        }
+    }
+
+    #[cfg(feature = "rodio")]
+    #[test]
+    fn test_rodio_source() {
+        use rodio::Source;
+
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+
+        let mut iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        let len = iter.clone().count();
+        assert_eq!(Source::sample_rate(&iter), SOURCE_SAMPLE_RATE as u32);
+        assert_eq!(
+            iter.total_duration(),
+            Some(Duration::from_secs_f64(
+                len as f64 / SOURCE_SAMPLE_RATE as f64
+            ))
+        );
+
+        assert_eq!(iter.current_frame_len(), Some(SAMPLES_PER_FRAME));
+        iter.nth(99);
+        assert_eq!(iter.current_frame_len(), Some(SAMPLES_PER_FRAME - 100));
+
+        let sound = Sound::new(&LOOPING_SQUARE_NOTE, 0, 0);
+        let iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        assert_eq!(iter.total_duration(), None);
+    }
+
+    #[test]
+    fn test_quantize_dac() {
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+
+        let levels = |options: SynthesisOptions| {
+            let mut data: Vec<u32> = sound
+                .pcm::<f32>(0, 0x100, options)
+                .map(f32::to_bits)
+                .collect();
+            data.sort_unstable();
+            data.dedup();
+            data.len()
+        };
+
+        assert!(levels(SynthesisOptions::new()) > 16);
+        assert!(levels(SynthesisOptions::new().quantize_dac(true)) <= 16);
+    }
+
+    #[test]
+    fn test_cut_at() {
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+
+        let uncut: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+
+        // 0.1 seconds is a bit less than 6 frames
+        let options = SynthesisOptions::new().cut_at(Some(Duration::from_millis(100)));
+        let cut: Vec<f32> = sound.pcm(0, 0x100, options).collect();
+
+        assert_eq!(cut.len(), 6 * SAMPLES_PER_FRAME);
+        assert_eq!(cut, uncut[..cut.len()]);
+        assert_eq!(sound.pcm::<f32>(0, 0x100, options).count(), cut.len());
+    }
+
+    #[test]
+    fn test_cut_at_infinite() {
+        let sound = Sound::new(&LOOPING_SQUARE_NOTE, 0, 0);
+
+        let options = SynthesisOptions::new();
+        assert_eq!(sound.pcm::<f32>(0, 0x100, options).count(), usize::MAX);
+
+        let options = options.cut_at(Some(Duration::from_millis(100)));
+        assert_eq!(
+            sound.pcm::<f32>(0, 0x100, options).count(),
+            6 * SAMPLES_PER_FRAME
+        );
+        assert_eq!(
+            sound
+                .pcm::<f32>(0, 0x100, options)
+                .collect::<Vec<_>>()
+                .len(),
+            6 * SAMPLES_PER_FRAME
+        );
+    }
+
+    #[test]
+    fn test_chunks() {
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+
+        let expected: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+
+        // sizes that are smaller than, larger than, and not aligned with a frame
+        for size in [
+            1000,
+            SAMPLES_PER_FRAME - 1,
+            SAMPLES_PER_FRAME,
+            3 * SAMPLES_PER_FRAME + 7,
+        ] {
+            let chunks: Vec<Vec<f32>> = sound
+                .pcm(0, 0x100, SynthesisOptions::new())
+                .chunks(size)
+                .collect();
+
+            assert!(chunks[..(chunks.len() - 1)].iter().all(|c| c.len() == size));
+            assert!(!chunks[chunks.len() - 1].is_empty());
+            assert_eq!(chunks.concat(), expected);
+        }
+    }
+
+    #[test]
+    fn test_fill_cut_at() {
+        let sound = Sound::new(&LOOPING_SQUARE_NOTE, 0, 0);
+
+        let options = SynthesisOptions::new().cut_at(Some(Duration::from_millis(100)));
+        let expected: Vec<f32> = sound.pcm(0, 0x100, options).collect();
+
+        let mut iter = sound.pcm(0, 0x100, options);
+        let mut buffer = vec![0.0; 5000];
+        let mut result = Vec::new();
+
+        loop {
+            let len = iter.fill(&mut buffer);
+            result.extend_from_slice(&buffer[..len]);
+
+            if len < buffer.len() {
+                break;
+            }
+        }
+
+        assert_eq!(result, expected);
+        assert_eq!(iter.fill(&mut buffer), 0);
+    }
+
+    #[test]
+    fn test_preview() {
+        let sound = Sound::new(&SQUARE_NOTE, 0, 0);
+
+        let full: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+
+        let options = SynthesisOptions::new().preview(8000);
+        let iter = sound.pcm::<f32>(0, 0x100, options);
+        assert_eq!(iter.sample_rate(), 8000);
+
+        let expected_len = (full.len() * 8000 + SOURCE_SAMPLE_RATE - 1) / SOURCE_SAMPLE_RATE;
+        assert_eq!(iter.clone().count(), expected_len);
+
+        let preview: Vec<f32> = iter.collect();
+        assert_eq!(preview.len(), expected_len);
+
+        for (i, sample) in preview.iter().enumerate() {
+            let start = i * SOURCE_SAMPLE_RATE / 8000;
+            let end = ((i + 1) * SOURCE_SAMPLE_RATE / 8000).min(full.len());
+            let average = full[start..end].iter().sum::<f32>() / ((end - start) as f32);
+            assert!((sample - average).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_preview_cut_at() {
+        let sound = Sound::new(&LOOPING_SQUARE_NOTE, 0, 0);
+
+        let options = SynthesisOptions::new()
+            .preview(8000)
+            .cut_at(Some(Duration::from_millis(100)));
+        let expected_len =
+            (6 * SAMPLES_PER_FRAME * 8000 + SOURCE_SAMPLE_RATE - 1) / SOURCE_SAMPLE_RATE;
+
+        assert_eq!(sound.pcm::<f32>(0, 0x100, options).count(), expected_len);
+        assert_eq!(
+            sound
+                .pcm::<f32>(0, 0x100, options)
+                .collect::<Vec<_>>()
+                .len(),
+            expected_len
+        );
+
+        let mut buffer = vec![0.0; 1000];
+        assert_eq!(sound.pcm(0, 0x100, options).fill(&mut buffer), expected_len);
+    }
+
+    #[test]
+    fn test_partial_drains() {
+        // two pulse channels, with fading notes that change every few frames
+        let rom = [
+            0x44, 0x06, 0x00, 0x05, 0x0f, 0x00, 0x0f, 0xf1, 0x00, 0x07, 0x0a, 0xa2, 0x80, 0x06,
+            0xff, 0x04, 0xf3, 0x40, 0x07, 0xff,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let expected: Vec<f32> = sound.pcm(0, 0x100, SynthesisOptions::new()).collect();
+
+        // drain the samples in uneven bursts, mixing single samples with bulk copies
+        let mut iter = sound.pcm(0, 0x100, SynthesisOptions::new());
+        let mut buffer = vec![0.0; 7919];
+        let mut result = Vec::new();
+
+        for burst in 0.. {
+            for _ in 0..(burst % 5) {
+                result.extend(iter.next());
+            }
+
+            let size = burst * 1237 % buffer.len();
+            let len = iter.fill(&mut buffer[..size]);
+            result.extend_from_slice(&buffer[..len]);
+
+            if len < size {
+                break;
+            }
+        }
+
+        assert_eq!(result, expected);
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_stuck_channel() {
+        // a pulse channel with a 16 frame long square_note, and a noise channel with an infinite
+        // loop that jumps to itself
+        let rom = [
+            0x44, 0x06, 0x00, 0x07, 0x0b, 0x00, 0x0f, 0xf1, 0x00, 0x07, 0xff, 0xfd, 0x00, 0x0b,
+            0x00,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        // the same pulse channel on its own
+        let expected: Vec<f32> = Sound::new(&SQUARE_NOTE, 0, 0)
+            .pcm(0, 0x100, SynthesisOptions::new())
+            .collect();
+
+        let mut iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        assert_eq!(iter.clone().count(), expected.len());
+        assert_eq!(iter.by_ref().collect::<Vec<_>>(), expected);
+        assert!(iter.is_stuck());
+    }
+
+    #[test]
+    fn test_runaway_channel() {
+        // a pulse channel of one frame long square_notes that never loops or returns, and runs off
+        // the end of bank 0 after 4096 notes
+        let mut rom = vec![0x00; 0x4004];
+        rom[..3].copy_from_slice(&[0x04, 0x03, 0x00]);
+
+        let error = ParseError {
+            kind: ParseErrorKind::OutOfBounds,
+            bank: 0,
+            addr: 0x4003,
+            channel: ChannelType::SfxPulse,
+        };
+
+        let iter = Sound::new(&rom, 0, 0).pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        assert_eq!(iter.clone().count(), 4096 * SAMPLES_PER_FRAME);
+        assert_eq!(iter.check(), Err(error));
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        // the same pulse channel as above, and a noise channel starting with an unknown command
+        let rom = [
+            0x44, 0x06, 0x00, 0x07, 0x0b, 0x00, 0x0f, 0xf1, 0x00, 0x07, 0xff, 0xe7,
+        ];
+        let sound = Sound::new(&rom, 0, 0);
+
+        let expected: Vec<f32> = Sound::new(&SQUARE_NOTE, 0, 0)
+            .pcm(0, 0x100, SynthesisOptions::new())
+            .collect();
+
+        let error = ParseError {
+            kind: ParseErrorKind::UnknownCommand(0xe7),
+            bank: 0,
+            addr: 0x0b,
+            channel: ChannelType::SfxNoise,
+        };
+
+        let mut iter = sound.pcm::<f32>(0, 0x100, SynthesisOptions::new());
+        assert_eq!(iter.clone().check(), Err(error));
+        assert_eq!(iter.error(), None);
+        assert_eq!(iter.by_ref().collect::<Vec<_>>(), expected);
+        assert_eq!(iter.error(), Some(error));
+        assert!(!iter.is_stuck());
+    }
+
+    #[test]
+    fn test_try_new() {
+        let rom = [0x04, 0x03, 0x00, 0xff];
+        assert!(Sound::try_new(&rom, 0, 0).is_ok());
+
+        // the first pulse channel, as both a music and an SFX channel
+        let rom = [0x40, 0x06, 0x00, 0x04, 0x06, 0x00, 0xff];
+        let err = Sound::try_new(&rom, 0, 0).unwrap_err();
+        assert_eq!(err, HeaderError::DuplicateChannel { id: 5 });
+    }
+
+    #[test]
+    fn test_music_tempo() {
+        let count = |rom: &[u8]| {
+            Sound::new(rom, 0, 0)
+                .pcm::<f32>(0, 0x100, SynthesisOptions::new())
+                .count()
+        };
+
+        // the first channel sets the tempo to half a frame, which the second channel plays at
+        // as well
+        let rom = [
+            0x40, 0x06, 0x00, 0x01, 0x10, 0x00, 0xda, 0x00, 0x80, 0xd8, 0x01, 0xf0, 0xd4, 0x17,
+            0x07, 0xff, 0xd8, 0x01, 0xf0, 0xd4, 0x17, 0x07, 0xff,
+        ];
+        assert_eq!(count(&rom), 8 * SAMPLES_PER_FRAME);
+
+        // on its own, the second channel is played at the tempo given to the sound
+        let pulse2 = [0x01, 0x03, 0x00, 0xd8, 0x01, 0xf0, 0xd4, 0x17, 0x07, 0xff];
+        assert_eq!(count(&pulse2), 16 * SAMPLES_PER_FRAME);
+    }
 }
@@ -0,0 +1,66 @@
+//! What the tools shared between the generations need to know about the sound engine of each of
+//! them, so that they are only written once.
+
+use std::fmt;
+use std::hash::Hash;
+
+use crate::{RomLocation, Voice};
+
+/// Upper bound on the number of commands read from a single channel, in case of malformed data.
+pub(crate) const MAX_COMMANDS: usize = 0x10000;
+
+/// Address of the first command of a channel, and its channel type.
+pub(crate) type ChannelStart<C> = (u16, C);
+
+/// Where the commands of a channel continue after a command, for walking the channel data
+/// without playing it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Flow {
+    /// Continues with the next command.
+    Next,
+    /// Continues with the next command, with notes interpreted as another kind of channel, see
+    /// `Generation::switch_channel`.
+    Switch,
+    /// Calls the commands at the address, the pointer follows the opcode.
+    Call(u16),
+    /// Jumps back to the address `count` times in total, 0 loops forever. The pointer follows
+    /// the count.
+    Loop { count: u8, addr: u16 },
+    /// Continues at the address, the pointer follows the opcode.
+    Jump(u16),
+    /// Ends the channel, or returns from a call.
+    Return,
+}
+
+/// The command set of a generation.
+pub(crate) trait Generation {
+    /// Number of the generation, as stored in sound blobs.
+    const NUMBER: u8;
+
+    type ChannelType: Copy + Eq + Hash;
+    type Command: Copy + PartialEq + fmt::Debug;
+
+    /// The channels of the sound with its header at `location`, by the voice they play on.
+    fn channels(
+        rom: &[u8],
+        location: RomLocation,
+    ) -> [(Voice, Option<ChannelStart<Self::ChannelType>>); 4];
+
+    /// Parse the command at `addr`, `None` if it isn't a valid command of the channel type.
+    fn parse(rom: &[u8], bank: u8, addr: u16, channel: Self::ChannelType) -> Option<Self::Command>;
+
+    fn len(cmd: &Self::Command) -> usize;
+
+    fn flow(cmd: &Self::Command) -> Flow;
+
+    /// The channel type that notes are interpreted as after a `Flow::Switch` command.
+    fn switch_channel(channel: Self::ChannelType) -> Self::ChannelType;
+}
+
+/// A sound being rendered, implemented by the `SoundIterator` of each generation.
+pub trait Render: Iterator<Item = f32> + Clone {
+    fn sample_rate(&self) -> u32;
+
+    /// Render samples into `out`, returns the number of samples written.
+    fn fill(&mut self, out: &mut [f32]) -> usize;
+}
@@ -0,0 +1,142 @@
+use std::fmt;
+
+use crate::RomLocation;
+
+/// Channel entries of a sound header, as the channel ID from 1 to 8 and the address of the
+/// channel data. IDs 1 to 4 are music channels, and 5 to 8 are SFX channels.
+pub(crate) type Header = Vec<(u8, u16)>;
+
+/// Error returned by `Sound::try_new` for a header that isn't a valid sound header.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HeaderError {
+    /// The header lists the same hardware channel twice, `id` is the second entry.
+    DuplicateChannel { id: u8 },
+    /// The channel ID isn't between 1 and 8.
+    InvalidChannel { id: u8 },
+    /// The channel entries run past the end of the bank.
+    PastBank { channel_count: u8 },
+    /// The header runs past the end of the ROM.
+    OutOfBounds,
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::DuplicateChannel { id } => write!(f, "Duplicate channel: {}", id),
+            HeaderError::InvalidChannel { id } => write!(f, "Invalid SFX channel: {}", id),
+            HeaderError::PastBank { channel_count } => write!(
+                f,
+                "Header with {} channels runs past the end of the bank",
+                channel_count
+            ),
+            HeaderError::OutOfBounds => write!(f, "Header runs past the end of the ROM"),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+/// Read the sound header at the given location.
+///
+/// The header format is the same in both generations: the top two bits of the first byte is the
+/// number of channels minus one, followed by three bytes per channel.
+///
+/// A header that runs past the end of the ROM is read as a header without any channels, which
+/// plays as silence.
+pub(crate) fn read(rom: &[u8], location: RomLocation) -> Header {
+    let pos = location.offset();
+
+    let Some(&first) = rom.get(pos) else {
+        return Header::new();
+    };
+
+    let channel_count = ((first >> 6) + 1) as usize;
+
+    let Some(entries) = rom.get(pos..(pos + channel_count * 3)) else {
+        return Header::new();
+    };
+
+    entries
+        .chunks(3)
+        .map(|entry| {
+            (
+                (entry[0] & 0xf) + 1,
+                u16::from_le_bytes([entry[1], entry[2]]),
+            )
+        })
+        .collect()
+}
+
+/// Read the sound header at the given location, and check that it can be played: every entry is
+/// a channel ID from 1 to 8, no hardware channel is used twice, and the header fits in its bank.
+pub(crate) fn try_read(rom: &[u8], location: RomLocation) -> Result<Header, HeaderError> {
+    let first = *rom.get(location.offset()).ok_or(HeaderError::OutOfBounds)?;
+    let channel_count = (first >> 6) + 1;
+
+    if ((location.addr as usize) & 0x3fff) + (channel_count as usize) * 3 > 0x4000 {
+        return Err(HeaderError::PastBank { channel_count });
+    }
+
+    let header = read(rom, location);
+
+    if header.len() != channel_count as usize {
+        return Err(HeaderError::OutOfBounds);
+    }
+
+    // IDs 1 and 5 are both the first pulse channel, 2 and 6 the second, and so on
+    let mut used = [false; 4];
+
+    for &(id, _) in &header {
+        if !(1..=8).contains(&id) {
+            return Err(HeaderError::InvalidChannel { id });
+        }
+
+        if std::mem::replace(&mut used[((id - 1) % 4) as usize], true) {
+            return Err(HeaderError::DuplicateChannel { id });
+        }
+    }
+
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let rom = [0x44, 0x06, 0x00, 0x07, 0x0b, 0x00];
+        let location = RomLocation::new(0, 0);
+
+        assert_eq!(read(&rom, location), vec![(5, 0x0006), (8, 0x000b)]);
+        assert_eq!(read(&rom[..5], location), Header::new());
+        assert_eq!(read(&rom, RomLocation::new(1, 0x4000)), Header::new());
+    }
+
+    #[test]
+    fn test_try_read() {
+        let location = RomLocation::new(0, 0);
+        let rom = [0x44, 0x06, 0x00, 0x07, 0x0b, 0x00];
+        assert_eq!(try_read(&rom, location), Ok(read(&rom, location)));
+
+        let err = try_read(&rom[..5], location);
+        assert_eq!(err, Err(HeaderError::OutOfBounds));
+
+        let err = try_read(&rom, RomLocation::new(1, 0x4000));
+        assert_eq!(err, Err(HeaderError::OutOfBounds));
+
+        // the first pulse channel, as both a music and an SFX channel
+        let rom = [0x40, 0x06, 0x00, 0x04, 0x0b, 0x00];
+        let err = try_read(&rom, location);
+        assert_eq!(err, Err(HeaderError::DuplicateChannel { id: 5 }));
+
+        let rom = [0x0c, 0x06, 0x00];
+        let err = try_read(&rom, location);
+        assert_eq!(err, Err(HeaderError::InvalidChannel { id: 13 }));
+
+        let mut rom = vec![0; 0x4000];
+        rom[0x3ffd..].copy_from_slice(&[0x44, 0x06, 0x00]);
+        let err = try_read(&rom, RomLocation::new(0, 0x3ffd));
+        assert_eq!(err, Err(HeaderError::PastBank { channel_count: 2 }));
+    }
+}
@@ -0,0 +1,108 @@
+//! Export of Audacity label tracks, for checking timing against recordings.
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::{NoteEvent, SOURCE_SAMPLE_RATE};
+
+/// Write one label per note in the Audacity label track format, i.e. `start\tend\tlabel` lines
+/// with the times in seconds.
+///
+/// The times are rounded to the nearest sample at `sample_rate`, so that the labels line up with
+/// audio exported at that rate. Notes starting at or after `cap` are left out, and notes
+/// running past it are shortened.
+pub fn write_audacity_labels<W: Write>(
+    mut writer: W,
+    events: &[NoteEvent],
+    sample_rate: u32,
+    cap: Option<Duration>,
+) -> io::Result<()> {
+    let cap = cap.map(|cap| to_output_samples(cap.as_nanos(), 1_000_000_000, sample_rate));
+
+    for event in events {
+        let start = to_output_samples(event.start as u128, SOURCE_SAMPLE_RATE, sample_rate);
+        let end = to_output_samples(
+            (event.start + event.len) as u128,
+            SOURCE_SAMPLE_RATE,
+            sample_rate,
+        );
+
+        let (start, end) = match cap {
+            Some(cap) if start >= cap => continue,
+            Some(cap) => (start, end.min(cap)),
+            None => (start, end),
+        };
+
+        writeln!(
+            writer,
+            "{:.6}\t{:.6}\t{}: freq {:#05x}, volume {}, fade {}",
+            start as f64 / sample_rate as f64,
+            end as f64 / sample_rate as f64,
+            event.voice.short_name(),
+            event.freq,
+            event.volume,
+            event.fade,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Convert a number of ticks at `rate` to the nearest number of samples at `sample_rate`.
+fn to_output_samples(ticks: u128, rate: usize, sample_rate: u32) -> u128 {
+    (ticks * (sample_rate as u128) + (rate as u128) / 2) / (rate as u128)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EventKind, Voice};
+
+    fn event(voice: Voice, start: usize, len: usize) -> NoteEvent {
+        NoteEvent {
+            voice,
+            start,
+            len,
+            freq: 0x7a0,
+            volume: 15,
+            fade: 1,
+            kind: EventKind::SquareNote,
+        }
+    }
+
+    #[test]
+    fn test_write_audacity_labels() {
+        let events = [
+            event(Voice::Pulse1, 0, SOURCE_SAMPLE_RATE / 4),
+            event(Voice::Noise, 0, SOURCE_SAMPLE_RATE),
+            event(Voice::Pulse1, SOURCE_SAMPLE_RATE / 4, 1000),
+        ];
+
+        let mut output = Vec::new();
+        write_audacity_labels(&mut output, &events, 48000, None).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "0.000000\t0.250000\tP1: freq 0x7a0, volume 15, fade 1\n\
+             0.000000\t1.000000\tN: freq 0x7a0, volume 15, fade 1\n\
+             0.250000\t0.250958\tP1: freq 0x7a0, volume 15, fade 1\n"
+        );
+    }
+
+    #[test]
+    fn test_write_audacity_labels_cap() {
+        let events = [
+            event(Voice::Pulse1, 0, SOURCE_SAMPLE_RATE),
+            event(Voice::Pulse2, SOURCE_SAMPLE_RATE, 1000),
+        ];
+
+        let mut output = Vec::new();
+        write_audacity_labels(&mut output, &events, 1000, Some(Duration::from_millis(500)))
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "0.000000\t0.500000\tP1: freq 0x7a0, volume 15, fade 1\n"
+        );
+    }
+}
@@ -1,2 +1,51 @@
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used))]
+#![deny(clippy::print_stdout, clippy::print_stderr, clippy::dbg_macro)]
+
+#[cfg(feature = "apu-ref")]
+#[doc(hidden)]
+pub mod apu;
+mod batch;
+mod buffer;
+pub mod diff;
+mod events;
+pub mod extract;
+#[cfg(any(test, feature = "fixtures"))]
+#[doc(hidden)]
+pub mod fixtures;
+mod game;
 pub mod gen1;
 pub mod gen2;
+mod generation;
+mod header;
+pub mod labels;
+mod location;
+pub mod mix;
+#[cfg(feature = "names")]
+pub mod names;
+mod options;
+pub mod pitch;
+mod render_handle;
+mod sample;
+mod save;
+pub mod sequence;
+mod session;
+pub mod symfile;
+mod synthesizer;
+mod timing;
+
+pub use buffer::{BufferError, Pcm64, PcmBuffer};
+pub use events::{EventKind, NoteEvent, Voice};
+pub use game::{detect_rom, DetectError, Game, RomInfo};
+pub use header::HeaderError;
+pub use location::RomLocation;
+pub use options::{ChannelMask, LoopBehavior, Metadata, SynthesisOptions};
+pub use render_handle::{Frame, OwnedSound, RenderHandle};
+pub use sample::Sample;
+pub use save::{write_wav_streaming, SaveError, SaveOptions, WavOptions};
+pub use synthesizer::{
+    CacheStats, OwnedSoundIterator, Synthesizer, SynthesizerError, SynthesizerSession,
+};
+pub use timing::{
+    duration_to_frames, frames_to_duration, samples_per_second, SAMPLES_PER_FRAME,
+    SOURCE_SAMPLE_RATE,
+};
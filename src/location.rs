@@ -0,0 +1,34 @@
+use std::fmt;
+
+/// Location of some data in a banked ROM, e.g. `02:40c3`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct RomLocation {
+    pub bank: u8,
+    pub addr: u16,
+}
+
+impl RomLocation {
+    pub fn new(bank: u8, addr: u16) -> RomLocation {
+        RomLocation { bank, addr }
+    }
+
+    /// Offset of the location in the ROM file.
+    pub fn offset(&self) -> usize {
+        ((self.bank as usize) * 0x4000) + ((self.addr as usize) & 0x3fff)
+    }
+
+    /// Whether the address is inside the window that the bank is mapped to, `0000-3fff` for
+    /// bank 0 and `4000-7fff` for every other bank.
+    pub fn is_mapped(&self) -> bool {
+        match self.bank {
+            0 => self.addr < 0x4000,
+            _ => (0x4000..0x8000).contains(&self.addr),
+        }
+    }
+}
+
+impl fmt::Display for RomLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}:{:04x}", self.bank, self.addr)
+    }
+}
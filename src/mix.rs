@@ -0,0 +1,352 @@
+//! Helpers for combining rendered sounds.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::{PcmBuffer, Voice, SOURCE_SAMPLE_RATE};
+
+/// Every channel of a sound rendered into its own buffer, e.g. for remixing them elsewhere. A
+/// channel that the sound doesn't use is `None`, the others all have the length of the sound.
+///
+/// The samples are the output of each channel before the mix divides them by 3, see `mix`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stems {
+    pub pulse1: Option<PcmBuffer>,
+    pub pulse2: Option<PcmBuffer>,
+    pub wave: Option<PcmBuffer>,
+    pub noise: Option<PcmBuffer>,
+}
+
+impl Stems {
+    pub(crate) fn new(stems: [Option<Vec<f32>>; 4], sample_rate: u32) -> Stems {
+        let [pulse1, pulse2, wave, noise] =
+            stems.map(|stem| stem.map(|data| PcmBuffer::rendered(data, sample_rate)));
+
+        Stems {
+            pulse1,
+            pulse2,
+            wave,
+            noise,
+        }
+    }
+
+    /// The stems in the order pulse1, pulse2, wave and noise.
+    pub fn iter(&self) -> impl Iterator<Item = (Voice, &PcmBuffer)> + '_ {
+        [
+            (Voice::Pulse1, &self.pulse1),
+            (Voice::Pulse2, &self.pulse2),
+            (Voice::Wave, &self.wave),
+            (Voice::Noise, &self.noise),
+        ]
+        .into_iter()
+        .filter_map(|(voice, stem)| Some((voice, stem.as_ref()?)))
+    }
+
+    /// Mix the stems back together the way the synthesizer does, which is the sum of the stems
+    /// divided by 3.
+    pub fn mix(&self) -> PcmBuffer {
+        let mut stems = self.iter().map(|(_, stem)| stem);
+
+        let Some(first) = stems.next() else {
+            return PcmBuffer::rendered(Vec::new(), SOURCE_SAMPLE_RATE as u32);
+        };
+
+        let mut data = first.data().to_vec();
+
+        for stem in stems {
+            for (out, sample) in data.iter_mut().zip(stem.data()) {
+                *out += sample;
+            }
+        }
+
+        for out in data.iter_mut() {
+            *out /= 3.0;
+        }
+
+        PcmBuffer::rendered(data, first.sample_rate())
+    }
+}
+
+/// How much, and how quickly, `duck` lowers the music.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DuckOptions {
+    /// How much to lower the music while the trigger plays, in decibels.
+    pub amount_db: f32,
+    /// Time it takes to lower the music, ending where the trigger starts.
+    pub attack: Duration,
+    /// Time it takes to bring the music back up, starting where the trigger ends.
+    pub release: Duration,
+}
+
+impl Default for DuckOptions {
+    fn default() -> Self {
+        DuckOptions {
+            amount_db: 12.0,
+            attack: Duration::from_millis(50),
+            release: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Error returned by `duck`, `concat` and `layer`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MixError {
+    /// The buffers have different sample rates, resample one of them first.
+    SampleRateMismatch,
+    /// The buffers have different numbers of channels. `duck` also accepts a mono trigger.
+    ChannelMismatch,
+}
+
+impl fmt::Display for MixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MixError::SampleRateMismatch => write!(f, "Sample rates doesn't match"),
+            MixError::ChannelMismatch => write!(f, "Channel counts doesn't match"),
+        }
+    }
+}
+
+impl std::error::Error for MixError {}
+
+/// Lower the volume of `music` around the time that `trigger` plays, and mix `trigger` in at
+/// `at`, e.g. to keep a cry audible on top of a music bed.
+///
+/// The music is lowered for the whole extent of the trigger, with linear ramps of the given
+/// attack and release times on each side. A mono trigger is mixed into every channel of the
+/// music. The trigger is cut off at the end of the music.
+pub fn duck(
+    music: &mut PcmBuffer,
+    trigger: &PcmBuffer,
+    at: Duration,
+    options: DuckOptions,
+) -> Result<(), MixError> {
+    if music.sample_rate() != trigger.sample_rate() {
+        return Err(MixError::SampleRateMismatch);
+    }
+
+    if trigger.channels() != 1 && trigger.channels() != music.channels() {
+        return Err(MixError::ChannelMismatch);
+    }
+
+    let rate = music.sample_rate();
+    let start = to_frames(at, rate);
+    let end = start.saturating_add(trigger.frames());
+    let attack = to_frames(options.attack, rate);
+    let release = to_frames(options.release, rate);
+    let ducked = 10f32.powf(-options.amount_db / 20.0);
+
+    let gain = |frame: usize| -> f32 {
+        let amount = if frame < start {
+            1.0 - ((start - frame) as f32) / (attack.max(1) as f32)
+        } else if frame < end {
+            1.0
+        } else {
+            1.0 - ((frame - end + 1) as f32) / (release.max(1) as f32)
+        };
+
+        1.0 - (1.0 - ducked) * amount.clamp(0.0, 1.0)
+    };
+
+    let channels = music.channels() as usize;
+    let frames = music.frames();
+
+    for frame in start.saturating_sub(attack)..end.saturating_add(release).min(frames) {
+        let gain = gain(frame);
+
+        for sample in &mut music.data_mut()[(frame * channels)..((frame + 1) * channels)] {
+            *sample *= gain;
+        }
+    }
+
+    let trigger_channels = trigger.channels() as usize;
+
+    for frame in start..end.min(frames) {
+        let source = (frame - start) * trigger_channels;
+
+        for channel in 0..channels {
+            let sample = trigger.data()[source + channel % trigger_channels];
+            music.data_mut()[frame * channels + channel] += sample;
+        }
+    }
+
+    Ok(())
+}
+
+/// Play `second` right after `first`, e.g. a cry followed by the faint sound effect.
+pub fn concat(first: &PcmBuffer, second: &PcmBuffer) -> Result<PcmBuffer, MixError> {
+    check_format(first, second)?;
+
+    Ok(first.with_data([first.data(), second.data()].concat()))
+}
+
+/// Play `a` and `b` on top of each other, scaled by `gain[0]` and `gain[1]` respectively, e.g.
+/// two cries for a fusion. The shorter one is padded with silence, and the sum isn't clamped.
+pub fn layer(a: &PcmBuffer, b: &PcmBuffer, gain: [f32; 2]) -> Result<PcmBuffer, MixError> {
+    check_format(a, b)?;
+
+    let len = a.data().len().max(b.data().len());
+    let mut data = vec![0.0; len];
+
+    for (input, gain) in [a, b].into_iter().zip(gain) {
+        for (out, sample) in data.iter_mut().zip(input.data()) {
+            *out += sample * gain;
+        }
+    }
+
+    Ok(a.with_data(data))
+}
+
+fn check_format(a: &PcmBuffer, b: &PcmBuffer) -> Result<(), MixError> {
+    if a.sample_rate() != b.sample_rate() {
+        return Err(MixError::SampleRateMismatch);
+    }
+
+    if a.channels() != b.channels() {
+        return Err(MixError::ChannelMismatch);
+    }
+
+    Ok(())
+}
+
+/// Convert a duration to the nearest number of sample frames at `sample_rate`, saturating at
+/// `usize::MAX`.
+fn to_frames(duration: Duration, sample_rate: u32) -> usize {
+    let frames = (duration.as_nanos() * (sample_rate as u128) + 500_000_000) / 1_000_000_000;
+    usize::try_from(frames).unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 1000;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / (samples.len() as f32)).sqrt()
+    }
+
+    fn music() -> PcmBuffer {
+        // a square wave at half volume, two seconds long
+        let data = (0..(2 * RATE)).map(|i| if i % 20 < 10 { 0.5 } else { -0.5 });
+        PcmBuffer::new(data.collect(), RATE).unwrap()
+    }
+
+    #[test]
+    fn test_duck() {
+        let mut music = music();
+        let original = music.clone();
+
+        // a silent trigger, half a second long, only its extent matters
+        let trigger = PcmBuffer::new(vec![0.0; 500], RATE).unwrap();
+        let options = DuckOptions {
+            amount_db: 12.0,
+            attack: Duration::from_millis(100),
+            release: Duration::from_millis(200),
+        };
+
+        duck(&mut music, &trigger, Duration::from_millis(500), options).unwrap();
+
+        let data = music.data();
+        let drop_db = 20.0 * (rms(&data[500..1000]) / rms(&original.data()[500..1000])).log10();
+        assert!((drop_db + 12.0).abs() < 0.01);
+
+        // untouched before the attack, and after the release
+        assert_eq!(&data[..400], &original.data()[..400]);
+        assert_eq!(&data[1200..], &original.data()[1200..]);
+
+        // ramping in between
+        assert!(rms(&data[400..500]) < rms(&original.data()[400..500]));
+        assert!(rms(&data[1000..1200]) > rms(&data[500..1000]));
+    }
+
+    #[test]
+    fn test_duck_mixes_trigger() {
+        let mut music = PcmBuffer::new(vec![0.0; 100], RATE).unwrap();
+        let trigger = PcmBuffer::new(vec![0.25; 10], RATE).unwrap();
+
+        duck(
+            &mut music,
+            &trigger,
+            Duration::from_millis(95),
+            DuckOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(&music.data()[..95], &[0.0; 95]);
+        assert_eq!(&music.data()[95..], &[0.25; 5]);
+    }
+
+    #[test]
+    fn test_duck_far_away() {
+        let mut music = music();
+        let original = music.clone();
+        let trigger = PcmBuffer::new(vec![0.25; 10], RATE).unwrap();
+
+        // a trigger that starts long after the end of the music leaves it untouched
+        let options = DuckOptions {
+            release: Duration::MAX,
+            ..DuckOptions::default()
+        };
+        duck(&mut music, &trigger, Duration::MAX, options).unwrap();
+        assert_eq!(music, original);
+
+        // with a release that never ends, the rest of the music stays ducked
+        duck(&mut music, &trigger, Duration::ZERO, options).unwrap();
+        assert_eq!(music.frames(), original.frames());
+        assert!(rms(&music.data()[1000..]) < rms(&original.data()[1000..]));
+    }
+
+    #[test]
+    fn test_concat() {
+        let first = PcmBuffer::new(vec![0.5; 10], RATE).unwrap();
+        let second = PcmBuffer::new(vec![-0.25; 5], RATE).unwrap();
+
+        let result = concat(&first, &second).unwrap();
+        assert_eq!(result.frames(), 15);
+        assert_eq!(result.sample_rate(), RATE);
+        assert_eq!(&result.data()[..10], &[0.5; 10]);
+        assert_eq!(&result.data()[10..], &[-0.25; 5]);
+
+        let other_rate = PcmBuffer::new(vec![0.0; 5], 2 * RATE).unwrap();
+        assert_eq!(
+            concat(&first, &other_rate),
+            Err(MixError::SampleRateMismatch)
+        );
+    }
+
+    #[test]
+    fn test_layer() {
+        let a = PcmBuffer::new(vec![0.5; 10], RATE).unwrap();
+        let b = PcmBuffer::new(vec![-0.25; 5], RATE).unwrap();
+
+        let result = layer(&a, &b, [1.0, 2.0]).unwrap();
+        assert_eq!(result.frames(), 10);
+        assert_eq!(&result.data()[..5], &[0.0; 5]);
+        assert_eq!(&result.data()[5..], &[0.5; 5]);
+
+        assert_eq!(layer(&b, &a, [0.5, 0.5]).unwrap().data()[..5], [0.125; 5]);
+
+        let stereo = PcmBuffer::from_interleaved(vec![0.0; 10], 2, RATE).unwrap();
+        assert_eq!(
+            layer(&a, &stereo, [1.0, 1.0]),
+            Err(MixError::ChannelMismatch)
+        );
+    }
+
+    #[test]
+    fn test_duck_mismatch() {
+        let mut music = music();
+
+        let trigger = PcmBuffer::new(vec![0.0; 10], 2 * RATE).unwrap();
+        assert_eq!(
+            duck(&mut music, &trigger, Duration::ZERO, DuckOptions::default()),
+            Err(MixError::SampleRateMismatch)
+        );
+
+        let trigger = PcmBuffer::from_interleaved(vec![0.0; 10], 2, RATE).unwrap();
+        assert_eq!(
+            duck(&mut music, &trigger, Duration::ZERO, DuckOptions::default()),
+            Err(MixError::ChannelMismatch)
+        );
+    }
+}
@@ -0,0 +1,62 @@
+//! Canonical names of known sounds, as used by the pret disassemblies.
+
+use crate::{Game, RomLocation};
+
+include!(concat!(env!("OUT_DIR"), "/names.rs"));
+
+/// Look up the name of the sound whose header is at the given location.
+///
+/// Returns `None` for locations that doesn't point at the header of a known sound.
+pub fn lookup(game: Game, location: RomLocation) -> Option<&'static str> {
+    let offset = location.addr.checked_sub(0x4000)?;
+
+    if offset % 3 != 0 {
+        return None;
+    }
+
+    let index = u8::try_from(offset / 3).ok()?;
+
+    NAMES
+        .binary_search_by_key(&(game, location.bank, index), |&(game, bank, index, _)| {
+            (game, bank, index)
+        })
+        .ok()
+        .map(|i| NAMES[i].3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let cases = [
+            (Game::Yellow, 0x02, 0x4003, "SFX_Noise_Instrument01_1"),
+            (Game::Yellow, 0x02, 0x4039, "SFX_Noise_Instrument19_1"),
+            (Game::Yellow, 0x02, 0x403c, "SFX_Cry00_1"),
+            (Game::Yellow, 0x02, 0x4069, "SFX_Cry05_1"),
+            (Game::Yellow, 0x02, 0x409f, "SFX_Cry0B_1"),
+            (Game::Yellow, 0x02, 0x40ba, "SFX_Cry0E_1"),
+            (Game::Yellow, 0x02, 0x40c3, "SFX_Cry0F_1"),
+            (Game::Yellow, 0x02, 0x4177, "SFX_Cry23_1"),
+            (Game::Yellow, 0x02, 0x4189, "SFX_Cry25_1"),
+            (Game::Red, 0x08, 0x403c, "SFX_Cry00_2"),
+            (Game::Blue, 0x1f, 0x40c3, "SFX_Cry0F_3"),
+            (Game::Red, 0x1f, 0x4003, "SFX_Noise_Instrument01_3"),
+        ];
+
+        for (game, bank, addr, name) in cases {
+            assert_eq!(lookup(game, RomLocation::new(bank, addr)), Some(name));
+        }
+    }
+
+    #[test]
+    fn test_lookup_unknown() {
+        assert_eq!(lookup(Game::Yellow, RomLocation::new(0x02, 0x4000)), None);
+        assert_eq!(lookup(Game::Yellow, RomLocation::new(0x02, 0x403d)), None);
+        assert_eq!(lookup(Game::Yellow, RomLocation::new(0x02, 0x403f)), None);
+        assert_eq!(lookup(Game::Yellow, RomLocation::new(0x03, 0x403c)), None);
+        assert_eq!(lookup(Game::Crystal, RomLocation::new(0x02, 0x403c)), None);
+        assert_eq!(lookup(Game::Yellow, RomLocation::new(0x02, 0x0100)), None);
+    }
+}
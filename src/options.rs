@@ -0,0 +1,349 @@
+use std::time::Duration;
+
+use crate::{Voice, SOURCE_SAMPLE_RATE};
+
+/// Number of commands a channel may execute in a row without playing anything, see
+/// `SynthesisOptions::command_limit`.
+pub(crate) const DEFAULT_COMMAND_LIMIT: usize = 4096;
+
+/// Options that tweaks how a sound is synthesized.
+///
+/// The default options renders the sound exactly like the game does.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct SynthesisOptions {
+    variation: Option<Variation>,
+    pub(crate) analog_drift: f32,
+    pub(crate) channel_phase: [f32; 4],
+    cut_at: Option<Duration>,
+    max_duration: Option<Duration>,
+    pub(crate) preview: Option<u32>,
+    command_limit: Option<usize>,
+    pub(crate) quantize_dac: bool,
+    pub(crate) channel_mask: ChannelMask,
+    loop_behavior: LoopBehavior,
+}
+
+impl SynthesisOptions {
+    pub fn new() -> SynthesisOptions {
+        SynthesisOptions::default()
+    }
+
+    /// Perturb the pitch and length of the sound with a random value within the given spreads,
+    /// using a seeded random number generator so that the same seed always gives the same result.
+    ///
+    /// A seed of 0, or spreads of 0, renders the unmodified sound.
+    pub fn variation(mut self, seed: u64, pitch_spread: i16, length_spread: u16) -> Self {
+        self.variation = Some(Variation {
+            seed,
+            pitch_spread: pitch_spread.unsigned_abs(),
+            length_spread,
+        });
+        self
+    }
+
+    /// Detune the two pulse channels from each other by the given amount of cents, applying
+    /// +cents/2 to pulse1 and -cents/2 to pulse2, rounded to the nearest frequency register value.
+    ///
+    /// Real hardware units have tiny clock and DAC differences that makes the pulse channels beat
+    /// slightly when playing the same note. This is an artistic option to make renders sound less
+    /// sterile, and not an accuracy feature. Defaults to 0, which renders the sound bit-exactly.
+    pub fn analog_drift(mut self, cents: f32) -> Self {
+        self.analog_drift = cents;
+        self
+    }
+
+    /// Set the initial phase of the pulse1, pulse2 and wave channels, expressed as a fraction of
+    /// a period. The last value, for the noise channel, is ignored.
+    ///
+    /// Defaults to 0 for every channel, which matches the reset behavior of the hardware.
+    pub fn channel_phase(mut self, phase: [f32; 4]) -> Self {
+        self.channel_phase = phase;
+        self
+    }
+
+    /// Stop all channels at the given time, the way the game engine cuts a cry when the battle
+    /// text advances: the output goes silent at the next frame boundary, without any fade-out.
+    ///
+    /// This is distinct from a safety cap on the duration, since it also reproduces the behavior
+    /// of the engine.
+    pub fn cut_at(mut self, cut_at: Option<Duration>) -> Self {
+        self.cut_at = cut_at;
+        self
+    }
+
+    /// Stop rendering after at most the given duration, rounded up to a whole frame, e.g. to
+    /// collect a sound that loops forever without running out of memory.
+    ///
+    /// The output is the same as with `cut_at`, and the earlier of the two is used when both are
+    /// set, but this is meant as a safety cap rather than as a part of the sound.
+    pub fn max_duration(mut self, max_duration: Option<Duration>) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// Render a cheap, low quality preview at the given sample rate, e.g. 8000 Hz for a grid of
+    /// thumbnails, instead of at `SOURCE_SAMPLE_RATE`.
+    ///
+    /// Each output sample is the plain average of the source samples it covers, which is fast
+    /// and keeps memory proportional to the preview rate, but lets some aliasing through. Use a
+    /// proper resampler on the full rate output when quality matters.
+    pub fn preview(mut self, rate: u32) -> Self {
+        self.preview = Some(rate.clamp(1, SOURCE_SAMPLE_RATE as u32));
+        self
+    }
+
+    /// Stop a channel that executes more than `limit` commands in a row without playing anything,
+    /// e.g. a loop that jumps to itself, instead of spinning forever. The channel is treated as
+    /// done, and `SoundIterator::is_stuck` reports it.
+    ///
+    /// Defaults to 4096, which is far more than any sound in the games needs between two notes.
+    ///
+    /// Notes reset the count, so this doesn't catch data that is a stream of notes without an
+    /// end, like a zeroed bank. Such a channel stops with `ParseErrorKind::OutOfBounds` once it
+    /// runs off the end of its bank.
+    pub fn command_limit(mut self, limit: usize) -> Self {
+        self.command_limit = Some(limit);
+        self
+    }
+
+    /// Output each channel at the exact levels of its 4-bit DAC before mixing.
+    ///
+    /// The channels already only output volume / 16 with either sign, which is the DAC output
+    /// with the DC offset taken out. The offset moves with the volume envelope, so this gives
+    /// the raw staircase of the hardware, at the cost of a DC offset in the output. Defaults to
+    /// off.
+    pub fn quantize_dac(mut self, enabled: bool) -> Self {
+        self.quantize_dac = enabled;
+        self
+    }
+
+    /// Only mix the channels that are enabled in `mask` into the output, e.g. to hear what the
+    /// noise channel of a cry does on its own.
+    ///
+    /// Muted channels still play along silently, so everything that depends on their timing, like
+    /// the pitch reset of the noise channel, stays the same. Defaults to every channel.
+    pub fn channel_mask(mut self, mask: ChannelMask) -> Self {
+        self.channel_mask = mask;
+        self
+    }
+
+    /// How many times to play the body of a loop that never ends, e.g. the main loop of a song.
+    ///
+    /// Defaults to `LoopBehavior::Infinite`, which keeps looping like the game does.
+    pub fn loop_behavior(mut self, behavior: LoopBehavior) -> Self {
+        self.loop_behavior = behavior;
+        self
+    }
+
+    /// When to stop rendering, the earliest of `cut_at`, `max_duration` and a
+    /// `LoopBehavior::TimeCap`.
+    pub(crate) fn end(&self) -> Option<Duration> {
+        let time_cap = match self.loop_behavior {
+            LoopBehavior::TimeCap(duration) => Some(duration),
+            _ => None,
+        };
+
+        [self.cut_at, self.max_duration, time_cap]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Number of times to play the body of an infinite loop, `None` to loop forever.
+    pub(crate) fn loop_iterations(&self) -> Option<u32> {
+        match self.loop_behavior {
+            LoopBehavior::Iterations(iterations) => Some(iterations),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn max_commands(&self) -> usize {
+        self.command_limit.unwrap_or(DEFAULT_COMMAND_LIMIT)
+    }
+
+    /// The sample rate of the rendered output.
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.preview.unwrap_or(SOURCE_SAMPLE_RATE as u32)
+    }
+
+    pub(crate) fn apply_variation(&self, pitch: i16, length: u16) -> (i16, u16) {
+        match self.variation {
+            Some(variation) if variation.seed != 0 => variation.apply(pitch, length),
+            _ => (pitch, length),
+        }
+    }
+}
+
+/// Which channels are mixed into the output, see `SynthesisOptions::channel_mask`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ChannelMask {
+    pub pulse1: bool,
+    pub pulse2: bool,
+    pub wave: bool,
+    pub noise: bool,
+}
+
+impl Default for ChannelMask {
+    fn default() -> Self {
+        ChannelMask::ALL
+    }
+}
+
+impl ChannelMask {
+    pub const ALL: ChannelMask = ChannelMask {
+        pulse1: true,
+        pulse2: true,
+        wave: true,
+        noise: true,
+    };
+
+    pub const NONE: ChannelMask = ChannelMask {
+        pulse1: false,
+        pulse2: false,
+        wave: false,
+        noise: false,
+    };
+
+    /// Only the given channel.
+    pub fn solo(voice: Voice) -> ChannelMask {
+        ChannelMask::NONE.with(voice, true)
+    }
+
+    /// The same mask, with the given channel enabled or muted.
+    pub fn with(mut self, voice: Voice, enabled: bool) -> ChannelMask {
+        match voice {
+            Voice::Pulse1 => self.pulse1 = enabled,
+            Voice::Pulse2 => self.pulse2 = enabled,
+            Voice::Wave => self.wave = enabled,
+            Voice::Noise => self.noise = enabled,
+        }
+
+        self
+    }
+
+    pub fn contains(&self, voice: Voice) -> bool {
+        match voice {
+            Voice::Pulse1 => self.pulse1,
+            Voice::Pulse2 => self.pulse2,
+            Voice::Wave => self.wave,
+            Voice::Noise => self.noise,
+        }
+    }
+}
+
+/// How loops that never end are rendered, see `SynthesisOptions::loop_behavior`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum LoopBehavior {
+    /// Loop forever, like the game does.
+    #[default]
+    Infinite,
+    /// Loop forever, but stop rendering after the given duration, like `max_duration`.
+    TimeCap(Duration),
+    /// Play the body of every infinite loop the given number of times, and then carry on with
+    /// what comes after it, which usually ends the channel. 0 is treated as 1.
+    Iterations(u32),
+}
+
+/// Information about how a sound was synthesized.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Metadata {
+    /// The pitch that was used, after any variation was applied.
+    pub pitch: i16,
+    /// The length that was used, after any variation was applied.
+    pub length: u16,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Variation {
+    seed: u64,
+    pitch_spread: u16,
+    length_spread: u16,
+}
+
+impl Variation {
+    fn apply(&self, pitch: i16, length: u16) -> (i16, u16) {
+        let mut state = self.seed;
+
+        let pitch_offset = random_offset(&mut state, self.pitch_spread);
+        let length_offset = random_offset(&mut state, self.length_spread);
+
+        (
+            (pitch as i64 + pitch_offset).clamp(i16::MIN as i64, i16::MAX as i64) as i16,
+            (length as i64 + length_offset).clamp(0, u16::MAX as i64) as u16,
+        )
+    }
+}
+
+/// SplitMix64, small and fast, and most importantly identical on every platform.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Random value in the range `-spread..=spread`.
+fn random_offset(state: &mut u64, spread: u16) -> i64 {
+    let range = 2 * (spread as u64) + 1;
+    (splitmix64(state) % range) as i64 - (spread as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variation() {
+        let options = SynthesisOptions::new().variation(1, 16, 8);
+        let (pitch, length) = options.apply_variation(100, 200);
+
+        assert!((84..=116).contains(&pitch));
+        assert!((192..=208).contains(&length));
+        assert_eq!(options.apply_variation(100, 200), (pitch, length));
+
+        let other = SynthesisOptions::new().variation(2, 16, 8);
+        assert_ne!(other.apply_variation(100, 200), (pitch, length));
+    }
+
+    #[test]
+    fn test_channel_mask() {
+        assert_eq!(SynthesisOptions::new().channel_mask, ChannelMask::ALL);
+
+        let mask = ChannelMask::solo(Voice::Noise);
+        assert!(mask.contains(Voice::Noise));
+        assert!(!mask.contains(Voice::Pulse1));
+        assert_eq!(mask.with(Voice::Noise, false), ChannelMask::NONE);
+        assert!(!ChannelMask::ALL.with(Voice::Wave, false).wave);
+    }
+
+    #[test]
+    fn test_loop_behavior() {
+        let options = SynthesisOptions::new();
+        assert_eq!(options.end(), None);
+        assert_eq!(options.loop_iterations(), None);
+
+        let options = options
+            .cut_at(Some(Duration::from_secs(3)))
+            .loop_behavior(LoopBehavior::TimeCap(Duration::from_secs(2)));
+        assert_eq!(options.end(), Some(Duration::from_secs(2)));
+        assert_eq!(options.loop_iterations(), None);
+
+        let options = options.loop_behavior(LoopBehavior::Iterations(2));
+        assert_eq!(options.end(), Some(Duration::from_secs(3)));
+        assert_eq!(options.loop_iterations(), Some(2));
+    }
+
+    #[test]
+    fn test_variation_disabled() {
+        let options = SynthesisOptions::new();
+        assert_eq!(options.apply_variation(-5, 300), (-5, 300));
+
+        let options = SynthesisOptions::new().variation(0, 16, 8);
+        assert_eq!(options.apply_variation(-5, 300), (-5, 300));
+
+        let options = SynthesisOptions::new().variation(1234, 0, 0);
+        assert_eq!(options.apply_variation(-5, 300), (-5, 300));
+    }
+}
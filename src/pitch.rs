@@ -0,0 +1,98 @@
+//! Transpose sounds to musical notes, e.g. to play cries chromatically from a MIDI keyboard.
+//!
+//! The pitch of a sound is an offset that the engine adds to the frequency register of every
+//! note, so it can only move the sound in steps of one register value. The period of a pulse
+//! channel is `2048 - register` steps of 1/131072 seconds, which makes one step roughly
+//! `1731 / (2048 - register)` cents: below a cent for the low notes, around 6 cents at 440 Hz,
+//! and a quarter of a semitone at 1760 Hz. The result of `for_midi_note` is rounded to the
+//! nearest step, so it is off by at most half of that.
+
+use crate::{NoteEvent, Voice};
+
+/// Frequency, in Hz, of a pulse channel playing the given frequency register value.
+pub fn register_to_hz(freq: u16) -> f64 {
+    131072.0 / (2048.0 - (freq & 0x7ff) as f64)
+}
+
+/// Frequency, in Hz, of the given MIDI note, in equal temperament with A4 (69) at 440 Hz.
+pub fn midi_note_to_hz(note: u8) -> f64 {
+    440.0 * 2f64.powf((note as f64 - 69.0) / 12.0)
+}
+
+/// The fundamental of a sound, in Hz, taken as the first note played by its pulse1 channel.
+///
+/// Pass the `note_events` of the sound rendered with a pitch of 0. Returns `None` if the pulse1
+/// channel doesn't play any notes.
+pub fn fundamental(events: &[NoteEvent]) -> Option<f64> {
+    events
+        .iter()
+        .find(|event| event.voice == Voice::Pulse1)
+        .map(|event| register_to_hz(event.freq))
+}
+
+/// The pitch value that transposes a sound with the given fundamental, see `fundamental`, to
+/// the given MIDI note.
+///
+/// Only the note that the fundamental was measured from lands exactly on the MIDI note. Since
+/// the pitch is added to the frequency register, and not multiplied with the frequency, the
+/// intervals to the other notes of the sound get slightly wider or narrower. Notes outside of
+/// the range of the channel are clamped to the lowest or highest register value.
+///
+/// The value can be passed as-is to `gen2::synthesis`. The first generation adds the pitch as
+/// an unsigned byte, so there only values in `0..=255` are usable, passed as `value as u8 as i8`.
+pub fn for_midi_note(base_sound_fundamental_hz: f64, midi_note: u8) -> i16 {
+    let register = |hz: f64| (2048.0 - 131072.0 / hz).round().clamp(0.0, 2047.0) as i16;
+    register(midi_note_to_hz(midi_note)) - register(base_sound_fundamental_hz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gen2, SOURCE_SAMPLE_RATE};
+
+    /// A single pulse channel SFX playing a duty cycle 2 note at register 0x500, about 170 Hz.
+    const ROM: [u8; 10] = [0x04, 0x03, 0x00, 0xdb, 0x02, 0x0f, 0xf0, 0x00, 0x05, 0xff];
+
+    /// Measure the frequency of a rendered pulse wave from its rising edges.
+    fn measure(pitch: i16) -> f64 {
+        let data: Vec<f32> = gen2::synthesis(&ROM, 0, 0, pitch, 0x100).iter().collect();
+
+        let edges: Vec<usize> = (1..data.len())
+            .filter(|&i| data[i - 1] < 0.0 && data[i] > 0.0)
+            .collect();
+
+        let period = (edges[edges.len() - 1] - edges[0]) as f64 / (edges.len() - 1) as f64;
+        SOURCE_SAMPLE_RATE as f64 / period
+    }
+
+    fn cents(a: f64, b: f64) -> f64 {
+        1200.0 * (a / b).log2()
+    }
+
+    #[test]
+    fn test_fundamental() {
+        let events = gen2::synthesis(&ROM, 0, 0, 0, 0x100).note_events();
+        assert_eq!(fundamental(&events), Some(register_to_hz(0x500)));
+        assert_eq!(fundamental(&[]), None);
+    }
+
+    #[test]
+    fn test_octave() {
+        let base = fundamental(&gen2::synthesis(&ROM, 0, 0, 0, 0x100).note_events()).unwrap();
+        assert!(cents(measure(0), base).abs() < 1.0);
+
+        let low = measure(for_midi_note(base, 48));
+        let high = measure(for_midi_note(base, 60));
+
+        assert!(cents(low, midi_note_to_hz(48)).abs() < 3.0);
+        assert!(cents(high, midi_note_to_hz(60)).abs() < 3.0);
+        assert!((cents(high, low) - 1200.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn test_clamped() {
+        let base = register_to_hz(0x500);
+        assert_eq!(for_midi_note(base, 0), -0x500);
+        assert!(for_midi_note(base, 127) <= 0x7ff - 0x500);
+    }
+}
@@ -0,0 +1,390 @@
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::{
+    duration_to_frames, OwnedSoundIterator, RomLocation, SynthesisOptions, Synthesizer,
+    SAMPLES_PER_FRAME, SOURCE_SAMPLE_RATE,
+};
+
+/// Number of frames that the worker renders ahead by default, about 130 ms.
+const DEFAULT_QUEUE_DEPTH: usize = 8;
+
+/// How often a worker that is waiting for room in the queue checks if it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// A sound in a ROM that is owned by a `Synthesizer`, so that it can be moved to another thread.
+#[derive(Debug, Clone)]
+pub struct OwnedSound {
+    synthesizer: Arc<Synthesizer>,
+    location: RomLocation,
+    pitch: i16,
+    length: u16,
+}
+
+impl OwnedSound {
+    /// The pitch and length are interpreted as in `Synthesizer::iter`.
+    pub fn new(
+        synthesizer: Arc<Synthesizer>,
+        location: RomLocation,
+        pitch: i16,
+        length: u16,
+    ) -> OwnedSound {
+        OwnedSound {
+            synthesizer,
+            location,
+            pitch,
+            length,
+        }
+    }
+}
+
+/// A frame of samples rendered by a `RenderHandle`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Frame {
+    /// Offset, in samples, of the first sample of the frame in the sound.
+    pub offset: usize,
+    /// Number of seeks that happened before the frame was rendered. Frames rendered before a
+    /// seek may still be in the queue, and can be skipped by comparing this value.
+    pub epoch: u64,
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    paused: bool,
+    cancelled: bool,
+    seek: Option<Duration>,
+    epoch: u64,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        // the state is plain flags, so a poisoned lock is fine to use
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut State)) {
+        f(&mut self.state());
+        self.changed.notify_all();
+    }
+}
+
+/// Renders a sound on a background thread, one frame at a time, for real-time apps that can't
+/// block their main thread on synthesis.
+///
+/// The worker renders ahead until the queue is full, and then waits for the receiver to catch
+/// up. It stops when the sound ends, when the `Receiver` is dropped, or when the handle is
+/// cancelled or dropped. Music loops forever, so it keeps rendering until it is stopped.
+#[derive(Debug)]
+pub struct RenderHandle {
+    shared: Arc<Shared>,
+    thread: JoinHandle<()>,
+}
+
+impl RenderHandle {
+    pub fn spawn(sound: OwnedSound, options: SynthesisOptions) -> (RenderHandle, Receiver<Frame>) {
+        RenderHandle::spawn_with_depth(sound, options, DEFAULT_QUEUE_DEPTH)
+    }
+
+    /// Same as `spawn`, rendering at most `depth` frames ahead of the receiver.
+    pub fn spawn_with_depth(
+        sound: OwnedSound,
+        options: SynthesisOptions,
+        depth: usize,
+    ) -> (RenderHandle, Receiver<Frame>) {
+        let (sender, receiver) = sync_channel(depth.max(1));
+        let shared = Arc::new(Shared::default());
+
+        let worker = Worker {
+            sound,
+            options,
+            shared: Arc::clone(&shared),
+            sender,
+        };
+
+        let thread = thread::spawn(move || worker.run());
+
+        (RenderHandle { shared, thread }, receiver)
+    }
+
+    /// Stop rendering new frames until `resume` is called.
+    pub fn pause(&self) {
+        self.shared.update(|state| state.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.shared.update(|state| state.paused = false);
+    }
+
+    /// Continue rendering from the given time, rounded down to a whole frame. Seeking past the
+    /// end of the sound ends it.
+    pub fn seek(&self, to: Duration) {
+        self.shared.update(|state| {
+            state.seek = Some(to);
+            state.epoch += 1;
+        });
+    }
+
+    /// Stop the worker, it exits as soon as it is done with the frame it is rendering.
+    pub fn cancel(&self) {
+        self.shared.update(|state| state.cancelled = true);
+    }
+
+    /// Whether the worker thread has exited.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+}
+
+impl Drop for RenderHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Convert a number of samples at `SOURCE_SAMPLE_RATE` to the given sample rate, rounded down and
+/// saturating at `usize::MAX`.
+fn samples_at(samples: usize, rate: u32) -> usize {
+    let samples = (samples as u128) * (rate as u128) / (SOURCE_SAMPLE_RATE as u128);
+    usize::try_from(samples).unwrap_or(usize::MAX)
+}
+
+struct Worker {
+    sound: OwnedSound,
+    options: SynthesisOptions,
+    shared: Arc<Shared>,
+    sender: SyncSender<Frame>,
+}
+
+impl Worker {
+    fn source(&self) -> OwnedSoundIterator {
+        let sound = &self.sound;
+        let synthesizer = &sound.synthesizer;
+        synthesizer.iter(sound.location, sound.pitch, sound.length, self.options)
+    }
+
+    fn run(&self) {
+        let mut source = self.source();
+        let rate = source.sample_rate();
+        let frame_len = samples_at(SAMPLES_PER_FRAME, rate).max(1);
+
+        let mut offset = 0;
+
+        loop {
+            let (seek, epoch) = {
+                let mut state = self.shared.state();
+
+                while state.paused && !state.cancelled && state.seek.is_none() {
+                    state = self
+                        .shared
+                        .changed
+                        .wait(state)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                }
+
+                if state.cancelled {
+                    return;
+                }
+
+                (state.seek.take(), state.epoch)
+            };
+
+            if let Some(to) = seek {
+                let frames = usize::try_from(duration_to_frames(to)).unwrap_or(usize::MAX);
+                let target = samples_at(frames.saturating_mul(SAMPLES_PER_FRAME), rate);
+                source = self.source();
+                offset = 0;
+
+                // skip everything before the seek target without synthesizing it, a frame at a
+                // time so that a long skip still reacts to being stopped or seeked again
+                while offset < target {
+                    let len = (target - offset).min(frame_len);
+                    let skipped = source.skip_samples(len);
+                    offset += skipped;
+
+                    let state = self.shared.state();
+
+                    if skipped < len || state.cancelled || state.seek.is_some() {
+                        break;
+                    }
+                }
+
+                continue;
+            }
+
+            let mut samples = vec![0.0; frame_len];
+            let len = source.fill(&mut samples);
+
+            if len == 0 {
+                return;
+            }
+
+            samples.truncate(len);
+
+            let mut frame = Frame {
+                offset,
+                epoch,
+                samples,
+                sample_rate: rate,
+            };
+
+            offset += len;
+
+            // wait for room in the queue, while still reacting to being stopped
+            loop {
+                match self.sender.try_send(frame) {
+                    Ok(()) => break,
+                    Err(TrySendError::Disconnected(_)) => return,
+                    Err(TrySendError::Full(rejected)) => frame = rejected,
+                }
+
+                let state = self.shared.state();
+
+                if state.cancelled || state.seek.is_some() {
+                    break;
+                }
+
+                let _ = self.shared.changed.wait_timeout(state, POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::time::Instant;
+
+    use super::*;
+
+    /// A ROM with the title of Pokémon Red, and a single pulse channel SFX at 02:4000 that loops
+    /// a 16 frame long note forever.
+    fn sound() -> OwnedSound {
+        let mut rom = vec![0; 0xc000];
+        rom[0x134..0x13f].copy_from_slice(b"POKEMON RED");
+        rom[0x8000..0x800b].copy_from_slice(&[
+            0x04, 0x03, 0x40, 0x2f, 0xf1, 0x00, 0x07, 0xfe, 0x00, 0x03, 0x40,
+        ]);
+
+        let synthesizer = Arc::new(Synthesizer::new(rom).unwrap());
+        OwnedSound::new(synthesizer, RomLocation::new(0x02, 0x4000), 0, 0x80)
+    }
+
+    fn wait_until_finished(handle: &RenderHandle) {
+        let start = Instant::now();
+
+        while !handle.is_finished() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Worker didn't exit"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_render_seek_cancel() {
+        let options = SynthesisOptions::new().preview(8000);
+        let (handle, receiver) = RenderHandle::spawn(sound(), options);
+
+        let first: Vec<Frame> = receiver.iter().take(3).collect();
+        assert_eq!(first[0].offset, 0);
+        assert_eq!(first[1].offset, first[0].samples.len());
+        assert!(first.iter().all(|frame| frame.epoch == 0));
+
+        handle.pause();
+        handle.seek(Duration::from_secs(1));
+        handle.resume();
+
+        let seeked = receiver.iter().find(|frame| frame.epoch == 1).unwrap();
+        assert_eq!(seeked.offset, samples_at(60 * SAMPLES_PER_FRAME, 8000));
+
+        handle.cancel();
+        wait_until_finished(&handle);
+
+        // the worker dropped its sender, so the remaining frames drain and then the queue ends
+        assert!(receiver.iter().count() <= DEFAULT_QUEUE_DEPTH);
+    }
+
+    #[test]
+    fn test_seek_skips() {
+        let sound = sound();
+        let options = SynthesisOptions::new();
+        let (handle, receiver) = RenderHandle::spawn(sound.clone(), options);
+
+        // the frames after a seek are the same as when rendering up to it
+        handle.seek(Duration::from_secs(1));
+        let seeked = receiver.iter().find(|frame| frame.epoch == 1).unwrap();
+        assert_eq!(seeked.offset, 60 * SAMPLES_PER_FRAME);
+
+        let synthesizer = &sound.synthesizer;
+        let expected: Vec<f32> = synthesizer
+            .iter(sound.location, sound.pitch, sound.length, options)
+            .skip(seeked.offset)
+            .take(seeked.samples.len())
+            .collect();
+        assert_eq!(seeked.samples, expected);
+
+        // seeking as far as possible in a sound that loops forever can still be cancelled
+        handle.seek(Duration::MAX);
+        thread::sleep(Duration::from_millis(20));
+        handle.cancel();
+        wait_until_finished(&handle);
+    }
+
+    #[test]
+    fn test_drop_stops_worker() {
+        let (handle, receiver) = RenderHandle::spawn(sound(), SynthesisOptions::new());
+        let shared = Arc::clone(&handle.shared);
+
+        // the queue fills up while nobody is reading, then the handle is dropped
+        thread::sleep(Duration::from_millis(50));
+        drop(handle);
+
+        assert!(shared.state().cancelled);
+        loop {
+            match receiver.recv_timeout(Duration::from_secs(5)) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => panic!("Worker didn't exit"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_finite_sound_ends() {
+        let mut rom = vec![0; 0xc000];
+        rom[0x134..0x13f].copy_from_slice(b"POKEMON RED");
+        rom[0x8000..0x8008].copy_from_slice(&[0x04, 0x03, 0x40, 0x2f, 0xf1, 0x00, 0x07, 0xff]);
+
+        let synthesizer = Arc::new(Synthesizer::new(rom).unwrap());
+        let sound = OwnedSound::new(synthesizer, RomLocation::new(0x02, 0x4000), 0, 0x80);
+
+        let (_handle, receiver) = RenderHandle::spawn(sound, SynthesisOptions::new());
+        let frames: Vec<Frame> = receiver.iter().collect();
+
+        assert_eq!(frames.len(), 16);
+        assert!(frames
+            .iter()
+            .all(|frame| frame.samples.len() == SAMPLES_PER_FRAME));
+    }
+
+    #[test]
+    fn test_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<RenderHandle>();
+        assert_send::<OwnedSound>();
+        assert_send::<Frame>();
+    }
+}
@@ -0,0 +1,33 @@
+use std::fmt::Debug;
+use std::ops::{AddAssign, Div, Mul};
+
+/// Sample type used when mixing the channels together.
+///
+/// `f32` is used by default, `f64` can be used for analysis workflows where the quantization
+/// introduced by accumulating in `f32` is measurable.
+pub trait Sample:
+    Copy + Default + PartialEq + Debug + AddAssign + Div<Output = Self> + Mul<Output = Self>
+{
+    fn from_f32(value: f32) -> Self;
+    fn to_f32(self) -> f32;
+}
+
+impl Sample for f32 {
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+impl Sample for f64 {
+    fn from_f32(value: f32) -> Self {
+        value as f64
+    }
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+}
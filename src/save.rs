@@ -0,0 +1,591 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::buffer::to_i16;
+use crate::PcmBuffer;
+
+/// File formats that `save` can write, by file extension.
+const FORMATS: [(&str, Format); 2] = [("wav", Format::Wav), ("raw", Format::Raw)];
+
+/// Error returned by `Pcm::save`.
+#[derive(Debug)]
+pub enum SaveError {
+    /// The file extension isn't one of the supported formats.
+    UnsupportedFormat {
+        extension: String,
+    },
+    /// The bit depth isn't 8, 16 or 32.
+    UnsupportedBits(u16),
+    /// The sample rate is 0, or too high for the byte rate to fit in a WAV header.
+    UnsupportedSampleRate(u32),
+    /// The sound is infinitely long, and no `cut_at` was given.
+    Infinite,
+    Io(io::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::UnsupportedFormat { extension } => {
+                let supported: Vec<&str> = FORMATS.iter().map(|(name, _)| *name).collect();
+                write!(
+                    f,
+                    "Unsupported file extension {:?}, supported extensions are: {}",
+                    extension,
+                    supported.join(", ")
+                )
+            }
+            SaveError::UnsupportedBits(bits) => {
+                write!(f, "Unsupported bit depth {}, use 8, 16 or 32", bits)
+            }
+            SaveError::UnsupportedSampleRate(rate) => {
+                write!(f, "Unsupported sample rate {}", rate)
+            }
+            SaveError::Infinite => write!(f, "Sound is infinitely long"),
+            SaveError::Io(err) => write!(f, "Failed to write file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SaveError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+/// Options for `Pcm::save_with_options`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SaveOptions {
+    sample_rate: Option<u32>,
+    bits: u16,
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        SaveOptions {
+            sample_rate: None,
+            bits: 16,
+        }
+    }
+}
+
+impl SaveOptions {
+    pub fn new() -> SaveOptions {
+        SaveOptions::default()
+    }
+
+    /// Render the sound at the given sample rate, the same way as `SynthesisOptions::preview`.
+    /// Defaults to the sample rate of the sound.
+    pub fn sample_rate(mut self, rate: u32) -> Self {
+        self.sample_rate = Some(rate);
+        self
+    }
+
+    /// Write 8 or 16-bit integer samples, or 32-bit float samples. Defaults to 16.
+    pub fn bits(mut self, bits: u16) -> Self {
+        self.bits = bits;
+        self
+    }
+}
+
+/// Options for `Pcm::write_wav`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct WavOptions {
+    sample_rate: Option<u32>,
+    bits: u16,
+}
+
+impl Default for WavOptions {
+    fn default() -> Self {
+        WavOptions {
+            sample_rate: None,
+            bits: 16,
+        }
+    }
+}
+
+impl WavOptions {
+    pub fn new() -> WavOptions {
+        WavOptions::default()
+    }
+
+    /// Resample the sound to the given sample rate, by linear interpolation between the two
+    /// nearest samples. Defaults to the sample rate of the sound. `Pcm::resample` aliases less
+    /// when going down from the full sample rate.
+    pub fn sample_rate(mut self, rate: u32) -> Self {
+        self.sample_rate = Some(rate);
+        self
+    }
+
+    /// Write 8 or 16-bit integer samples, or 32-bit float samples. Defaults to 16.
+    pub fn bits(mut self, bits: u16) -> Self {
+        self.bits = bits;
+        self
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Format {
+    /// RIFF WAVE, with integer or float samples depending on the bit depth.
+    Wav,
+    /// Headerless little endian samples.
+    Raw,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Result<Format, SaveError> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        FORMATS
+            .iter()
+            .find(|(name, _)| *name == extension)
+            .map(|(_, format)| *format)
+            .ok_or(SaveError::UnsupportedFormat { extension })
+    }
+}
+
+/// Render a sound with `render`, which gets the sample rate from the options, and write it to
+/// `path` in the format given by its extension. The format is checked before rendering.
+pub(crate) fn save(
+    path: &Path,
+    options: SaveOptions,
+    render: impl FnOnce(Option<u32>) -> Option<PcmBuffer>,
+) -> Result<(), SaveError> {
+    let format = Format::from_path(path)?;
+
+    if !matches!(options.bits, 8 | 16 | 32) {
+        return Err(SaveError::UnsupportedBits(options.bits));
+    }
+
+    let buffer = render(options.sample_rate).ok_or(SaveError::Infinite)?;
+
+    let data = match format {
+        Format::Wav => {
+            let mut data = Vec::new();
+            let options = WavOptions::new().bits(options.bits);
+            let (len, channels, rate) = (buffer.frames(), buffer.channels(), buffer.sample_rate());
+            write_wav(&mut data, buffer.iter(), len, channels, rate, options)?;
+            data
+        }
+        Format::Raw => encode_samples(&buffer, options.bits),
+    };
+
+    fs::write(path, data)?;
+    Ok(())
+}
+
+/// Write a mono sound to `w` as a RIFF WAVE file, without knowing its length up front. The
+/// samples are streamed through in small chunks, so memory use doesn't grow with the length of
+/// the sound: a placeholder header is written first, and its sizes are patched afterwards.
+///
+/// `sample_rate` is the rate of `samples`, they are resampled to the rate of the options.
+pub fn write_wav_streaming<W: Write + Seek>(
+    mut w: W,
+    samples: impl IntoIterator<Item = f32>,
+    sample_rate: u32,
+    options: WavOptions,
+) -> io::Result<()> {
+    let format = WavFormat::new(1, sample_rate, options)?;
+    let start = w.stream_position()?;
+
+    w.write_all(&format.header(0))?;
+    let data_len = format.write_samples(&mut w, samples.into_iter(), sample_rate)?;
+    let data_len = u32::try_from(data_len).map_err(|_| too_long())?;
+
+    w.seek(SeekFrom::Start(start + 4))?;
+    w.write_all(&36u32.saturating_add(data_len).to_le_bytes())?;
+    w.seek(SeekFrom::Start(start + 40))?;
+    w.write_all(&data_len.to_le_bytes())?;
+    w.seek(SeekFrom::Start(start + 44 + data_len as u64))?;
+
+    Ok(())
+}
+
+/// Write `len` frames of interleaved samples to `w` as a RIFF WAVE file, streaming them through
+/// in small chunks. `sample_rate` is the rate of `samples`, see `write_wav_streaming`.
+pub(crate) fn write_wav(
+    mut w: impl Write,
+    samples: impl Iterator<Item = f32>,
+    len: usize,
+    channels: u16,
+    sample_rate: u32,
+    options: WavOptions,
+) -> io::Result<()> {
+    let format = WavFormat::new(channels, sample_rate, options)?;
+    let frames = match format.sample_rate == sample_rate {
+        true => len,
+        false => (len as f64 / format.ratio(sample_rate)).ceil() as usize,
+    };
+
+    let data_len = frames.saturating_mul(format.block_align() as usize);
+    w.write_all(&format.header(u32::try_from(data_len).map_err(|_| too_long())?))?;
+    format.write_samples(w, samples, sample_rate)?;
+
+    Ok(())
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Sound is too long for a WAV file",
+    )
+}
+
+/// Layout of the samples in a WAV file.
+#[derive(Debug, Clone, Copy)]
+struct WavFormat {
+    channels: u16,
+    sample_rate: u32,
+    bits: u16,
+}
+
+impl WavFormat {
+    fn new(channels: u16, sample_rate: u32, options: WavOptions) -> io::Result<WavFormat> {
+        if !matches!(options.bits, 8 | 16 | 32) {
+            let err = SaveError::UnsupportedBits(options.bits);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+        }
+
+        if channels.checked_mul(options.bits / 8).is_none() {
+            let err = "Too many channels for a WAV file";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+        }
+
+        let format = WavFormat {
+            channels,
+            sample_rate: options.sample_rate.unwrap_or(sample_rate),
+            bits: options.bits,
+        };
+
+        if format.sample_rate == 0 || format.byte_rate().is_none() {
+            let err = SaveError::UnsupportedSampleRate(format.sample_rate);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+        }
+
+        Ok(format)
+    }
+
+    fn block_align(&self) -> u16 {
+        self.channels * (self.bits / 8)
+    }
+
+    fn byte_rate(&self) -> Option<u32> {
+        self.sample_rate.checked_mul(self.block_align() as u32)
+    }
+
+    /// Number of input frames per output frame.
+    fn ratio(&self, sample_rate: u32) -> f64 {
+        sample_rate as f64 / self.sample_rate as f64
+    }
+
+    fn header(&self, data_len: u32) -> Vec<u8> {
+        let format: u16 = if self.bits == 32 { 3 } else { 1 };
+
+        let mut header = Vec::with_capacity(44);
+        header.extend(b"RIFF");
+        header.extend(36u32.saturating_add(data_len).to_le_bytes());
+        header.extend(b"WAVEfmt ");
+        header.extend(16u32.to_le_bytes()); // remaining header size
+        header.extend(format.to_le_bytes()); // PCM or IEEE float type
+        header.extend(self.channels.to_le_bytes());
+        header.extend(self.sample_rate.to_le_bytes());
+        header.extend(self.byte_rate().unwrap_or_default().to_le_bytes()); // byte rate
+        header.extend(self.block_align().to_le_bytes());
+        header.extend(self.bits.to_le_bytes());
+        header.extend(b"data");
+        header.extend(data_len.to_le_bytes());
+        header
+    }
+
+    /// Resample and encode the samples, returns the number of bytes written.
+    fn write_samples(
+        &self,
+        mut w: impl Write,
+        samples: impl Iterator<Item = f32>,
+        sample_rate: u32,
+    ) -> io::Result<usize> {
+        match self.sample_rate == sample_rate {
+            true => self.write_encoded(&mut w, samples),
+            false => self.write_encoded(
+                &mut w,
+                Resampler::new(samples, self.channels, self.ratio(sample_rate)),
+            ),
+        }
+    }
+
+    fn write_encoded(
+        &self,
+        mut w: impl Write,
+        samples: impl Iterator<Item = f32>,
+    ) -> io::Result<usize> {
+        let mut chunk = Vec::with_capacity(CHUNK_LEN);
+        let mut written = 0;
+
+        for sample in samples {
+            encode_sample(sample, self.bits, &mut chunk);
+
+            if chunk.len() >= CHUNK_LEN {
+                w.write_all(&chunk)?;
+                written += chunk.len();
+                chunk.clear();
+            }
+        }
+
+        w.write_all(&chunk)?;
+        Ok(written + chunk.len())
+    }
+}
+
+/// Size in bytes of the chunks that samples are encoded into before being written.
+const CHUNK_LEN: usize = 4096;
+
+/// Resamples interleaved samples by linear interpolation between the two nearest sample frames,
+/// holding on to no more than those two frames.
+struct Resampler<I> {
+    samples: I,
+    channels: usize,
+    /// Number of input frames per output frame.
+    ratio: f64,
+    /// Index of the next output frame.
+    index: usize,
+    /// Index of the input frame in `prev`, `next` is the one after it.
+    at: usize,
+    /// Input frames, empty once past the end of the input.
+    prev: Vec<f32>,
+    next: Vec<f32>,
+    /// The current output frame, and how many of its samples have been yielded.
+    frame: Vec<f32>,
+    channel: usize,
+}
+
+impl<I: Iterator<Item = f32>> Resampler<I> {
+    fn new(mut samples: I, channels: u16, ratio: f64) -> Resampler<I> {
+        let channels = channels as usize;
+        let prev = read_frame(&mut samples, channels);
+        let next = read_frame(&mut samples, channels);
+
+        Resampler {
+            samples,
+            channels,
+            ratio,
+            index: 0,
+            at: 0,
+            prev,
+            next,
+            frame: Vec::with_capacity(channels),
+            channel: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = f32>> Iterator for Resampler<I> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel < self.frame.len() {
+            self.channel += 1;
+            return Some(self.frame[self.channel - 1]);
+        }
+
+        let position = self.index as f64 * self.ratio;
+        let prev_index = position.floor() as usize;
+
+        while self.at < prev_index {
+            if self.next.is_empty() {
+                self.prev.clear();
+                break;
+            }
+
+            std::mem::swap(&mut self.prev, &mut self.next);
+            self.next = read_frame(&mut self.samples, self.channels);
+            self.at += 1;
+        }
+
+        if self.prev.is_empty() {
+            return None;
+        }
+
+        // how far the position is from `prev` towards `next`
+        let fraction = position - prev_index as f64;
+
+        self.frame.clear();
+
+        if self.next.is_empty() || fraction == 0.0 {
+            self.frame.extend(&self.prev);
+        } else {
+            self.frame
+                .extend(self.prev.iter().zip(&self.next).map(|(&prev, &next)| {
+                    ((1.0 - fraction) * prev as f64 + fraction * next as f64) as f32
+                }));
+        }
+
+        self.index += 1;
+        self.channel = 1;
+        Some(self.frame[0])
+    }
+}
+
+/// Read a whole frame, returns an empty frame at the end of the input.
+fn read_frame(samples: &mut impl Iterator<Item = f32>, channels: usize) -> Vec<f32> {
+    let frame: Vec<f32> = samples.take(channels).collect();
+
+    match frame.len() == channels {
+        true => frame,
+        false => Vec::new(),
+    }
+}
+
+fn encode_sample(sample: f32, bits: u16, output: &mut Vec<u8>) {
+    match bits {
+        8 => output.push((sample.clamp(-1.0, 1.0) * 127.0 + 128.0) as u8),
+        16 => output.extend(to_i16(sample).to_le_bytes()),
+        _ => output.extend(sample.to_le_bytes()),
+    }
+}
+
+fn encode_samples(buffer: &PcmBuffer, bits: u16) -> Vec<u8> {
+    let mut output = Vec::with_capacity(buffer.data().len() * bits as usize / 8);
+
+    for sample in buffer.iter() {
+        encode_sample(sample, bits, &mut output);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pokemon-synthesizer-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    fn render(rate: Option<u32>) -> Option<PcmBuffer> {
+        Some(PcmBuffer::new(vec![0.0, 0.5, -0.5, 1.0], rate.unwrap_or(1000)).unwrap())
+    }
+
+    #[test]
+    fn test_save_wav() {
+        for (name, bits) in [("test.wav", 16), ("TEST.WAV", 8), ("float.Wav", 32)] {
+            let path = temp_path(name);
+            save(&path, SaveOptions::new().bits(bits), render).unwrap();
+
+            let data = fs::read(&path).unwrap();
+            assert_eq!(&data[0..4], b"RIFF");
+            assert_eq!(&data[8..12], b"WAVE");
+            assert_eq!(u16::from_le_bytes([data[34], data[35]]), bits);
+            assert_eq!(data.len(), 44 + 4 * (bits as usize) / 8);
+        }
+    }
+
+    #[test]
+    fn test_save_raw() {
+        let path = temp_path("test.raw");
+        save(&path, SaveOptions::new().sample_rate(500), render).unwrap();
+
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data, [0, 0, 0, 64, 0, 192, 255, 127]);
+    }
+
+    fn write_mono(samples: &[f32], options: WavOptions) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let len = samples.len();
+        write_wav(&mut data, samples.iter().copied(), len, 1, 1000, options)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn test_write_wav() {
+        let samples = [0.0, 0.5, -0.5, 1.0];
+
+        let data = write_mono(&samples, WavOptions::new().bits(8)).unwrap();
+        assert_eq!(data.len(), 44 + 4);
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), 36 + 4);
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 4);
+        assert_eq!(&data[44..], [128, 191, 64, 255]);
+
+        // resampled to twice the sample rate, the first sample is kept as is
+        let data = write_mono(&samples, WavOptions::new().sample_rate(2000)).unwrap();
+        assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 2000);
+        assert_eq!(u32::from_le_bytes(data[28..32].try_into().unwrap()), 4000);
+        assert_eq!(u16::from_le_bytes(data[32..34].try_into().unwrap()), 2);
+        assert_eq!(u16::from_le_bytes(data[34..36].try_into().unwrap()), 16);
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 2 * 8);
+        assert_eq!(data.len(), 44 + 2 * 8);
+        assert_eq!(&data[44..46], [0, 0]);
+
+        // interpolated linearly between the two nearest samples
+        let data = write_mono(&[0.0, 1.0], WavOptions::new().sample_rate(4000).bits(32)).unwrap();
+        let samples: Vec<f32> = data[44..]
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert_eq!(samples, [0.0, 0.25, 0.5, 0.75, 1.0, 1.0, 1.0, 1.0]);
+
+        // downsampled, rounding the length up
+        let samples = vec![0.25; 1000];
+        let data = write_mono(&samples, WavOptions::new().sample_rate(48).bits(8)).unwrap();
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 48);
+        assert_eq!(data.len(), 44 + 48);
+
+        let err = write_mono(&samples, WavOptions::new().bits(24)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_write_wav_streaming() {
+        let samples: Vec<f32> = (0..5000).map(|i| (i as f32 / 100.0).sin()).collect();
+
+        for options in [
+            WavOptions::new(),
+            WavOptions::new().bits(8).sample_rate(48),
+            WavOptions::new().bits(32).sample_rate(1500),
+        ] {
+            let mut cursor = io::Cursor::new(b"prefix".to_vec());
+            cursor.seek(SeekFrom::End(0)).unwrap();
+            write_wav_streaming(&mut cursor, samples.iter().copied(), 1000, options).unwrap();
+
+            let data = cursor.into_inner();
+            assert_eq!(&data[..6], b"prefix");
+            assert_eq!(data[6..], write_mono(&samples, options).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let path = temp_path("test.mp3");
+        let err = save(&path, SaveOptions::new(), render).unwrap_err();
+
+        assert!(matches!(&err, SaveError::UnsupportedFormat { extension } if extension == "mp3"));
+        assert_eq!(
+            err.to_string(),
+            "Unsupported file extension \"mp3\", supported extensions are: wav, raw"
+        );
+        assert!(!path.exists());
+
+        let err = save(&temp_path("test.wav"), SaveOptions::new().bits(24), render);
+        assert!(matches!(err, Err(SaveError::UnsupportedBits(24))));
+
+        let err = save(&temp_path("infinite.wav"), SaveOptions::new(), |_| None);
+        assert!(matches!(err, Err(SaveError::Infinite)));
+    }
+}
@@ -0,0 +1,269 @@
+//! Render several sounds back to back into a single buffer, e.g. a cry followed by a jingle.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::{gen1, gen2, PcmBuffer, RomLocation, SynthesisOptions, SOURCE_SAMPLE_RATE};
+
+/// A sound to render as part of a sequence, with the arguments to `synthesis_with_options`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SoundRef<'a> {
+    Gen1 {
+        rom: &'a [u8],
+        location: RomLocation,
+        pitch: i8,
+        length: u8,
+    },
+    Gen2 {
+        rom: &'a [u8],
+        location: RomLocation,
+        pitch: i16,
+        length: u16,
+    },
+}
+
+impl SoundRef<'_> {
+    /// Render the sound, returns `None` if it is infinitely long.
+    fn render(&self, options: SynthesisOptions) -> Option<PcmBuffer> {
+        match *self {
+            SoundRef::Gen1 {
+                rom,
+                location,
+                pitch,
+                length,
+            } => gen1::synthesis_with_options(
+                rom,
+                location.bank,
+                location.addr,
+                pitch,
+                length,
+                options,
+            )
+            .to_buffer(),
+            SoundRef::Gen2 {
+                rom,
+                location,
+                pitch,
+                length,
+            } => gen2::synthesis_with_options(
+                rom,
+                location.bank,
+                location.addr,
+                pitch,
+                length,
+                options,
+            )
+            .to_buffer(),
+        }
+    }
+}
+
+/// Time between the end of one sound and the start of the next one.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Spacing {
+    /// Silence between the sounds.
+    Gap(Duration),
+    /// Start the next sound this long before the previous one ends, mixing them together, e.g.
+    /// to let the fade-out of a cry ring into the next sound. The overlap is limited to the
+    /// length of the previous sound.
+    Overlap(Duration),
+}
+
+impl Default for Spacing {
+    fn default() -> Self {
+        Spacing::Gap(Duration::ZERO)
+    }
+}
+
+/// Error returned by `render`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SequenceError {
+    /// The sound at the given index is infinitely long, and no `cut_at` was given.
+    Infinite { index: usize },
+    /// The sound at the given index has a different sample rate than the first sound, e.g.
+    /// because only some of them are rendered as previews.
+    SampleRateMismatch { index: usize },
+}
+
+impl fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceError::Infinite { index } => {
+                write!(f, "Sound {} is infinitely long", index)
+            }
+            SequenceError::SampleRateMismatch { index } => {
+                write!(f, "Sound {} has a different sample rate", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SequenceError {}
+
+/// The rendered sequence, see `render`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Sequence {
+    pub buffer: PcmBuffer,
+    /// Offset, in samples, of the start of each sound in `buffer`.
+    pub starts: Vec<usize>,
+}
+
+impl Sequence {
+    /// Time of the start of each sound in `buffer`.
+    pub fn start_times(&self) -> Vec<Duration> {
+        let rate = self.buffer.sample_rate() as f64;
+        self.starts
+            .iter()
+            .map(|&start| Duration::from_secs_f64((start as f64) / rate))
+            .collect()
+    }
+}
+
+/// Render the sounds back to back, sample accurately, with the given spacing between them.
+///
+/// Every sound is rendered with its own options, so infinitely long sounds, e.g. music, needs
+/// a `cut_at`.
+pub fn render(
+    sounds: &[(SoundRef, SynthesisOptions)],
+    spacing: Spacing,
+) -> Result<Sequence, SequenceError> {
+    let mut data: Vec<f32> = Vec::new();
+    let mut starts = Vec::with_capacity(sounds.len());
+    let mut sample_rate = None;
+
+    for (index, (sound, options)) in sounds.iter().enumerate() {
+        let buffer = sound
+            .render(*options)
+            .ok_or(SequenceError::Infinite { index })?;
+
+        let rate = *sample_rate.get_or_insert(buffer.sample_rate());
+        if buffer.sample_rate() != rate {
+            return Err(SequenceError::SampleRateMismatch { index });
+        }
+
+        let start = match (starts.last(), spacing) {
+            (None, _) => 0,
+            (Some(_), Spacing::Gap(gap)) => data.len() + to_samples(gap, rate),
+            (Some(&previous), Spacing::Overlap(overlap)) => {
+                data.len() - to_samples(overlap, rate).min(data.len() - previous)
+            }
+        };
+
+        let samples = buffer.data();
+        let end = start + samples.len();
+
+        if data.len() < end {
+            data.resize(end, 0.0);
+        }
+
+        for (out, sample) in data[start..end].iter_mut().zip(samples) {
+            *out += *sample;
+        }
+
+        starts.push(start);
+    }
+
+    Ok(Sequence {
+        buffer: PcmBuffer::rendered(data, sample_rate.unwrap_or(SOURCE_SAMPLE_RATE as u32)),
+        starts,
+    })
+}
+
+/// Convert a duration to the nearest number of samples at `sample_rate`.
+fn to_samples(duration: Duration, sample_rate: u32) -> usize {
+    ((duration.as_nanos() * (sample_rate as u128) + 500_000_000) / 1_000_000_000) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two single pulse channel SFX, 16 and 8 frames long, and one that loops forever.
+    const ROM: [u8; 27] = [
+        0x04, 0x09, 0x00, 0x04, 0x0e, 0x00, 0x04, 0x13, 0x00, // headers
+        0x2f, 0xf1, 0x00, 0x07, 0xff, // 16 frames
+        0x27, 0x81, 0x80, 0x06, 0xff, // 8 frames
+        0x2f, 0xf0, 0x00, 0x07, 0xfe, 0x00, 0x13, 0x00, // forever
+    ];
+
+    fn sound(addr: u16) -> SoundRef<'static> {
+        SoundRef::Gen1 {
+            rom: &ROM,
+            location: RomLocation::new(0, addr),
+            pitch: 0,
+            length: 0x80,
+        }
+    }
+
+    fn frames(n: usize) -> usize {
+        n * crate::SAMPLES_PER_FRAME
+    }
+
+    #[test]
+    fn test_gap() {
+        let options = SynthesisOptions::new();
+        let gap = Duration::from_millis(100);
+        let sequence = render(
+            &[(sound(0), options), (sound(3), options)],
+            Spacing::Gap(gap),
+        )
+        .unwrap();
+
+        let rate = sequence.buffer.sample_rate();
+        let gap = to_samples(gap, rate);
+
+        assert_eq!(sequence.starts, vec![0, frames(16) + gap]);
+        assert_eq!(sequence.buffer.frames(), frames(16) + gap + frames(8));
+        assert_eq!(sequence.start_times()[0], Duration::ZERO);
+
+        let first = sound(0).render(options).unwrap();
+        let second = sound(3).render(options).unwrap();
+
+        let samples = sequence.buffer.data();
+        assert_eq!(&samples[..frames(16)], first.data());
+        assert!(samples[frames(16)..(frames(16) + gap)]
+            .iter()
+            .all(|&sample| sample == 0.0));
+        assert_eq!(&samples[(frames(16) + gap)..], second.data());
+    }
+
+    #[test]
+    fn test_overlap() {
+        let options = SynthesisOptions::new();
+        let overlap = Spacing::Overlap(Duration::from_secs(1));
+        let sequence = render(&[(sound(0), options), (sound(3), options)], overlap).unwrap();
+
+        // the overlap is limited to the length of the first sound
+        assert_eq!(sequence.starts, vec![0, 0]);
+        assert_eq!(sequence.buffer.frames(), frames(16));
+    }
+
+    #[test]
+    fn test_infinite() {
+        let options = SynthesisOptions::new();
+        let sounds = [(sound(0), options), (sound(6), options)];
+
+        assert_eq!(
+            render(&sounds, Spacing::default()),
+            Err(SequenceError::Infinite { index: 1 })
+        );
+
+        let capped = options.cut_at(Some(Duration::from_millis(100)));
+        let sequence = render(
+            &[(sound(0), options), (sound(6), capped)],
+            Spacing::default(),
+        );
+        assert_eq!(sequence.unwrap().buffer.frames(), frames(16) + frames(6));
+    }
+
+    #[test]
+    fn test_sample_rate_mismatch() {
+        let options = SynthesisOptions::new();
+        let sounds = [(sound(0), options), (sound(3), options.preview(8000))];
+
+        assert_eq!(
+            render(&sounds, Spacing::default()),
+            Err(SequenceError::SampleRateMismatch { index: 1 })
+        );
+    }
+}
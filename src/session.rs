@@ -0,0 +1,88 @@
+//! Rendering a sound in small parts, as it is played.
+
+use std::time::Duration;
+
+use crate::generation::Render;
+
+/// Renders a sound a few milliseconds at a time, e.g. from an audio callback or a UI thread.
+///
+/// The returned samples are written to a buffer that is reused between calls, so nothing is
+/// allocated once the buffer has grown to the largest requested size.
+///
+/// Created with `gen1::Pcm::session` or `gen2::Pcm::session`.
+#[derive(Debug, Clone)]
+pub struct RenderSession<I> {
+    iter: I,
+    buffer: Vec<f32>,
+    /// Number of samples rendered so far.
+    position: usize,
+    /// Total number of samples, or `usize::MAX` if the sound is infinitely long.
+    total: usize,
+    /// Fraction of a sample that was requested but not yet rendered.
+    remainder: f64,
+    is_done: bool,
+}
+
+impl<I: Render> RenderSession<I> {
+    pub(crate) fn new(iter: I) -> RenderSession<I> {
+        RenderSession {
+            total: iter.clone().count(),
+            iter,
+            buffer: Vec::new(),
+            position: 0,
+            remainder: 0.0,
+            is_done: false,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.iter.sample_rate()
+    }
+
+    /// Render the next `ms` milliseconds of the sound. \
+    /// The result is shorter than requested at the end of the sound, and empty once it's done.
+    ///
+    /// A negative, NaN or infinite `ms` is rejected, and renders nothing.
+    pub fn render_ms(&mut self, ms: f32) -> &[f32] {
+        self.buffer.clear();
+
+        if !ms.is_finite() || ms < 0.0 {
+            return &self.buffer;
+        }
+
+        let samples = (ms as f64) * (self.sample_rate() as f64) / 1000.0 + self.remainder;
+        let remaining = self.total.saturating_sub(self.position);
+        let count = (samples.floor() as usize).min(remaining);
+        self.remainder = samples.fract();
+
+        self.buffer.resize(count, 0.0);
+        let len = self.iter.fill(&mut self.buffer);
+        self.buffer.truncate(len);
+        self.position += len;
+
+        if self.buffer.len() < count || self.position == self.total {
+            self.is_done = true;
+        }
+
+        &self.buffer
+    }
+
+    /// Duration of the audio that has been rendered so far.
+    pub fn position(&self) -> Duration {
+        Duration::from_secs_f64((self.position as f64) / (self.sample_rate() as f64))
+    }
+
+    /// Fraction of the sound that has been rendered so far, between 0 and 1. \
+    /// Returns `None` if the sound is infinitely long.
+    pub fn progress(&self) -> Option<f32> {
+        match self.total {
+            usize::MAX => None,
+            0 => Some(1.0),
+            total => Some((self.position as f32) / (total as f32)),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.is_done
+    }
+}
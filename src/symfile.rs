@@ -0,0 +1,186 @@
+//! Parsing of the `.sym` files produced by RGBDS, for resolving names of sounds in ROM hacks.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use crate::RomLocation;
+
+/// The ROM symbols of a `.sym` file.
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    symbols: Vec<(String, RomLocation)>,
+    by_name: HashMap<String, usize>,
+    by_location: HashMap<RomLocation, usize>,
+    ram_symbols: usize,
+    malformed_lines: usize,
+}
+
+/// Parse a `.sym` file, with one `BB:AAAA LabelName` symbol per line.
+///
+/// Comments starting with `;` and empty lines are ignored. Symbols in RAM (any address at or
+/// above `0x8000`) are counted but not stored, and lines that can't be parsed are skipped and
+/// counted in `malformed_lines`.
+pub fn parse<R: BufRead>(reader: R) -> io::Result<SymbolTable> {
+    let mut table = SymbolTable::default();
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = match line.find(';') {
+            Some(index) => &line[..index],
+            None => &line,
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match parse_line(line) {
+            Some((_, location)) if location.addr >= 0x8000 => table.ram_symbols += 1,
+            Some((name, location)) => table.insert(name, location),
+            None => table.malformed_lines += 1,
+        }
+    }
+
+    Ok(table)
+}
+
+fn parse_line(line: &str) -> Option<(&str, RomLocation)> {
+    let mut fields = line.split_whitespace();
+
+    let (bank, addr) = fields.next()?.split_once(':')?;
+    let name = fields.next()?;
+
+    if fields.next().is_some() {
+        return None;
+    }
+
+    let bank = u8::from_str_radix(bank, 16).ok()?;
+    let addr = u16::from_str_radix(addr, 16).ok()?;
+
+    Some((name, RomLocation::new(bank, addr)))
+}
+
+impl SymbolTable {
+    fn insert(&mut self, name: &str, location: RomLocation) {
+        let index = self.symbols.len();
+
+        self.symbols.push((name.to_owned(), location));
+        self.by_name.entry(name.to_owned()).or_insert(index);
+        self.by_location.entry(location).or_insert(index);
+    }
+
+    /// The name of the first symbol at the given location.
+    pub fn lookup_addr(&self, location: RomLocation) -> Option<&str> {
+        self.by_location
+            .get(&location)
+            .map(|&index| self.symbols[index].0.as_str())
+    }
+
+    /// The location of the symbol with the given name.
+    pub fn lookup_name(&self, name: &str) -> Option<RomLocation> {
+        self.by_name.get(name).map(|&index| self.symbols[index].1)
+    }
+
+    /// All symbols whose name starts with the given prefix, ignoring case, in file order.
+    pub fn search<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, RomLocation)> {
+        self.iter().filter(move |(name, _)| {
+            name.len() >= prefix.len()
+                && name.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+        })
+    }
+
+    /// Iterate over all ROM symbols, in file order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, RomLocation)> {
+        self.symbols
+            .iter()
+            .map(|(name, location)| (name.as_str(), *location))
+    }
+
+    /// Number of ROM symbols.
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Number of symbols that were skipped because they are in RAM.
+    pub fn ram_symbols(&self) -> usize {
+        self.ram_symbols
+    }
+
+    /// Number of lines that were skipped because they couldn't be parsed.
+    pub fn malformed_lines(&self) -> usize {
+        self.malformed_lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYM_FILE: &str = "; File generated by rgblink
+00:0000 RST_00
+02:4000 SFX_Headers_1
+02:403c SFX_Cry00_1
+02:40c3 SFX_Cry0F_1
+02:40c3 SFX_Cry0F_1_Alias
+02:61d1 SFX_Cry0A_1_Ch5 ; channel data
+02:61d1 SFX_Cry0A_1_Ch5.loop
+c0ef wAudioROMBank
+00:c0ef wAudioROMBank
+garbage
+02:zzzz SFX_Broken
+
+";
+
+    #[test]
+    fn test_parse() {
+        let table = parse(SYM_FILE.as_bytes()).unwrap();
+
+        assert_eq!(table.len(), 7);
+        assert_eq!(table.ram_symbols(), 1);
+        assert_eq!(table.malformed_lines(), 3);
+
+        assert_eq!(
+            table.lookup_name("SFX_Cry0F_1"),
+            Some(RomLocation::new(0x02, 0x40c3))
+        );
+        assert_eq!(
+            table.lookup_name("SFX_Cry0A_1_Ch5"),
+            Some(RomLocation::new(0x02, 0x61d1))
+        );
+        assert_eq!(table.lookup_name("SFX_Cry01_1"), None);
+
+        assert_eq!(
+            table.lookup_addr(RomLocation::new(0x02, 0x40c3)),
+            Some("SFX_Cry0F_1")
+        );
+        assert_eq!(
+            table.lookup_addr(RomLocation::new(0x02, 0x403c)),
+            Some("SFX_Cry00_1")
+        );
+        assert_eq!(table.lookup_addr(RomLocation::new(0x02, 0x403d)), None);
+    }
+
+    #[test]
+    fn test_search() {
+        let table = parse(SYM_FILE.as_bytes()).unwrap();
+
+        let names: Vec<&str> = table.search("sfx_cry0").map(|(name, _)| name).collect();
+        assert_eq!(
+            names,
+            [
+                "SFX_Cry00_1",
+                "SFX_Cry0F_1",
+                "SFX_Cry0F_1_Alias",
+                "SFX_Cry0A_1_Ch5",
+                "SFX_Cry0A_1_Ch5.loop"
+            ]
+        );
+
+        assert_eq!(table.search("SFX_Cry0A").count(), 2);
+        assert_eq!(table.search("wAudio").count(), 0);
+    }
+}
@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::generation::Render;
+use crate::session::RenderSession;
+use crate::{gen1, gen2, Game, RomLocation, SynthesisOptions};
+
+/// Maximum number of parsed sounds to keep around.
+const CACHE_CAPACITY: usize = 256;
+
+/// Error returned by `Synthesizer`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SynthesizerError {
+    /// The ROM isn't one of the supported games.
+    UnknownRom,
+    /// Looking up cries isn't supported for the game, only for Red, Blue and Yellow.
+    CriesNotSupported(Game),
+    /// The cry couldn't be looked up, see `gen1::lookup_cry`.
+    Cry(gen1::CryError),
+}
+
+impl fmt::Display for SynthesizerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SynthesizerError::UnknownRom => write!(f, "Unknown ROM"),
+            SynthesizerError::CriesNotSupported(game) => {
+                write!(f, "Looking up cries isn't supported for {:?}", game)
+            }
+            SynthesizerError::Cry(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SynthesizerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SynthesizerError::Cry(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Number of sound lookups that were served from the cache, see `Synthesizer::cache_stats`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Number of sounds currently in the cache.
+    pub len: usize,
+}
+
+/// A parsed sound of either generation, borrowing the ROM of the synthesizer.
+#[derive(Debug, Clone, Copy)]
+enum ParsedSound {
+    Gen1(gen1::Sound<'static>),
+    Gen2(gen2::Sound<'static>),
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    /// Parsed sounds, with the tick they were last used at. The least recently used one is
+    /// evicted once the cache is full.
+    sounds: HashMap<RomLocation, (ParsedSound, u64)>,
+    /// Looked up cries by Pokédex number, there are at most 256 of them.
+    cries: HashMap<u8, Result<gen1::Cry, gen1::CryError>>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+/// Owns a ROM and renders sounds from it, for long running processes that render many sounds
+/// from the same ROM.
+///
+/// The game is detected and the sound tables are read once, and parsed sounds and cries are
+/// cached by location and species. The synthesizer is `Send` and `Sync`, so it can be shared
+/// between threads, e.g. behind an `Arc`. The sounds are returned as sessions that own what they
+/// need of the ROM, so they can be moved to another thread and outlive the synthesizer.
+#[derive(Debug)]
+pub struct Synthesizer {
+    // declared before `rom`, so that the parsed sounds are dropped before the ROM they borrow
+    cache: Mutex<Cache>,
+    tables: Vec<gen1::SoundTable>,
+    rom: Arc<[u8]>,
+    game: Game,
+}
+
+/// A sound rendered a few milliseconds at a time by a `Synthesizer`, see `RenderSession`.
+pub type SynthesizerSession = RenderSession<OwnedSoundIterator>;
+
+impl Synthesizer {
+    pub fn new(rom: impl Into<Arc<[u8]>>) -> Result<Synthesizer, SynthesizerError> {
+        let rom = rom.into();
+        let game = Game::detect(&rom).ok_or(SynthesizerError::UnknownRom)?;
+        let tables = game
+            .sound_banks()
+            .iter()
+            .map(|&bank| gen1::SoundTable::read(&rom, bank))
+            .collect();
+
+        Ok(Synthesizer {
+            cache: Mutex::new(Cache::default()),
+            tables,
+            rom,
+            game,
+        })
+    }
+
+    pub fn game(&self) -> Game {
+        self.game
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// The sound table of one of the banks in `Game::sound_banks`, `None` for any other bank.
+    pub fn sound_table(&self, bank: u8) -> Option<&gen1::SoundTable> {
+        let index = self.game.sound_banks().iter().position(|&b| b == bank)?;
+        self.tables.get(index)
+    }
+
+    /// The samples of a sound with the given pitch and length, e.g. a cry. The values are passed
+    /// as-is to `gen1::synthesis_with_options` or `gen2::synthesis_with_options`, and clamped to
+    /// the range of the first generation for Red, Blue and Yellow. Nothing is rendered until the
+    /// samples are read.
+    pub fn iter(
+        &self,
+        location: RomLocation,
+        pitch: i16,
+        length: u16,
+        options: SynthesisOptions,
+    ) -> OwnedSoundIterator {
+        let samples = match self.sound(location) {
+            ParsedSound::Gen1(sound) => {
+                let pitch = pitch.clamp(i8::MIN as i16, i8::MAX as i16) as i8;
+                let length = length.min(u8::MAX as u16) as u8;
+                let pcm = gen1::synthesis_from_sound(sound, pitch, length, options);
+                Samples::Gen1(Box::new(pcm.iter()))
+            }
+            ParsedSound::Gen2(sound) => {
+                let pcm = gen2::synthesis_from_sound(sound, pitch, length, options);
+                Samples::Gen2(Box::new(pcm.iter()))
+            }
+        };
+
+        OwnedSoundIterator {
+            samples,
+            _rom: Arc::clone(&self.rom),
+        }
+    }
+
+    /// Start rendering a sound with the given pitch and length, see `iter`.
+    pub fn render(
+        &self,
+        location: RomLocation,
+        pitch: i16,
+        length: u16,
+        options: SynthesisOptions,
+    ) -> SynthesizerSession {
+        RenderSession::new(self.iter(location, pitch, length, options))
+    }
+
+    /// Start rendering a sound effect, with the pitch and length that the engine uses for
+    /// anything that isn't a cry.
+    pub fn sfx(&self, location: RomLocation, options: SynthesisOptions) -> SynthesizerSession {
+        match self.game.generation() {
+            1 => self.render(location, 0, 0x80, options),
+            _ => self.render(location, 0, 0x100, options),
+        }
+    }
+
+    /// Start rendering the cry of the species with the given Pokédex number, see
+    /// `gen1::lookup_cry`. Looking up cries is only supported for Red, Blue and Yellow.
+    pub fn cry(
+        &self,
+        species: u8,
+        options: SynthesisOptions,
+    ) -> Result<SynthesizerSession, SynthesizerError> {
+        if self.game.generation() != 1 {
+            return Err(SynthesizerError::CriesNotSupported(self.game));
+        }
+
+        let cry = *self
+            .cache()
+            .cries
+            .entry(species)
+            .or_insert_with(|| gen1::lookup_cry(&self.rom, species));
+        let cry = cry.map_err(SynthesizerError::Cry)?;
+
+        Ok(self.render(cry.location, cry.pitch as i16, cry.length as u16, options))
+    }
+
+    /// Start rendering a piece of music. Music loops forever, so the session doesn't end unless
+    /// `options` ends it, with a `cut_at`, a `max_duration` or a `LoopBehavior` other than
+    /// `LoopBehavior::Infinite`.
+    pub fn music(&self, location: RomLocation, options: SynthesisOptions) -> SynthesizerSession {
+        self.sfx(location, options)
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        let cache = self.cache();
+        CacheStats {
+            len: cache.sounds.len(),
+            ..cache.stats
+        }
+    }
+
+    fn sound(&self, location: RomLocation) -> ParsedSound {
+        let mut cache = self.cache();
+        cache.tick += 1;
+        let tick = cache.tick;
+
+        if let Some((sound, last_used)) = cache.sounds.get_mut(&location) {
+            *last_used = tick;
+            let sound = *sound;
+            cache.stats.hits += 1;
+            return sound;
+        }
+
+        cache.stats.misses += 1;
+
+        if cache.sounds.len() >= CACHE_CAPACITY {
+            let evicted = cache
+                .sounds
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(&location, _)| location);
+
+            if let Some(evicted) = evicted {
+                cache.sounds.remove(&evicted);
+            }
+        }
+
+        let rom = static_rom(&self.rom);
+        let RomLocation { bank, addr } = location;
+        let sound = match self.game.generation() {
+            1 => ParsedSound::Gen1(gen1::Sound::new(rom, bank, addr)),
+            _ => ParsedSound::Gen2(gen2::Sound::new(rom, bank, addr)),
+        };
+
+        cache.sounds.insert(location, (sound, tick));
+        sound
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Cache> {
+        // the cache is never left in an inconsistent state, so a poisoned lock is fine to use
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The ROM with its lifetime extended, for the parsed sounds and iterators that are stored
+/// together with an `Arc` of it.
+fn static_rom(rom: &Arc<[u8]>) -> &'static [u8] {
+    // SAFETY: the bytes are on the heap, and never move or change while an `Arc` of them is
+    // alive. Every value that the slice ends up in is private to a struct that also holds such
+    // an `Arc`, and is dropped before it, so the slice is never used after the ROM is freed.
+    unsafe { &*Arc::as_ptr(rom) }
+}
+
+/// Samples of either generation, boxed, since the iterators of the two generations don't keep
+/// the same state.
+#[derive(Debug, Clone)]
+enum Samples {
+    Gen1(Box<gen1::SoundIterator<'static>>),
+    Gen2(Box<gen2::SoundIterator<'static>>),
+}
+
+/// The samples of a sound rendered by a `Synthesizer`, see `Synthesizer::iter`. Keeps the ROM
+/// alive by itself, so it can be moved to another thread and outlive the synthesizer.
+#[derive(Debug, Clone)]
+pub struct OwnedSoundIterator {
+    // declared before `_rom`, so that it is dropped before the ROM it borrows
+    samples: Samples,
+    _rom: Arc<[u8]>,
+}
+
+impl OwnedSoundIterator {
+    pub fn sample_rate(&self) -> u32 {
+        match &self.samples {
+            Samples::Gen1(iter) => iter.sample_rate(),
+            Samples::Gen2(iter) => iter.sample_rate(),
+        }
+    }
+
+    /// Fill `out` with the next samples of the sound, see `gen1::SoundIterator::fill`.
+    pub fn fill(&mut self, out: &mut [f32]) -> usize {
+        match &mut self.samples {
+            Samples::Gen1(iter) => iter.fill(out),
+            Samples::Gen2(iter) => iter.fill(out),
+        }
+    }
+
+    /// Skip the next `n` samples without synthesizing them, see
+    /// `gen1::SoundIterator::skip_samples`.
+    pub fn skip_samples(&mut self, n: usize) -> usize {
+        match &mut self.samples {
+            Samples::Gen1(iter) => iter.skip_samples(n),
+            Samples::Gen2(iter) => iter.skip_samples(n),
+        }
+    }
+}
+
+impl Iterator for OwnedSoundIterator {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        match &mut self.samples {
+            Samples::Gen1(iter) => iter.next(),
+            Samples::Gen2(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.samples {
+            Samples::Gen1(iter) => iter.size_hint(),
+            Samples::Gen2(iter) => iter.size_hint(),
+        }
+    }
+
+    fn count(self) -> usize {
+        match self.samples {
+            // `Box` only forwards `next` and `size_hint`, the iterators count without rendering
+            Samples::Gen1(iter) => (*iter).count(),
+            Samples::Gen2(iter) => (*iter).count(),
+        }
+    }
+}
+
+impl Render for OwnedSoundIterator {
+    fn sample_rate(&self) -> u32 {
+        OwnedSoundIterator::sample_rate(self)
+    }
+
+    fn fill(&mut self, out: &mut [f32]) -> usize {
+        OwnedSoundIterator::fill(self, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::LoopBehavior;
+
+    /// A ROM with the title of Pokémon Red, and a single pulse channel SFX at 02:4000.
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0xc000];
+        rom[0x134..0x13f].copy_from_slice(b"POKEMON RED");
+        rom[0x8000..0x8008].copy_from_slice(&[0x04, 0x03, 0x40, 0x2f, 0xf1, 0x00, 0x07, 0xff]);
+        rom
+    }
+
+    fn render_all(mut session: SynthesizerSession) -> Vec<f32> {
+        let mut samples = Vec::new();
+
+        while !session.is_done() {
+            samples.extend_from_slice(session.render_ms(100.0));
+        }
+
+        samples
+    }
+
+    #[test]
+    fn test_render_cached() {
+        let synthesizer = Synthesizer::new(rom()).unwrap();
+        let location = RomLocation::new(0x02, 0x4000);
+        assert_eq!(synthesizer.game(), Game::Red);
+
+        let first = render_all(synthesizer.sfx(location, SynthesisOptions::new()));
+        let second = render_all(synthesizer.sfx(location, SynthesisOptions::new()));
+
+        assert_eq!(first, second);
+        assert_eq!(
+            synthesizer.cache_stats(),
+            CacheStats {
+                hits: 1,
+                misses: 1,
+                len: 1
+            }
+        );
+
+        let expected: Vec<f32> = gen1::synthesis(synthesizer.rom(), 0x02, 0x4000, 0, 0x80)
+            .iter()
+            .collect();
+        assert_eq!(first, expected);
+    }
+
+    #[test]
+    fn test_least_recently_used() {
+        let synthesizer = Synthesizer::new(rom()).unwrap();
+        let location = |index: usize| RomLocation::new(0x02, 0x4000 + index as u16);
+        let lookup = |index| synthesizer.iter(location(index), 0, 0, SynthesisOptions::new());
+
+        for index in 0..CACHE_CAPACITY {
+            lookup(index);
+        }
+
+        // the first sound is used again, so the second one is evicted instead
+        lookup(0);
+        lookup(CACHE_CAPACITY);
+        assert_eq!(synthesizer.cache_stats().len, CACHE_CAPACITY);
+
+        let stats = synthesizer.cache_stats();
+        lookup(0);
+        assert_eq!(synthesizer.cache_stats().hits, stats.hits + 1);
+        lookup(1);
+        assert_eq!(synthesizer.cache_stats().misses, stats.misses + 1);
+    }
+
+    #[test]
+    fn test_owned_session() {
+        let synthesizer = Synthesizer::new(rom()).unwrap();
+        let location = RomLocation::new(0x02, 0x4000);
+        let expected: Vec<f32> = synthesizer
+            .iter(location, 0, 0x80, SynthesisOptions::new())
+            .collect();
+
+        let session = synthesizer.sfx(location, SynthesisOptions::new());
+        drop(synthesizer);
+
+        let samples = thread::spawn(move || render_all(session)).join().unwrap();
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn test_infinite() {
+        let mut rom = rom();
+        rom[0x8007..0x800b].copy_from_slice(&[0xfe, 0x00, 0x03, 0x40]);
+        let synthesizer = Synthesizer::new(rom).unwrap();
+        let location = RomLocation::new(0x02, 0x4000);
+
+        let mut session = synthesizer.music(location, SynthesisOptions::new());
+        assert_eq!(session.progress(), None);
+        assert_eq!(
+            session.render_ms(1000.0).len(),
+            session.sample_rate() as usize
+        );
+        assert!(!session.is_done());
+
+        for options in [
+            SynthesisOptions::new().cut_at(Some(Duration::from_secs(1))),
+            SynthesisOptions::new().loop_behavior(LoopBehavior::Iterations(2)),
+        ] {
+            let session = synthesizer.music(location, options);
+            assert!(session.progress().is_some());
+            assert!(!render_all(session).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_cries() {
+        let synthesizer = Synthesizer::new(rom()).unwrap();
+        let err = synthesizer.cry(1, SynthesisOptions::new()).unwrap_err();
+        assert_eq!(err, SynthesizerError::Cry(gen1::CryError::TablesNotFound));
+
+        let mut rom = rom();
+        rom[0x134..0x13f].copy_from_slice(b"PM_CRYSTAL\0");
+        let synthesizer = Synthesizer::new(rom).unwrap();
+        let err = synthesizer.cry(1, SynthesisOptions::new()).unwrap_err();
+        assert_eq!(err, SynthesizerError::CriesNotSupported(Game::Crystal));
+    }
+
+    #[test]
+    fn test_sound_tables() {
+        let synthesizer = Synthesizer::new(rom()).unwrap();
+        assert!(synthesizer.sound_table(0x02).is_some());
+        assert!(synthesizer.sound_table(0x03).is_none());
+    }
+
+    #[test]
+    fn test_unknown_rom() {
+        assert_eq!(
+            Synthesizer::new(vec![0; 0x8000]).unwrap_err(),
+            SynthesizerError::UnknownRom
+        );
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        fn assert_send<T: Send>() {}
+        assert_send_sync::<Synthesizer>();
+        assert_send::<SynthesizerSession>();
+        assert_send::<OwnedSoundIterator>();
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Number of samples generated for every frame. \
+/// A Game Boy frame is 70224 CPU cycles long, and the sound is generated at a quarter of the CPU
+/// clock, which makes each frame 17556 samples long (roughly 59.73 frames per second).
+pub const SAMPLES_PER_FRAME: usize = 17556;
+
+/// Sample rate of the synthesized sound, a quarter of the 4 MiHz Game Boy CPU clock.
+pub const SOURCE_SAMPLE_RATE: usize = 1048576;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Sample rate of the synthesized sound, the same as `SOURCE_SAMPLE_RATE`.
+pub fn samples_per_second() -> u32 {
+    SOURCE_SAMPLE_RATE as u32
+}
+
+/// Duration of the given number of Game Boy frames, truncated to whole nanoseconds.
+pub fn frames_to_duration(frames: u64) -> Duration {
+    let nanos = (frames as u128) * (SAMPLES_PER_FRAME as u128) * NANOS_PER_SECOND
+        / (SOURCE_SAMPLE_RATE as u128);
+
+    Duration::new(
+        (nanos / NANOS_PER_SECOND) as u64,
+        (nanos % NANOS_PER_SECOND) as u32,
+    )
+}
+
+/// Number of Game Boy frames needed to cover the given duration, a partial frame counts as a
+/// whole frame.
+pub fn duration_to_frames(duration: Duration) -> u64 {
+    let samples = duration.as_nanos() * (SOURCE_SAMPLE_RATE as u128);
+    let nanos_per_frame = (SAMPLES_PER_FRAME as u128) * NANOS_PER_SECOND;
+
+    ((samples + nanos_per_frame - 1) / nanos_per_frame) as u64
+}
+
+/// The length of a sound in samples, computed the first time it is needed, since that takes a
+/// pass through the whole sound. An atomic is used rather than a `Cell`, so that the sound
+/// iterators stay `Sync`.
+#[derive(Debug, Default)]
+pub(crate) struct LengthCache(AtomicUsize);
+
+impl LengthCache {
+    // 0 is not computed yet, 1 is infinitely long, and any other value is the length plus 2
+    const UNKNOWN: usize = 0;
+    const INFINITE: usize = 1;
+
+    /// The cached length, or the result of `init` if it hasn't been computed yet. `None` is an
+    /// infinitely long sound.
+    pub(crate) fn get_or_init(&self, init: impl FnOnce() -> Option<usize>) -> Option<usize> {
+        match self.0.load(Ordering::Relaxed) {
+            LengthCache::UNKNOWN => {
+                let len = init();
+                let value = len.map_or(LengthCache::INFINITE, |len| len.saturating_add(2));
+                self.0.store(value, Ordering::Relaxed);
+                len
+            }
+            LengthCache::INFINITE => None,
+            value => Some(value - 2),
+        }
+    }
+}
+
+impl Clone for LengthCache {
+    fn clone(&self) -> LengthCache {
+        LengthCache(AtomicUsize::new(self.0.load(Ordering::Relaxed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_to_duration() {
+        assert_eq!(frames_to_duration(0), Duration::ZERO);
+        assert_eq!(frames_to_duration(1), Duration::from_nanos(16742706));
+        assert_eq!(frames_to_duration(60), Duration::from_nanos(1004562377));
+    }
+
+    #[test]
+    fn test_duration_to_frames() {
+        assert_eq!(duration_to_frames(Duration::ZERO), 0);
+        assert_eq!(duration_to_frames(Duration::from_nanos(1)), 1);
+        assert_eq!(duration_to_frames(Duration::from_millis(100)), 6);
+        assert_eq!(duration_to_frames(Duration::from_secs(1)), 60);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for frames in (0..10_000).chain([u32::MAX as u64]) {
+            assert_eq!(duration_to_frames(frames_to_duration(frames)), frames);
+        }
+    }
+}